@@ -6,6 +6,7 @@ set -ex
 main() {
     cross build --target $TARGET
     cross build --target $TARGET --release
+    cross build --target $TARGET --no-default-features --all-targets
 
     if [ ! -z $DISABLE_TESTS ]; then
         return
@@ -13,6 +14,7 @@ main() {
 
     cross test --target $TARGET
     cross test --target $TARGET --release
+    cross test --target $TARGET --no-default-features
 
     #cross run --target $TARGET
     #cross run --target $TARGET --release
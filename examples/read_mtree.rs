@@ -3,7 +3,7 @@ use std::env;
 use std::error::Error;
 use std::fs::File;
 
-fn main() -> Result<(), Box<Error>> {
+fn main() -> Result<(), Box<dyn Error>> {
     let path = env::current_dir()?.join("examples/gedit.mtree");
     let mtree = MTree::from_reader(File::open(path)?);
     for entry in mtree {
@@ -0,0 +1,601 @@
+//! Changing a directory tree to match an mtree spec, enabled by the `apply` feature.
+use crate::parser::{FileMode, FileType};
+use crate::verify::{spec_path, Listed};
+use crate::{Entry, Error};
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{lchown, symlink, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Changes the ownership, permissions and modification times of files in a directory tree to
+/// match the entries of a spec, like `mtree -U`.
+///
+/// Paths in the spec are found the same way as by the [`Verifier`](crate::Verifier). Only the
+/// `uid`, `gid`, `mode` and `time` keywords are applied, and only when they differ from the
+/// file. Entries with `nochange` are left alone, and symlinks are changed themselves rather
+/// than what they point to (except for `mode`, which symlinks don't have).
+///
+/// Missing directories, symlinks and fifos are created (an entry for a symlink must have a
+/// `link`), before the other keywords are applied to them. Nothing below a directory whose
+/// entry has `ignore` is changed.
+///
+/// A path with a `..` in it is an error, before anything is changed for it, so a spec can't
+/// change files outside the root.
+#[derive(Debug, Clone)]
+pub struct Applier {
+    /// The directory the spec describes.
+    root: PathBuf,
+    /// Whether to remove files that aren't in the spec.
+    prune: bool,
+    /// Whether to only work out what would be changed.
+    dry_run: bool,
+}
+
+impl Applier {
+    /// Create an applier for the tree at `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Applier {
+        Applier {
+            root: root.into(),
+            prune: false,
+            dry_run: false,
+        }
+    }
+
+    /// Choose whether to only work out what would be changed, without changing anything.
+    ///
+    /// When `true`, the actions that are returned are the ones that would have been performed.
+    pub fn dry_run(mut self, dry_run: bool) -> Applier {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Choose whether to remove files that aren't in the spec, like `mtree -r`.
+    ///
+    /// When `true`, [`Applier::apply`] removes every file and directory (along with everything
+    /// in it) that has no entry, except below directories whose entry has `ignore`. This makes
+    /// the tree match the spec exactly, so be careful with it.
+    pub fn prune(mut self, prune: bool) -> Applier {
+        self.prune = prune;
+        self
+    }
+
+    /// Apply each of `entries`, returning everything that was changed.
+    ///
+    /// This stops at the first error.
+    pub fn apply<I>(&self, entries: I) -> Result<Vec<Action>, Error>
+    where
+        I: IntoIterator<Item = Result<Entry, Error>>,
+    {
+        let mut actions = Vec::new();
+        let mut listed = Listed::default();
+        for entry in entries {
+            let entry = entry?;
+            if listed.insert(spec_path(entry.spec_path()), &entry) {
+                actions.extend(self.apply_entry(&entry)?);
+            }
+        }
+        if self.prune {
+            let extras = listed.extras(&self.root).collect::<Result<Vec<_>, _>>()?;
+            for path in extras {
+                let remove = Action::Remove { path };
+                self.perform(&remove)?;
+                actions.push(remove);
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Apply a single entry, returning what was changed.
+    ///
+    /// It is an error for the file not to exist, unless its entry has `optional` or it can be
+    /// created.
+    pub fn apply_entry(&self, entry: &Entry) -> Result<Vec<Action>, Error> {
+        let path = spec_path(entry.spec_path());
+        check_inside_root(&path)?;
+        let full_path = self.root.join(&path);
+        let path = Path::new(".").join(path);
+        let mut actions = Vec::new();
+        let metadata = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) => Some(metadata),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if entry.params.optional {
+                    return Ok(actions);
+                }
+                let create = Action::create(&path, entry).ok_or(e)?;
+                self.perform(&create)?;
+                actions.push(create);
+                if self.dry_run {
+                    None
+                } else {
+                    Some(fs::symlink_metadata(&full_path)?)
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if entry.params.no_change {
+            return Ok(actions);
+        }
+        let changes = plan(&path, entry, metadata.as_ref())?;
+        for action in &changes {
+            self.perform(action)?;
+        }
+        actions.extend(changes);
+        Ok(actions)
+    }
+
+    /// Perform `action`, unless this is a dry run.
+    fn perform(&self, action: &Action) -> io::Result<()> {
+        check_inside_root(action.path())?;
+        if self.dry_run {
+            return Ok(());
+        }
+        action.perform(&self.root.join(action.path()))
+    }
+}
+
+/// An error if `path`, relative to the root, could lead outside of it.
+fn check_inside_root(path: &Path) -> io::Result<()> {
+    let outside = path.components().any(|c| {
+        matches!(
+            c,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+    if outside {
+        let msg = format!("{} is outside the root", path.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    Ok(())
+}
+
+/// The changes needed to make the file with `metadata` match `entry`.
+///
+/// If there's no metadata (because the file would have been created in a dry run), every
+/// keyword is applied.
+fn plan(path: &Path, entry: &Entry, metadata: Option<&Metadata>) -> io::Result<Vec<Action>> {
+    /// The uid or gid from the spec, if it differs from the current one.
+    fn id(id: Option<u32>, current: Option<u32>) -> Option<u32> {
+        id.filter(|id| Some(*id) != current)
+    }
+
+    let mut actions = Vec::new();
+    let uid = id(entry.params.uid, metadata.map(|m| m.uid()));
+    let gid = id(entry.params.gid, metadata.map(|m| m.gid()));
+    if uid.is_some() || gid.is_some() {
+        actions.push(Action::Chown {
+            path: path.to_owned(),
+            uid,
+            gid,
+        });
+    }
+    // chown clears the setuid and setgid bits, so this has to come after it
+    if let Some(mode) = entry.params.mode {
+        let is_symlink = match metadata {
+            Some(metadata) => metadata.file_type().is_symlink(),
+            None => entry.params.file_type == Some(FileType::SymbolicLink),
+        };
+        let current = metadata.map(FileMode::from_metadata);
+        if !is_symlink && (!actions.is_empty() || current != Some(mode)) {
+            actions.push(Action::Chmod {
+                path: path.to_owned(),
+                mode,
+            });
+        }
+    }
+    if let Some(time) = entry.params.time {
+        if metadata.and_then(|m| m.modified().ok()) != Some(time) {
+            actions.push(Action::SetTime {
+                path: path.to_owned(),
+                time,
+            });
+        }
+    }
+    Ok(actions)
+}
+
+/// A change made to a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Action {
+    /// Create a directory.
+    CreateDir {
+        /// The path of the directory, relative to the root.
+        path: PathBuf,
+    },
+    /// Create a symlink.
+    CreateSymlink {
+        /// The path of the symlink, relative to the root.
+        path: PathBuf,
+        /// What the symlink points to.
+        target: PathBuf,
+    },
+    /// Create a fifo.
+    CreateFifo {
+        /// The path of the fifo, relative to the root.
+        path: PathBuf,
+    },
+    /// Remove a file, or a directory and everything in it.
+    Remove {
+        /// The path of the file, relative to the root.
+        path: PathBuf,
+    },
+    /// Change the owner and/or group of a file.
+    Chown {
+        /// The path of the file, relative to the root.
+        path: PathBuf,
+        /// The new owner, if it changed.
+        uid: Option<u32>,
+        /// The new group, if it changed.
+        gid: Option<u32>,
+    },
+    /// Change the permissions of a file.
+    Chmod {
+        /// The path of the file, relative to the root.
+        path: PathBuf,
+        /// The new permissions.
+        mode: FileMode,
+    },
+    /// Change the modification time of a file.
+    SetTime {
+        /// The path of the file, relative to the root.
+        path: PathBuf,
+        /// The new modification time.
+        time: SystemTime,
+    },
+}
+
+impl Action {
+    /// The action that creates the missing file for `entry`, if it can be created.
+    fn create(path: &Path, entry: &Entry) -> Option<Action> {
+        let path = path.to_owned();
+        match entry.params.file_type? {
+            FileType::Directory => Some(Action::CreateDir { path }),
+            FileType::SymbolicLink => Some(Action::CreateSymlink {
+                path,
+                target: entry.params.link.clone()?,
+            }),
+            FileType::Fifo => Some(Action::CreateFifo { path }),
+            _ => None,
+        }
+    }
+
+    /// The path of the file that is changed, relative to the root.
+    pub fn path(&self) -> &Path {
+        match self {
+            Action::CreateDir { path }
+            | Action::CreateSymlink { path, .. }
+            | Action::CreateFifo { path }
+            | Action::Remove { path }
+            | Action::Chown { path, .. }
+            | Action::Chmod { path, .. }
+            | Action::SetTime { path, .. } => path,
+        }
+    }
+
+    /// Make the change to the file at `full_path`.
+    fn perform(&self, full_path: &Path) -> io::Result<()> {
+        match self {
+            Action::CreateDir { .. } => fs::create_dir(full_path),
+            Action::CreateSymlink { target, .. } => symlink(target, full_path),
+            Action::CreateFifo { .. } => mkfifo(full_path),
+            Action::Remove { .. } => {
+                if fs::symlink_metadata(full_path)?.is_dir() {
+                    fs::remove_dir_all(full_path)
+                } else {
+                    fs::remove_file(full_path)
+                }
+            }
+            Action::Chown { uid, gid, .. } => lchown(full_path, *uid, *gid),
+            Action::Chmod { mode, .. } => fs::set_permissions(full_path, (*mode).into()),
+            Action::SetTime { time, .. } => set_modified(full_path, *time),
+        }
+    }
+}
+
+/// Writes e.g. `./etc/passwd: chmod 644`.
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.path().display())?;
+        match self {
+            Action::CreateDir { .. } => f.write_str("mkdir"),
+            Action::CreateSymlink { target, .. } => write!(f, "symlink to {}", target.display()),
+            Action::CreateFifo { .. } => f.write_str("mkfifo"),
+            Action::Remove { .. } => f.write_str("remove"),
+            Action::Chown { uid, gid, .. } => {
+                f.write_str("chown ")?;
+                if let Some(uid) = uid {
+                    write!(f, "{}", uid)?;
+                }
+                if let Some(gid) = gid {
+                    write!(f, ":{}", gid)?;
+                }
+                Ok(())
+            }
+            Action::Chmod { mode, .. } => write!(f, "chmod {:o}", mode),
+            Action::SetTime { time, .. } => {
+                let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                write!(
+                    f,
+                    "set time to {}.{:09}",
+                    time.as_secs(),
+                    time.subsec_nanos()
+                )
+            }
+        }
+    }
+}
+
+/// Create a fifo at `path`.
+fn mkfifo(path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a valid C string.
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o644) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Set the modification time of the file at `path`, without following symlinks.
+fn set_modified(path: &Path, time: SystemTime) -> io::Result<()> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "time is before the unix epoch")
+    })?;
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos().into(),
+        },
+    ];
+    // SAFETY: `path` is a valid C string and `times` holds two timespecs, as required.
+    let res = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[test]
+fn test_apply() {
+    use crate::{MTree, Params};
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+    let root = crate::scan::test_dir("apply");
+    fs::write(root.join("file"), b"").unwrap();
+    fs::set_permissions(root.join("file"), fs::Permissions::from_mode(0o600)).unwrap();
+    std::os::unix::fs::symlink("file", root.join("link")).unwrap();
+    let uid = fs::metadata(&root).unwrap().uid();
+
+    let spec = format!(
+        "./file uid={} mode=644 time=1000000000.5\n./link type=link mode=600 time=1000000000\n",
+        uid
+    );
+    let actions = Applier::new(&root)
+        .apply(MTree::from_reader(spec.as_bytes()))
+        .unwrap();
+    assert_eq!(
+        actions.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+        vec![
+            "./file: chmod 644",
+            "./file: set time to 1000000000.500000000",
+            "./link: set time to 1000000000.000000000",
+        ]
+    );
+    let metadata = fs::metadata(root.join("file")).unwrap();
+    assert_eq!(metadata.mode() & 0o7777, 0o644);
+    assert_eq!(
+        metadata.modified().unwrap(),
+        UNIX_EPOCH + Duration::new(1000000000, 500000000)
+    );
+    let link = fs::symlink_metadata(root.join("link")).unwrap();
+    assert_eq!(
+        link.modified().unwrap(),
+        UNIX_EPOCH + Duration::new(1000000000, 0)
+    );
+    // applying again changes nothing
+    assert!(Applier::new(&root)
+        .apply(MTree::from_reader(spec.as_bytes()))
+        .unwrap()
+        .is_empty());
+    // missing optional entries are skipped
+    let entry = Entry::new("./missing").with_params(Params::builder().optional(true).build());
+    assert!(Applier::new(&root).apply_entry(&entry).unwrap().is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_apply_create() {
+    use crate::MTree;
+    use std::os::unix::fs::FileTypeExt;
+    let root = crate::scan::test_dir("apply-create");
+
+    let spec = b"\
+./dir type=dir mode=700
+./dir/link type=link link=../target
+./dir/fifo type=fifo
+";
+    let actions = Applier::new(&root)
+        .apply(MTree::from_reader(&spec[..]))
+        .unwrap();
+    assert_eq!(
+        actions.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+        vec![
+            "./dir: mkdir",
+            "./dir: chmod 700",
+            "./dir/link: symlink to ../target",
+            "./dir/fifo: mkfifo",
+        ]
+    );
+    let metadata = fs::metadata(root.join("dir")).unwrap();
+    assert!(metadata.is_dir());
+    assert_eq!(
+        fs::read_link(root.join("dir/link")).unwrap(),
+        Path::new("../target")
+    );
+    let fifo = fs::symlink_metadata(root.join("dir/fifo")).unwrap();
+    assert!(fifo.file_type().is_fifo());
+    // regular files can't be created
+    let err = Applier::new(&root)
+        .apply(MTree::from_reader(&b"./file type=file\n"[..]))
+        .unwrap_err();
+    assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::NotFound));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_apply_outside_root() {
+    use crate::MTree;
+    let parent = crate::scan::test_dir("apply-outside");
+    let root = parent.join("root");
+    fs::create_dir_all(&root).unwrap();
+
+    for spec in [
+        &b"./../outside type=dir\n"[..],
+        b"./dir type=dir\n./dir/../../outside type=dir mode=700\n",
+        b"dir type=dir\n    ../../outside type=fifo\n",
+    ] {
+        for applier in [Applier::new(&root), Applier::new(&root).prune(true)] {
+            let err = applier.apply(MTree::from_reader(spec)).unwrap_err();
+            assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+            assert!(!parent.join("outside").exists());
+        }
+    }
+    fs::remove_dir_all(&parent).unwrap();
+}
+
+#[test]
+fn test_apply_prune() {
+    use crate::MTree;
+    let root = crate::scan::test_dir("apply-prune");
+    for dir in ["keep", "extra/sub", "cache/sub"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for file in [
+        "keep/file",
+        "keep/extra",
+        "extra/sub/file",
+        "cache/sub/file",
+    ] {
+        fs::write(root.join(file), b"").unwrap();
+    }
+
+    let spec = b"\
+./keep type=dir
+./keep/file type=file
+./cache type=dir ignore
+";
+    let actions = Applier::new(&root)
+        .apply(MTree::from_reader(&spec[..]))
+        .unwrap();
+    assert!(actions.is_empty());
+    assert!(root.join("keep/extra").exists());
+
+    let actions = Applier::new(&root)
+        .prune(true)
+        .apply(MTree::from_reader(&spec[..]))
+        .unwrap();
+    assert_eq!(
+        actions.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+        vec!["./extra: remove", "./keep/extra: remove"]
+    );
+    assert!(!root.join("extra").exists());
+    assert!(!root.join("keep/extra").exists());
+    assert!(root.join("keep/file").exists());
+    assert!(root.join("cache/sub/file").exists());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_apply_with_root() {
+    use crate::MTree;
+    let root = crate::scan::test_dir("apply-root");
+    fs::create_dir_all(root.join("usr")).unwrap();
+    fs::write(root.join("extra"), b"").unwrap();
+
+    // the entries have absolute paths below `root`, but are found by their spec paths
+    let spec = b". type=dir\n    usr type=dir\n        bin type=dir\n";
+    let actions = Applier::new(&root)
+        .prune(true)
+        .dry_run(true)
+        .apply(MTree::from_reader_with_root(&spec[..], &root))
+        .unwrap();
+    let expected = vec![
+        Action::CreateDir {
+            path: "./usr/bin".into(),
+        },
+        Action::Remove {
+            path: "./extra".into(),
+        },
+    ];
+    assert_eq!(actions, expected);
+    assert!(!root.join("usr/bin").exists());
+
+    let actions = Applier::new(&root)
+        .prune(true)
+        .apply(MTree::from_reader_with_root(&spec[..], &root))
+        .unwrap();
+    assert_eq!(actions, expected);
+    assert!(root.join("usr/bin").is_dir());
+    assert!(!root.join("extra").exists());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_apply_dry_run() {
+    use crate::MTree;
+    use std::os::unix::fs::PermissionsExt;
+    let root = crate::scan::test_dir("apply-dry-run");
+    fs::write(root.join("file"), b"").unwrap();
+    fs::set_permissions(root.join("file"), fs::Permissions::from_mode(0o600)).unwrap();
+    fs::write(root.join("extra"), b"").unwrap();
+
+    let spec = b"./file mode=644\n./dir type=dir mode=700\n";
+    let actions = Applier::new(&root)
+        .prune(true)
+        .dry_run(true)
+        .apply(MTree::from_reader(&spec[..]))
+        .unwrap();
+    assert_eq!(
+        actions,
+        vec![
+            Action::Chmod {
+                path: "./file".into(),
+                mode: FileMode::from(0o644),
+            },
+            Action::CreateDir {
+                path: "./dir".into()
+            },
+            Action::Chmod {
+                path: "./dir".into(),
+                mode: FileMode::from(0o700),
+            },
+            Action::Remove {
+                path: "./extra".into()
+            },
+        ]
+    );
+    assert_eq!(
+        fs::metadata(root.join("file")).unwrap().mode() & 0o7777,
+        0o600
+    );
+    assert!(!root.join("dir").exists());
+    assert!(root.join("extra").exists());
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,546 @@
+//! Checking the contents of a tar or zip archive against an mtree spec.
+#[cfg(feature = "tar")]
+use crate::parser::{Device, Format};
+use crate::parser::{FileMode, FileType, KeywordSet};
+use crate::scan::readable_keywords;
+use crate::verify::{spec_path, Listed};
+#[cfg(feature = "tar")]
+use crate::{compress, Gname, MTree, Uname};
+use crate::{Entry, Error, Params, Verification, Verifier};
+use std::collections::BTreeMap;
+#[cfg(feature = "tar")]
+use std::convert::TryFrom;
+#[cfg(feature = "zip")]
+use std::ffi::OsString;
+#[cfg(feature = "zip")]
+use std::io::Seek;
+use std::io::{self, Read};
+#[cfg(feature = "zip")]
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tar")]
+use std::time::{Duration, UNIX_EPOCH};
+
+impl Verifier {
+    /// Check the members of a tar archive against `entries`, as if the archive had been
+    /// unpacked at the root.
+    ///
+    /// This works like [`Verifier::verify`], but reads the `type`, `mode`, `uid`, `gid`,
+    /// `uname`, `gname`, `size`, `time`, `link` and `device` keywords from the member headers,
+    /// and computes digests from the member contents. Hard links are checked against the
+    /// member they link to. The archive is only read once, so all of `entries` are read
+    /// before it, and the results are returned together, followed by the extra members.
+    ///
+    /// The verifier's root is not used.
+    #[cfg(feature = "tar")]
+    pub fn verify_tar<I, R>(
+        &self,
+        entries: I,
+        archive: &mut tar::Archive<R>,
+    ) -> Result<Vec<Verification>, Error>
+    where
+        I: IntoIterator<Item = Result<Entry, Error>>,
+        R: Read,
+    {
+        self.verify_members(entries, tar_keywords(), |keywords| {
+            let mut members = BTreeMap::new();
+            for member in archive.entries()? {
+                let member = member?;
+                let path = spec_path(&member.path()?);
+                let params = if member.header().entry_type().is_hard_link() {
+                    let target = member.link_name()?.unwrap_or_default();
+                    members
+                        .get(&spec_path(&target))
+                        .cloned()
+                        .unwrap_or_default()
+                } else {
+                    params_from_tar(member, keywords)?
+                };
+                members.insert(path, params);
+            }
+            Ok(members)
+        })
+    }
+
+    /// Check the files in a zip archive against `entries`, as if the archive had been
+    /// extracted at the root.
+    ///
+    /// This works like [`Verifier::verify_tar`], but only the `type`, `size` and `link`
+    /// keywords, the `mode` if the archive was made on unix, and digests of the contents are
+    /// checked. Zip archives store times without a time zone, so `time` isn't checked.
+    #[cfg(feature = "zip")]
+    pub fn verify_zip<I, R>(
+        &self,
+        entries: I,
+        archive: &mut zip::ZipArchive<R>,
+    ) -> Result<Vec<Verification>, Error>
+    where
+        I: IntoIterator<Item = Result<Entry, Error>>,
+        R: Read + Seek,
+    {
+        self.verify_members(entries, zip_keywords(), |keywords| {
+            let mut members = BTreeMap::new();
+            for i in 0..archive.len() {
+                let file = archive.by_index(i).map_err(io::Error::from)?;
+                let path = spec_path(Path::new(file.name()));
+                members.insert(path, params_from_zip(file, keywords)?);
+            }
+            Ok(members)
+        })
+    }
+
+    /// Check `entries` against the params of the members of an archive, read by
+    /// `read_members` given the keywords that are needed.
+    ///
+    /// Only the keywords in `readable` are checked.
+    fn verify_members<I, F>(
+        &self,
+        entries: I,
+        readable: KeywordSet,
+        read_members: F,
+    ) -> Result<Vec<Verification>, Error>
+    where
+        I: IntoIterator<Item = Result<Entry, Error>>,
+        F: FnOnce(KeywordSet) -> io::Result<BTreeMap<PathBuf, Params>>,
+    {
+        let mut listed = Listed::default();
+        let mut checked = Vec::new();
+        let mut needed = KeywordSet::empty();
+        for entry in entries {
+            let entry = entry?;
+            let path = spec_path(entry.spec_path());
+            if listed.insert(path.clone(), &entry) {
+                let keywords = self.checked_keywords(&entry, readable);
+                needed |= keywords;
+                checked.push((entry, path, keywords));
+            }
+        }
+
+        let members = read_members(needed)?;
+        let mut results = checked
+            .into_iter()
+            .map(|(entry, path, keywords)| {
+                let actual = members.get(&path);
+                self.compare(&entry, path, keywords, actual)
+            })
+            .collect::<Vec<_>>();
+        let mut last: Option<&Path> = None;
+        for path in members.keys() {
+            if path.as_os_str().is_empty() || last.is_some_and(|last| path.starts_with(last)) {
+                continue;
+            }
+            if !listed.contains(path) {
+                last = Some(path);
+                results.push(Verification::Extra(Path::new(".").join(path)));
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(feature = "tar")]
+impl MTree<Box<dyn Read>> {
+    /// Open the package at `path`, and read the spec in its `.MTREE` member.
+    ///
+    /// See [`MTree::from_package_reader`].
+    pub fn from_package(path: impl AsRef<Path>) -> io::Result<MTree<Box<dyn Read>>> {
+        MTree::from_package_reader(std::fs::File::open(path)?)
+    }
+
+    /// Read the spec in the `.MTREE` member of a package, like the `.pkg.tar.zst` packages of
+    /// Arch Linux.
+    ///
+    /// The package is a tar archive, which may be compressed as described in
+    /// [`MTree::from_compressed_reader`], and so may the `.MTREE` member. Returns an error
+    /// with kind [`io::ErrorKind::NotFound`] if there is no `.MTREE` member.
+    ///
+    /// The paths in the spec are relative to the root the package is installed to, so the
+    /// whole workflow of checking an installed package looks like this:
+    ///
+    /// ```no_run
+    /// use mtree::{MTree, Verifier};
+    ///
+    /// let spec = MTree::from_package("gedit-3.28.1-1-x86_64.pkg.tar.zst").unwrap();
+    /// let report = Verifier::new("/").verify(spec).report().unwrap();
+    /// print!("{}", report);
+    /// ```
+    pub fn from_package_reader(reader: impl Read + 'static) -> io::Result<MTree<Box<dyn Read>>> {
+        let mut archive = tar::Archive::new(compress::decompress(reader)?);
+        for member in archive.entries()? {
+            let mut member = member?;
+            if spec_path(&member.path()?) == Path::new(".MTREE") {
+                let mut spec = Vec::new();
+                member.read_to_end(&mut spec)?;
+                return MTree::from_compressed_reader(io::Cursor::new(spec));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the package doesn't have an .MTREE member",
+        ))
+    }
+}
+
+/// The digest keywords that can be computed with the features enabled.
+fn digest_keywords() -> KeywordSet {
+    readable_keywords()
+        & (KeywordSet::MD5
+            | KeywordSet::RMD160
+            | KeywordSet::SHA1
+            | KeywordSet::SHA256
+            | KeywordSet::SHA384
+            | KeywordSet::SHA512)
+}
+
+/// The keywords that can be read from a tar archive.
+#[cfg(feature = "tar")]
+fn tar_keywords() -> KeywordSet {
+    KeywordSet::TYPE
+        | KeywordSet::MODE
+        | KeywordSet::UID
+        | KeywordSet::GID
+        | KeywordSet::UNAME
+        | KeywordSet::GNAME
+        | KeywordSet::SIZE
+        | KeywordSet::TIME
+        | KeywordSet::LINK
+        | KeywordSet::DEVICE
+        | digest_keywords()
+}
+
+/// Read the params named in `keywords` from a tar archive member.
+///
+/// The contents are only read if a digest is needed.
+#[cfg(feature = "tar")]
+#[cfg_attr(not(feature = "digest"), allow(unused_mut))]
+fn params_from_tar<R: Read>(
+    mut member: tar::Entry<'_, R>,
+    keywords: KeywordSet,
+) -> io::Result<Params> {
+    let mut params = params_from_tar_header(member.header())?;
+    params.link = member.link_name()?.map(|link| link.into_owned());
+    #[cfg(feature = "digest")]
+    {
+        if params.file_type == Some(FileType::File) {
+            crate::scan::hash_reader(&mut member, keywords, &mut params)?;
+        }
+    }
+    params.unset(!keywords);
+    Ok(params)
+}
+
+#[cfg(feature = "tar")]
+impl Entry {
+    /// An entry describing a tar archive member, from its header.
+    ///
+    /// The `type`, `mode`, `uid`, `gid`, `uname`, `gname`, `size`, `time`, `link` and `device`
+    /// keywords are set. Hard links are described as files, since the member they link to
+    /// isn't known. The path and link target only come from the header itself, so members with
+    /// long names need their paths from [`tar::Entry::path`] instead.
+    ///
+    /// ```
+    /// use mtree::{Entry, FileType};
+    ///
+    /// let mut header = tar::Header::new_gnu();
+    /// header.set_path("usr/bin/gedit").unwrap();
+    /// header.set_size(10);
+    /// header.set_mode(0o755);
+    /// header.set_uid(0);
+    /// header.set_gid(0);
+    /// header.set_mtime(1523250065);
+    /// let entry = Entry::from_tar_header(&header).unwrap();
+    /// assert_eq!(entry.path().to_str(), Some("./usr/bin/gedit"));
+    /// assert_eq!(entry.file_type(), Some(FileType::File));
+    /// assert_eq!(entry.size(), Some(10));
+    /// ```
+    pub fn from_tar_header(header: &tar::Header) -> io::Result<Entry> {
+        let path = Path::new(".").join(spec_path(&header.path()?));
+        Ok(Entry::new(path).with_params(params_from_tar_header(header)?))
+    }
+}
+
+/// Map the fields of a tar header to params.
+///
+/// See [`Entry::from_tar_header`].
+#[cfg(feature = "tar")]
+fn params_from_tar_header(header: &tar::Header) -> io::Result<Params> {
+    /// Convert a uid or gid from the header, which may be larger than a unix one.
+    fn id(id: u64) -> io::Result<u32> {
+        u32::try_from(id)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "uid or gid is too large"))
+    }
+
+    let time = UNIX_EPOCH
+        .checked_add(Duration::from_secs(header.mtime()?))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "mtime is too large"))?;
+    let entry_type = header.entry_type();
+    let file_type = if entry_type.is_dir() {
+        FileType::Directory
+    } else if entry_type.is_symlink() {
+        FileType::SymbolicLink
+    } else if entry_type.is_character_special() {
+        FileType::CharacterDevice
+    } else if entry_type.is_block_special() {
+        FileType::BlockDevice
+    } else if entry_type.is_fifo() {
+        FileType::Fifo
+    } else {
+        FileType::File
+    };
+    let device = if matches!(file_type, FileType::CharacterDevice | FileType::BlockDevice) {
+        match (header.device_major()?, header.device_minor()?) {
+            (Some(major), Some(minor)) => Some(Device {
+                format: Format::Native,
+                major,
+                minor,
+                subunit: None,
+            }),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    Ok(Params {
+        file_type: Some(file_type),
+        mode: Some(FileMode::from(header.mode()?)),
+        uid: Some(id(header.uid()?)?),
+        gid: Some(id(header.gid()?)?),
+        uname: header
+            .username_bytes()
+            .filter(|name| !name.is_empty())
+            .map(Uname::new),
+        gname: header
+            .groupname_bytes()
+            .filter(|name| !name.is_empty())
+            .map(Gname::new),
+        size: if file_type == FileType::File {
+            Some(header.size()?)
+        } else {
+            None
+        },
+        time: Some(time),
+        link: header.link_name()?.map(|link| link.into_owned()),
+        device,
+        ..Params::default()
+    })
+}
+
+/// The keywords that can be read from a zip archive.
+#[cfg(feature = "zip")]
+fn zip_keywords() -> KeywordSet {
+    KeywordSet::TYPE | KeywordSet::MODE | KeywordSet::SIZE | KeywordSet::LINK | digest_keywords()
+}
+
+/// Read the params named in `keywords` from a file in a zip archive.
+///
+/// The contents are only read if a digest is needed, or for the target of a symlink.
+#[cfg(feature = "zip")]
+fn params_from_zip(mut file: zip::read::ZipFile<'_>, keywords: KeywordSet) -> io::Result<Params> {
+    let mut params = Params {
+        mode: file.unix_mode().map(FileMode::from),
+        ..Params::default()
+    };
+    if file.is_dir() {
+        params.file_type = Some(FileType::Directory);
+    } else if file.is_symlink() {
+        let mut link = Vec::new();
+        file.read_to_end(&mut link)?;
+        params.file_type = Some(FileType::SymbolicLink);
+        params.link = Some(PathBuf::from(OsString::from_vec(link)));
+    } else {
+        params.file_type = Some(FileType::File);
+        params.size = Some(file.size());
+        #[cfg(feature = "digest")]
+        crate::scan::hash_reader(&mut file, keywords, &mut params)?;
+    }
+    params.unset(!keywords);
+    Ok(params)
+}
+
+#[cfg(feature = "tar")]
+#[test]
+fn test_verify_tar() {
+    use crate::MTree;
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut append = |path: &str, entry_type, mode, contents: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(1000);
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, path, contents).unwrap();
+    };
+    append("./", tar::EntryType::Directory, 0o755, b"");
+    append("./hello", tar::EntryType::Regular, 0o644, b"hello\n");
+    append("./big", tar::EntryType::Regular, 0o600, b"1234567890");
+    append("./extra", tar::EntryType::Directory, 0o755, b"");
+    append("./extra/file", tar::EntryType::Regular, 0o644, b"");
+    let data = builder.into_inner().unwrap();
+
+    let spec = "\
+/set type=file uid=0 gid=0 mode=644 time=1000.0
+. type=dir mode=755
+hello size=6
+big size=9
+missing
+";
+    let entries = MTree::from_reader(spec.as_bytes());
+    let results = Verifier::new("/nonexistent")
+        .verify_tar(entries, &mut tar::Archive::new(&data[..]))
+        .unwrap();
+    let results = results.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        [
+            "./: ok",
+            "./hello: ok",
+            "./big: mode changed from 644 to 600\n./big: size changed from 9 to 10",
+            "./missing: missing",
+            "./extra: extra",
+        ]
+    );
+}
+
+#[cfg(feature = "tar")]
+#[test]
+fn test_entry_from_tar_header() {
+    let mut header = tar::Header::new_ustar();
+    header.set_path("./usr/lib/libfoo.so").unwrap();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_link_name("libfoo.so.1").unwrap();
+    header.set_mode(0o777);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_username("root").unwrap();
+    header.set_groupname("root").unwrap();
+    header.set_mtime(1523250074);
+    assert_eq!(
+        Entry::from_tar_header(&header).unwrap().to_mtree_line(),
+        "./usr/lib/libfoo.so type=link gid=0 gname=root link=libfoo.so.1 mode=777 \
+         time=1523250074.000000000 uid=0 uname=root"
+    );
+
+    let mut header = tar::Header::new_ustar();
+    header.set_path("dev/null").unwrap();
+    header.set_entry_type(tar::EntryType::Char);
+    header.set_device_major(1).unwrap();
+    header.set_device_minor(3).unwrap();
+    header.set_mode(0o666);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    let entry = Entry::from_tar_header(&header).unwrap();
+    assert_eq!(entry.file_type(), Some(FileType::CharacterDevice));
+    assert_eq!(entry.device().map(|d| (d.major, d.minor)), Some((1, 3)));
+    assert_eq!(entry.size(), None);
+}
+
+#[cfg(feature = "tar")]
+#[test]
+fn test_tar_mtime_overflow() {
+    use crate::MTree;
+
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o644);
+    header.set_mtime(u64::MAX);
+    header.set_size(0);
+    let err = Entry::from_tar_header(&header).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let mut builder = tar::Builder::new(Vec::new());
+    builder
+        .append_data(&mut header, "./file", &b""[..])
+        .unwrap();
+    let data = builder.into_inner().unwrap();
+    let err = Verifier::new("/nonexistent")
+        .verify_tar(
+            MTree::from_reader(&b"./file type=file\n"[..]),
+            &mut tar::Archive::new(&data[..]),
+        )
+        .unwrap_err();
+    assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+}
+
+#[cfg(feature = "tar")]
+#[test]
+fn test_from_package_reader() {
+    let spec = b"#mtree\n./usr type=dir\n./usr/bin type=dir\n";
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, contents) in [
+        (".PKGINFO", &b"pkgname = test\n"[..]),
+        (".MTREE", &spec[..]),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, contents).unwrap();
+    }
+    let package = builder.into_inner().unwrap();
+
+    let paths = MTree::from_package_reader(io::Cursor::new(package))
+        .unwrap()
+        .map(|entry| spec_path(entry.unwrap().path()))
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [Path::new("usr"), Path::new("usr/bin")]);
+
+    let empty = tar::Builder::new(Vec::new()).into_inner().unwrap();
+    let error = MTree::from_package_reader(io::Cursor::new(empty))
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_verify_zip() {
+    use crate::MTree;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().unix_permissions(0o644);
+    writer
+        .add_directory("dir/", options.unix_permissions(0o755))
+        .unwrap();
+    writer.start_file("dir/hello", options).unwrap();
+    writer.write_all(b"hello\n").unwrap();
+    writer.start_file("big", options).unwrap();
+    writer.write_all(b"1234567890").unwrap();
+    writer.add_symlink("link", "dir/hello", options).unwrap();
+    writer.start_file("extra", options).unwrap();
+    let data = writer.finish().unwrap().into_inner();
+
+    let spec = "\
+/set type=file mode=644
+./dir type=dir mode=755
+./dir/hello size=6
+./big size=9
+./link type=link link=dir/hello
+./missing
+";
+    let entries = MTree::from_reader(spec.as_bytes());
+    let results = Verifier::new("/nonexistent")
+        .verify_zip(
+            entries,
+            &mut zip::ZipArchive::new(Cursor::new(data)).unwrap(),
+        )
+        .unwrap();
+    let results = results.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        [
+            "./dir: ok",
+            "./dir/hello: ok",
+            "./big: size changed from 9 to 10",
+            "./link: ok",
+            "./missing: missing",
+            "./extra: extra",
+        ]
+    );
+}
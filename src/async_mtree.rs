@@ -0,0 +1,262 @@
+//! Parsing specs from async readers.
+use crate::{Entry, Error, Event, FormatVersion, MTree, MTreeOptions, Params, ParserError};
+use futures_core::Stream;
+use std::future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncBufRead;
+
+/// An mtree parser that reads from an async reader, so it doesn't block the runtime.
+///
+/// Use [`MTree::from_async_reader`] to construct an instance. Lines are parsed in just the same
+/// way as by [`MTree`]. Entries can be read one at a time with
+/// [`next_entry`](AsyncMTree::next_entry), or it can be used as a [`Stream`].
+///
+/// ```
+/// use mtree::MTree;
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let spec = b"./a type=file size=12\n./b type=file size=34\n";
+/// let mut entries = MTree::from_async_reader(&spec[..]);
+/// while let Some(entry) = entries.next_entry().await {
+///     println!("{}", entry.unwrap());
+/// }
+/// # });
+/// ```
+pub struct AsyncMTree<R> {
+    /// The reader the lines come from.
+    inner: R,
+    /// The current line, which may be read over several polls.
+    line_buf: Vec<u8>,
+    /// How much of the current line has been skipped, if it is over the length limit.
+    skipped: Option<u64>,
+    /// Parses the lines, but never reads anything itself.
+    parser: MTree<io::Empty>,
+}
+
+impl MTree<io::Empty> {
+    /// Construct an async parser for the spec in `reader`.
+    pub fn from_async_reader<R>(reader: R) -> AsyncMTree<R>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        MTree::from_async_reader_with_options(reader, MTreeOptions::default())
+    }
+
+    /// Construct an async parser for the spec in `reader` that parses according to `options`.
+    pub fn from_async_reader_with_options<R>(reader: R, options: MTreeOptions) -> AsyncMTree<R>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        AsyncMTree {
+            inner: reader,
+            line_buf: Vec::new(),
+            skipped: None,
+            parser: MTree::with_options(io::empty(), options),
+        }
+    }
+}
+
+impl<R> AsyncMTree<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    /// Read the next entry, or `None` at the end of the spec.
+    ///
+    /// As with [`MTree`], iteration ends after the first error, unless the parser is lenient.
+    pub async fn next_entry(&mut self) -> Option<Result<Entry, Error>> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// The problems that have been skipped over so far.
+    ///
+    /// See [`MTree::errors`].
+    pub fn errors(&self) -> &[ParserError] {
+        self.parser.errors()
+    }
+
+    /// The format version declared by the `#mtree` signature on the first line.
+    ///
+    /// See [`MTree::format_version`].
+    pub fn format_version(&self) -> Option<FormatVersion> {
+        self.parser.format_version()
+    }
+
+    /// The defaults set by the `/set` and `/unset` lines read so far.
+    ///
+    /// See [`MTree::current_defaults`].
+    pub fn current_defaults(&self) -> &Params {
+        self.parser.current_defaults()
+    }
+}
+
+impl<R> Stream for AsyncMTree<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    type Item = Result<Entry, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Entry, Error>>> {
+        let this = self.get_mut();
+        if this.parser.state.finished {
+            return Poll::Ready(None);
+        }
+        loop {
+            let buf = match Pin::new(&mut this.inner).poll_fill_buf(cx) {
+                Poll::Ready(Ok(buf)) => buf,
+                Poll::Ready(Err(e)) => {
+                    this.parser.state.finished = true;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            let max = this.parser.state.options.max_line_length;
+            let (line, used, line_end) = match memchr::memchr(b'\n', buf) {
+                Some(end) => (&buf[..end], end + 1, true),
+                // the last line may not end in a newline
+                None if buf.is_empty() && this.line_buf.is_empty() && this.skipped.is_none() => {
+                    return Poll::Ready(None);
+                }
+                None => (buf, buf.len(), buf.is_empty()),
+            };
+            match this.skipped.as_mut() {
+                Some(skipped) => *skipped += used as u64,
+                None => {
+                    this.line_buf.extend_from_slice(line);
+                    // too long lines are skipped rather than kept in memory
+                    let len = this.line_buf.len();
+                    if max.is_some_and(|max| len > max) {
+                        let newline = (used - line.len()) as u64;
+                        this.skipped = Some(len as u64 + newline);
+                        this.line_buf.clear();
+                    }
+                }
+            }
+            Pin::new(&mut this.inner).consume(used);
+            if !line_end {
+                continue;
+            }
+            let event = match this.skipped.take() {
+                Some(skipped) => this.parser.long_line(skipped),
+                None => this.parser.parse_line(&this.line_buf),
+            };
+            this.line_buf.clear();
+            match event {
+                Some(Ok(Event::Entry(entry))) => return Poll::Ready(Some(Ok(entry))),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Some(Ok(_)) | None => (),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_from_async_reader() {
+    use crate::Strictness;
+
+    let spec = b"\
+#mtree v2.0
+/set type=file uid=0
+./a size=1
+./b size=bogus
+./c size=3
+";
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut parser = MTree::from_async_reader(&spec[..]);
+        let mut entries = Vec::new();
+        while let Some(entry) = parser.next_entry().await {
+            entries.push(entry);
+        }
+        let expected = MTree::from_reader(&spec[..]).collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].as_ref().unwrap(), expected[0].as_ref().unwrap());
+        assert!(entries[1].is_err());
+        assert_eq!(parser.format_version(), Some(FormatVersion::V2));
+
+        let options = MTreeOptions::new().strictness(Strictness::Lenient);
+        let mut parser = MTree::from_async_reader_with_options(&spec[..], options);
+        let mut sizes = Vec::new();
+        while let Some(entry) = parser.next_entry().await {
+            sizes.push(entry.unwrap().size());
+        }
+        assert_eq!(sizes, [Some(1), None, Some(3)]);
+        assert_eq!(parser.errors().len(), 1);
+    });
+}
+
+#[test]
+fn test_stream() {
+    use futures::StreamExt;
+    use std::collections::VecDeque;
+
+    /// Gives out its data a few bytes at a time, and is sometimes not ready.
+    struct Trickle {
+        data: VecDeque<u8>,
+        ready: bool,
+    }
+
+    impl tokio::io::AsyncRead for Trickle {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            unreachable!()
+        }
+    }
+
+    impl AsyncBufRead for Trickle {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let (front, _) = this.data.as_slices();
+            Poll::Ready(Ok(&front[..front.len().min(3)]))
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            self.get_mut().data.drain(..amt);
+        }
+    }
+
+    let spec = b"./a type=file size=1\n\n./b type=file size=2\n./c type=file size=3";
+    let reader = Trickle {
+        data: spec.iter().copied().collect(),
+        ready: false,
+    };
+    let sizes = futures::executor::block_on(
+        MTree::from_async_reader(reader)
+            .map(|entry| entry.unwrap().size())
+            .collect::<Vec<_>>(),
+    );
+    assert_eq!(sizes, [Some(1), Some(2), Some(3)]);
+
+    // too long lines are skipped, even when they come in pieces
+    let spec = b"./a size=1\n./long size=100000\n./b size=2\n./also/long";
+    let reader = Trickle {
+        data: spec.iter().copied().collect(),
+        ready: false,
+    };
+    let options = MTreeOptions::new()
+        .strictness(crate::Strictness::Lenient)
+        .max_line_length(10);
+    let mut parser = MTree::from_async_reader_with_options(reader, options.clone());
+    let sizes = futures::executor::block_on(
+        parser
+            .by_ref()
+            .map(|entry| entry.unwrap().size())
+            .collect::<Vec<_>>(),
+    );
+    assert_eq!(sizes, [Some(1), Some(2)]);
+    let mut expected = MTree::with_options(&spec[..], options);
+    expected.by_ref().for_each(drop);
+    assert_eq!(parser.errors(), expected.errors());
+    assert_eq!(parser.errors().len(), 2);
+}
@@ -0,0 +1,197 @@
+//! A command line tool for creating, checking and comparing mtree specs, like BSD `mtree`.
+//!
+//! Built with the `cli` feature.
+use mtree::{Entry, KeywordSet, MTree, MTreeWriter, Scanner, Verifier};
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "\
+usage: mtree create [-L] [-x] [-k keywords] [-X pattern]... [dir]
+       mtree check [-k keywords] [-f spec] [dir]
+       mtree diff spec1 spec2
+       mtree dump [spec]
+
+create  write a spec for the tree at dir (default .) to stdout
+check   check the tree at dir (default .) against the spec (default stdin)
+diff    compare two specs, like `mtree -f spec1 -f spec2`
+dump    write every entry of a spec on a line of its own, with all its keywords
+
+-L            follow symlinks
+-x            don't walk into other filesystems
+-k keywords   the keywords to record or check, e.g. type,size,sha256digest
+-X pattern    skip files matching the glob pattern
+-f spec       read the spec from this file
+
+check and diff exit with status 2 if there are differences.";
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("create") => create(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        Some("dump") => dump(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+        _ => Err(Usage.into()),
+    };
+    match result {
+        Ok(true) => (),
+        Ok(false) => process::exit(2),
+        Err(e) if e.is::<Usage>() => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+        Err(e) => {
+            let mut message = e.to_string();
+            let mut source = e.source();
+            while let Some(e) = source {
+                message.push_str(": ");
+                message.push_str(&e.to_string());
+                source = e.source();
+            }
+            eprintln!("mtree: {}", message);
+            process::exit(1);
+        }
+    }
+}
+
+/// The result of a command: whether there were no differences.
+type CommandResult = Result<bool, Box<dyn Error>>;
+
+/// The arguments weren't right.
+#[derive(Debug)]
+struct Usage;
+
+impl std::fmt::Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("invalid arguments")
+    }
+}
+
+impl Error for Usage {}
+
+/// The options and operands of a command.
+#[derive(Debug, Default)]
+struct Args {
+    follow_symlinks: bool,
+    one_filesystem: bool,
+    keywords: Option<KeywordSet>,
+    excludes: Vec<String>,
+    spec: Option<PathBuf>,
+    operands: Vec<String>,
+}
+
+impl Args {
+    /// Parse `args`, allowing only the options in `allowed`.
+    fn parse(args: &[String], allowed: &str) -> Result<Args, Box<dyn Error>> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let option = match arg.strip_prefix('-') {
+                Some(option) if allowed.contains(option) && option.len() == 1 => option,
+                Some(_) => return Err(Usage.into()),
+                None => {
+                    parsed.operands.push(arg.clone());
+                    continue;
+                }
+            };
+            let mut value = || args.next().ok_or(Usage);
+            match option {
+                "L" => parsed.follow_symlinks = true,
+                "x" => parsed.one_filesystem = true,
+                "k" => parsed.keywords = Some(value()?.parse()?),
+                "X" => parsed.excludes.push(value()?.clone()),
+                "f" => parsed.spec = Some(value()?.into()),
+                _ => unreachable!(),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The directory operand, if there is at most one operand.
+    fn dir(&self) -> Result<&Path, Usage> {
+        match self.operands.as_slice() {
+            [] => Ok(Path::new(".")),
+            [dir] => Ok(Path::new(dir)),
+            _ => Err(Usage),
+        }
+    }
+}
+
+/// Read the entries of the spec at `path`, or stdin if there isn't one.
+fn read_spec(path: Option<&Path>) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let reader: Box<dyn Read> = match path {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+    Ok(MTree::from_compressed_reader(reader)?.collect::<Result<_, _>>()?)
+}
+
+fn create(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, "LxkX")?;
+    let mut scanner = Scanner::new(args.dir()?)
+        .follow_symlinks(args.follow_symlinks)
+        .one_filesystem(args.one_filesystem);
+    if let Some(keywords) = args.keywords {
+        scanner = scanner.keywords(keywords);
+    }
+    for pattern in &args.excludes {
+        scanner = scanner.exclude(pattern);
+    }
+    let mut writer = MTreeWriter::new(io::BufWriter::new(io::stdout().lock()));
+    writer.write_signature()?;
+    scanner.write_to(&mut writer)?;
+    writer.flush()?;
+    Ok(true)
+}
+
+fn check(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, "kf")?;
+    let entries = read_spec(args.spec.as_deref())?;
+    let mut verifier = Verifier::new(args.dir()?);
+    if let Some(keywords) = args.keywords {
+        verifier = verifier.keywords(keywords);
+    }
+    let mut ok = true;
+    for result in verifier.verify(entries.into_iter().map(Ok)) {
+        let result = result?;
+        if !result.is_ok() {
+            println!("{}", result);
+            ok = false;
+        }
+    }
+    Ok(ok)
+}
+
+fn diff(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, "")?;
+    let (a, b) = match args.operands.as_slice() {
+        [a, b] => (read_spec(Some(a.as_ref()))?, read_spec(Some(b.as_ref()))?),
+        _ => return Err(Usage.into()),
+    };
+    let diff = mtree::diff(a, b);
+    print!("{}", diff.bsd_format());
+    Ok(diff.is_empty())
+}
+
+fn dump(args: &[String]) -> CommandResult {
+    let args = Args::parse(args, "")?;
+    let spec = match args.operands.as_slice() {
+        [] => None,
+        [spec] => Some(Path::new(spec)),
+        _ => return Err(Usage.into()),
+    };
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for entry in read_spec(spec)? {
+        writeln!(out, "{}", entry.to_mtree_line())?;
+    }
+    out.flush()?;
+    Ok(true)
+}
@@ -0,0 +1,518 @@
+//! A C interface to the parser, enabled by the `capi` feature.
+//!
+//! Everything here is laid out so that cbindgen can generate a C header for it. To get a library
+//! that C can link against, build the crate as one, e.g.
+//! `cargo rustc --release --features capi --crate-type cdylib` (or `staticlib`).
+//!
+//! A parser is opened with [`mtree_open`] or [`mtree_from_buffer`], then [`mtree_next`] gives
+//! each entry in turn until it returns `NULL`. Entries are freed with [`mtree_entry_free`] and
+//! the parser with [`mtree_free`].
+//!
+//! A panic never unwinds into C: it is caught, and the function fails as it would for an error.
+#![allow(non_camel_case_types)]
+
+use crate::util::{bytes_to_path, path_to_bytes};
+use crate::{Device, Entry, Error, FileType, KeywordSet, MTree};
+use std::ffi::{CStr, CString};
+use std::io::{self, Read};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+/// An mtree parser.
+pub struct mtree_parser {
+    /// The entries.
+    inner: MTree<Box<dyn Read>>,
+    /// The message for the error that ended parsing, if there was one.
+    error: Option<CString>,
+}
+
+/// The type of a file, from the `type` keyword.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum mtree_file_type {
+    /// There is no `type` keyword.
+    MTREE_TYPE_NONE = 0,
+    /// `block`
+    MTREE_TYPE_BLOCK,
+    /// `char`
+    MTREE_TYPE_CHAR,
+    /// `dir`
+    MTREE_TYPE_DIR,
+    /// `fifo`
+    MTREE_TYPE_FIFO,
+    /// `file`
+    MTREE_TYPE_FILE,
+    /// `link`
+    MTREE_TYPE_LINK,
+    /// `socket`
+    MTREE_TYPE_SOCKET,
+}
+
+/// A device, from the `device` or `resdevice` keyword.
+#[repr(C)]
+#[derive(Debug)]
+pub struct mtree_device {
+    /// The name of the format, like `linux`.
+    pub format: *const c_char,
+    /// The major number.
+    pub major: u32,
+    /// The minor number.
+    pub minor: u32,
+    /// The subunit number, if `has_subunit` is set.
+    pub subunit: u32,
+    /// Whether there is a subunit number.
+    pub has_subunit: bool,
+}
+
+/// An entry of a spec.
+///
+/// `keywords` has the `MTREE_KEYWORD_*` bit set for each keyword the entry has. The fields for
+/// other keywords are zero, or `NULL` for strings. Strings are NUL-terminated, and are cut short
+/// at the first NUL if they contain one. Extended attributes and non-standard keywords aren't
+/// included, in `keywords` or otherwise.
+#[repr(C)]
+#[derive(Debug)]
+pub struct mtree_entry {
+    /// The path of the entry.
+    pub path: *const c_char,
+    /// The keywords the entry has.
+    pub keywords: u32,
+    /// `type`
+    pub file_type: mtree_file_type,
+    /// `mode`, e.g. `0755`.
+    pub mode: u32,
+    /// `uid`
+    pub uid: u32,
+    /// `gid`
+    pub gid: u32,
+    /// `uname`
+    pub uname: *const c_char,
+    /// `gname`
+    pub gname: *const c_char,
+    /// `size`
+    pub size: u64,
+    /// The seconds part of `time`, since the unix epoch.
+    pub time_sec: u64,
+    /// The nanoseconds part of `time`.
+    pub time_nsec: u32,
+    /// `link`
+    pub link: *const c_char,
+    /// `contents`
+    pub contents: *const c_char,
+    /// `nlink`
+    pub nlink: u64,
+    /// `inode`
+    pub inode: u64,
+    /// `cksum`
+    pub checksum: u64,
+    /// `flags`, as the bits of the flags.
+    pub flags: u32,
+    /// `device`
+    pub device: mtree_device,
+    /// `resdevice`
+    pub resident_device: mtree_device,
+    /// `md5digest`, most significant byte first.
+    pub md5: [u8; 16],
+    /// `rmd160digest`
+    pub rmd160: [u8; 20],
+    /// `sha1digest`
+    pub sha1: [u8; 20],
+    /// `sha256digest`
+    pub sha256: [u8; 32],
+    /// `sha384digest`
+    pub sha384: [u8; 48],
+    /// `sha512digest`
+    pub sha512: [u8; 64],
+}
+
+/// An entry, with the strings it points to.
+#[repr(C)]
+struct OwnedEntry {
+    /// This must come first, so a pointer to it is a pointer to the `OwnedEntry`.
+    entry: mtree_entry,
+    /// The strings `entry` points to.
+    strings: Vec<CString>,
+}
+
+/// `cksum`
+pub const MTREE_KEYWORD_CHECKSUM: u32 = 1 << 0;
+/// `device`
+pub const MTREE_KEYWORD_DEVICE: u32 = 1 << 1;
+/// `contents`
+pub const MTREE_KEYWORD_CONTENTS: u32 = 1 << 2;
+/// `flags`
+pub const MTREE_KEYWORD_FLAGS: u32 = 1 << 3;
+/// `gid`
+pub const MTREE_KEYWORD_GID: u32 = 1 << 4;
+/// `gname`
+pub const MTREE_KEYWORD_GNAME: u32 = 1 << 5;
+/// `ignore`
+pub const MTREE_KEYWORD_IGNORE: u32 = 1 << 6;
+/// `inode`
+pub const MTREE_KEYWORD_INODE: u32 = 1 << 7;
+/// `link`
+pub const MTREE_KEYWORD_LINK: u32 = 1 << 8;
+/// `md5digest`
+pub const MTREE_KEYWORD_MD5: u32 = 1 << 9;
+/// `mode`
+pub const MTREE_KEYWORD_MODE: u32 = 1 << 10;
+/// `nlink`
+pub const MTREE_KEYWORD_NLINK: u32 = 1 << 11;
+/// `nochange`
+pub const MTREE_KEYWORD_NO_CHANGE: u32 = 1 << 12;
+/// `optional`
+pub const MTREE_KEYWORD_OPTIONAL: u32 = 1 << 13;
+/// `resdevice`
+pub const MTREE_KEYWORD_RESIDENT_DEVICE: u32 = 1 << 14;
+/// `rmd160digest`
+pub const MTREE_KEYWORD_RMD160: u32 = 1 << 15;
+/// `sha1digest`
+pub const MTREE_KEYWORD_SHA1: u32 = 1 << 16;
+/// `sha256digest`
+pub const MTREE_KEYWORD_SHA256: u32 = 1 << 17;
+/// `sha384digest`
+pub const MTREE_KEYWORD_SHA384: u32 = 1 << 18;
+/// `sha512digest`
+pub const MTREE_KEYWORD_SHA512: u32 = 1 << 19;
+/// `size`
+pub const MTREE_KEYWORD_SIZE: u32 = 1 << 20;
+/// `time`
+pub const MTREE_KEYWORD_TIME: u32 = 1 << 21;
+/// `type`
+pub const MTREE_KEYWORD_TYPE: u32 = 1 << 22;
+/// `uid`
+pub const MTREE_KEYWORD_UID: u32 = 1 << 23;
+/// `uname`
+pub const MTREE_KEYWORD_UNAME: u32 = 1 << 24;
+
+/// Open the spec at `path`, decompressing it if needed.
+///
+/// Returns `NULL` if the file can't be opened.
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mtree_open(path: *const c_char) -> *mut mtree_parser {
+    let path = bytes_to_path(CStr::from_ptr(path).to_bytes());
+    match catch_panic(|| MTree::from_path(path)) {
+        Ok(Ok(inner)) => new_parser(inner),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Parse the spec in the `len` bytes at `data`, which are copied.
+///
+/// Returns `NULL` if the data is compressed in a format that isn't supported.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mtree_from_buffer(data: *const u8, len: usize) -> *mut mtree_parser {
+    let data = if len == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(data, len).to_vec()
+    };
+    match catch_panic(|| MTree::from_compressed_reader(io::Cursor::new(data))) {
+        Ok(Ok(inner)) => new_parser(inner),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Read the next entry, which must be freed with [`mtree_entry_free`].
+///
+/// Returns `NULL` at the end of the spec, or if there is an error, in which case
+/// [`mtree_error`] gives its message.
+///
+/// # Safety
+///
+/// `parser` must have come from [`mtree_open`] or [`mtree_from_buffer`], and not have been
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn mtree_next(parser: *mut mtree_parser) -> *mut mtree_entry {
+    let parser = &mut *parser;
+    let inner = &mut parser.inner;
+    let next = catch_panic(|| {
+        inner
+            .next()
+            .map(|entry| entry.map(|entry| Box::new(owned_entry(&entry))))
+    });
+    match next {
+        Ok(Some(Ok(entry))) => Box::into_raw(entry).cast(),
+        Ok(Some(Err(e))) => {
+            parser.error = Some(c_string(error_message(&e).as_bytes()));
+            ptr::null_mut()
+        }
+        Ok(None) => ptr::null_mut(),
+        Err(message) => {
+            // the parser may be in a bad state, so don't read any more
+            parser.inner.state.finished = true;
+            parser.error = Some(c_string(message.as_bytes()));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The message for the error that ended parsing, or `NULL` if there wasn't one.
+///
+/// The message lives as long as the parser.
+///
+/// # Safety
+///
+/// `parser` must have come from [`mtree_open`] or [`mtree_from_buffer`], and not have been
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn mtree_error(parser: *const mtree_parser) -> *const c_char {
+    match &(*parser).error {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Free an entry from [`mtree_next`]. Passing `NULL` does nothing.
+///
+/// # Safety
+///
+/// `entry` must have come from [`mtree_next`], and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn mtree_entry_free(entry: *mut mtree_entry) {
+    if !entry.is_null() {
+        let entry = Box::from_raw(entry.cast::<OwnedEntry>());
+        let _ = catch_panic(|| drop(entry));
+    }
+}
+
+/// Free a parser. Passing `NULL` does nothing.
+///
+/// # Safety
+///
+/// `parser` must have come from [`mtree_open`] or [`mtree_from_buffer`], and not have been
+/// freed already.
+#[no_mangle]
+pub unsafe extern "C" fn mtree_free(parser: *mut mtree_parser) {
+    if !parser.is_null() {
+        let parser = Box::from_raw(parser);
+        let _ = catch_panic(|| drop(parser));
+    }
+}
+
+/// Run `f`, catching any panic so it doesn't unwind into C, and giving its message instead.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_default(),
+        };
+        format!("the parser panicked: {}", message)
+    })
+}
+
+/// Box up a parser for C.
+fn new_parser(inner: MTree<Box<dyn Read>>) -> *mut mtree_parser {
+    Box::into_raw(Box::new(mtree_parser { inner, error: None }))
+}
+
+/// A C string of `bytes`, up to the first NUL.
+fn c_string(bytes: &[u8]) -> CString {
+    let end = memchr::memchr(0, bytes).unwrap_or(bytes.len());
+    CString::new(&bytes[..end]).expect("no NUL")
+}
+
+/// The message for `error`, including what caused it.
+fn error_message(error: &Error) -> String {
+    match error {
+        Error::Io(e) => format!("{}: {}", error, e),
+        Error::Parser(e) => format!("{}: {}", error, e),
+    }
+}
+
+/// Keep a C string of `bytes` in `strings`, giving a pointer to it.
+fn push_string(strings: &mut Vec<CString>, bytes: &[u8]) -> *const c_char {
+    let string = c_string(bytes);
+    // moving the `CString` doesn't move its contents
+    let ptr = string.as_ptr();
+    strings.push(string);
+    ptr
+}
+
+/// The flat view of `device`, keeping its strings in `strings`.
+fn c_device(strings: &mut Vec<CString>, device: Option<&Device>) -> mtree_device {
+    match device {
+        Some(device) => mtree_device {
+            format: push_string(strings, device.format.as_str().as_bytes()),
+            major: device.major,
+            minor: device.minor,
+            subunit: device.subunit.unwrap_or(0),
+            has_subunit: device.subunit.is_some(),
+        },
+        None => mtree_device {
+            format: ptr::null(),
+            major: 0,
+            minor: 0,
+            subunit: 0,
+            has_subunit: false,
+        },
+    }
+}
+
+/// The flat view of `entry`.
+fn owned_entry(entry: &Entry) -> OwnedEntry {
+    let mut strings = Vec::new();
+    let params = entry.params();
+    let mut string = |bytes: Option<&[u8]>| match bytes {
+        Some(bytes) => push_string(&mut strings, bytes),
+        None => ptr::null(),
+    };
+    let path = string(Some(&entry.path_bytes()));
+    let uname = string(params.uname.as_deref());
+    let gname = string(params.gname.as_deref());
+    let link = string(params.link.as_deref().map(path_to_bytes).as_deref());
+    let contents = string(params.contents.as_deref().map(path_to_bytes).as_deref());
+    let time = params.mtime().unwrap_or_default();
+    let entry = mtree_entry {
+        path,
+        keywords: params
+            .keywords()
+            .map(|keyword| keyword.kind())
+            .filter(|kind| !kind.intersects(KeywordSet::XATTR | KeywordSet::EXTENSIONS))
+            .collect::<KeywordSet>()
+            .bits(),
+        file_type: match params.file_type {
+            None => mtree_file_type::MTREE_TYPE_NONE,
+            Some(FileType::BlockDevice) => mtree_file_type::MTREE_TYPE_BLOCK,
+            Some(FileType::CharacterDevice) => mtree_file_type::MTREE_TYPE_CHAR,
+            Some(FileType::Directory) => mtree_file_type::MTREE_TYPE_DIR,
+            Some(FileType::Fifo) => mtree_file_type::MTREE_TYPE_FIFO,
+            Some(FileType::File) => mtree_file_type::MTREE_TYPE_FILE,
+            Some(FileType::SymbolicLink) => mtree_file_type::MTREE_TYPE_LINK,
+            Some(FileType::Socket) => mtree_file_type::MTREE_TYPE_SOCKET,
+        },
+        mode: params.mode.map_or(0, |mode| mode.as_raw()),
+        uid: params.uid.unwrap_or(0),
+        gid: params.gid.unwrap_or(0),
+        uname,
+        gname,
+        size: params.size.unwrap_or(0),
+        time_sec: time.as_secs(),
+        time_nsec: time.subsec_nanos(),
+        link,
+        contents,
+        nlink: params.nlink.unwrap_or(0),
+        inode: params.inode.unwrap_or(0),
+        checksum: params.checksum.unwrap_or(0),
+        flags: params.flags.map_or(0, |flags| flags.bits()),
+        device: c_device(&mut strings, params.device.as_ref()),
+        resident_device: c_device(&mut strings, params.resident_device.as_ref()),
+        md5: params.md5.unwrap_or(0).to_be_bytes(),
+        rmd160: params.rmd160.unwrap_or_default(),
+        sha1: params.sha1.unwrap_or_default(),
+        sha256: params.sha256.unwrap_or_default(),
+        sha384: params.sha384.unwrap_or([0; 48]),
+        sha512: params.sha512.unwrap_or([0; 64]),
+    };
+    OwnedEntry { entry, strings }
+}
+
+#[test]
+fn test_capi() {
+    let spec = b"\
+/set type=file uid=0 gname=wheel
+./a size=12 mode=0644 time=1.5 md5digest=000102030405060708090a0b0c0d0e0f
+./b type=char device=linux,8,1 link=./a xattr.user.a=1
+./c size=bogus
+";
+    let keywords = [
+        (MTREE_KEYWORD_CHECKSUM, KeywordSet::CHECKSUM),
+        (MTREE_KEYWORD_DEVICE, KeywordSet::DEVICE),
+        (MTREE_KEYWORD_CONTENTS, KeywordSet::CONTENTS),
+        (MTREE_KEYWORD_FLAGS, KeywordSet::FLAGS),
+        (MTREE_KEYWORD_GID, KeywordSet::GID),
+        (MTREE_KEYWORD_GNAME, KeywordSet::GNAME),
+        (MTREE_KEYWORD_IGNORE, KeywordSet::IGNORE),
+        (MTREE_KEYWORD_INODE, KeywordSet::INODE),
+        (MTREE_KEYWORD_LINK, KeywordSet::LINK),
+        (MTREE_KEYWORD_MD5, KeywordSet::MD5),
+        (MTREE_KEYWORD_MODE, KeywordSet::MODE),
+        (MTREE_KEYWORD_NLINK, KeywordSet::NLINK),
+        (MTREE_KEYWORD_NO_CHANGE, KeywordSet::NO_CHANGE),
+        (MTREE_KEYWORD_OPTIONAL, KeywordSet::OPTIONAL),
+        (MTREE_KEYWORD_RESIDENT_DEVICE, KeywordSet::RESIDENT_DEVICE),
+        (MTREE_KEYWORD_RMD160, KeywordSet::RMD160),
+        (MTREE_KEYWORD_SHA1, KeywordSet::SHA1),
+        (MTREE_KEYWORD_SHA256, KeywordSet::SHA256),
+        (MTREE_KEYWORD_SHA384, KeywordSet::SHA384),
+        (MTREE_KEYWORD_SHA512, KeywordSet::SHA512),
+        (MTREE_KEYWORD_SIZE, KeywordSet::SIZE),
+        (MTREE_KEYWORD_TIME, KeywordSet::TIME),
+        (MTREE_KEYWORD_TYPE, KeywordSet::TYPE),
+        (MTREE_KEYWORD_UID, KeywordSet::UID),
+        (MTREE_KEYWORD_UNAME, KeywordSet::UNAME),
+    ];
+    for (bit, keyword) in keywords {
+        assert_eq!(bit, keyword.bits());
+    }
+
+    let string = |ptr: *const c_char| unsafe { CStr::from_ptr(ptr) }.to_bytes();
+    unsafe {
+        let parser = mtree_from_buffer(spec.as_ptr(), spec.len());
+        assert!(!parser.is_null());
+
+        let a = mtree_next(parser);
+        assert_eq!(string((*a).path), b"./a");
+        assert_eq!(
+            (*a).keywords,
+            MTREE_KEYWORD_TYPE
+                | MTREE_KEYWORD_UID
+                | MTREE_KEYWORD_GNAME
+                | MTREE_KEYWORD_SIZE
+                | MTREE_KEYWORD_MODE
+                | MTREE_KEYWORD_TIME
+                | MTREE_KEYWORD_MD5
+        );
+        assert_eq!((*a).file_type, mtree_file_type::MTREE_TYPE_FILE);
+        assert_eq!((*a).size, 12);
+        assert_eq!((*a).mode, 0o644);
+        assert_eq!(((*a).time_sec, (*a).time_nsec), (1, 500_000_000));
+        assert_eq!((*a).md5[15], 15);
+        assert_eq!(string((*a).gname), b"wheel");
+        assert!((*a).uname.is_null());
+        mtree_entry_free(a);
+
+        let b = mtree_next(parser);
+        assert_eq!((*b).file_type, mtree_file_type::MTREE_TYPE_CHAR);
+        assert_eq!(string((*b).device.format), b"linux");
+        assert_eq!(((*b).device.major, (*b).device.minor), (8, 1));
+        assert!(!(*b).device.has_subunit);
+        assert!((*b).resident_device.format.is_null());
+        assert_eq!(string((*b).link), b"./a");
+        // there are no bits for extended attributes
+        assert_eq!(
+            (*b).keywords,
+            MTREE_KEYWORD_TYPE
+                | MTREE_KEYWORD_UID
+                | MTREE_KEYWORD_GNAME
+                | MTREE_KEYWORD_DEVICE
+                | MTREE_KEYWORD_LINK
+        );
+        mtree_entry_free(b);
+
+        assert!(mtree_error(parser).is_null());
+        assert!(mtree_next(parser).is_null());
+        assert!(string(mtree_error(parser)).starts_with(b"an error occured"));
+        assert!(mtree_next(parser).is_null());
+        mtree_free(parser);
+    }
+}
+
+#[test]
+fn test_catch_panic() {
+    assert_eq!(catch_panic(|| 1), Ok(1));
+    let message = catch_panic(|| -> () { panic!("at the {}", "disco") }).unwrap_err();
+    assert_eq!(message, "the parser panicked: at the disco");
+}
@@ -0,0 +1,262 @@
+//! Holding a whole spec in memory compactly.
+use crate::util::{bytes_to_path, path_to_bytes};
+use crate::{Entry, Params};
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+
+/// The entries of a spec, with their paths stored as a tree of names so that the directories
+/// they share are only stored once.
+///
+/// Collecting a spec into a `Vec<Entry>` stores the full path of every entry, which is mostly
+/// the same directory names over and over. A `CompactSpec` stores each directory name once, and
+/// rebuilds full paths on demand with [`CompactEntry::full_path`].
+///
+/// ```
+/// use mtree::{CompactSpec, MTree};
+///
+/// let spec = "\
+/// ./usr/share/doc/a type=file
+/// ./usr/share/doc/b type=file
+/// ";
+/// let spec = MTree::from_reader(spec.as_bytes())
+///     .collect::<Result<CompactSpec, _>>()
+///     .unwrap();
+/// assert_eq!(spec.len(), 2);
+/// assert_eq!(spec.get(1).unwrap().full_path().to_str(), Some("./usr/share/doc/b"));
+/// ```
+#[derive(Clone, Default)]
+pub struct CompactSpec {
+    /// The names of all the path components, one after another.
+    names: Vec<u8>,
+    /// The path components, each of which may be the path of an entry.
+    nodes: Vec<Node>,
+    /// The node for each name below each parent node, so that children are found without
+    /// looking through their siblings.
+    children: HashMap<(Option<usize>, Box<[u8]>), usize>,
+    /// The entries, in order, as the node of their path and their params.
+    entries: Vec<(usize, Params)>,
+    /// The nodes of the path of the last entry, from the top down.
+    last_path: Vec<usize>,
+}
+
+/// A component of the paths in a [`CompactSpec`].
+#[derive(Debug, Clone)]
+struct Node {
+    /// The node of the parent directory, if this isn't a top level component.
+    parent: Option<usize>,
+    /// The start and end of the name in `CompactSpec::names`.
+    name: (usize, usize),
+}
+
+impl CompactSpec {
+    /// An empty spec.
+    pub fn new() -> CompactSpec {
+        CompactSpec::default()
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add an entry to the end of the spec.
+    pub fn push(&mut self, entry: Entry) {
+        let mut path = Vec::new();
+        let mut parent = None;
+        for (depth, component) in entry.path().components().enumerate() {
+            let name = path_to_bytes(Path::new(component.as_os_str()));
+            // entries are usually in tree order, so try the path of the last entry first
+            let node = match self.last_path.get(depth) {
+                Some(&node) if self.node_name(node) == &*name => node,
+                _ => self.child(parent, &name),
+            };
+            path.push(node);
+            parent = Some(node);
+        }
+        if let Some(node) = parent {
+            self.entries.push((node, entry.params));
+            self.last_path = path;
+        }
+    }
+
+    /// The entry at `index`.
+    pub fn get(&self, index: usize) -> Option<CompactEntry<'_>> {
+        self.entries.get(index).map(|(node, params)| CompactEntry {
+            spec: self,
+            node: *node,
+            params,
+        })
+    }
+
+    /// The entries, in order.
+    pub fn iter(&self) -> impl Iterator<Item = CompactEntry<'_>> + '_ {
+        self.entries.iter().map(move |(node, params)| CompactEntry {
+            spec: self,
+            node: *node,
+            params,
+        })
+    }
+
+    /// The name of `node`.
+    fn node_name(&self, node: usize) -> &[u8] {
+        let (start, end) = self.nodes[node].name;
+        &self.names[start..end]
+    }
+
+    /// The node called `name` below `parent`, which is added if there isn't one already.
+    fn child(&mut self, parent: Option<usize>, name: &[u8]) -> usize {
+        let (names, nodes) = (&mut self.names, &mut self.nodes);
+        *self
+            .children
+            .entry((parent, name.into()))
+            .or_insert_with(|| {
+                let start = names.len();
+                names.extend_from_slice(name);
+                nodes.push(Node {
+                    parent,
+                    name: (start, names.len()),
+                });
+                nodes.len() - 1
+            })
+    }
+}
+
+impl fmt::Debug for CompactSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Extend<Entry> for CompactSpec {
+    fn extend<T: IntoIterator<Item = Entry>>(&mut self, iter: T) {
+        for entry in iter {
+            self.push(entry);
+        }
+    }
+}
+
+impl FromIterator<Entry> for CompactSpec {
+    fn from_iter<T: IntoIterator<Item = Entry>>(iter: T) -> CompactSpec {
+        let mut spec = CompactSpec::new();
+        spec.extend(iter);
+        spec
+    }
+}
+
+/// An entry of a [`CompactSpec`].
+#[derive(Clone, Copy)]
+pub struct CompactEntry<'a> {
+    /// The spec the entry is in.
+    spec: &'a CompactSpec,
+    /// The node of its path.
+    node: usize,
+    /// Its params.
+    params: &'a Params,
+}
+
+impl<'a> CompactEntry<'a> {
+    /// The path of the entry, as it would be for the [`Entry`].
+    pub fn full_path(&self) -> PathBuf {
+        let mut nodes = Vec::new();
+        let mut node = Some(self.node);
+        while let Some(n) = node {
+            nodes.push(n);
+            node = self.spec.nodes[n].parent;
+        }
+        nodes
+            .iter()
+            .rev()
+            .map(|node| bytes_to_path(self.spec.node_name(*node)))
+            .collect()
+    }
+
+    /// The params of the entry.
+    pub fn params(&self) -> &'a Params {
+        self.params
+    }
+
+    /// A copy of the entry as an [`Entry`].
+    pub fn to_entry(&self) -> Entry {
+        Entry::new(self.full_path()).with_params(self.params.clone())
+    }
+}
+
+impl fmt::Debug for CompactEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CompactEntry")
+            .field("path", &self.full_path())
+            .field("params", self.params)
+            .finish()
+    }
+}
+
+#[test]
+fn test_compact_spec() {
+    use crate::FileType;
+    use std::path::Path;
+
+    let paths = [
+        "./usr",
+        "./usr/bin",
+        "./usr/bin/gedit",
+        "./usr/share",
+        "./usr/bin/gvim",
+        "/etc/fstab",
+        "./usr/bin",
+        "relative",
+    ];
+    let entries = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| Entry::new(path).with_params(Params::builder().size(i as u64).build()))
+        .collect::<Vec<_>>();
+    let spec = entries.iter().cloned().collect::<CompactSpec>();
+    assert_eq!(spec.len(), paths.len());
+    assert!(!spec.is_empty());
+    for (compact, entry) in spec.iter().zip(&entries) {
+        assert_eq!(compact.to_entry(), *entry);
+    }
+    assert_eq!(
+        spec.get(4).unwrap().full_path(),
+        Path::new("./usr/bin/gvim")
+    );
+    assert!(spec.get(8).is_none());
+    // `.`, `usr`, `bin`, `gedit`, `share`, `gvim`, `/`, `etc`, `fstab` and `relative`
+    assert_eq!(spec.nodes.len(), 10);
+
+    let mut spec = CompactSpec::new();
+    spec.push(
+        Entry::new(".").with_params(Params::builder().file_type(FileType::Directory).build()),
+    );
+    assert_eq!(spec.get(0).unwrap().full_path(), Path::new("."));
+    assert_eq!(
+        spec.get(0).unwrap().params().file_type,
+        Some(FileType::Directory)
+    );
+}
+
+#[test]
+fn test_compact_spec_siblings() {
+    let mut spec = CompactSpec::new();
+    for i in 0..10_000 {
+        spec.push(Entry::new(format!("./usr/lib/{}", i)));
+    }
+    // going back to an earlier sibling finds its node again
+    spec.push(Entry::new("./usr/lib/5000"));
+    spec.push(Entry::new("./usr/lib/10"));
+    assert_eq!(spec.len(), 10_002);
+    // `.`, `usr`, `lib` and the files
+    assert_eq!(spec.nodes.len(), 10_003);
+    assert_eq!(
+        spec.get(10_000).unwrap().full_path(),
+        Path::new("./usr/lib/5000")
+    );
+    assert_eq!(spec.get(10_001).unwrap().node, spec.get(10).unwrap().node);
+}
@@ -0,0 +1,128 @@
+//! Reading specs that have been compressed.
+use std::io::{self, Cursor, Read};
+
+/// A compression format, recognised by the magic bytes at the start of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// The longest magic number.
+    const MAGIC_LEN: usize = 6;
+
+    /// The format of data starting with `start`, if it's compressed.
+    fn detect(start: &[u8]) -> Option<Compression> {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if start.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Wrap `reader` in a decoder for this format.
+    #[cfg_attr(
+        not(any(feature = "flate2", feature = "zstd", feature = "xz")),
+        allow(unused_variables)
+    )]
+    fn decoder<R: Read + 'static>(self, reader: R) -> io::Result<Box<dyn Read>> {
+        match self {
+            #[cfg(feature = "flate2")]
+            Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(zstd::Decoder::new(reader)?)),
+            #[cfg(feature = "xz")]
+            Compression::Xz => Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(reader))),
+            #[allow(unreachable_patterns)]
+            _ => {
+                let (name, feature) = match self {
+                    Compression::Gzip => ("gzip", "flate2"),
+                    Compression::Zstd => ("zstd", "zstd"),
+                    Compression::Xz => ("xz", "xz"),
+                };
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "the data is compressed with {}, which needs the `{}` feature",
+                        name, feature
+                    ),
+                ))
+            }
+        }
+    }
+}
+
+/// Wrap `reader` in a decoder if the data is compressed with a format we recognise.
+///
+/// An error is returned if it is compressed, but support for the format isn't enabled.
+pub(crate) fn decompress<R: Read + 'static>(mut reader: R) -> io::Result<Box<dyn Read>> {
+    let mut start = [0; Compression::MAGIC_LEN];
+    let mut len = 0;
+    while len < start.len() {
+        match reader.read(&mut start[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    let reader = Cursor::new(start).take(len as u64).chain(reader);
+    match Compression::detect(&start[..len]) {
+        Some(compression) => compression.decoder(reader),
+        None => Ok(Box::new(reader)),
+    }
+}
+
+#[test]
+fn test_decompress() {
+    let mut plain = String::new();
+    decompress(&b"#mtree\n"[..])
+        .unwrap()
+        .read_to_string(&mut plain)
+        .unwrap();
+    assert_eq!(plain, "#mtree\n");
+
+    let mut short = String::new();
+    decompress(&b"a"[..])
+        .unwrap()
+        .read_to_string(&mut short)
+        .unwrap();
+    assert_eq!(short, "a");
+
+    #[cfg(feature = "zstd")]
+    {
+        let compressed = zstd::encode_all(&b"#mtree\n"[..], 0).unwrap();
+        let mut decompressed = String::new();
+        decompress(Cursor::new(compressed))
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, "#mtree\n");
+    }
+
+    #[cfg(feature = "xz")]
+    {
+        let mut compressed = Vec::new();
+        xz2::read::XzEncoder::new(&b"#mtree\n"[..], 6)
+            .read_to_end(&mut compressed)
+            .unwrap();
+        let mut decompressed = String::new();
+        decompress(Cursor::new(compressed))
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, "#mtree\n");
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    {
+        let error = decompress(&[0x28, 0xb5, 0x2f, 0xfd, 0][..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
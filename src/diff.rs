@@ -0,0 +1,461 @@
+//! Comparing parameters and specs.
+use crate::parser::KeywordSet;
+use crate::writer::full_path;
+use crate::{Device, Entry, ExtensionValue, FileFlags, FileMode, FileType, Gname, Params, Uname};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A parameter that differs between two [`Params`], as found by [`Params::diff`].
+///
+/// Each variant holds the value from the params `diff` was called on, followed by the value
+/// from the params it was compared with. `None` (or `false`) means the parameter isn't set.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ParamMismatch {
+    /// `cksum`
+    Checksum(Option<u64>, Option<u64>),
+    /// `device`
+    Device(Option<Device>, Option<Device>),
+    /// `contents`
+    Contents(Option<PathBuf>, Option<PathBuf>),
+    /// `flags`
+    Flags(Option<FileFlags>, Option<FileFlags>),
+    /// `gid`
+    Gid(Option<u32>, Option<u32>),
+    /// `gname`
+    Gname(Option<Gname>, Option<Gname>),
+    /// `ignore`
+    Ignore(bool, bool),
+    /// `inode`
+    Inode(Option<u64>, Option<u64>),
+    /// `link`
+    Link(Option<PathBuf>, Option<PathBuf>),
+    /// `md5digest`
+    Md5(Option<u128>, Option<u128>),
+    /// `mode`
+    Mode(Option<FileMode>, Option<FileMode>),
+    /// `nlink`
+    NLink(Option<u64>, Option<u64>),
+    /// `nochange`
+    NoChange(bool, bool),
+    /// `optional`
+    Optional(bool, bool),
+    /// `resdevice`
+    ResidentDevice(Option<Device>, Option<Device>),
+    /// `rmd160digest`
+    Rmd160(Option<[u8; 20]>, Option<[u8; 20]>),
+    /// `sha1digest`
+    Sha1(Option<[u8; 20]>, Option<[u8; 20]>),
+    /// `sha256digest`
+    Sha256(Option<[u8; 32]>, Option<[u8; 32]>),
+    /// `sha384digest`
+    Sha384(Option<[u8; 48]>, Option<[u8; 48]>),
+    /// `sha512digest`
+    Sha512(Option<[u8; 64]>, Option<[u8; 64]>),
+    /// `size`
+    Size(Option<u64>, Option<u64>),
+    /// `time`
+    Time(Option<SystemTime>, Option<SystemTime>),
+    /// `type`
+    Type(Option<FileType>, Option<FileType>),
+    /// `uid`
+    Uid(Option<u32>, Option<u32>),
+    /// `uname`
+    Uname(Option<Uname>, Option<Uname>),
+    /// `xattr.<name>`, with the name of the attribute first.
+    Xattr(Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>),
+    /// A non-standard keyword, with its name first.
+    Extension(Vec<u8>, Option<ExtensionValue>, Option<ExtensionValue>),
+}
+
+impl ParamMismatch {
+    /// The keyword that differs.
+    pub fn keyword(&self) -> KeywordSet {
+        use ParamMismatch::*;
+        match self {
+            Checksum(..) => KeywordSet::CHECKSUM,
+            Device(..) => KeywordSet::DEVICE,
+            Contents(..) => KeywordSet::CONTENTS,
+            Flags(..) => KeywordSet::FLAGS,
+            Gid(..) => KeywordSet::GID,
+            Gname(..) => KeywordSet::GNAME,
+            Ignore(..) => KeywordSet::IGNORE,
+            Inode(..) => KeywordSet::INODE,
+            Link(..) => KeywordSet::LINK,
+            Md5(..) => KeywordSet::MD5,
+            Mode(..) => KeywordSet::MODE,
+            NLink(..) => KeywordSet::NLINK,
+            NoChange(..) => KeywordSet::NO_CHANGE,
+            Optional(..) => KeywordSet::OPTIONAL,
+            ResidentDevice(..) => KeywordSet::RESIDENT_DEVICE,
+            Rmd160(..) => KeywordSet::RMD160,
+            Sha1(..) => KeywordSet::SHA1,
+            Sha256(..) => KeywordSet::SHA256,
+            Sha384(..) => KeywordSet::SHA384,
+            Sha512(..) => KeywordSet::SHA512,
+            Size(..) => KeywordSet::SIZE,
+            Time(..) => KeywordSet::TIME,
+            Type(..) => KeywordSet::TYPE,
+            Uid(..) => KeywordSet::UID,
+            Uname(..) => KeywordSet::UNAME,
+            Xattr(..) => KeywordSet::XATTR,
+            Extension(..) => KeywordSet::EXTENSIONS,
+        }
+    }
+
+    /// Both sides of the mismatch, as params with just the one parameter set.
+    fn sides(&self) -> (Params, Params) {
+        use ParamMismatch::*;
+        let (mut left, mut right) = (Params::default(), Params::default());
+        match self.clone() {
+            Checksum(l, r) => (left.checksum, right.checksum) = (l, r),
+            Device(l, r) => (left.device, right.device) = (l, r),
+            Contents(l, r) => (left.contents, right.contents) = (l, r),
+            Flags(l, r) => (left.flags, right.flags) = (l, r),
+            Gid(l, r) => (left.gid, right.gid) = (l, r),
+            Gname(l, r) => (left.gname, right.gname) = (l, r),
+            Ignore(l, r) => (left.ignore, right.ignore) = (l, r),
+            Inode(l, r) => (left.inode, right.inode) = (l, r),
+            Link(l, r) => (left.link, right.link) = (l, r),
+            Md5(l, r) => (left.md5, right.md5) = (l, r),
+            Mode(l, r) => (left.mode, right.mode) = (l, r),
+            NLink(l, r) => (left.nlink, right.nlink) = (l, r),
+            NoChange(l, r) => (left.no_change, right.no_change) = (l, r),
+            Optional(l, r) => (left.optional, right.optional) = (l, r),
+            ResidentDevice(l, r) => (left.resident_device, right.resident_device) = (l, r),
+            Rmd160(l, r) => (left.rmd160, right.rmd160) = (l, r),
+            Sha1(l, r) => (left.sha1, right.sha1) = (l, r),
+            Sha256(l, r) => (left.sha256, right.sha256) = (l, r),
+            Sha384(l, r) => (left.sha384, right.sha384) = (l, r),
+            Sha512(l, r) => (left.sha512, right.sha512) = (l, r),
+            Size(l, r) => (left.size, right.size) = (l, r),
+            Time(l, r) => (left.time, right.time) = (l, r),
+            Type(l, r) => (left.file_type, right.file_type) = (l, r),
+            Uid(l, r) => (left.uid, right.uid) = (l, r),
+            Uname(l, r) => (left.uname, right.uname) = (l, r),
+            Xattr(name, l, r) => {
+                left.xattrs.extend(l.map(|l| (name.clone(), l)));
+                right.xattrs.extend(r.map(|r| (name, r)));
+            }
+            Extension(name, l, r) => {
+                left.extensions.extend(l.map(|l| (name.clone(), l)));
+                right.extensions.extend(r.map(|r| (name, r)));
+            }
+        }
+        (left, right)
+    }
+
+    /// The name of the keyword that differs, as it appears in an mtree file.
+    pub(crate) fn name(&self) -> String {
+        match self {
+            ParamMismatch::Xattr(name, ..) => format!("xattr.{}", String::from_utf8_lossy(name)),
+            ParamMismatch::Extension(name, ..) => String::from_utf8_lossy(name).into_owned(),
+            other => other.keyword().to_string(),
+        }
+    }
+
+    /// Both values as they appear in an mtree file, `set` for keywords without a value, or
+    /// `None` if the keyword isn't set.
+    pub(crate) fn values(&self) -> (Option<String>, Option<String>) {
+        /// The value part of the (only) keyword in `params`.
+        fn value(params: &Params) -> Option<String> {
+            let keyword = params.keywords().next()?.to_string();
+            Some(match keyword.split_once('=') {
+                Some((_, value)) => value.to_owned(),
+                None => "set".to_owned(),
+            })
+        }
+        let (left, right) = self.sides();
+        (value(&left), value(&right))
+    }
+}
+
+/// Writes e.g. `mode changed from 644 to 755`, with values as they would appear in an mtree
+/// file.
+impl fmt::Display for ParamMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (left, right) = self.values();
+        write!(
+            f,
+            "{} changed from {} to {}",
+            self.name(),
+            left.as_deref().unwrap_or("unset"),
+            right.as_deref().unwrap_or("unset")
+        )
+    }
+}
+
+impl Params {
+    /// The parameters that differ between these params and `other`, in a stable order.
+    ///
+    /// Each mismatch holds the value from `self` followed by the value from `other`.
+    pub fn diff(&self, other: &Params) -> Vec<ParamMismatch> {
+        let mut mismatches = Vec::new();
+        macro_rules! compare {
+            ($($field:ident => $variant:ident),* $(,)?) => {
+                $(
+                    if self.$field != other.$field {
+                        mismatches.push(ParamMismatch::$variant(
+                            self.$field.clone(),
+                            other.$field.clone(),
+                        ));
+                    }
+                )*
+            };
+        }
+        compare! {
+            checksum => Checksum,
+            device => Device,
+            contents => Contents,
+            flags => Flags,
+            gid => Gid,
+            gname => Gname,
+            ignore => Ignore,
+            inode => Inode,
+            link => Link,
+            md5 => Md5,
+            mode => Mode,
+            nlink => NLink,
+            no_change => NoChange,
+            optional => Optional,
+            resident_device => ResidentDevice,
+            rmd160 => Rmd160,
+            sha1 => Sha1,
+            sha256 => Sha256,
+            sha384 => Sha384,
+            sha512 => Sha512,
+            size => Size,
+            time => Time,
+            file_type => Type,
+            uid => Uid,
+            uname => Uname,
+        }
+        let names = self.xattrs.keys().chain(other.xattrs.keys());
+        for name in names.collect::<BTreeSet<_>>() {
+            let (left, right) = (self.xattrs.get(name), other.xattrs.get(name));
+            if left != right {
+                mismatches.push(ParamMismatch::Xattr(
+                    name.clone(),
+                    left.cloned(),
+                    right.cloned(),
+                ));
+            }
+        }
+        let names = self.extensions.keys().chain(other.extensions.keys());
+        for name in names.collect::<BTreeSet<_>>() {
+            let (left, right) = (self.extensions.get(name), other.extensions.get(name));
+            if left != right {
+                mismatches.push(ParamMismatch::Extension(
+                    name.clone(),
+                    left.cloned(),
+                    right.cloned(),
+                ));
+            }
+        }
+        mismatches
+    }
+}
+
+/// Compare two specs without looking at the filesystem, like `mtree -f spec1 -f spec2`.
+///
+/// Entries are matched up by their [`spec_path`](Entry::spec_path), so the specs can be read
+/// with different roots. If a spec has more than one entry for a path, the last one
+/// is used.
+///
+/// ```
+/// use mtree::MTree;
+///
+/// let a = MTree::from_reader(&b"./a size=1\n./b size=2\n"[..]);
+/// let b = MTree::from_reader(&b"./b size=3\n./c size=4\n"[..]);
+/// let diff = mtree::diff(
+///     a.collect::<Result<Vec<_>, _>>().unwrap(),
+///     b.collect::<Result<Vec<_>, _>>().unwrap(),
+/// );
+/// assert_eq!(diff.only_in_a.len(), 1);
+/// assert_eq!(diff.only_in_b.len(), 1);
+/// assert_eq!(diff.changed.len(), 1);
+/// ```
+pub fn diff<A, B>(a: A, b: B) -> SpecDiff
+where
+    A: IntoIterator<Item = Entry>,
+    B: IntoIterator<Item = Entry>,
+{
+    let mut only_in_a = a
+        .into_iter()
+        .map(|entry| (entry.spec_path().to_owned(), entry))
+        .collect::<BTreeMap<_, _>>();
+    let mut only_in_b = BTreeMap::new();
+    let mut changed = BTreeMap::new();
+    for entry in b {
+        let path = entry.spec_path().to_owned();
+        match only_in_a.remove(&path) {
+            Some(a) if a.params != entry.params => {
+                changed.insert(path, (a, entry));
+            }
+            Some(_) => {}
+            None => {
+                only_in_b.insert(path, entry);
+            }
+        }
+    }
+    SpecDiff {
+        only_in_a,
+        only_in_b,
+        changed,
+    }
+}
+
+/// The differences between two specs, as found by [`diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpecDiff {
+    /// The entries that are only in the first spec, by spec path.
+    pub only_in_a: BTreeMap<PathBuf, Entry>,
+    /// The entries that are only in the second spec, by spec path.
+    pub only_in_b: BTreeMap<PathBuf, Entry>,
+    /// The entries that are in both specs but have different params, by spec path, with the entry
+    /// from the first spec followed by the entry from the second.
+    pub changed: BTreeMap<PathBuf, (Entry, Entry)>,
+}
+
+impl SpecDiff {
+    /// Whether the specs have the same entries.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty() && self.changed.is_empty()
+    }
+
+    /// Format the differences like BSD `mtree -f spec1 -f spec2`.
+    ///
+    /// Like comm(1), there is a column of entries only in the first spec, a column of entries
+    /// only in the second spec indented by a tab, and a column of changed entries indented by
+    /// two tabs. Each changed entry takes two lines, one from each spec, holding just the
+    /// keywords that differ. Everything is sorted by path.
+    pub fn bsd_format(&self) -> BsdFormat<'_> {
+        BsdFormat { diff: self }
+    }
+
+    /// The params that differ for each changed entry, by spec path.
+    pub fn mismatches(&self) -> impl Iterator<Item = (&Path, Vec<ParamMismatch>)> {
+        self.changed
+            .iter()
+            .map(|(path, (a, b))| (path.as_path(), a.params.diff(&b.params)))
+    }
+}
+
+/// A [`SpecDiff`] formatted like BSD `mtree`, created by [`SpecDiff::bsd_format`].
+#[derive(Debug, Clone, Copy)]
+pub struct BsdFormat<'a> {
+    /// The differences to format.
+    diff: &'a SpecDiff,
+}
+
+impl fmt::Display for BsdFormat<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Write the line for `entry`, with just the keywords that aren't the same in `other`.
+        fn line(f: &mut fmt::Formatter, entry: &Entry, other: Option<&Entry>) -> fmt::Result {
+            f.write_str(&String::from_utf8_lossy(&full_path(entry.spec_path())))?;
+            for keyword in entry.params.keywords() {
+                if let Some(other) = other {
+                    if other.params.keywords().any(|k| k == keyword) {
+                        continue;
+                    }
+                }
+                write!(f, " {}", keyword)?;
+            }
+            writeln!(f)
+        }
+
+        let diff = self.diff;
+        let paths = diff
+            .only_in_a
+            .keys()
+            .chain(diff.only_in_b.keys())
+            .chain(diff.changed.keys())
+            .collect::<BTreeSet<_>>();
+        for path in paths {
+            if let Some(entry) = diff.only_in_a.get(path) {
+                line(f, entry, None)?;
+            } else if let Some(entry) = diff.only_in_b.get(path) {
+                f.write_str("\t")?;
+                line(f, entry, None)?;
+            } else if let Some((a, b)) = diff.changed.get(path) {
+                f.write_str("\t\t")?;
+                line(f, a, Some(b))?;
+                f.write_str("\t\t")?;
+                line(f, b, Some(a))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_params_diff() {
+    let old = Params::builder()
+        .file_type(FileType::File)
+        .mode(0o644)
+        .size(10)
+        .optional(true)
+        .xattr("user.a", "YQ==")
+        .build();
+    let new = Params::builder()
+        .file_type(FileType::File)
+        .mode(0o755)
+        .size(12)
+        .xattr("user.b", "Yg==")
+        .build();
+    let diff = old.diff(&new);
+    assert_eq!(
+        diff.iter().map(|m| m.to_string()).collect::<Vec<_>>(),
+        vec![
+            "mode changed from 644 to 755",
+            "optional changed from set to unset",
+            "size changed from 10 to 12",
+            "xattr.user.a changed from YQ== to unset",
+            "xattr.user.b changed from unset to Yg==",
+        ]
+    );
+    assert_eq!(diff[2], ParamMismatch::Size(Some(10), Some(12)));
+    assert_eq!(diff[0].keyword(), KeywordSet::MODE);
+    assert!(old.diff(&old).is_empty());
+}
+
+#[test]
+fn test_spec_diff() {
+    let entry =
+        |path: &str, size| Entry::new(path).with_params(Params::builder().size(size).build());
+    let changes = diff(
+        vec![entry("./a", 1), entry("./b", 2), entry("./c", 3)],
+        vec![entry("./b", 2), entry("./c", 4), entry("./d", 5)],
+    );
+    assert!(!changes.is_empty());
+    assert_eq!(
+        changes.only_in_a.keys().collect::<Vec<_>>(),
+        vec![Path::new("./a")]
+    );
+    assert_eq!(
+        changes.only_in_b.keys().collect::<Vec<_>>(),
+        vec![Path::new("./d")]
+    );
+    assert_eq!(
+        changes.mismatches().collect::<Vec<_>>(),
+        vec![(
+            Path::new("./c"),
+            vec![ParamMismatch::Size(Some(3), Some(4))]
+        )]
+    );
+    assert!(diff(vec![entry("./a", 1)], vec![entry("./a", 1)]).is_empty());
+    assert_eq!(
+        changes.bsd_format().to_string(),
+        "./a size=1\n\t\t./c size=3\n\t\t./c size=4\n\t./d size=5\n"
+    );
+
+    // entries are matched by their spec paths, whatever root they were read with
+    let read = |root: &str| {
+        crate::MTree::from_reader_with_root(&b". type=dir\n    a size=1\n"[..], root)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    };
+    assert!(diff(read("/a"), read("/b")).is_empty());
+}
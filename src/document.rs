@@ -0,0 +1,488 @@
+//! An editable mtree file, that can be written back out without losing comments or layout.
+use crate::parser::{Keyword, KeywordSet, MTreeLine, ParserError, ParserResult};
+use crate::util::{is_space, unvis, vis, Words};
+use crate::writer::full_path;
+use crate::{Entry, Error, MTree};
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+/// An mtree file as a list of lines, for editing.
+///
+/// Unlike [`MTree`], which resolves each entry against the `/set` defaults and current
+/// directory, a `Document` keeps every line as it was written: comments, blank lines,
+/// indentation and `/set` lines are all preserved. Lines that haven't been edited are written
+/// back out byte-for-byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    /// The lines of the file, in order.
+    lines: Vec<Line>,
+    /// Whether the last line ends with a newline.
+    trailing_newline: bool,
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document {
+            lines: Vec::new(),
+            trailing_newline: true,
+        }
+    }
+}
+
+impl Document {
+    /// An empty document.
+    pub fn new() -> Document {
+        Document::default()
+    }
+
+    /// Parse a document, failing on the first line that isn't valid.
+    pub fn from_bytes(input: &[u8]) -> Result<Document, ParserError> {
+        let (input, trailing_newline) = match input.strip_suffix(b"\n") {
+            Some(input) => (input, true),
+            None => (input, false),
+        };
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        if !input.is_empty() || trailing_newline {
+            for (idx, raw) in input.split(|ch| *ch == b'\n').enumerate() {
+                lines.push(Line::from_raw(raw).map_err(|e| e.at(idx + 1, offset))?);
+                offset += raw.len() as u64 + 1;
+            }
+        }
+        Ok(Document {
+            lines,
+            trailing_newline,
+        })
+    }
+
+    /// Read and parse a document.
+    pub fn from_reader(mut reader: impl Read) -> Result<Document, Error> {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        Ok(Document::from_bytes(&input)?)
+    }
+
+    /// The lines of the document.
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// The lines of the document, for editing.
+    pub fn lines_mut(&mut self) -> &mut [Line] {
+        &mut self.lines
+    }
+
+    /// Add a line to the end of the document.
+    pub fn push(&mut self, line: Line) {
+        self.lines.push(line)
+    }
+
+    /// Insert a line at position `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > self.lines().len()`.
+    pub fn insert(&mut self, index: usize, line: Line) {
+        self.lines.insert(index, line)
+    }
+
+    /// Remove and return the line at position `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Line {
+        self.lines.remove(index)
+    }
+
+    /// The first entry line with the path `path`, as written on the line (after unescaping).
+    ///
+    /// Relative entries are matched against their name, not their resolved path.
+    pub fn entry_mut(&mut self, path: &[u8]) -> Option<&mut Line> {
+        self.lines
+            .iter_mut()
+            .find(|line| line.path().is_some_and(|p| &*p == path))
+    }
+
+    /// The entries described by the document, as [`MTree`] would read them.
+    pub fn entries(&self) -> Result<Vec<Entry>, Error> {
+        MTree::from_reader(&self.to_bytes()[..]).collect()
+    }
+
+    /// Write the document to `writer`.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut lines = self.lines.iter().peekable();
+        while let Some(line) = lines.next() {
+            line.write_to(&mut writer)?;
+            if lines.peek().is_some() || self.trailing_newline {
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The document as it would be written to a file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        // writing to a vec cannot fail
+        self.write_to(&mut output).unwrap();
+        output
+    }
+}
+
+/// The kinds of line in an mtree file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LineKind {
+    /// A line with nothing but whitespace.
+    Blank,
+    /// A line starting with `#`.
+    Comment,
+    /// A `/set` line.
+    Set,
+    /// An `/unset` line.
+    Unset,
+    /// An entry whose path has no `/`, so is relative to the current directory.
+    Relative,
+    /// A `..` line.
+    DotDot,
+    /// An entry whose path contains a `/`, so is relative to the root of the spec.
+    Full,
+}
+
+/// A single line of a [`Document`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// The line as it was read, or `None` once it has been edited.
+    raw: Option<Vec<u8>>,
+    /// What sort of line this is.
+    kind: LineKind,
+    /// The whitespace before the first word.
+    indent: Vec<u8>,
+    /// The first word, as written (the whole line for comments).
+    head: Vec<u8>,
+    /// The remaining words, as written.
+    words: Vec<Vec<u8>>,
+}
+
+impl Line {
+    /// Parse a single line (without the newline).
+    fn from_raw(raw: &[u8]) -> ParserResult<Line> {
+        let kind = match MTreeLine::from_bytes(raw, None)? {
+            MTreeLine::Blank => LineKind::Blank,
+            MTreeLine::Comment(_) => LineKind::Comment,
+            MTreeLine::Set(_) => LineKind::Set,
+            MTreeLine::Unset(_) => LineKind::Unset,
+            MTreeLine::Relative(_, _) => LineKind::Relative,
+            MTreeLine::DotDot => LineKind::DotDot,
+            MTreeLine::Full(_, _) => LineKind::Full,
+        };
+        let start = raw
+            .iter()
+            .position(|ch| !is_space(*ch))
+            .unwrap_or(raw.len());
+        let (indent, rest) = raw.split_at(start);
+        let (head, words) = if kind == LineKind::Comment {
+            (rest.to_owned(), Vec::new())
+        } else {
+            let mut words = Words::new(rest).map(|word| word.to_owned());
+            (words.next().unwrap_or_default(), words.collect())
+        };
+        Ok(Line {
+            raw: Some(raw.to_owned()),
+            kind,
+            indent: indent.to_owned(),
+            head,
+            words,
+        })
+    }
+
+    /// A new line from its parts.
+    fn new(kind: LineKind, head: Vec<u8>, words: Vec<Vec<u8>>) -> Line {
+        Line {
+            raw: None,
+            kind,
+            indent: Vec::new(),
+            head,
+            words,
+        }
+    }
+
+    /// A blank line.
+    pub fn blank() -> Line {
+        Line::new(LineKind::Blank, Vec::new(), Vec::new())
+    }
+
+    /// A comment line. Any newlines in `comment` are replaced with spaces.
+    pub fn comment(comment: &str) -> Line {
+        let text = comment.replace('\n', " ");
+        Line::new(
+            LineKind::Comment,
+            format!("# {}", text).into_bytes(),
+            Vec::new(),
+        )
+    }
+
+    /// A `/set` line with no keywords. Add keywords with [`Line::set_keyword`].
+    pub fn set() -> Line {
+        Line::new(LineKind::Set, b"/set".to_vec(), Vec::new())
+    }
+
+    /// An `/unset` line for `keywords`.
+    ///
+    /// An empty set gives a bare `/unset`, which changes nothing.
+    pub fn unset(keywords: KeywordSet) -> Line {
+        let words = keywords
+            .to_string()
+            .split_whitespace()
+            .map(|name| name.as_bytes().to_owned())
+            .collect();
+        Line::new(LineKind::Unset, b"/unset".to_vec(), words)
+    }
+
+    /// An entry line for `path`, with no keywords. Add keywords with [`Line::set_keyword`].
+    ///
+    /// If `path` contains a `/` the entry is relative to the root of the spec, otherwise it is
+    /// relative to the current directory.
+    pub fn entry(path: &[u8]) -> Line {
+        let kind = if path.contains(&b'/') {
+            LineKind::Full
+        } else {
+            LineKind::Relative
+        };
+        Line::new(kind, vis(path).into_owned(), Vec::new())
+    }
+
+    /// An entry line with the full path and parameters of `entry`.
+    pub fn from_entry(entry: &Entry) -> Line {
+        let words = entry
+            .params
+            .keywords()
+            .map(|keyword| keyword.to_string().into_bytes())
+            .collect();
+        let path = full_path(&entry.path);
+        // the root is written as a bare `.`, naming the current directory
+        let kind = if path.contains(&b'/') {
+            LineKind::Full
+        } else {
+            LineKind::Relative
+        };
+        Line::new(kind, path, words)
+    }
+
+    /// What sort of line this is.
+    pub fn kind(&self) -> LineKind {
+        self.kind
+    }
+
+    /// The path of an entry line, with any escapes decoded.
+    ///
+    /// This is `None` for lines that aren't entries.
+    pub fn path(&self) -> Option<Cow<'_, [u8]>> {
+        match self.kind {
+            LineKind::Relative | LineKind::Full => Some(unvis(&self.head)),
+            _ => None,
+        }
+    }
+
+    /// The `key=value` words (or keyword names for `/unset`) on the line, as written.
+    pub fn keywords(&self) -> impl Iterator<Item = &[u8]> {
+        self.words.iter().map(|word| word.as_ref())
+    }
+
+    /// The value of keyword `name` on this line, as written.
+    ///
+    /// Keywords without values (e.g. `optional`) have an empty value.
+    pub fn keyword(&self, name: &[u8]) -> Option<&[u8]> {
+        self.words.iter().find_map(|word| {
+            let mut iter = word.splitn(2, |ch| *ch == b'=');
+            if iter.next() == Some(name) {
+                Some(iter.next().unwrap_or_default())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Set a keyword from its `key=value` form, e.g. `sha256digest=...`.
+    ///
+    /// If the line already has the keyword (under any of its names) it is replaced in place,
+    /// otherwise it is added to the end of the line.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is not a `/set` or entry line.
+    pub fn set_keyword(&mut self, keyword: &str) -> Result<(), ParserError> {
+        assert!(
+            matches!(
+                self.kind,
+                LineKind::Set | LineKind::Relative | LineKind::Full
+            ),
+            "only /set and entry lines have keywords"
+        );
+        let word = keyword.as_bytes();
+        let key = same_keyword(&Keyword::from_bytes(word, None)?, word);
+        match self
+            .words
+            .iter_mut()
+            .find(|existing| matches_key(existing, key))
+        {
+            Some(existing) => *existing = word.to_owned(),
+            None => self.words.push(word.to_owned()),
+        }
+        self.raw = None;
+        Ok(())
+    }
+
+    /// Remove keyword `name` (under any of its names) from the line.
+    ///
+    /// `xattr` removes all extended attributes, while `xattr.<name>` removes just that one.
+    ///
+    /// Returns whether the keyword was present.
+    pub fn remove_keyword(&mut self, name: &str) -> bool {
+        let len = self.words.len();
+        match (self.kind, KeywordSet::from_bytes(name.as_bytes())) {
+            // `/unset` lines only have names
+            (LineKind::Unset, _) => self.words.retain(|word| word != name.as_bytes()),
+            (_, Ok(kind)) if kind != KeywordSet::all() => self.words.retain(|word| {
+                Keyword::from_bytes(word, None).map_or(true, |keyword| keyword.kind() != kind)
+            }),
+            _ => self
+                .words
+                .retain(|word| word.splitn(2, |ch| *ch == b'=').next() != Some(name.as_bytes())),
+        }
+        let removed = self.words.len() != len;
+        if removed {
+            self.raw = None;
+        }
+        removed
+    }
+
+    /// Write the line, without a newline.
+    fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        if let Some(ref raw) = self.raw {
+            return writer.write_all(raw);
+        }
+        writer.write_all(&self.indent)?;
+        writer.write_all(&self.head)?;
+        for word in &self.words {
+            writer.write_all(b" ")?;
+            writer.write_all(word)?;
+        }
+        Ok(())
+    }
+}
+
+/// What identifies a keyword for replacement: its kind, and for `xattr.*` its name.
+fn same_keyword<'a>(keyword: &Keyword<'_>, word: &'a [u8]) -> (KeywordSet, &'a [u8]) {
+    match keyword.kind() {
+        KeywordSet::XATTR => (
+            KeywordSet::XATTR,
+            word.splitn(2, |ch| *ch == b'=').next().unwrap(),
+        ),
+        kind => (kind, b""),
+    }
+}
+
+/// Whether the `key=value` word `word` is the keyword identified by `key`.
+fn matches_key(word: &[u8], key: (KeywordSet, &[u8])) -> bool {
+    match Keyword::from_bytes(word, None) {
+        Ok(keyword) => same_keyword(&keyword, word) == key,
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn test_document_round_trip() {
+    let raw = b"\
+#mtree v2.0
+# a comment
+
+/set type=file uid=0  gid=0
+. type=dir
+    usr   type=dir
+        gedit size=10 md5=13c0a46c2fb9f18a1a237d4904b6916e
+    ..
+/unset uid
+./etc/a\\040b size=1";
+    let document = Document::from_bytes(raw).unwrap();
+    assert_eq!(document.to_bytes(), &raw[..]);
+    let kinds = document.lines().iter().map(Line::kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            LineKind::Comment,
+            LineKind::Comment,
+            LineKind::Blank,
+            LineKind::Set,
+            LineKind::Relative,
+            LineKind::Relative,
+            LineKind::Relative,
+            LineKind::DotDot,
+            LineKind::Unset,
+            LineKind::Full,
+        ]
+    );
+    assert_eq!(
+        document.lines()[9].path().as_deref(),
+        Some(&b"./etc/a b"[..])
+    );
+    assert!(Document::from_bytes(b"# ok\n./a bogus=1\n").is_err());
+}
+
+#[test]
+fn test_document_edit() {
+    let raw = b"\
+# packages
+/set type=file uid=0  gid=0
+    gedit size=10 md5=13c0a46c2fb9f18a1a237d4904b6916e
+./b size=1
+";
+    let mut document = Document::from_bytes(raw).unwrap();
+    let line = document.entry_mut(b"gedit").unwrap();
+    line.set_keyword("md5digest=fdb9ac9040f2e78f3561f27e5b31c815")
+        .unwrap();
+    line.set_keyword("mode=755").unwrap();
+    assert!(line.set_keyword("size=big").is_err());
+    assert!(document.entry_mut(b"./b").unwrap().remove_keyword("size"));
+    document.push(Line::comment("added"));
+    let mut entry = Line::entry(b"./c d");
+    entry.set_keyword("size=3").unwrap();
+    document.push(entry);
+    assert_eq!(
+        String::from_utf8_lossy(&document.to_bytes()),
+        "\
+# packages
+/set type=file uid=0  gid=0
+    gedit size=10 md5digest=fdb9ac9040f2e78f3561f27e5b31c815 mode=755
+./b
+# added
+./c\\040d size=3
+"
+    );
+    let entries = document.entries().unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[2].size(), Some(3));
+
+    // tabs count as indentation, like spaces
+    let mut document = Document::from_bytes(b"usr type=dir\n\tgedit\tsize=10\n").unwrap();
+    assert_eq!(document.lines()[1].path().as_deref(), Some(&b"gedit"[..]));
+    document
+        .entry_mut(b"gedit")
+        .unwrap()
+        .set_keyword("mode=755")
+        .unwrap();
+    assert_eq!(
+        document.to_bytes(),
+        &b"usr type=dir\n\tgedit size=10 mode=755\n"[..]
+    );
+
+    let mut document = Document::from_bytes(b"").unwrap();
+    document.push(Line::unset(KeywordSet::empty()));
+    document.push(Line::unset(KeywordSet::UID | KeywordSet::GID));
+    assert_eq!(document.to_bytes(), &b"/unset\n/unset gid uid"[..]);
+
+    // the root is written as a bare `.`
+    let line = Line::from_entry(&Entry::new("."));
+    assert_eq!(line.kind(), LineKind::Relative);
+    assert_eq!(line.path().as_deref(), Some(&b"."[..]));
+}
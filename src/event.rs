@@ -0,0 +1,127 @@
+//! Reading a spec line by line, rather than entry by entry.
+use crate::{Entry, Error, KeywordSet, MTree, Params};
+use std::io::Read;
+
+/// A line of a spec, as read by [`MTree::next_event`].
+///
+/// Iterating over an [`MTree`] gives just the entries, with the defaults applied. Events show
+/// the rest of the file too, for tools like reformatters and linters that care about how a spec
+/// is written as well as what it describes.
+///
+/// ```
+/// use mtree::{Event, MTree};
+///
+/// let spec = b"#mtree\n/set uid=0\nusr type=dir\n..\n/unset uid\n";
+/// let mut mtree = MTree::from_reader(&spec[..]);
+/// let events = mtree.events().collect::<Result<Vec<_>, _>>().unwrap();
+/// assert!(matches!(events[0], Event::Comment(_)));
+/// assert!(matches!(&events[1], Event::SetDefaults(params) if params.uid == Some(0)));
+/// assert!(matches!(&events[2], Event::Entry(entry) if entry.uid() == Some(0)));
+/// assert_eq!(events[3], Event::DirPop);
+/// assert!(matches!(events[4], Event::UnsetDefaults(_)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A `/set` line, with just the params it sets.
+    ///
+    /// The defaults it results in are [`MTree::current_defaults`].
+    SetDefaults(Params),
+    /// An `/unset` line, with the keywords it removes from the defaults.
+    UnsetDefaults(KeywordSet),
+    /// An entry, with the defaults applied, just as iterating over the [`MTree`] gives.
+    Entry(Entry),
+    /// A comment line, including the `#`.
+    Comment(Vec<u8>),
+    /// A blank line.
+    Blank,
+    /// A `..` line, which goes back to the parent of the current directory.
+    DirPop,
+}
+
+/// An iterator over the lines of a spec as [`Event`]s, from [`MTree::events`].
+///
+/// The `MTree` is borrowed rather than consumed, so its defaults and errors can still be looked
+/// at, and the rest of the spec can be read as entries.
+pub struct Events<'a, R>
+where
+    R: Read,
+{
+    mtree: &'a mut MTree<R>,
+}
+
+impl<'a, R> Events<'a, R>
+where
+    R: Read,
+{
+    pub(crate) fn new(mtree: &'a mut MTree<R>) -> Events<'a, R> {
+        Events { mtree }
+    }
+}
+
+impl<R> Iterator for Events<'_, R>
+where
+    R: Read,
+{
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Result<Event, Error>> {
+        self.mtree.next_event()
+    }
+}
+
+#[test]
+fn test_events() {
+    use crate::{FileType, MTreeOptions, Strictness};
+
+    let spec = b"#mtree v2.0
+/set type=file mode=644
+
+./a size=1
+/unset mode
+sub type=dir
+    b mode=600
+..
+./c bogus=1
+";
+    let mut mtree = MTree::with_options(
+        &spec[..],
+        MTreeOptions::new().strictness(Strictness::Lenient),
+    );
+    let events = mtree.events().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(events.len(), 9);
+    assert_eq!(events[0], Event::Comment(b"#mtree v2.0".to_vec()));
+    let set = Params {
+        file_type: Some(FileType::File),
+        mode: Some(0o644.into()),
+        ..Params::default()
+    };
+    assert_eq!(events[1], Event::SetDefaults(set));
+    assert_eq!(events[2], Event::Blank);
+    match &events[3] {
+        Event::Entry(entry) => {
+            assert_eq!(entry.size(), Some(1));
+            assert_eq!(entry.mode(), Some(0o644.into()));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert_eq!(events[4], Event::UnsetDefaults(KeywordSet::MODE));
+    match &events[6] {
+        Event::Entry(entry) => assert_eq!(entry.spec_path(), std::path::Path::new("./sub/b")),
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert_eq!(events[7], Event::DirPop);
+    // the bad keyword is skipped, as it is for entries
+    assert!(matches!(&events[8], Event::Entry(entry) if entry.size().is_none()));
+    assert_eq!(mtree.errors().len(), 1);
+    assert_eq!(mtree.current_defaults().mode, None);
+
+    // events and entries can be mixed
+    let mut mtree = MTree::from_reader(&b"/set uid=1\n./a\n./b\n"[..]);
+    assert!(matches!(
+        mtree.next_event(),
+        Some(Ok(Event::SetDefaults(_)))
+    ));
+    let entries = mtree.collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].uid(), Some(1));
+}
@@ -0,0 +1,333 @@
+//! Adapters for choosing some of the entries of a spec.
+use crate::Entry;
+#[cfg(feature = "regex")]
+use regex::bytes::Regex;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Something an iterator of entries produces: an [`Entry`], a reference to one, or the result of
+/// parsing one.
+pub trait AsEntry {
+    /// The entry, or `None` for an error.
+    fn as_entry(&self) -> Option<&Entry>;
+}
+
+impl AsEntry for Entry {
+    fn as_entry(&self) -> Option<&Entry> {
+        Some(self)
+    }
+}
+
+impl AsEntry for &Entry {
+    fn as_entry(&self) -> Option<&Entry> {
+        Some(self)
+    }
+}
+
+impl<E> AsEntry for Result<Entry, E> {
+    fn as_entry(&self) -> Option<&Entry> {
+        self.as_ref().ok()
+    }
+}
+
+/// Adapters for iterators of entries, such as [`MTree`](crate::MTree) or the iterator of an
+/// [`MTreeSpec`](crate::MTreeSpec).
+///
+/// Errors are always kept, so they still reach the caller.
+///
+/// ```
+/// use mtree::{EntriesExt, MTree};
+///
+/// let spec = "\
+/// ./usr/bin type=dir
+/// ./usr/bin/gedit type=file sha256digest=6f2a4b8bb3b6ef6fd5e1a1bc9a3ad1f2d8f5c06bd9ee0bd44d73e0b2b4c7c2a1
+/// ./usr/bin/vi type=link link=vim
+/// ";
+/// let files = MTree::from_reader(spec.as_bytes())
+///     .files()
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(files.len(), 1);
+/// ```
+pub trait EntriesExt: Iterator + Sized
+where
+    Self::Item: AsEntry,
+{
+    /// Only keep the entries with `type=file`.
+    fn files(self) -> FilterEntries<Self> {
+        FilterEntries::new(self, Entry::is_file)
+    }
+
+    /// Only keep the entries with `type=dir`.
+    fn directories(self) -> FilterEntries<Self> {
+        FilterEntries::new(self, Entry::is_dir)
+    }
+
+    /// Only keep the entries with `type=link`.
+    fn symlinks(self) -> FilterEntries<Self> {
+        FilterEntries::new(self, Entry::is_symlink)
+    }
+
+    /// Only keep the entries with at least one digest of their contents, e.g. `sha256digest`.
+    fn with_digest(self) -> FilterEntries<Self> {
+        FilterEntries::new(self, |entry| {
+            let params = entry.params();
+            params.md5.is_some()
+                || params.rmd160.is_some()
+                || params.sha1.is_some()
+                || params.sha256.is_some()
+                || params.sha384.is_some()
+                || params.sha512.is_some()
+        })
+    }
+
+    /// Drop the entries below a directory whose entry has `ignore`, as mtree(5) intends.
+    ///
+    /// The entry with `ignore` itself is kept.
+    ///
+    /// ```
+    /// use mtree::{EntriesExt, MTree};
+    ///
+    /// let spec = "\
+    /// ./var/cache type=dir ignore
+    /// ./var/cache/junk type=file
+    /// ./var/log type=dir
+    /// ";
+    /// let entries = MTree::from_reader(spec.as_bytes())
+    ///     .prune_ignored()
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(entries.len(), 2);
+    /// ```
+    fn prune_ignored(self) -> PruneIgnored<Self> {
+        PruneIgnored {
+            inner: self,
+            ignored: Vec::new(),
+        }
+    }
+
+    /// Only keep the entries whose paths match `regex`. This is enabled by the `regex` feature.
+    ///
+    /// The regex is matched against the path as bytes, as it is stored in the entry (e.g.
+    /// `./usr/bin/gedit`), so paths that aren't valid UTF-8 can still be matched exactly.
+    ///
+    /// ```
+    /// use mtree::{EntriesExt, MTree};
+    /// use regex::bytes::Regex;
+    ///
+    /// let spec = "\
+    /// ./usr/lib/libfoo.so.1 type=file
+    /// ./usr/lib/libfoo.a type=file
+    /// ";
+    /// let shared = MTree::from_reader(spec.as_bytes())
+    ///     .filter_path(Regex::new(r"\.so(\.[0-9]+)*$").unwrap())
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(shared.len(), 1);
+    /// ```
+    #[cfg(feature = "regex")]
+    fn filter_path(self, regex: Regex) -> FilterPath<Self> {
+        FilterPath { inner: self, regex }
+    }
+}
+
+impl<I> EntriesExt for I
+where
+    I: Iterator,
+    I::Item: AsEntry,
+{
+}
+
+/// An iterator over the entries of some kind, created by e.g. [`EntriesExt::files`].
+#[derive(Clone)]
+pub struct FilterEntries<I> {
+    /// The entries to choose from.
+    inner: I,
+    /// Whether to keep an entry.
+    predicate: fn(&Entry) -> bool,
+}
+
+impl<I> FilterEntries<I> {
+    /// Keep the entries of `inner` for which `predicate` returns `true`.
+    fn new(inner: I, predicate: fn(&Entry) -> bool) -> FilterEntries<I> {
+        FilterEntries { inner, predicate }
+    }
+}
+
+impl<I: fmt::Debug> fmt::Debug for FilterEntries<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FilterEntries")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<I> Iterator for FilterEntries<I>
+where
+    I: Iterator,
+    I::Item: AsEntry,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let predicate = self.predicate;
+        self.inner
+            .find(|item| item.as_entry().is_none_or(predicate))
+    }
+}
+
+/// An iterator over the entries that aren't below an ignored directory, created by
+/// [`EntriesExt::prune_ignored`].
+#[derive(Debug, Clone)]
+pub struct PruneIgnored<I> {
+    /// The entries to choose from.
+    inner: I,
+    /// The paths of the entries with `ignore`.
+    ignored: Vec<PathBuf>,
+}
+
+impl<I> Iterator for PruneIgnored<I>
+where
+    I: Iterator,
+    I::Item: AsEntry,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let ignored = &mut self.ignored;
+        self.inner.find(|item| {
+            let entry = match item.as_entry() {
+                Some(entry) => entry,
+                None => return true,
+            };
+            let path = entry.path();
+            if ignored
+                .iter()
+                .any(|ignored| path != ignored && path.starts_with(ignored))
+            {
+                return false;
+            }
+            if entry.ignore() {
+                ignored.push(path.to_owned());
+            }
+            true
+        })
+    }
+}
+
+/// An iterator over the entries with paths matching a regex, created by
+/// [`EntriesExt::filter_path`].
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub struct FilterPath<I> {
+    /// The entries to choose from.
+    inner: I,
+    /// The regex paths must match.
+    regex: Regex,
+}
+
+#[cfg(feature = "regex")]
+impl<I> Iterator for FilterPath<I>
+where
+    I: Iterator,
+    I::Item: AsEntry,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let regex = &self.regex;
+        self.inner.find(|item| match item.as_entry() {
+            Some(entry) => regex.is_match(&entry.path_bytes()),
+            None => true,
+        })
+    }
+}
+
+#[test]
+fn test_filter_entries() {
+    use crate::MTree;
+    use std::path::Path;
+    let raw = b"\
+./usr type=dir
+./usr/a type=file md5digest=b1946ac92492d2347c6235b4d2611184
+./usr/b type=file
+./usr/c type=link link=a
+./usr/d type=file size=bogus
+";
+    let entries = MTree::from_reader(&raw[..])
+        .take(4)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    fn paths<'a>(entries: impl Iterator<Item = &'a Entry>) -> Vec<&'a Path> {
+        entries.map(Entry::path).collect()
+    }
+    assert_eq!(
+        paths(entries.iter().files()),
+        [Path::new("./usr/a"), Path::new("./usr/b")]
+    );
+    assert_eq!(paths(entries.iter().directories()), [Path::new("./usr")]);
+    assert_eq!(paths(entries.iter().symlinks()), [Path::new("./usr/c")]);
+    assert_eq!(paths(entries.iter().with_digest()), [Path::new("./usr/a")]);
+
+    // the error for `./usr/d` is kept
+    let files = MTree::from_reader(&raw[..]).files().collect::<Vec<_>>();
+    assert_eq!(files.len(), 3);
+    assert!(files[2].is_err());
+}
+
+#[test]
+fn test_prune_ignored() {
+    use crate::MTree;
+    let raw = b"\
+./a type=dir ignore
+./a/b type=dir
+./a/b/c type=file
+./ab type=file
+./d type=dir
+./d/e type=dir ignore
+./d/e/f type=file
+./d/g type=file
+";
+    let paths = MTree::from_reader(&raw[..])
+        .prune_ignored()
+        .map(|entry| entry.unwrap().path().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        ["./a", "./ab", "./d", "./d/e", "./d/g"].map(PathBuf::from)
+    );
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_filter_path() {
+    use crate::MTree;
+    let raw = b"\
+./usr/bin/gedit type=file
+./usr/bin/caf\\303\\251 type=file
+./usr/lib/caf\\351 type=file
+./etc/gedit.conf type=file
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let paths = |regex: &str| {
+        entries
+            .iter()
+            .filter_path(Regex::new(regex).unwrap())
+            .map(|entry| entry.path_bytes().into_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        paths("^./usr/bin/"),
+        [&b"./usr/bin/gedit"[..], "./usr/bin/café".as_bytes()]
+    );
+    // `\xe9` is é in latin-1, which isn't valid UTF-8
+    assert_eq!(paths(r"(?-u)caf\xe9$"), [&b"./usr/lib/caf\xe9"[..]]);
+
+    let results = MTree::from_reader(&b"./a type=file\n./b size=x\n"[..])
+        .filter_path(Regex::new("^./b").unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+}
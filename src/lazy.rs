@@ -0,0 +1,709 @@
+//! Parsing keyword values only when they are asked for.
+use crate::name::is_valid_name;
+use crate::parser::SpecialKind;
+use crate::state::LineState;
+use crate::util::{bytes_to_path, read_line, unvis, ReadLine, Words};
+use crate::{
+    Device, Entry, Error, FileFlags, FileMode, FileType, Keyword, KeywordSet, MTreeOptions, Params,
+    ParserError, ParserErrorKind,
+};
+use std::borrow::Cow;
+use std::io::{BufReader, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An mtree parser that leaves keyword values unparsed until they are asked for.
+///
+/// This is like [`MTree`](crate::MTree), but produces [`LazyEntry`]s, which keep their keywords
+/// as they were written. When only a few keywords of each entry are needed (say the paths and
+/// sizes), this saves decoding all the others, like long digests. The price is that invalid
+/// keywords aren't found until they are read, so a spec that parses fine here may still fail
+/// with `MTree`.
+///
+/// The same [`MTreeOptions`] apply. Only the keywords that have to be parsed as lines are read
+/// are checked then: `/unset` lines, the type of relative entries (so directories can be
+/// entered), and names if they are validated. With
+/// [`Strictness::Lenient`](crate::Strictness::Lenient) those are left out
+/// of their line on their own, as they are by `MTree`; any other invalid keyword is an error
+/// when it's read.
+///
+/// ```
+/// use mtree::LazyMTree;
+///
+/// let spec = b"\
+/// /set type=file uid=0
+/// ./a size=12 sha256digest=not-checked-unless-asked-for
+/// ";
+/// let entry = LazyMTree::from_reader(&spec[..]).next().unwrap().unwrap();
+/// assert_eq!(entry.size().unwrap(), Some(12));
+/// assert_eq!(entry.uid().unwrap(), Some(0));
+/// assert!(entry.sha256().is_err());
+/// ```
+pub struct LazyMTree<R>
+where
+    R: Read,
+{
+    /// The reader the lines come from.
+    inner: BufReader<R>,
+    /// The current line, kept between lines to reuse its allocation.
+    line_buf: Vec<u8>,
+    /// Parses the lines.
+    parser: LazyParser,
+}
+
+impl<R> LazyMTree<R>
+where
+    R: Read,
+{
+    /// The constructor function for a LazyMTree instance.
+    pub fn from_reader(reader: R) -> LazyMTree<R> {
+        LazyMTree::with_options(reader, MTreeOptions::default())
+    }
+
+    /// Construct a LazyMTree instance that parses according to `options`.
+    pub fn with_options(reader: R, options: MTreeOptions) -> LazyMTree<R> {
+        LazyMTree {
+            inner: BufReader::new(reader),
+            line_buf: Vec::new(),
+            parser: LazyParser::new(options),
+        }
+    }
+
+    /// Resolve entries on relative lines against `root`, rather than the current directory of
+    /// the process.
+    ///
+    /// See [`MTree::with_root`](crate::MTree::with_root).
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> LazyMTree<R> {
+        self.parser.state.cwd = root.as_ref().to_owned();
+        self
+    }
+
+    /// The problems that have been skipped over so far.
+    ///
+    /// See [`MTree::errors`](crate::MTree::errors).
+    pub fn errors(&self) -> &[ParserError] {
+        &self.parser.state.errors
+    }
+}
+
+impl<R> Iterator for LazyMTree<R>
+where
+    R: Read,
+{
+    type Item = Result<LazyEntry<'static>, Error>;
+
+    fn next(&mut self) -> Option<Result<LazyEntry<'static>, Error>> {
+        if self.parser.state.finished {
+            return None;
+        }
+        let mut line = mem::take(&mut self.line_buf);
+        let max = self.parser.state.options.max_line_length;
+        let entry = loop {
+            let result = match read_line(&mut self.inner, &mut line, max) {
+                Ok(ReadLine::End) => break None,
+                Ok(ReadLine::Line) => match self.parser.next_entry(&line) {
+                    Ok(entry) => Ok(entry.map(LazyEntry::into_owned)),
+                    Err(e) => Err(e.into()),
+                },
+                Ok(ReadLine::TooLong(len)) => Err(self.parser.state.long_line(len).into()),
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(Some(entry)) => break Some(Ok(entry)),
+                Ok(None) => (),
+                Err(e) => {
+                    if let Some(e) = self.parser.state.line_error(e) {
+                        break Some(Err(e));
+                    }
+                }
+            }
+        };
+        self.line_buf = line;
+        entry
+    }
+}
+
+/// The state kept between lines while parsing lazily, whatever the lines are read from.
+pub(crate) struct LazyParser {
+    /// Where we are in the spec.
+    pub(crate) state: LineState,
+    /// The unparsed keywords set with `/set`, and not since `/unset`.
+    defaults: Vec<Vec<u8>>,
+}
+
+impl LazyParser {
+    /// A parser for the start of a spec.
+    pub(crate) fn new(options: MTreeOptions) -> LazyParser {
+        LazyParser {
+            state: LineState::new(options),
+            defaults: Vec::new(),
+        }
+    }
+
+    /// Parse the next line (without its newline), giving its entry if it has one.
+    ///
+    /// The entry borrows its path and keywords from `line` where it can.
+    pub(crate) fn next_entry<'a>(
+        &mut self,
+        line: &'a [u8],
+    ) -> Result<Option<LazyEntry<'a>>, ParserError> {
+        let (line_no, offset) = self.state.start_line(line.len() as u64 + 1);
+        let at = |e: ParserError| e.at(line_no, offset);
+        self.state.check_keywords(line, line_no, offset)?;
+
+        let mut words = Words::new(line);
+        let first = match words.next() {
+            Some(first) if first[0] != b'#' => first,
+            _ => return Ok(None),
+        };
+        if first == b".." {
+            self.state.leave_dir();
+            return Ok(None);
+        }
+        if first[0] == b'/' {
+            let mut errors = Vec::new();
+            match SpecialKind::from_bytes(&first[1..]).map_err(at)? {
+                SpecialKind::Set => {
+                    let words = words
+                        .filter(|word| match self.check_word(word, false) {
+                            Ok(()) => true,
+                            Err(e) => {
+                                errors.push(at(e));
+                                false
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    self.state.keyword_errors(errors)?;
+                    for word in words {
+                        self.defaults.retain(|default| !same_keyword(default, word));
+                        self.defaults.push(word.to_owned());
+                    }
+                }
+                SpecialKind::Unset => {
+                    let mut unset = KeywordSet::empty();
+                    for word in words {
+                        match KeywordSet::from_bytes(word) {
+                            Ok(keyword) => unset |= keyword,
+                            Err(e) => errors.push(at(e)),
+                        }
+                    }
+                    self.state.keyword_errors(errors)?;
+                    self.defaults
+                        .retain(|default| !unset.contains(keyword_kind(default)));
+                }
+            }
+            return Ok(None);
+        }
+
+        // the entry's own keywords go last, so they are found in place of any defaults
+        let relative = !first.contains(&b'/');
+        let own = words.collect::<Vec<_>>();
+        let written = own.len();
+        let mut errors = Vec::new();
+        let mut usable = |word: &&[u8]| match self.check_word(word, relative) {
+            Ok(()) => true,
+            Err(e) => {
+                errors.push(at(e));
+                false
+            }
+        };
+        let own = own.into_iter().filter(&mut usable).collect::<Vec<_>>();
+        let defaults = self
+            .defaults
+            .iter()
+            .map(Vec::as_slice)
+            .filter(|default| !own.iter().any(|word| same_keyword(default, word)))
+            .filter(&mut usable)
+            .collect::<Vec<_>>();
+        self.state.keyword_errors(errors)?;
+        let keywords = match (own.first(), own.last()) {
+            // with no defaults, and nothing left out, the keywords are just the rest of the line
+            (Some(first), Some(last)) if defaults.is_empty() && own.len() == written => {
+                let start = first.as_ptr() as usize - line.as_ptr() as usize;
+                let end = last.as_ptr() as usize - line.as_ptr() as usize + last.len();
+                Cow::Borrowed(&line[start..end])
+            }
+            _ => {
+                let mut keywords = Vec::new();
+                for word in defaults.into_iter().chain(own.iter().copied()) {
+                    if !keywords.is_empty() {
+                        keywords.push(b' ');
+                    }
+                    keywords.extend_from_slice(word);
+                }
+                Cow::Owned(keywords)
+            }
+        };
+        let mut entry = LazyEntry {
+            path: Cow::Borrowed(Path::new("")),
+            spec_path: None,
+            keywords,
+            explicit: own.iter().map(|word| keyword_kind(word)).collect(),
+            line: line_no,
+            offset,
+        };
+
+        let path = unvis(first);
+        if relative {
+            let (path, spec_path) = self.state.relative_path(&path, line_no, offset)?;
+            // relative directories become the current directory for following entries, so
+            // their type can't wait
+            if entry.file_type()? == Some(FileType::Directory) {
+                self.state.enter_dir(path.clone(), spec_path.clone());
+            }
+            entry.path = Cow::Owned(path);
+            entry.spec_path = Some(spec_path);
+        } else {
+            self.state.check_full_path(&path, line_no, offset)?;
+            entry.path = match path {
+                Cow::Borrowed(path) => bytes_to_path(path),
+                Cow::Owned(path) => Cow::Owned(bytes_to_path(&path).into_owned()),
+            };
+        }
+        Ok(Some(entry))
+    }
+
+    /// An error if `word` is one of the keywords `MTree` has to parse as the line is read, and
+    /// is invalid: the type of a relative entry, or a name when names are validated.
+    fn check_word(&self, word: &[u8], relative: bool) -> Result<(), ParserError> {
+        let kind = keyword_kind(word);
+        if kind == KeywordSet::TYPE && relative {
+            Keyword::from_bytes(word, None)?;
+        } else if kind.intersects(KeywordSet::UNAME | KeywordSet::GNAME)
+            && self.state.options.validate_names
+        {
+            if let Ok(Keyword::Uname(value)) | Ok(Keyword::Gname(value)) =
+                Keyword::from_bytes(word, None)
+            {
+                if !is_valid_name(&value) {
+                    return Err(ParserErrorKind::InvalidName {
+                        keyword: keyword_name(word).to_vec(),
+                        value: value.into_owned(),
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The keyword that `word` is for.
+///
+/// `xattr.*` and keywords we don't recognise can appear more than once, so they need their
+/// names comparing as well.
+fn keyword_kind(word: &[u8]) -> KeywordSet {
+    let key = keyword_name(word);
+    if key.starts_with(b"xattr.") {
+        KeywordSet::XATTR
+    } else {
+        KeywordSet::from_bytes(key).unwrap_or(KeywordSet::EXTENSIONS)
+    }
+}
+
+/// The part of `word` before any `=`.
+fn keyword_name(word: &[u8]) -> &[u8] {
+    match memchr::memchr(b'=', word) {
+        Some(i) => &word[..i],
+        None => word,
+    }
+}
+
+/// Whether `a` and `b` set the same keyword, so only one of them applies.
+fn same_keyword(a: &[u8], b: &[u8]) -> bool {
+    let kind = keyword_kind(a);
+    kind == keyword_kind(b)
+        && (!kind.intersects(KeywordSet::XATTR | KeywordSet::EXTENSIONS)
+            || keyword_name(a) == keyword_name(b))
+}
+
+/// Generates an accessor that parses a single keyword.
+macro_rules! lazy_accessor {
+    ($(#[$attr:meta])* $name:ident -> $ty:ty, $kind:ident, $variant:ident($value:ident) => $convert:expr) => {
+        $(#[$attr])*
+        pub fn $name(&self) -> Result<Option<$ty>, ParserError> {
+            Ok(match self.keyword(KeywordSet::$kind)? {
+                Some(Keyword::$variant($value)) => Some($convert),
+                _ => None,
+            })
+        }
+    };
+}
+
+/// An entry whose keywords haven't been parsed yet.
+///
+/// Each accessor parses its keyword when it is called, returning an error if it is invalid.
+/// Defaults from `/set` are included, as they are for an [`Entry`].
+///
+/// Entries from [`MmapMTree::lazy_entries`](crate::MmapMTree::lazy_entries) borrow their path
+/// and keywords from the map where they can (a relative path, or keywords with defaults, have
+/// to be put together). Those from [`LazyMTree`] own them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LazyEntry<'a> {
+    /// The path of this entry.
+    path: Cow<'a, Path>,
+    /// The path relative to the root of the spec, if it's different from `path`.
+    spec_path: Option<PathBuf>,
+    /// The words of the keywords that apply to this entry, separated by whitespace.
+    keywords: Cow<'a, [u8]>,
+    /// The keywords on the entry's own line.
+    explicit: KeywordSet,
+    /// The line the entry was on, for errors.
+    line: usize,
+    /// The byte offset of the start of the line, for errors.
+    offset: u64,
+}
+
+impl LazyEntry<'_> {
+    /// The full path for this entry.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of this entry relative to the root of the spec, as for
+    /// [`Entry::spec_path`].
+    pub fn spec_path(&self) -> &Path {
+        self.spec_path.as_deref().unwrap_or(&self.path)
+    }
+
+    /// The keywords that were on the entry's own line, as for [`Entry::explicit_keywords`].
+    pub fn explicit_keywords(&self) -> KeywordSet {
+        self.explicit
+    }
+
+    /// The keywords of this entry as they were written, like `size=12`.
+    pub fn raw_keywords(&self) -> impl Iterator<Item = &[u8]> {
+        Words::new(&self.keywords)
+    }
+
+    /// Parse all the keywords of this entry.
+    ///
+    /// Keywords that aren't part of the mtree spec are an error, since there is no
+    /// [`KeywordHandler`](crate::KeywordHandler) to parse them.
+    pub fn params(&self) -> Result<Params, ParserError> {
+        let mut params = Params::default();
+        for word in self.raw_keywords() {
+            params.set(self.parse(word)?);
+        }
+        Ok(params)
+    }
+
+    /// A copy of this entry that owns its path and keywords.
+    pub fn into_owned(self) -> LazyEntry<'static> {
+        LazyEntry {
+            path: Cow::Owned(self.path.into_owned()),
+            spec_path: self.spec_path,
+            keywords: Cow::Owned(self.keywords.into_owned()),
+            explicit: self.explicit,
+            line: self.line,
+            offset: self.offset,
+        }
+    }
+
+    /// Parse all the keywords of this entry, giving the [`Entry`] `MTree` would have.
+    pub fn to_entry(&self) -> Result<Entry, ParserError> {
+        let mut entry = Entry::new(&*self.path).with_params(self.params()?);
+        entry.spec_path = self.spec_path.clone();
+        entry.explicit = Some(self.explicit);
+        Ok(entry)
+    }
+
+    /// Parse `word`, with the location of the entry in any error.
+    fn parse<'a>(&self, word: &'a [u8]) -> Result<Keyword<'a>, ParserError> {
+        Keyword::from_bytes(word, None).map_err(|e| e.at(self.line, self.offset))
+    }
+
+    /// Parse the keyword of `kind`, if there is one.
+    fn keyword(&self, kind: KeywordSet) -> Result<Option<Keyword<'_>>, ParserError> {
+        self.raw_keywords()
+            .filter(|word| keyword_kind(word) == kind)
+            .last()
+            .map(|word| self.parse(word))
+            .transpose()
+    }
+
+    /// Whether the keyword of `kind` is present, for keywords without values.
+    fn has(&self, kind: KeywordSet) -> bool {
+        self.raw_keywords().any(|word| keyword_kind(word) == kind)
+    }
+
+    lazy_accessor! {
+        /// `cksum` The checksum of the file using the default algorithm specified by
+        /// the cksum(1) utility.
+        checksum -> u64, CHECKSUM, Checksum(value) => value
+    }
+
+    lazy_accessor! {
+        /// `device` The device number for *block* or *char* file types.
+        device -> Device, DEVICE, Device(value) => value
+    }
+
+    lazy_accessor! {
+        /// `contents` The full pathname of a file that holds the contents of this file.
+        contents -> PathBuf, CONTENTS, Contents(value) => bytes_to_path(&value).into_owned()
+    }
+
+    lazy_accessor! {
+        /// `flags` The file flags as a symbolic name.
+        flags -> FileFlags, FLAGS, Flags(value) => value
+    }
+
+    lazy_accessor! {
+        /// `gid` The file group as a numeric value.
+        gid -> u32, GID, Gid(value) => value
+    }
+
+    lazy_accessor! {
+        /// `gname` The file group as a symbolic name.
+        gname -> Cow<'_, [u8]>, GNAME, Gname(value) => value
+    }
+
+    /// `ignore` Ignore any file hierarchy below this line.
+    pub fn ignore(&self) -> bool {
+        self.has(KeywordSet::IGNORE)
+    }
+
+    lazy_accessor! {
+        /// `inode` The inode number.
+        inode -> u64, INODE, Inode(value) => value
+    }
+
+    lazy_accessor! {
+        /// `link` The target of the symbolic link when type=link.
+        link -> PathBuf, LINK, Link(value) => bytes_to_path(&value).into_owned()
+    }
+
+    lazy_accessor! {
+        /// `md5|md5digest` The MD5 message digest of the file.
+        md5 -> u128, MD5, Md5(value) => value
+    }
+
+    lazy_accessor! {
+        /// `mode` The current file's permissions as a numeric (octal) or symbolic value.
+        mode -> FileMode, MODE, Mode(value) => value
+    }
+
+    lazy_accessor! {
+        /// `nlink` The number of hard links the file is expected to have.
+        nlink -> u64, NLINK, NLink(value) => value
+    }
+
+    /// `nochange` Make sure this file or directory exists but otherwise ignore all attributes.
+    pub fn no_change(&self) -> bool {
+        self.has(KeywordSet::NO_CHANGE)
+    }
+
+    /// `optional` The file is optional; do not complain about the file if it is not in the
+    /// file hierarchy.
+    pub fn optional(&self) -> bool {
+        self.has(KeywordSet::OPTIONAL)
+    }
+
+    lazy_accessor! {
+        /// `resdevice` The "resident" device number of the file, e.g. the ID of the device that
+        /// contains the file.
+        resident_device -> Device, RESIDENT_DEVICE, ResidentDevice(value) => value
+    }
+
+    lazy_accessor! {
+        /// `rmd160|rmd160digest|ripemd160digest` The RIPEMD160 message digest of the file.
+        rmd160 -> [u8; 20], RMD160, Rmd160(value) => value
+    }
+
+    lazy_accessor! {
+        /// `sha1|sha1digest` The FIPS 160-1 ("SHA-1") message digest of the file.
+        sha1 -> [u8; 20], SHA1, Sha1(value) => value
+    }
+
+    lazy_accessor! {
+        /// `sha256|sha256digest` The FIPS 180-2 ("SHA-256") message digest of the file.
+        sha256 -> [u8; 32], SHA256, Sha256(value) => value
+    }
+
+    lazy_accessor! {
+        /// `sha384|sha384digest` The FIPS 180-2 ("SHA-384") message digest of the file.
+        sha384 -> [u8; 48], SHA384, Sha384(value) => value
+    }
+
+    lazy_accessor! {
+        /// `sha512|sha512digest` The FIPS 180-2 ("SHA-512") message digest of the file.
+        sha512 -> [u8; 64], SHA512, Sha512(value) => value
+    }
+
+    lazy_accessor! {
+        /// `size` The size, in bytes, of the file.
+        size -> u64, SIZE, Size(value) => value
+    }
+
+    /// `time` The last modification time of the file.
+    pub fn time(&self) -> Result<Option<SystemTime>, ParserError> {
+        Ok(match self.keyword(KeywordSet::TIME)? {
+            Some(Keyword::Time(value)) => UNIX_EPOCH.checked_add(value),
+            _ => None,
+        })
+    }
+
+    lazy_accessor! {
+        /// `type` The type of the file.
+        file_type -> FileType, TYPE, Type(value) => value
+    }
+
+    lazy_accessor! {
+        /// `uid` The file owner as a numeric value.
+        uid -> u32, UID, Uid(value) => value
+    }
+
+    lazy_accessor! {
+        /// `uname` The file owner as a symbolic name.
+        uname -> Cow<'_, [u8]>, UNAME, Uname(value) => value
+    }
+}
+
+#[test]
+fn test_lazy_mtree() {
+    let spec = b"\
+/set type=file uid=0 mode=644 xattr.user.a=1
+./a size=1 uid=1 xattr.user.b=2
+/set md5=ffffffffffffffffffffffffffffffff
+/unset mode
+usr type=dir
+bin type=dir mode=755
+ls size=3 md5digest=00000000000000000000000000000001 nochange
+..
+..
+./b size=bogus
+";
+    let entries = LazyMTree::from_reader(&spec[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let eager = crate::MTree::from_reader(&spec[..]);
+    for (lazy, eager) in entries.iter().zip(eager) {
+        match eager {
+            Ok(eager) => {
+                assert_eq!(lazy.explicit_keywords(), eager.explicit_keywords());
+                assert_eq!(lazy.to_entry().unwrap(), eager);
+            }
+            Err(_) => assert!(lazy.to_entry().is_err()),
+        }
+    }
+
+    assert_eq!(entries[3].spec_path(), Path::new("./usr/bin/ls"));
+    assert_eq!(entries[0].spec_path(), entries[0].path());
+
+    let a = &entries[0];
+    assert_eq!(a.uid().unwrap(), Some(1));
+    assert_eq!(a.size().unwrap(), Some(1));
+    assert_eq!(a.file_type().unwrap(), Some(FileType::File));
+    assert_eq!(
+        a.raw_keywords().collect::<Vec<_>>(),
+        [
+            &b"type=file"[..],
+            b"mode=644",
+            b"xattr.user.a=1",
+            b"size=1",
+            b"uid=1",
+            b"xattr.user.b=2"
+        ]
+    );
+
+    let ls = &entries[3];
+    assert!(ls.path().ends_with("usr/bin/ls"));
+    assert_eq!(ls.md5().unwrap(), Some(1));
+    assert_eq!(ls.mode().unwrap(), None);
+    assert_eq!(ls.uname().unwrap(), None);
+    assert!(ls.no_change());
+    assert!(!ls.optional());
+
+    let b = &entries[4];
+    assert_eq!(b.path(), Path::new("./b"));
+    assert_eq!(b.file_type().unwrap(), Some(FileType::File));
+    let error = b.size().unwrap_err();
+    assert_eq!(error.line(), Some(10));
+}
+
+#[test]
+fn test_lazy_options() {
+    let long = format!("./{} size=2", "b".repeat(100));
+    let raw = format!(
+        "./a size=1\n{}\n./c size=3 mode=644 uid=0 gid=0\n/bogus\ndir type=dir\n    \
+         subdirectory.txt size=5\n    e size=6\n..\n./d size=4",
+        long
+    );
+    let options = MTreeOptions::new()
+        .strictness(crate::Strictness::Lenient)
+        .max_line_length(40)
+        .max_keywords(3)
+        .max_path_length(12);
+    let mut lazy = LazyMTree::with_options(raw.as_bytes(), options.clone()).with_root("/root");
+    let paths = lazy
+        .by_ref()
+        .map(|entry| entry.unwrap().path().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        ["./a", "/root/dir", "/root/dir/e", "./d"].map(PathBuf::from)
+    );
+    // the same lines are skipped as by `MTree`
+    let mut eager = crate::MTree::with_options(raw.as_bytes(), options);
+    eager.by_ref().for_each(drop);
+    assert_eq!(lazy.errors(), eager.errors());
+    assert_eq!(lazy.errors().len(), 4);
+
+    // in strict mode the first problem ends iteration
+    let options = MTreeOptions::new().max_line_length(10);
+    let mut lazy = LazyMTree::with_options(&b"./a size=1\n./b size=22\n./c"[..], options);
+    assert!(lazy.next().unwrap().is_ok());
+    assert!(matches!(lazy.next(), Some(Err(Error::Parser(e))) if e.line() == Some(2)));
+    assert!(lazy.next().is_none());
+}
+
+#[test]
+fn test_lazy_lenient_keywords() {
+    let raw = b"\
+/set uname=root gname=bad:name
+/unset nonsense size
+dir type=nonsense uid=1
+./a uname=bad:name size=1
+./b uname=bin
+";
+    let options = MTreeOptions::new()
+        .strictness(crate::Strictness::Lenient)
+        .validate_names(true);
+    let mut lazy = LazyMTree::with_options(&raw[..], options.clone()).with_root("/root");
+    let entries = lazy.by_ref().map(Result::unwrap).collect::<Vec<_>>();
+    // only the bad keywords are left out, not their lines
+    let dir = &entries[0];
+    assert_eq!(dir.path(), Path::new("/root/dir"));
+    assert_eq!(dir.file_type().unwrap(), None);
+    assert_eq!(dir.uid().unwrap(), Some(1));
+    assert_eq!(dir.explicit_keywords(), KeywordSet::UID);
+    let a = &entries[1];
+    assert_eq!(a.path(), Path::new("./a"));
+    assert_eq!(a.explicit_keywords(), KeywordSet::SIZE);
+    // the default still applies in place of the bad name
+    assert_eq!(a.uname().unwrap().as_deref(), Some(&b"root"[..]));
+    assert_eq!(entries[2].uname().unwrap().as_deref(), Some(&b"bin"[..]));
+    assert_eq!(entries[2].gname().unwrap(), None);
+
+    // the same problems are found as by `MTree`
+    let mut eager = crate::MTree::with_options(&raw[..], options).with_root("/root");
+    let eager_entries = eager.by_ref().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(lazy.errors(), eager.errors());
+    assert_eq!(lazy.errors().len(), 4);
+    for (lazy, eager) in entries.iter().zip(&eager_entries) {
+        assert_eq!(&lazy.to_entry().unwrap(), eager);
+    }
+
+    // with `SkipLines` each of those lines is skipped instead
+    let options = MTreeOptions::new()
+        .strictness(crate::Strictness::SkipLines)
+        .validate_names(true);
+    let mut lazy = LazyMTree::with_options(&raw[..], options.clone());
+    let paths = lazy
+        .by_ref()
+        .map(|entry| entry.unwrap().path().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [PathBuf::from("./b")]);
+    assert_eq!(lazy.errors().len(), 4);
+}
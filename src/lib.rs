@@ -5,9 +5,49 @@
 //!
 //! For details on the spec see [mtree(5)].
 //!
+//! Entries can also be written back out as a spec using [`MTreeWriter`], or an existing spec
+//! can be edited in place using [`Document`]. A spec for a directory tree on disk can be
+//! generated with [`Scanner`], and a tree can be checked against a spec with [`Verifier`].
+//! [`LazyMTree`] parses keyword values only when they are asked for.
+//! [`MTree::events`] gives every line of a spec, including `/set` lines and comments, rather
+//! than just the entries, and [`validate`] lists every problem in a spec at once.
+//!
+//! Everything apart from the line and keyword parser in the [`parser`] module ([`MTreeLine`],
+//! [`Keyword`] and the types they contain) needs the `std` feature, which is on by default.
+//! Without it the parser works in `no_std` environments, as long as `alloc` is available.
+//!
+//! Paths are bytes in mtree specs, as they are on unix. On other targets, like Windows and
+//! `wasm32-unknown-unknown`, paths are converted to and from UTF-8 instead (see
+//! [`Entry::path_bytes`]), and the parts that work with the filesystem ([`Scanner`], [`Verifier`]
+//! and the `apply` feature) aren't available.
+//!
+//! Optional features add more:
+//!
+//! - `serde`: [`Entry`], [`Params`] and the types they contain implement `Serialize` and
+//!   `Deserialize`, and the results of verification implement `Serialize`.
+//! - `md-5`, `sha1`, `sha2` and `ripemd`: the [`Scanner`] and [`Verifier`] compute digests of
+//!   files. `ring` and `openssl` compute them with those libraries instead of the RustCrypto
+//!   crates. With `sha2`, `MTreeSpec::fingerprint` hashes a whole spec.
+//! - `rayon`: the [`Scanner`] reads files in parallel, and `Verifier::verify_parallel` hashes
+//!   them in parallel.
+//! - `tar` and `zip`: the [`Verifier`] checks the contents of archives, and with `tar` the spec
+//!   in a package can be read with `MTree::from_package`.
+//! - `flate2`, `zstd` and `xz`: [`MTree::from_path`] reads compressed specs.
+//! - `regex`: `EntriesExt::filter_path` chooses entries by matching their paths with a regex.
+//! - `chrono` and `time`: `Entry::mtime_datetime` and `Entry::mtime_offset_datetime` give the
+//!   modification time as a date and time from those crates.
+//! - `memmap2`: `MTree::from_mmap` parses very large specs straight from a memory map, with
+//!   entries that borrow from the map if wanted.
+//! - `async`: `MTree::from_async_reader` parses specs from tokio readers, as a `Stream`.
+//! - `apply`: an `Applier` changes files to match a spec.
+//! - `capi`: the `capi` module, a C interface to the parser.
+//! - `python`: a Python extension module, with classes for parsing and verifying specs.
+//! - `cli`: an `mtree` binary with `create`, `check`, `diff` and `dump` commands.
+//!
 //! # Examples
 //!
 //! ```
+//! # #[cfg(feature = "std")] {
 //! use mtree::MTree;
 //! use std::time::{SystemTime, UNIX_EPOCH};
 //!
@@ -36,94 +76,400 @@
 //!     // We might also want to take a checksum of the file, and compare it to the digests
 //!     // supplied by mtree, but this example doesn't have access to a filesystem.
 //! }
+//! # }
 //! ```
 //!
 //! [mtree(5)]: https://www.freebsd.org/cgi/man.cgi?mtree(5)
 
-#[macro_use]
-extern crate newtype_array;
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use smallvec::SmallVec;
-use std::env;
-use std::ffi::OsStr;
-use std::fmt;
-use std::io::{self, BufRead, BufReader, Read, Split};
-use std::os::unix::ffi::OsStrExt;
-use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+extern crate alloc;
 
-mod parser;
-mod util;
+#[cfg(feature = "std")]
+use name::is_valid_name;
+#[cfg(feature = "std")]
+use state::LineState;
+#[cfg(any(feature = "chrono", feature = "time"))]
+use std::convert::TryFrom;
+#[cfg(feature = "std")]
+use std::{
+    borrow::Cow,
+    cmp,
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt,
+    io::{self, BufReader, Read},
+    mem,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+#[cfg(feature = "std")]
+use util::{bytes_to_path, canonical_cmp, fnmatch, path_to_bytes, read_line, ReadLine};
 
-pub use parser::{FileMode, FileType, Format, ParserError, Perms};
-use parser::{Keyword, MTreeLine, SpecialKind};
-use util::{Array48, Array64};
+#[cfg(all(feature = "apply", unix))]
+mod apply;
+#[cfg(all(any(feature = "tar", feature = "zip"), unix))]
+mod archive;
+#[cfg(feature = "async")]
+mod async_mtree;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "std")]
+mod compact;
+#[cfg(feature = "std")]
+mod compress;
+#[cfg(feature = "std")]
+mod diff;
+#[cfg(feature = "std")]
+mod document;
+#[cfg(feature = "std")]
+mod event;
+#[cfg(feature = "std")]
+mod filter;
+#[cfg(feature = "std")]
+mod lazy;
+#[cfg(feature = "memmap2")]
+mod mmap;
+#[cfg(feature = "std")]
+mod name;
+pub mod parser;
+#[cfg(all(feature = "python", unix))]
+mod python;
+#[cfg(all(feature = "std", unix))]
+mod scan;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "std")]
+mod spec;
+#[cfg(feature = "std")]
+mod state;
+mod util;
+#[cfg(all(feature = "std", unix))]
+mod verify;
+#[cfg(feature = "std")]
+mod writer;
 
-#[cfg(not(unix))]
-compiler_error!("This library currently only supports unix, due to windows using utf-16 for paths");
+#[cfg(all(feature = "apply", unix))]
+pub use apply::{Action, Applier};
+#[cfg(feature = "async")]
+pub use async_mtree::AsyncMTree;
+#[cfg(feature = "std")]
+pub use compact::{CompactEntry, CompactSpec};
+#[cfg(feature = "std")]
+pub use diff::{diff, BsdFormat, ParamMismatch, SpecDiff};
+#[cfg(feature = "std")]
+pub use document::{Document, Line, LineKind};
+#[cfg(feature = "std")]
+pub use event::{Event, Events};
+#[cfg(feature = "regex")]
+pub use filter::FilterPath;
+#[cfg(feature = "std")]
+pub use filter::{AsEntry, EntriesExt, FilterEntries, PruneIgnored};
+#[cfg(feature = "std")]
+pub use lazy::{LazyEntry, LazyMTree};
+#[cfg(feature = "memmap2")]
+pub use mmap::{MmapLazyEntries, MmapMTree};
+#[cfg(feature = "std")]
+pub use name::{Gname, Uname};
+pub use parser::{
+    Device, ExtensionValue, FileFlags, FileMode, FileType, Format, FormatVersion, Keyword,
+    KeywordHandler, KeywordSet, Limit, MTreeLine, ParserError, ParserErrorKind, Perms,
+};
+#[cfg(all(feature = "std", unix))]
+pub use scan::{Progress, Scan, Scanner};
+#[cfg(feature = "std")]
+pub use spec::{DuplicatePolicy, MTreeSpec, SpecStats};
+#[cfg(feature = "std")]
+pub use util::cksum;
+#[cfg(all(feature = "digest", unix))]
+pub use verify::{verify_digest, verify_digests, DigestMatches};
+#[cfg(all(feature = "std", unix))]
+pub use verify::{Finding, Verification, VerificationReport, Verifier, Verify};
+#[cfg(feature = "std")]
+pub use writer::{MTreeWriter, WriterOptions};
 
 /// An mtree parser (start here).
 ///
 /// This is the main struct for the lib. Semantically, an mtree file is a sequence of filesystem
 /// records. These are provided as an iterator. Use the `from_reader` function to construct an
-/// instance.
+/// instance, or `with_options` to control how the input is parsed.
+#[cfg(feature = "std")]
 pub struct MTree<R>
 where
     R: Read,
 {
-    /// The iterator over lines (lines are guaranteed to end in \n since we only support unix).
-    inner: Split<BufReader<R>>,
-    /// The current working directory for dir calculations.
-    cwd: PathBuf,
+    /// The reader the lines come from (lines are guaranteed to end in \n since we only support
+    /// unix).
+    inner: BufReader<R>,
+    /// The current line, kept between lines to reuse its allocation.
+    line_buf: Vec<u8>,
+    /// Where we are in the spec, and the problems skipped over so far.
+    state: LineState,
     /// These are set with the '/set' and '/unset' special functions.
     default_params: Params,
+    /// The version from the `#mtree` signature, if present.
+    format_version: Option<FormatVersion>,
+    /// Gives values to keywords we don't recognise.
+    keyword_handler: Option<Box<KeywordHandler>>,
 }
 
+#[cfg(feature = "std")]
 impl<R> MTree<R>
 where
     R: Read,
 {
     /// The constructor function for an MTree instance.
     pub fn from_reader(reader: R) -> MTree<R> {
+        MTree::with_options(reader, MTreeOptions::default())
+    }
+
+    /// Construct an MTree instance that resolves relative entries against `root`, rather than
+    /// the current directory.
+    ///
+    /// See [`MTree::with_root`].
+    pub fn from_reader_with_root(reader: R, root: impl AsRef<Path>) -> MTree<R> {
+        MTree::from_reader(reader).with_root(root)
+    }
+
+    /// Construct an MTree instance that parses according to `options`.
+    pub fn with_options(reader: R, options: MTreeOptions) -> MTree<R> {
         MTree {
-            inner: BufReader::new(reader).split(b'\n'),
-            cwd: env::current_dir().unwrap_or(PathBuf::new()),
+            inner: BufReader::new(reader),
+            line_buf: Vec::new(),
+            state: LineState::new(options),
             default_params: Params::default(),
+            format_version: None,
+            keyword_handler: None,
+        }
+    }
+
+    /// Resolve entries on relative lines (those without a `/`, like `bin type=dir`) against
+    /// `root`, rather than the current directory of the process.
+    ///
+    /// If the current directory can't be found and no root is given, relative lines are an
+    /// error ([`ParserErrorKind::NoRoot`]).
+    ///
+    /// This should be set before any entries are read. [`Entry::spec_path`] is the same
+    /// whatever the root.
+    ///
+    /// ```
+    /// use mtree::MTree;
+    /// use std::path::Path;
+    ///
+    /// let spec = b". type=dir\n    usr type=dir\n";
+    /// let entries = MTree::from_reader_with_root(&spec[..], "/tmp/package")
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(entries[1].path(), Path::new("/tmp/package/usr"));
+    /// ```
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> MTree<R> {
+        self.state.cwd = root.as_ref().to_owned();
+        self
+    }
+
+    /// Use `handler` to parse keywords that aren't part of the mtree spec.
+    ///
+    /// The handler is passed the keyword and its value (if any). If it returns a value, it is
+    /// stored in the entry's [`extensions`](Entry::extensions), otherwise the keyword is an
+    /// error as usual.
+    pub fn with_keyword_handler(
+        mut self,
+        handler: impl Fn(&[u8], Option<&[u8]>) -> Option<ExtensionValue> + Send + Sync + 'static,
+    ) -> MTree<R> {
+        self.keyword_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// The problems that have been skipped over so far.
+    ///
+    /// This is always empty in [`Strictness::Strict`] mode. Each error has the line number it was
+    /// found on (see [`ParserError::line`]).
+    pub fn errors(&self) -> &[ParserError] {
+        &self.state.errors
+    }
+
+    /// The format version declared by the `#mtree` signature on the first line.
+    ///
+    /// This is `None` if the file has no signature, or if the first line hasn't been read yet.
+    pub fn format_version(&self) -> Option<FormatVersion> {
+        self.format_version
+    }
+
+    /// The defaults set by the `/set` and `/unset` lines read so far, which entries after them
+    /// start from.
+    ///
+    /// ```
+    /// use mtree::MTree;
+    ///
+    /// let mut mtree = MTree::from_reader(&b"/set type=file uid=0\n./a\n/unset uid\n./b\n"[..]);
+    /// mtree.next();
+    /// assert_eq!(mtree.current_defaults().uid, Some(0));
+    /// mtree.next();
+    /// assert_eq!(mtree.current_defaults().uid, None);
+    /// ```
+    pub fn current_defaults(&self) -> &Params {
+        &self.default_params
+    }
+
+    /// Read the next line of the spec as an [`Event`], or `None` at the end of the spec.
+    ///
+    /// This shows every line, where iterating over the `MTree` only gives the entries. Events and
+    /// entries can be read from the same `MTree` in turn. As with entries, iteration ends after
+    /// the first error unless the parser is lenient, when lines with errors are skipped.
+    pub fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        if self.state.finished {
+            return None;
+        }
+        let mut line = mem::take(&mut self.line_buf);
+        let max = self.state.options.max_line_length;
+        let event = loop {
+            let event = match read_line(&mut self.inner, &mut line, max) {
+                Ok(ReadLine::End) => break None,
+                Ok(ReadLine::Line) => self.parse_line(&line),
+                Ok(ReadLine::TooLong(len)) => self.long_line(len),
+                Err(e) => {
+                    self.state.finished = true;
+                    Some(Err(e.into()))
+                }
+            };
+            if event.is_some() {
+                break event;
+            }
+        };
+        self.line_buf = line;
+        event
+    }
+
+    /// An iterator over the remaining lines of the spec as [`Event`]s.
+    ///
+    /// See [`MTree::next_event`].
+    pub fn events(&mut self) -> Events<'_, R> {
+        Events::new(self)
+    }
+
+    /// The params of an entry with `keywords`, on top of the defaults, along with the keywords
+    /// that were given explicitly.
+    fn entry_params(&self, keywords: Vec<Keyword<'_>>) -> (Params, KeywordSet) {
+        let explicit = keywords.iter().map(Keyword::kind).collect();
+        let mut params = self.default_params.clone_except(explicit);
+        params.set_list(keywords.into_iter());
+        (params, explicit)
+    }
+
+    /// Check the names in `keywords`, if that was asked for.
+    ///
+    /// Invalid names are removed in lenient mode, and otherwise are an error.
+    fn validate_names(
+        &mut self,
+        keywords: &mut Vec<Keyword<'_>>,
+        line_no: usize,
+        offset: u64,
+    ) -> Result<(), ParserError> {
+        if !self.state.options.validate_names {
+            return Ok(());
+        }
+        let mut errors = Vec::new();
+        keywords.retain(|keyword| {
+            let (name, value) = match keyword {
+                Keyword::Uname(value) => ("uname", value),
+                Keyword::Gname(value) => ("gname", value),
+                _ => return true,
+            };
+            if is_valid_name(value) {
+                return true;
+            }
+            let kind = ParserErrorKind::InvalidName {
+                keyword: name.as_bytes().to_vec(),
+                value: value.to_vec(),
+            };
+            errors.push(ParserError::from(kind).at(line_no, offset));
+            false
+        });
+        self.state.keyword_errors(errors)
+    }
+
+    /// Parse the next line (without its newline), giving its event or error.
+    ///
+    /// Errors end iteration in strict mode, and otherwise are skipped over.
+    fn parse_line(&mut self, line: &[u8]) -> Option<Result<Event, Error>> {
+        match self.line_event(line) {
+            Ok(event) => Some(Ok(event)),
+            Err(e) => self.line_error(e),
         }
     }
 
+    /// Note a line that was skipped for being too long, `len` bytes including its newline.
+    fn long_line(&mut self, len: u64) -> Option<Result<Event, Error>> {
+        let error = self.state.long_line(len);
+        self.line_error(error.into())
+    }
+
+    /// Skip over the error in a line, unless parsing is strict.
+    fn line_error(&mut self, error: Error) -> Option<Result<Event, Error>> {
+        self.state.line_error(error).map(Err)
+    }
+
     /// This is a helper function to make error handling easier.
-    fn next_entry(&mut self, line: io::Result<Vec<u8>>) -> Result<Option<Entry>, Error> {
-        let line = line?;
-        let line = MTreeLine::from_bytes(&line)?;
+    fn line_event(&mut self, line: &[u8]) -> Result<Event, Error> {
+        let (line_no, offset) = self.state.start_line(line.len() as u64 + 1);
+        let first_line = line_no == 1;
+        self.state.check_keywords(line, line_no, offset)?;
+        let handler = self.keyword_handler.as_deref();
+        let state = &mut self.state;
+        let line = MTreeLine::from_bytes_with(line, handler, |e| {
+            state.keyword_errors(Some(e.at(line_no, offset)))
+        })
+        .map_err(|e| e.at(line_no, offset))?;
+        let mut line = line;
+        if let MTreeLine::Set(keywords)
+        | MTreeLine::Relative(_, keywords)
+        | MTreeLine::Full(_, keywords) = &mut line
+        {
+            self.validate_names(keywords, line_no, offset)?;
+        }
         Ok(match line {
-            MTreeLine::Blank | MTreeLine::Comment(_) => None,
-            MTreeLine::Special(SpecialKind::Set, keywords) => {
-                self.default_params.set_list(keywords.into_iter());
-                None
+            MTreeLine::Comment(comment) => {
+                if first_line {
+                    self.format_version = FormatVersion::from_signature(comment);
+                }
+                Event::Comment(comment.to_vec())
             }
-            // this won't work because keywords need to be parsed without arguments.
-            MTreeLine::Special(SpecialKind::Unset, _keywords) => unimplemented!(),
-            MTreeLine::Relative(path, keywords) => {
-                let mut params = self.default_params.clone();
+            MTreeLine::Blank => Event::Blank,
+            MTreeLine::Set(keywords) => {
+                let mut params = Params::default();
                 params.set_list(keywords.into_iter());
-                if self.cwd.file_name().is_none() {
-                    panic!("relative without a current working dir");
+                self.default_params.merge(&params);
+                Event::SetDefaults(params)
+            }
+            MTreeLine::Unset(keywords) => {
+                self.default_params.unset(keywords);
+                Event::UnsetDefaults(keywords)
+            }
+            MTreeLine::Relative(path, keywords) => {
+                let (path, spec_path) = self.state.relative_path(&path, line_no, offset)?;
+                let (params, explicit) = self.entry_params(keywords);
+                // relative directories become the current directory for following entries
+                if params.file_type == Some(FileType::Directory) {
+                    self.state.enter_dir(path.clone(), spec_path.clone());
                 }
-                Some(Entry {
-                    path: self.cwd.join(OsStr::from_bytes(path)),
+                Event::Entry(Entry {
+                    path,
+                    spec_path: Some(spec_path),
+                    explicit: Some(explicit),
                     params,
                 })
             }
             MTreeLine::DotDot => {
-                self.cwd.pop();
-                None
+                self.state.leave_dir();
+                Event::DirPop
             }
             MTreeLine::Full(path, keywords) => {
-                let mut params = self.default_params.clone();
-                params.set_list(keywords.into_iter());
-                Some(Entry {
-                    path: Path::new(OsStr::from_bytes(path)).to_owned(),
+                self.state.check_full_path(&path, line_no, offset)?;
+                let (params, explicit) = self.entry_params(keywords);
+                Event::Entry(Entry {
+                    path: bytes_to_path(&path).into_owned(),
+                    spec_path: None,
+                    explicit: Some(explicit),
                     params,
                 })
             }
@@ -131,6 +477,38 @@ where
     }
 }
 
+#[cfg(feature = "std")]
+impl MTree<Box<dyn Read>> {
+    /// Open the spec at `path`, decompressing it if needed.
+    ///
+    /// See [`MTree::from_compressed_reader`].
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<MTree<Box<dyn Read>>> {
+        MTree::from_compressed_reader(std::fs::File::open(path)?)
+    }
+
+    /// Construct an MTree instance from data that may be compressed.
+    ///
+    /// Gzip, zstd and xz compressed data is recognised by its magic bytes, and decompressed if
+    /// the `flate2`, `zstd` or `xz` feature respectively is enabled. Otherwise an error is
+    /// returned. Data that isn't compressed is read as it is.
+    pub fn from_compressed_reader(reader: impl Read + 'static) -> io::Result<MTree<Box<dyn Read>>> {
+        Ok(MTree::from_reader(compress::decompress(reader)?))
+    }
+}
+
+#[cfg(feature = "flate2")]
+impl<R> MTree<flate2::read::GzDecoder<R>>
+where
+    R: Read,
+{
+    /// Construct an MTree instance from gzip compressed data, like the `.MTREE` files in Arch
+    /// Linux packages.
+    pub fn from_gzip_reader(reader: R) -> MTree<flate2::read::GzDecoder<R>> {
+        MTree::from_reader(flate2::read::GzDecoder::new(reader))
+    }
+}
+
+#[cfg(feature = "std")]
 impl<R> Iterator for MTree<R>
 where
     R: Read,
@@ -138,28 +516,217 @@ where
     type Item = Result<Entry, Error>;
 
     fn next(&mut self) -> Option<Result<Entry, Error>> {
-        while let Some(line) = self.inner.next() {
-            match self.next_entry(line) {
-                Ok(Some(entry)) => return Some(Ok(entry)),
-                Ok(None) => (),
+        loop {
+            match self.next_event()? {
+                Ok(Event::Entry(entry)) => return Some(Ok(entry)),
+                Ok(_) => (),
                 Err(e) => return Some(Err(e)),
             }
         }
-        None
     }
 }
 
+/// Parse all of a spec, and report every problem in it with its line number.
+///
+/// Parsing carries on past bad keywords and lines, as in [`Strictness::Lenient`] mode, so this
+/// finds all the problems at once rather than just the first. An empty list means the spec is
+/// fine. Names aren't checked, see [`MTreeOptions::validate_names`].
+///
+/// An i/o error stops reading and is returned instead.
+///
+/// ```
+/// use mtree::ParserErrorKind;
+///
+/// let spec = b"./a size=1\n./b size=big\n/bogus\n./c mode=644\n";
+/// let problems = mtree::validate(&spec[..]).unwrap();
+/// let lines = problems.iter().map(|(line, _)| *line).collect::<Vec<_>>();
+/// assert_eq!(lines, [2, 3]);
+/// ```
+#[cfg(feature = "std")]
+pub fn validate(reader: impl Read) -> io::Result<Vec<(usize, ParserError)>> {
+    let options = MTreeOptions::new().strictness(Strictness::Lenient);
+    let mut mtree = MTree::with_options(reader, options);
+    for entry in mtree.by_ref() {
+        if let Err(Error::Io(e)) = entry {
+            return Err(e);
+        }
+    }
+    Ok(mem::take(&mut mtree.state.errors)
+        .into_iter()
+        .map(|e| (e.line().unwrap_or(0), e))
+        .collect())
+}
+
+/// Options controlling how an mtree is parsed.
+///
+/// Pass these to [`MTree::with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct MTreeOptions {
+    strictness: Strictness,
+    validate_names: bool,
+    max_line_length: Option<usize>,
+    max_keywords: Option<usize>,
+    max_path_length: Option<usize>,
+}
+
+impl MTreeOptions {
+    /// The default options: strict parsing.
+    pub fn new() -> MTreeOptions {
+        MTreeOptions::default()
+    }
+
+    /// Choose how to handle malformed input.
+    pub fn strictness(mut self, strictness: Strictness) -> MTreeOptions {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Check that `uname` and `gname` values are valid names (see [`Uname::is_valid`]).
+    ///
+    /// Invalid names are errors, which are handled like other bad keywords. This is off by
+    /// default, as some systems allow names that don't follow the rules.
+    pub fn validate_names(mut self, validate_names: bool) -> MTreeOptions {
+        self.validate_names = validate_names;
+        self
+    }
+
+    /// Limit lines to `max` bytes, not counting the newline.
+    ///
+    /// A longer line is skipped without being read into memory, so a spec from an untrusted
+    /// source can't use up memory with one huge line. The line is a
+    /// [`ParserErrorKind::LimitExceeded`] error, handled like any other bad line. There is no
+    /// limit by default.
+    pub fn max_line_length(mut self, max: usize) -> MTreeOptions {
+        self.max_line_length = Some(max);
+        self
+    }
+
+    /// Limit lines to `max` keywords.
+    ///
+    /// The words are counted before any are parsed. A line with more is a
+    /// [`ParserErrorKind::LimitExceeded`] error. There is no limit by default.
+    pub fn max_keywords(mut self, max: usize) -> MTreeOptions {
+        self.max_keywords = Some(max);
+        self
+    }
+
+    /// Limit the paths of entries to `max` bytes.
+    ///
+    /// This is the unescaped path relative to the root of the spec, so it includes the
+    /// directories a relative entry is in (see [`Entry::spec_path`]). A longer path is a
+    /// [`ParserErrorKind::LimitExceeded`] error. There is no limit by default.
+    pub fn max_path_length(mut self, max: usize) -> MTreeOptions {
+        self.max_path_length = Some(max);
+        self
+    }
+
+    /// An error if `len` is over the limit set for `limit`, if any.
+    #[cfg(feature = "std")]
+    pub(crate) fn check_limit(&self, limit: Limit, len: usize) -> Result<(), ParserError> {
+        let max = match limit {
+            Limit::LineLength => self.max_line_length,
+            Limit::Keywords => self.max_keywords,
+            Limit::PathLength => self.max_path_length,
+        };
+        match max {
+            Some(max) if len > max => Err(ParserErrorKind::LimitExceeded { limit, max }.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How the parser handles malformed input.
+///
+/// This covers unknown keywords, keywords with bad values, and lines that can't be parsed at
+/// all. I/O errors always end iteration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Strictness {
+    /// The first problem is returned as an error, and ends iteration.
+    #[default]
+    Strict,
+    /// Bad keywords and lines are skipped, and the problems are collected (see
+    /// [`MTree::errors`]).
+    ///
+    /// An entry with a bad keyword is still given, without that keyword.
+    Lenient,
+    /// Any line with a problem is skipped as a whole, and the problems are collected (see
+    /// [`MTree::errors`]).
+    ///
+    /// This suits specs with the odd malformed line, where an entry missing some of its
+    /// keywords would be misleading.
+    SkipLines,
+}
+
 /// An entry in the mtree file.
 ///
 /// Entries have a path to the entity in question, and a list of optional params.
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+///
+/// Entries are compared by their paths and params, so whether a param came from a `/set`
+/// default or the entry's own line (see [`Entry::explicit_keywords`]) doesn't matter.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Entry {
     /// The path of this entry
+    #[cfg_attr(feature = "serde", serde(with = "serialize::byte_string"))]
     path: PathBuf,
+    /// The path relative to the root of the spec, if it's different from `path`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "serialize::option_byte_string"
+        )
+    )]
+    spec_path: Option<PathBuf>,
+    /// The keywords on the entry's own line, if it was parsed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    explicit: Option<KeywordSet>,
     /// All parameters applicable to this entry
     params: Params,
 }
 
+#[cfg(feature = "std")]
+impl Entry {
+    /// The parts of the entry that are compared.
+    fn key(&self) -> (&Path, Option<&Path>, &Params) {
+        (&self.path, self.spec_path.as_deref(), &self.params)
+    }
+}
+
+#[cfg(feature = "std")]
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.key() == other.key()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Eq for Entry {}
+
+#[cfg(feature = "std")]
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::hash::Hash for Entry {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+#[cfg(feature = "std")]
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, r#"mtree entry for "{}""#, self.path.display())?;
@@ -167,12 +734,192 @@ impl fmt::Display for Entry {
     }
 }
 
+#[cfg(feature = "std")]
 impl Entry {
+    /// An entry for `path`, with no parameters.
+    ///
+    /// The path is normalized the same way for all entries: repeated and trailing `/`s, and
+    /// `.` components other than a leading one, are removed.
+    ///
+    /// ```
+    /// use mtree::{Entry, FileType, Params};
+    ///
+    /// let entry = Entry::new("./usr//bin/")
+    ///     .with_params(Params::builder().file_type(FileType::Directory).build());
+    /// assert_eq!(entry.path().to_str(), Some("./usr/bin"));
+    /// assert_eq!(entry.file_type(), Some(FileType::Directory));
+    /// ```
+    pub fn new(path: impl AsRef<Path>) -> Entry {
+        Entry {
+            path: path.as_ref().components().collect(),
+            spec_path: None,
+            explicit: None,
+            params: Params::default(),
+        }
+    }
+
+    /// Replace the parameters of this entry.
+    pub fn with_params(mut self, params: Params) -> Entry {
+        self.params = params;
+        self
+    }
+
+    /// All the parameters of this entry, for editing.
+    pub fn params_mut(&mut self) -> &mut Params {
+        &mut self.params
+    }
+
+    /// The entry as a single line of an mtree file, e.g. `./usr/bin/gedit type=file size=10`.
+    ///
+    /// The path is written as a full path (see [`MTreeWriter`]) and escaped, and every parameter
+    /// is included, so the line doesn't depend on any `/set` defaults.
+    pub fn to_mtree_line(&self) -> String {
+        // the path is escaped to ascii
+        let mut line = String::from_utf8(writer::full_path(self.spec_path())).unwrap();
+        for keyword in self.params.keywords() {
+            line.push(' ');
+            line.push_str(&keyword.to_string());
+        }
+        line
+    }
+
     /// The path of this entry
+    ///
+    /// Entries on relative lines (those without a `/`, like `bin type=dir`) are joined onto the
+    /// current directory of the process, as if the spec described it. See
+    /// [`Entry::spec_path`] for the path within the spec.
     pub fn path(&self) -> &Path {
         self.path.as_ref()
     }
 
+    /// The path of this entry relative to the root of the spec, e.g. `./usr/bin`, whatever the
+    /// current directory.
+    ///
+    /// For an entry on a relative line this is made from the directories it's in, and
+    /// otherwise it's the same as [`Entry::path`]. Join it onto a directory to find the entry
+    /// somewhere else.
+    ///
+    /// ```
+    /// use mtree::MTree;
+    /// use std::path::Path;
+    ///
+    /// let spec = "\
+    /// . type=dir
+    ///     usr type=dir
+    ///         bin type=dir
+    /// ";
+    /// let entries = MTree::from_reader(spec.as_bytes())
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(entries[2].spec_path(), Path::new("./usr/bin"));
+    /// assert!(entries[2].path().ends_with("usr/bin"));
+    /// ```
+    pub fn spec_path(&self) -> &Path {
+        self.spec_path.as_deref().unwrap_or(&self.path)
+    }
+
+    /// The keywords that were on the entry's own line, rather than coming from `/set` defaults.
+    ///
+    /// For an entry that wasn't parsed from a spec, such as one from [`Scanner`] or
+    /// [`Entry::new`], every keyword it has counts as explicit. The set isn't updated if the
+    /// params are changed afterwards.
+    ///
+    /// ```
+    /// use mtree::{KeywordSet, MTree};
+    ///
+    /// let mut entries = MTree::from_reader(&b"/set type=file uid=0\n./a mode=644\n"[..]);
+    /// let entry = entries.next().unwrap().unwrap();
+    /// assert_eq!(entry.explicit_keywords(), KeywordSet::MODE);
+    /// assert_eq!(entry.uid(), Some(0));
+    /// ```
+    pub fn explicit_keywords(&self) -> KeywordSet {
+        match self.explicit {
+            Some(explicit) => explicit,
+            None => self
+                .params
+                .keywords()
+                .map(|keyword| keyword.kind())
+                .collect(),
+        }
+    }
+
+    /// The last component of the path of this entry, e.g. `gedit` for `./usr/bin/gedit`.
+    ///
+    /// This is `None` for the root, `.`.
+    pub fn file_name(&self) -> Option<&OsStr> {
+        self.path.file_name()
+    }
+
+    /// Whether this entry has `type=file`.
+    pub fn is_file(&self) -> bool {
+        self.params.file_type == Some(FileType::File)
+    }
+
+    /// Whether this entry has `type=dir`.
+    pub fn is_dir(&self) -> bool {
+        self.params.file_type == Some(FileType::Directory)
+    }
+
+    /// Whether this entry has `type=link`.
+    pub fn is_symlink(&self) -> bool {
+        self.params.file_type == Some(FileType::SymbolicLink)
+    }
+
+    /// Compare the paths of this entry and `other` in canonical order, as used by
+    /// [`MTreeSpec::sort`] and [`WriterOptions::sorted`].
+    ///
+    /// Paths are compared a directory name at a time, byte by byte, so each directory comes just
+    /// before everything inside it. A leading `./` or `/` is ignored.
+    ///
+    /// ```
+    /// use mtree::Entry;
+    ///
+    /// let mut entries = ["./a.txt", "./a/b", "./a"].map(Entry::new);
+    /// entries.sort_by(Entry::cmp_canonical);
+    /// let paths = entries.iter().map(|entry| entry.path().to_str().unwrap()).collect::<Vec<_>>();
+    /// assert_eq!(paths, ["./a", "./a/b", "./a.txt"]);
+    /// ```
+    pub fn cmp_canonical(&self, other: &Entry) -> cmp::Ordering {
+        canonical_cmp(self.spec_path(), other.spec_path())
+    }
+
+    /// Whether the path of this entry matches the glob `pattern`, e.g. `usr/share/**/*.png`.
+    ///
+    /// A leading `./` or `/` on either the pattern or the path is ignored. `*`, `?` and `[...]`
+    /// work as in the shell, but never match a `/`, while `**` matches any number of
+    /// directories.
+    ///
+    /// ```
+    /// use mtree::Entry;
+    ///
+    /// let entry = Entry::new("./usr/share/icons/hicolor/gedit.png");
+    /// assert!(entry.matches("usr/share/**/*.png"));
+    /// assert!(!entry.matches("/usr/share/*.png"));
+    /// ```
+    pub fn matches(&self, pattern: impl AsRef<[u8]>) -> bool {
+        fn strip_root(path: &[u8]) -> &[u8] {
+            path.strip_prefix(b"./")
+                .or_else(|| path.strip_prefix(b"/"))
+                .unwrap_or(path)
+        }
+        let path = path_to_bytes(self.spec_path());
+        fnmatch(strip_root(pattern.as_ref()), strip_root(&path))
+    }
+
+    /// The path of this entry as bytes, the way it is written in a spec (before escaping).
+    ///
+    /// On unix this is exactly the path. On other targets paths are converted from UTF-8 when
+    /// parsed, so a path that wasn't valid UTF-8 in the spec has had the invalid parts replaced,
+    /// and on Windows any `\` separators are given as `/`.
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
+        path_to_bytes(&self.path)
+    }
+
+    /// All the parameters of this entry.
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
     /// `cksum` The checksum of the file using the default algorithm specified by
     /// the cksum(1) utility.
     pub fn checksum(&self) -> Option<u64> {
@@ -189,21 +936,22 @@ impl Entry {
         self.params.contents.as_ref().map(|v| v.as_ref())
     }
 
-    /// `flags` The file flags as a symbolic name.
-    pub fn flags(&self) -> Option<&[u8]> {
-        self.params.flags.as_ref().map(|v| v.as_ref())
+    /// `flags` The file flags.
+    pub fn flags(&self) -> Option<FileFlags> {
+        self.params.flags
     }
 
     /// `gid` The file group as a numeric value.
-    pub fn gid(&self) -> Option<u64> {
+    pub fn gid(&self) -> Option<u32> {
         self.params.gid
     }
 
     /// `gname` The file group as a symbolic name.
     ///
-    /// The name can be up to 32 chars and must match regex `[a-z_][a-z0-9_-]*[$]?`.
-    pub fn gname(&self) -> Option<&[u8]> {
-        self.params.gname.as_ref().map(|v| v.as_ref())
+    /// The name should be up to 32 chars and match regex `[a-z_][a-z0-9_-]*[$]?` (see
+    /// [`Gname::is_valid`]).
+    pub fn gname(&self) -> Option<&Gname> {
+        self.params.gname.as_ref()
     }
 
     /// `ignore` Ignore any file hierarchy below this line.
@@ -273,12 +1021,12 @@ impl Entry {
 
     /// `sha384|sha384digest` The FIPS 180-2 ("SHA-384") message digest of the file.
     pub fn sha384(&self) -> Option<&[u8; 48]> {
-        self.params.sha384.as_ref().map(|v| v.as_ref())
+        self.params.sha384.as_ref()
     }
 
     /// `sha512|sha512digest` The FIPS 180-2 ("SHA-512") message digest of the file.
     pub fn sha512(&self) -> Option<&[u8; 64]> {
-        self.params.sha512.as_ref().map(|v| v.as_ref())
+        self.params.sha512.as_ref()
     }
 
     /// `size` The size, in bytes, of the file.
@@ -291,21 +1039,82 @@ impl Entry {
         self.params.time
     }
 
+    /// `time` The last modification time of the file, as the time since the unix epoch.
+    ///
+    /// See [`Params::mtime`].
+    pub fn mtime(&self) -> Option<Duration> {
+        self.params.mtime()
+    }
+
+    /// `time` The last modification time of the file, as a `chrono` date and time. This is
+    /// enabled by the `chrono` feature.
+    ///
+    /// ```
+    /// use mtree::MTree;
+    ///
+    /// let entry = MTree::from_reader(&b"./a time=1523250049.905171912"[..])
+    ///     .next()
+    ///     .unwrap()
+    ///     .unwrap();
+    /// let time = entry.mtime_datetime().unwrap();
+    /// assert_eq!(time.to_string(), "2018-04-09 05:00:49.905171912 UTC");
+    /// ```
+    #[cfg(feature = "chrono")]
+    pub fn mtime_datetime(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        let mtime = self.mtime()?;
+        let secs = i64::try_from(mtime.as_secs()).ok()?;
+        chrono::DateTime::from_timestamp(secs, mtime.subsec_nanos())
+    }
+
+    /// `time` The last modification time of the file, as a `time` date and time. This is
+    /// enabled by the `time` feature.
+    ///
+    /// `None` is returned if the time is too far in the future for `time` to represent.
+    #[cfg(feature = "time")]
+    pub fn mtime_offset_datetime(&self) -> Option<time::OffsetDateTime> {
+        let nanos = i128::try_from(self.mtime()?.as_nanos()).ok()?;
+        time::OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()
+    }
+
     /// `type` The type of the file.
     pub fn file_type(&self) -> Option<FileType> {
         self.params.file_type
     }
 
     /// The file owner as a numeric value.
-    pub fn uid(&self) -> Option<u64> {
+    pub fn uid(&self) -> Option<u32> {
         self.params.uid
     }
 
     /// The file owner as a symbolic name.
     ///
-    /// The name can be up to 32 chars and must match regex `[a-z_][a-z0-9_-]*[$]?`.
-    pub fn uname(&self) -> Option<&[u8]> {
-        self.params.uname.as_ref().map(|v| v.as_ref())
+    /// The name should be up to 32 chars and match regex `[a-z_][a-z0-9_-]*[$]?` (see
+    /// [`Uname::is_valid`]).
+    pub fn uname(&self) -> Option<&Uname> {
+        self.params.uname.as_ref()
+    }
+
+    /// `xattr.<name>` The extended attributes of the file, keyed by name.
+    ///
+    /// Values are returned as written in the spec; go-mtree base64-encodes them.
+    pub fn xattrs(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.params.xattrs
+    }
+
+    /// The value of the extended attribute `name`, if present.
+    pub fn xattr(&self, name: &[u8]) -> Option<&[u8]> {
+        self.params.xattrs.get(name).map(|v| v.as_ref())
+    }
+
+    /// Non-standard keywords, as parsed by the handler passed to
+    /// [`MTree::with_keyword_handler`].
+    pub fn extensions(&self) -> &BTreeMap<Vec<u8>, ExtensionValue> {
+        &self.params.extensions
+    }
+
+    /// The value of the non-standard keyword `name`, if present.
+    pub fn extension(&self, name: &[u8]) -> Option<&ExtensionValue> {
+        self.params.extensions.get(name)
     }
 }
 
@@ -313,30 +1122,37 @@ impl Entry {
 ///
 /// All parameters are optional. `ignore`, `nochange` and `optional` all have no value, and so
 /// `true` represets their presence.
+#[cfg(feature = "std")]
 #[derive(Default, Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-struct Params {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Params {
     /// `cksum` The checksum of the file using the default algorithm specified by
     /// the cksum(1) utility.
     pub checksum: Option<u64>,
     /// `device` The device number for *block* or *char* file types.
     pub device: Option<Device>,
     /// `contents` The full pathname of a file that holds the contents of this file.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_byte_string"))]
     pub contents: Option<PathBuf>,
-    /// `flags` The file flags as a symbolic name.
-    pub flags: Option<Vec<u8>>,
+    /// `flags` The file flags.
+    pub flags: Option<FileFlags>,
     /// `gid` The file group as a numeric value.
-    pub gid: Option<u64>,
+    pub gid: Option<u32>,
     /// `gname` The file group as a symbolic name.
     ///
-    /// The name can be up to 32 chars and must match regex `[a-z_][a-z0-9_-]*[$]?`.
-    pub gname: Option<SmallVec<[u8; 32]>>,
+    /// The name should be up to 32 chars and match regex `[a-z_][a-z0-9_-]*[$]?`.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_byte_string"))]
+    pub gname: Option<Gname>,
     /// `ignore` Ignore any file hierarchy below this line.
     pub ignore: bool,
     /// `inode` The inode number.
     pub inode: Option<u64>,
     /// `link` The target of the symbolic link when type=link.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_byte_string"))]
     pub link: Option<PathBuf>,
     /// `md5|md5digest` The MD5 message digest of the file.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_hex"))]
     pub md5: Option<u128>,
     /// `mode` The current file's permissions as a numeric (octal) or symbolic value.
     pub mode: Option<FileMode>,
@@ -354,15 +1170,20 @@ struct Params {
     pub resident_device: Option<Device>,
     /// `rmd160|rmd160digest|ripemd160digest` The RIPEMD160 message digest of
     /// the file.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_hex"))]
     pub rmd160: Option<[u8; 20]>,
     /// `sha1|sha1digest` The FIPS 160-1 ("SHA-1") message digest of the file.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_hex"))]
     pub sha1: Option<[u8; 20]>,
     /// `sha256|sha256digest` The FIPS 180-2 ("SHA-256") message digest of the file.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_hex"))]
     pub sha256: Option<[u8; 32]>,
     /// `sha384|sha384digest` The FIPS 180-2 ("SHA-384") message digest of the file.
-    pub sha384: Option<Array48<u8>>,
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_hex"))]
+    pub sha384: Option<[u8; 48]>,
     /// `sha512|sha512digest` The FIPS 180-2 ("SHA-512") message digest of the file.
-    pub sha512: Option<Array64<u8>>,
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_hex"))]
+    pub sha512: Option<[u8; 64]>,
     /// `size` The size, in bytes, of the file.
     pub size: Option<u64>,
     /// `time` The last modification time of the file.
@@ -370,14 +1191,54 @@ struct Params {
     /// `type` The type of the file.
     pub file_type: Option<FileType>,
     /// The file owner as a numeric value.
-    pub uid: Option<u64>,
+    pub uid: Option<u32>,
     /// The file owner as a symbolic name.
     ///
-    /// The name can be up to 32 chars and must match regex `[a-z_][a-z0-9_-]*[$]?`.
-    pub uname: Option<SmallVec<[u8; 32]>>,
+    /// The name should be up to 32 chars and match regex `[a-z_][a-z0-9_-]*[$]?`.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_byte_string"))]
+    pub uname: Option<Uname>,
+    /// `xattr.<name>` The extended attributes of the file, keyed by name.
+    #[cfg_attr(feature = "serde", serde(with = "serialize::byte_string_map"))]
+    pub xattrs: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Non-standard keywords, as parsed by the [`KeywordHandler`] passed to
+    /// [`MTree::with_keyword_handler`].
+    #[cfg_attr(feature = "serde", serde(with = "serialize::byte_string_keys"))]
+    pub extensions: BTreeMap<Vec<u8>, ExtensionValue>,
 }
 
+#[cfg(feature = "std")]
 impl Params {
+    /// A builder for params, starting with nothing set.
+    pub fn builder() -> ParamsBuilder {
+        ParamsBuilder::default()
+    }
+
+    /// `time` The last modification time of the file, as the time since the unix epoch. This is
+    /// how `time` is written in a spec, as seconds and nanoseconds.
+    ///
+    /// `None` is returned if there's no time, or if it's before the epoch (which a spec can't
+    /// hold). On platforms where `SystemTime` is less precise than a nanosecond, such as
+    /// Windows, the time has already been rounded.
+    pub fn mtime(&self) -> Option<Duration> {
+        self.time?.duration_since(UNIX_EPOCH).ok()
+    }
+
+    /// Overlay `other` onto these params: every parameter that is set in `other` replaces the
+    /// one here, and the rest are left alone.
+    ///
+    /// This is how entries combine with the `/set` defaults. Extended attributes and extensions
+    /// are merged by name.
+    pub fn merge(&mut self, other: &Params) {
+        self.set_list(other.keywords());
+    }
+
+    /// A copy of these params with `other` overlaid, see [`Params::merge`].
+    pub fn merged(&self, other: &Params) -> Params {
+        let mut params = self.clone();
+        params.merge(other);
+        params
+    }
+
     /// Helper method to set a number of parsed keywords.
     fn set_list<'a>(&mut self, keywords: impl Iterator<Item = Keyword<'a>>) {
         for keyword in keywords {
@@ -385,96 +1246,538 @@ impl Params {
         }
     }
 
-    /// Set a parameter from a parsed keyword.
-    fn set(&mut self, keyword: Keyword<'_>) {
+    /// Set a parameter from a keyword.
+    ///
+    /// A `time` too far in the future for a `SystemTime` unsets the time. The parser doesn't
+    /// give such times, but a `Keyword` made by hand can hold one.
+    pub fn set(&mut self, keyword: Keyword<'_>) {
         match keyword {
             Keyword::Checksum(cksum) => self.checksum = Some(cksum),
-            Keyword::DeviceRef(device) => self.device = Some(device.to_device()),
+            Keyword::Device(device) => self.device = Some(device),
             Keyword::Contents(contents) => {
-                self.contents = Some(Path::new(OsStr::from_bytes(contents)).to_owned())
+                self.contents = Some(bytes_to_path(&contents).into_owned())
             }
-            Keyword::Flags(flags) => self.flags = Some(flags.to_owned()),
+            Keyword::Flags(flags) => self.flags = Some(flags),
             Keyword::Gid(gid) => self.gid = Some(gid),
-            Keyword::Gname(gname) => {
-                self.gname = Some({
-                    let mut vec = SmallVec::new();
-                    vec.extend_from_slice(gname);
-                    vec
-                })
-            }
+            Keyword::Gname(gname) => self.gname = Some(Gname::new(gname)),
             Keyword::Ignore => self.ignore = true,
             Keyword::Inode(inode) => self.inode = Some(inode),
-            Keyword::Link(link) => self.link = Some(Path::new(OsStr::from_bytes(link)).to_owned()),
+            Keyword::Link(link) => self.link = Some(bytes_to_path(&link).into_owned()),
             Keyword::Md5(md5) => self.md5 = Some(md5),
             Keyword::Mode(mode) => self.mode = Some(mode),
             Keyword::NLink(nlink) => self.nlink = Some(nlink),
-            Keyword::NoChange => self.no_change = false,
-            Keyword::Optional => self.optional = false,
-            Keyword::ResidentDeviceRef(device) => self.resident_device = Some(device.to_device()),
+            Keyword::NoChange => self.no_change = true,
+            Keyword::Optional => self.optional = true,
+            Keyword::ResidentDevice(device) => self.resident_device = Some(device),
             Keyword::Rmd160(rmd160) => self.rmd160 = Some(rmd160),
             Keyword::Sha1(sha1) => self.sha1 = Some(sha1),
             Keyword::Sha256(sha256) => self.sha256 = Some(sha256),
             Keyword::Sha384(sha384) => self.sha384 = Some(sha384),
             Keyword::Sha512(sha512) => self.sha512 = Some(sha512),
             Keyword::Size(size) => self.size = Some(size),
-            Keyword::Time(time) => self.time = Some(UNIX_EPOCH + time),
+            Keyword::Time(time) => self.time = UNIX_EPOCH.checked_add(time),
             Keyword::Type(ty) => self.file_type = Some(ty),
             Keyword::Uid(uid) => self.uid = Some(uid),
-            Keyword::Uname(uname) => {
-                self.uname = Some({
-                    let mut vec = SmallVec::new();
-                    vec.extend_from_slice(uname);
-                    vec
-                })
+            Keyword::Uname(uname) => self.uname = Some(Uname::new(uname)),
+            Keyword::Xattr(name, value) => {
+                self.xattrs.insert(name.to_owned(), value.to_owned());
+            }
+            Keyword::Extension(name, value) => {
+                self.extensions.insert(name.to_owned(), value);
             }
         }
     }
 
-    /*
-    /// Empty this params list (better mem usage than creating a new one).
-    fn clear(&mut self) {
-        self.checksum = None;
-        self.device = None;
-        self.contents = None;
-        self.flags = None;
-        self.gid = None;
-        self.gname = None;
-        self.ignore = false;
-        self.inode = None;
-        self.link = None;
-        self.md5 = None;
-        self.mode = None;
-        self.nlink = None;
-        self.no_change = false;
-        self.optional = false;
-        self.resident_device = None;
-        self.rmd160 = None;
-        self.sha1 = None;
-        self.sha256 = None;
-        self.sha384 = None;
-        self.sha512 = None;
-        self.size = None;
-        self.time = None;
-        self.file_type = None;
-        self.uid = None;
-        self.uname = None;
-    }
-    */
+    /// The parameters that are set, as keywords, in a stable order.
+    ///
+    /// This is the inverse of [`Params::set`]: setting each keyword on empty params gives back
+    /// the same params.
+    pub fn keywords(&self) -> impl Iterator<Item = Keyword<'_>> {
+        let mut keywords = Vec::new();
+        if let Some(v) = self.file_type {
+            keywords.push(Keyword::Type(v));
+        }
+        if let Some(v) = self.checksum {
+            keywords.push(Keyword::Checksum(v));
+        }
+        if let Some(v) = self.device {
+            keywords.push(Keyword::Device(v));
+        }
+        if let Some(ref v) = self.contents {
+            keywords.push(Keyword::Contents(path_to_bytes(v)));
+        }
+        if let Some(v) = self.flags {
+            keywords.push(Keyword::Flags(v));
+        }
+        if let Some(v) = self.gid {
+            keywords.push(Keyword::Gid(v));
+        }
+        if let Some(ref v) = self.gname {
+            keywords.push(Keyword::Gname(Cow::Borrowed(v.as_bytes())));
+        }
+        if self.ignore {
+            keywords.push(Keyword::Ignore);
+        }
+        if let Some(v) = self.inode {
+            keywords.push(Keyword::Inode(v));
+        }
+        if let Some(ref v) = self.link {
+            keywords.push(Keyword::Link(path_to_bytes(v)));
+        }
+        if let Some(v) = self.md5 {
+            keywords.push(Keyword::Md5(v));
+        }
+        if let Some(v) = self.mode {
+            keywords.push(Keyword::Mode(v));
+        }
+        if let Some(v) = self.nlink {
+            keywords.push(Keyword::NLink(v));
+        }
+        if self.no_change {
+            keywords.push(Keyword::NoChange);
+        }
+        if self.optional {
+            keywords.push(Keyword::Optional);
+        }
+        if let Some(v) = self.resident_device {
+            keywords.push(Keyword::ResidentDevice(v));
+        }
+        if let Some(v) = self.rmd160 {
+            keywords.push(Keyword::Rmd160(v));
+        }
+        if let Some(v) = self.sha1 {
+            keywords.push(Keyword::Sha1(v));
+        }
+        if let Some(v) = self.sha256 {
+            keywords.push(Keyword::Sha256(v));
+        }
+        if let Some(v) = self.sha384 {
+            keywords.push(Keyword::Sha384(v));
+        }
+        if let Some(v) = self.sha512 {
+            keywords.push(Keyword::Sha512(v));
+        }
+        if let Some(v) = self.size {
+            keywords.push(Keyword::Size(v));
+        }
+        // times before the epoch can't be written
+        if let Some(v) = self.mtime() {
+            keywords.push(Keyword::Time(v));
+        }
+        if let Some(v) = self.uid {
+            keywords.push(Keyword::Uid(v));
+        }
+        if let Some(ref v) = self.uname {
+            keywords.push(Keyword::Uname(Cow::Borrowed(v.as_bytes())));
+        }
+        for (name, value) in &self.xattrs {
+            keywords.push(Keyword::Xattr(name, value));
+        }
+        for (name, value) in &self.extensions {
+            keywords.push(Keyword::Extension(name, value.clone()));
+        }
+        keywords.into_iter()
+    }
+
+    /// A copy of these params as the starting point for an entry that sets the `overridden`
+    /// keywords itself, without cloning the overridden values that own allocations.
+    fn clone_except(&self, overridden: KeywordSet) -> Params {
+        /// A clone of `value`, unless `keyword` is overridden.
+        fn keep<T: Clone>(
+            value: &Option<T>,
+            keyword: KeywordSet,
+            overridden: KeywordSet,
+        ) -> Option<T> {
+            if overridden.contains(keyword) {
+                None
+            } else {
+                value.clone()
+            }
+        }
+        Params {
+            checksum: self.checksum,
+            device: self.device,
+            contents: keep(&self.contents, KeywordSet::CONTENTS, overridden),
+            flags: self.flags,
+            gid: self.gid,
+            gname: keep(&self.gname, KeywordSet::GNAME, overridden),
+            ignore: self.ignore,
+            inode: self.inode,
+            link: keep(&self.link, KeywordSet::LINK, overridden),
+            md5: self.md5,
+            mode: self.mode,
+            nlink: self.nlink,
+            no_change: self.no_change,
+            optional: self.optional,
+            resident_device: self.resident_device,
+            rmd160: self.rmd160,
+            sha1: self.sha1,
+            sha256: self.sha256,
+            sha384: self.sha384,
+            sha512: self.sha512,
+            size: self.size,
+            time: self.time,
+            file_type: self.file_type,
+            uid: self.uid,
+            uname: keep(&self.uname, KeywordSet::UNAME, overridden),
+            // these are merged by name, so are always needed
+            xattrs: self.xattrs.clone(),
+            extensions: self.extensions.clone(),
+        }
+    }
+
+    /// Clear the parameters named in `keywords`.
+    pub(crate) fn unset(&mut self, keywords: KeywordSet) {
+        if keywords.contains(KeywordSet::CHECKSUM) {
+            self.checksum = None;
+        }
+        if keywords.contains(KeywordSet::DEVICE) {
+            self.device = None;
+        }
+        if keywords.contains(KeywordSet::CONTENTS) {
+            self.contents = None;
+        }
+        if keywords.contains(KeywordSet::FLAGS) {
+            self.flags = None;
+        }
+        if keywords.contains(KeywordSet::GID) {
+            self.gid = None;
+        }
+        if keywords.contains(KeywordSet::GNAME) {
+            self.gname = None;
+        }
+        if keywords.contains(KeywordSet::IGNORE) {
+            self.ignore = false;
+        }
+        if keywords.contains(KeywordSet::INODE) {
+            self.inode = None;
+        }
+        if keywords.contains(KeywordSet::LINK) {
+            self.link = None;
+        }
+        if keywords.contains(KeywordSet::MD5) {
+            self.md5 = None;
+        }
+        if keywords.contains(KeywordSet::MODE) {
+            self.mode = None;
+        }
+        if keywords.contains(KeywordSet::NLINK) {
+            self.nlink = None;
+        }
+        if keywords.contains(KeywordSet::NO_CHANGE) {
+            self.no_change = false;
+        }
+        if keywords.contains(KeywordSet::OPTIONAL) {
+            self.optional = false;
+        }
+        if keywords.contains(KeywordSet::RESIDENT_DEVICE) {
+            self.resident_device = None;
+        }
+        if keywords.contains(KeywordSet::RMD160) {
+            self.rmd160 = None;
+        }
+        if keywords.contains(KeywordSet::SHA1) {
+            self.sha1 = None;
+        }
+        if keywords.contains(KeywordSet::SHA256) {
+            self.sha256 = None;
+        }
+        if keywords.contains(KeywordSet::SHA384) {
+            self.sha384 = None;
+        }
+        if keywords.contains(KeywordSet::SHA512) {
+            self.sha512 = None;
+        }
+        if keywords.contains(KeywordSet::SIZE) {
+            self.size = None;
+        }
+        if keywords.contains(KeywordSet::TIME) {
+            self.time = None;
+        }
+        if keywords.contains(KeywordSet::TYPE) {
+            self.file_type = None;
+        }
+        if keywords.contains(KeywordSet::UID) {
+            self.uid = None;
+        }
+        if keywords.contains(KeywordSet::UNAME) {
+            self.uname = None;
+        }
+        if keywords.contains(KeywordSet::XATTR) {
+            self.xattrs.clear();
+        }
+        if keywords.contains(KeywordSet::EXTENSIONS) {
+            self.extensions.clear();
+        }
+    }
+}
+
+/// A builder for [`Params`].
+///
+/// ```
+/// use mtree::{FileType, Params};
+///
+/// let params = Params::builder()
+///     .file_type(FileType::File)
+///     .mode(0o644)
+///     .size(123)
+///     .build();
+/// assert_eq!(params.size, Some(123));
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct ParamsBuilder {
+    /// The params built so far.
+    params: Params,
 }
 
+#[cfg(feature = "std")]
+impl ParamsBuilder {
+    /// `cksum` The checksum of the file using the default algorithm specified by
+    /// the cksum(1) utility.
+    pub fn checksum(mut self, checksum: u64) -> ParamsBuilder {
+        self.params.checksum = Some(checksum);
+        self
+    }
+
+    /// `device` The device number for *block* or *char* file types.
+    pub fn device(mut self, device: Device) -> ParamsBuilder {
+        self.params.device = Some(device);
+        self
+    }
+
+    /// `contents` The full pathname of a file that holds the contents of this file.
+    pub fn contents(mut self, contents: impl Into<PathBuf>) -> ParamsBuilder {
+        self.params.contents = Some(contents.into());
+        self
+    }
+
+    /// `flags` The file flags.
+    pub fn flags(mut self, flags: FileFlags) -> ParamsBuilder {
+        self.params.flags = Some(flags);
+        self
+    }
+
+    /// `gid` The file group as a numeric value.
+    pub fn gid(mut self, gid: u32) -> ParamsBuilder {
+        self.params.gid = Some(gid);
+        self
+    }
+
+    /// `gname` The file group as a symbolic name.
+    pub fn gname(mut self, gname: impl AsRef<[u8]>) -> ParamsBuilder {
+        self.params.gname = Some(Gname::new(gname));
+        self
+    }
+
+    /// `ignore` Ignore any file hierarchy below this line.
+    pub fn ignore(mut self, ignore: bool) -> ParamsBuilder {
+        self.params.ignore = ignore;
+        self
+    }
+
+    /// `inode` The inode number.
+    pub fn inode(mut self, inode: u64) -> ParamsBuilder {
+        self.params.inode = Some(inode);
+        self
+    }
+
+    /// `link` The target of the symbolic link when type=link.
+    pub fn link(mut self, link: impl Into<PathBuf>) -> ParamsBuilder {
+        self.params.link = Some(link.into());
+        self
+    }
+
+    /// `md5|md5digest` The MD5 message digest of the file.
+    pub fn md5(mut self, md5: u128) -> ParamsBuilder {
+        self.params.md5 = Some(md5);
+        self
+    }
+
+    /// `mode` The file's permissions, as a number like `0o644`.
+    ///
+    /// Bits other than the permission and setuid/setgid/sticky bits are ignored.
+    pub fn mode(mut self, mode: u32) -> ParamsBuilder {
+        self.params.mode = Some(FileMode::from(mode));
+        self
+    }
+
+    /// `nlink` The number of hard links the file is expected to have.
+    pub fn nlink(mut self, nlink: u64) -> ParamsBuilder {
+        self.params.nlink = Some(nlink);
+        self
+    }
+
+    /// `nochange` Make sure this file or directory exists but otherwise ignore
+    /// all attributes.
+    pub fn no_change(mut self, no_change: bool) -> ParamsBuilder {
+        self.params.no_change = no_change;
+        self
+    }
+
+    /// `optional` The file is optional; do not complain about the file if it is
+    /// not in the file hierarchy.
+    pub fn optional(mut self, optional: bool) -> ParamsBuilder {
+        self.params.optional = optional;
+        self
+    }
+
+    /// `resdevice` The "resident" device number of the file, e.g. the ID of the
+    /// device that contains the file.
+    pub fn resident_device(mut self, device: Device) -> ParamsBuilder {
+        self.params.resident_device = Some(device);
+        self
+    }
+
+    /// `rmd160|rmd160digest|ripemd160digest` The RIPEMD160 message digest of
+    /// the file.
+    pub fn rmd160(mut self, rmd160: [u8; 20]) -> ParamsBuilder {
+        self.params.rmd160 = Some(rmd160);
+        self
+    }
+
+    /// `sha1|sha1digest` The FIPS 160-1 ("SHA-1") message digest of the file.
+    pub fn sha1(mut self, sha1: [u8; 20]) -> ParamsBuilder {
+        self.params.sha1 = Some(sha1);
+        self
+    }
+
+    /// `sha256|sha256digest` The FIPS 180-2 ("SHA-256") message digest of the file.
+    pub fn sha256(mut self, sha256: [u8; 32]) -> ParamsBuilder {
+        self.params.sha256 = Some(sha256);
+        self
+    }
+
+    /// `sha384|sha384digest` The FIPS 180-2 ("SHA-384") message digest of the file.
+    pub fn sha384(mut self, sha384: [u8; 48]) -> ParamsBuilder {
+        self.params.sha384 = Some(sha384);
+        self
+    }
+
+    /// `sha512|sha512digest` The FIPS 180-2 ("SHA-512") message digest of the file.
+    pub fn sha512(mut self, sha512: [u8; 64]) -> ParamsBuilder {
+        self.params.sha512 = Some(sha512);
+        self
+    }
+
+    /// `size` The size, in bytes, of the file.
+    pub fn size(mut self, size: u64) -> ParamsBuilder {
+        self.params.size = Some(size);
+        self
+    }
+
+    /// `time` The last modification time of the file.
+    pub fn time(mut self, time: SystemTime) -> ParamsBuilder {
+        self.params.time = Some(time);
+        self
+    }
+
+    /// `time` The last modification time of the file, as the time since the unix epoch.
+    ///
+    /// A time too far in the future for a `SystemTime` leaves the time unset.
+    pub fn mtime(mut self, mtime: Duration) -> ParamsBuilder {
+        self.params.time = UNIX_EPOCH.checked_add(mtime);
+        self
+    }
+
+    /// `type` The type of the file.
+    pub fn file_type(mut self, file_type: FileType) -> ParamsBuilder {
+        self.params.file_type = Some(file_type);
+        self
+    }
+
+    /// `uid` The file owner as a numeric value.
+    pub fn uid(mut self, uid: u32) -> ParamsBuilder {
+        self.params.uid = Some(uid);
+        self
+    }
+
+    /// `uname` The file owner as a symbolic name.
+    pub fn uname(mut self, uname: impl AsRef<[u8]>) -> ParamsBuilder {
+        self.params.uname = Some(Uname::new(uname));
+        self
+    }
+
+    /// `xattr.<name>` Add an extended attribute.
+    pub fn xattr(mut self, name: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> ParamsBuilder {
+        self.params.xattrs.insert(name.into(), value.into());
+        self
+    }
+
+    /// Add a non-standard keyword.
+    pub fn extension(mut self, name: impl Into<Vec<u8>>, value: ExtensionValue) -> ParamsBuilder {
+        self.params.extensions.insert(name.into(), value);
+        self
+    }
+
+    /// Finish building the params.
+    pub fn build(self) -> Params {
+        self.params
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_params_merge() {
+    let base = Params::builder()
+        .file_type(FileType::File)
+        .uid(0)
+        .mode(0o644)
+        .optional(true)
+        .xattr("user.a", "YQ==")
+        .build();
+    let patch = Params::builder()
+        .mode(0o755)
+        .size(12)
+        .xattr("user.b", "Yg==")
+        .build();
+    let merged = base.merged(&patch);
+    assert_eq!(merged.file_type, Some(FileType::File));
+    assert_eq!(merged.uid, Some(0));
+    assert_eq!(merged.mode, patch.mode);
+    assert_eq!(merged.size, Some(12));
+    assert!(merged.optional);
+    assert_eq!(merged.xattrs.len(), 2);
+    let mut unchanged = base.clone();
+    unchanged.merge(&Params::default());
+    assert_eq!(unchanged, base);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_params_builder() {
+    let params = Params::builder()
+        .file_type(FileType::File)
+        .mode(0o100644)
+        .uname("root")
+        .optional(true)
+        .xattr("user.a", "Yg==")
+        .build();
+    assert_eq!(params.file_type, Some(FileType::File));
+    assert_eq!(
+        params.mode.map(|mode| mode.to_string()),
+        Some("rw-r--r--".into())
+    );
+    assert_eq!(params.uname.as_deref(), Some(&b"root"[..]));
+    assert!(params.optional);
+    assert_eq!(params.xattrs.len(), 1);
+    assert_eq!(Params::builder().build(), Params::default());
+}
+
+#[cfg(feature = "std")]
 impl fmt::Display for Params {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let Some(v) = self.checksum {
             writeln!(f, "checksum: {}", v)?;
         }
         if let Some(ref v) = self.device {
-            writeln!(f, "device: {:?}", v)?;
+            writeln!(f, "device: {}", v)?;
         }
         if let Some(ref v) = self.contents {
             writeln!(f, "contents: {}", v.display())?;
         }
-        if let Some(ref v) = self.flags {
-            writeln!(f, "flags: {:?}", v)?;
+        if let Some(v) = self.flags {
+            writeln!(f, "flags: {}", v)?;
         }
         if let Some(v) = self.gid {
             if v != 0 {
@@ -482,7 +1785,7 @@ impl fmt::Display for Params {
             }
         }
         if let Some(ref v) = self.gname {
-            writeln!(f, "gname: {}", String::from_utf8_lossy(v))?;
+            writeln!(f, "gname: {}", v)?;
         }
         if self.ignore {
             writeln!(f, "ignore")?;
@@ -509,7 +1812,7 @@ impl fmt::Display for Params {
             writeln!(f, "optional")?;
         }
         if let Some(ref v) = self.resident_device {
-            writeln!(f, "resident device: {:?}", v)?;
+            writeln!(f, "resident device: {}", v)?;
         }
         if let Some(ref v) = self.rmd160 {
             write!(f, "rmd160: ")?;
@@ -561,31 +1864,28 @@ impl fmt::Display for Params {
             }
         }
         if let Some(ref v) = self.uname {
-            writeln!(f, "uname: {}", String::from_utf8_lossy(v))?;
+            writeln!(f, "uname: {}", v)?;
+        }
+        for (name, value) in &self.xattrs {
+            writeln!(
+                f,
+                "xattr {}: {}",
+                String::from_utf8_lossy(name),
+                String::from_utf8_lossy(value)
+            )?;
+        }
+        for (name, value) in &self.extensions {
+            writeln!(f, "{}: {:?}", String::from_utf8_lossy(name), value)?;
         }
         Ok(())
     }
 }
 
-/// A unix device.
-///
-/// The parsing for this could probably do with some work.
-#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
-pub struct Device {
-    /// The device format.
-    pub format: Format,
-    /// The device major identifier.
-    pub major: Vec<u8>,
-    /// The device minor identifier.
-    pub minor: Vec<u8>,
-    /// The device subunit identifier, if applicable.
-    pub subunit: Option<Vec<u8>>,
-}
-
 /// The error type for this crate.
 ///
 /// There are 2 possible ways that this lib can fail - there can be a problem parsing a record, or
 /// there can be a fault in the underlying reader.
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub enum Error {
     /// There was an i/o error reading data from the reader.
@@ -594,6 +1894,7 @@ pub enum Error {
     Parser(ParserError),
 }
 
+#[cfg(feature = "std")]
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(match self {
@@ -603,6 +1904,7 @@ impl fmt::Display for Error {
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
@@ -612,14 +1914,632 @@ impl std::error::Error for Error {
     }
 }
 
+#[cfg(feature = "std")]
 impl From<io::Error> for Error {
     fn from(from: io::Error) -> Error {
         Error::Io(from)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<parser::ParserError> for Error {
     fn from(from: parser::ParserError) -> Error {
         Error::Parser(from)
     }
 }
+
+#[cfg(feature = "std")]
+#[test]
+fn test_unset() {
+    let raw = b"\
+/set type=file uid=0 gid=0 mode=644
+./a size=1
+/unset uid mode
+./b size=2
+/unset all
+./c size=3
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries[0].uid(), Some(0));
+    assert!(entries[0].mode().is_some());
+    assert_eq!(entries[1].uid(), None);
+    assert_eq!(entries[1].mode(), None);
+    assert_eq!(entries[1].gid(), Some(0));
+    assert_eq!(entries[1].file_type(), Some(FileType::File));
+    assert_eq!(entries[2].gid(), None);
+    assert_eq!(entries[2].file_type(), None);
+    assert_eq!(entries[2].size(), Some(3));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_xattrs() {
+    let raw = b"\
+/set xattr.user.default=ZGVm
+./a type=file xattr.security.selinux=c3lzdGVt
+/unset xattr
+./b type=file
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries[0].xattrs().len(), 2);
+    assert_eq!(entries[0].xattr(b"user.default"), Some(&b"ZGVm"[..]));
+    assert_eq!(
+        entries[0].xattr(b"security.selinux"),
+        Some(&b"c3lzdGVt"[..])
+    );
+    assert!(entries[1].xattrs().is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_keyword_handler() {
+    let raw = b"\
+/set type=file acme.tier=2
+./a acme.signed
+./b acme.tier=x
+";
+    let entries =
+        MTree::from_reader(&raw[..]).with_keyword_handler(|key, value| match (key, value) {
+            (b"acme.tier", Some(v)) => std::str::from_utf8(v)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(ExtensionValue::Number),
+            (b"acme.signed", None) => Some(ExtensionValue::Flag),
+            _ => None,
+        });
+    let entries = entries.collect::<Vec<_>>();
+    let a = entries[0].as_ref().unwrap();
+    assert_eq!(a.extension(b"acme.tier"), Some(&ExtensionValue::Number(2)));
+    assert_eq!(a.extension(b"acme.signed"), Some(&ExtensionValue::Flag));
+    // the handler declined the value, so it is an unknown keyword
+    assert!(entries[1].is_err());
+    // without a handler, extensions are errors
+    assert!(MTree::from_reader(&raw[..]).next().unwrap().is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_to_mtree_line() {
+    let raw = b"\
+/set type=file uid=0 mode=644
+./usr/bin/a\\040b size=10 time=1523250049.905171912
+";
+    let entry = MTree::from_reader(&raw[..]).next().unwrap().unwrap();
+    assert_eq!(
+        entry.to_mtree_line(),
+        r"./usr/bin/a\040b type=file mode=644 size=10 time=1523250049.905171912 uid=0"
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_params_keywords() {
+    let raw = b"\
+./a type=file uid=0 mode=644 optional nochange xattr.user.a=Yg== size=10 flags=uchg
+";
+    let entry = MTree::from_reader(&raw[..]).next().unwrap().unwrap();
+    let mut params = Params::default();
+    for keyword in entry.params().keywords() {
+        params.set(keyword);
+    }
+    assert_eq!(&params, entry.params());
+    assert!(params.optional && params.no_change);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_entry_new() {
+    for (input, res) in [
+        ("./usr/bin", "./usr/bin"),
+        ("./usr//bin/", "./usr/bin"),
+        ("usr/./bin", "usr/bin"),
+        ("/usr/bin/", "/usr/bin"),
+    ] {
+        assert_eq!(Entry::new(input).path(), Path::new(res));
+    }
+    let mut entry = Entry::new("./a");
+    entry.params_mut().size = Some(1);
+    assert_eq!(entry.to_mtree_line(), "./a size=1");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_entry_predicates() {
+    let raw = b"\
+./usr type=dir
+./usr/bin/gedit type=file
+./usr/bin/vi type=link link=vim
+./usr/lib
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let kinds = entries
+        .iter()
+        .map(|entry| (entry.is_dir(), entry.is_file(), entry.is_symlink()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            (true, false, false),
+            (false, true, false),
+            (false, false, true),
+            (false, false, false)
+        ]
+    );
+    assert_eq!(entries[1].file_name(), Some(OsStr::new("gedit")));
+    assert_eq!(Entry::new(".").file_name(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_path_bytes() {
+    let raw = br"./caf\303\251/a\040b type=file";
+    let entry = MTree::from_reader(&raw[..]).next().unwrap().unwrap();
+    assert_eq!(&*entry.path_bytes(), "./café/a b".as_bytes());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_relative_dirs() {
+    let raw = b"\
+/set type=file
+. type=dir
+    usr type=dir
+        bin type=dir
+            gedit size=10
+        ..
+        share type=dir
+            README size=5
+        ..
+    ..
+    top size=1
+..
+";
+    let mut mtree = MTree::from_reader(&raw[..]);
+    let root = mtree.state.cwd.clone();
+    let entries = mtree.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    let paths = entries
+        .iter()
+        .map(|entry| entry.path.clone())
+        .collect::<Vec<_>>();
+    let spec_paths = entries.iter().map(Entry::spec_path).collect::<Vec<_>>();
+    assert_eq!(
+        spec_paths,
+        [
+            ".",
+            "./usr",
+            "./usr/bin",
+            "./usr/bin/gedit",
+            "./usr/share",
+            "./usr/share/README",
+            "./top"
+        ]
+        .map(Path::new)
+    );
+    assert_eq!(
+        entries[3].to_mtree_line(),
+        "./usr/bin/gedit type=file size=10"
+    );
+    assert_eq!(
+        paths,
+        vec![
+            root.clone(),
+            root.join("usr"),
+            root.join("usr/bin"),
+            root.join("usr/bin/gedit"),
+            root.join("usr/share"),
+            root.join("usr/share/README"),
+            root.join("top"),
+        ]
+    );
+    assert_eq!(mtree.state.cwd, root);
+    assert_eq!(mtree.state.spec_cwd, Path::new("."));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_with_root() {
+    let raw = b"\
+. type=dir
+    bin type=dir
+        ls type=file
+    ..
+./etc type=dir
+";
+    let paths = MTree::from_reader(&raw[..])
+        .with_root("/srv/root")
+        .map(|entry| entry.unwrap().path)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        ["/srv/root", "/srv/root/bin", "/srv/root/bin/ls", "./etc"].map(PathBuf::from)
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_no_root() {
+    let raw = b"usr type=dir\n./etc type=dir\n";
+    let mut mtree = MTree::from_reader(&raw[..]).with_root("");
+    let error = match mtree.next() {
+        Some(Err(Error::Parser(error))) => error,
+        other => panic!("expected an error, got {:?}", other),
+    };
+    assert_eq!(
+        error.kind(),
+        &ParserErrorKind::NoRoot {
+            path: b"usr".to_vec()
+        }
+    );
+    assert_eq!(error.line(), Some(1));
+    assert!(mtree.next().is_none());
+
+    let options = MTreeOptions::new().strictness(Strictness::Lenient);
+    let mut mtree = MTree::with_options(&raw[..], options).with_root("");
+    let paths = mtree
+        .by_ref()
+        .map(|entry| entry.unwrap().path)
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [PathBuf::from("./etc")]);
+    assert_eq!(mtree.errors().len(), 1);
+
+    // the root of the filesystem is fine
+    let entry = MTree::from_reader_with_root(&raw[..], "/")
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(entry.path(), Path::new("/usr"));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_error_source() {
+    use std::error::Error as _;
+
+    struct Broken;
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk on fire"))
+        }
+    }
+
+    let error = MTree::from_reader(Broken).next().unwrap().unwrap_err();
+    assert!(matches!(error, Error::Io(..)));
+    let source = error.source().unwrap();
+    assert_eq!(source.to_string(), "disk on fire");
+    assert!(source.downcast_ref::<io::Error>().is_some());
+
+    let error = MTree::from_reader(&b"./a size=x\n"[..])
+        .next()
+        .unwrap()
+        .unwrap_err();
+    let source = error.source().unwrap();
+    assert!(source.downcast_ref::<ParserError>().is_some());
+
+    // the error works with `?` in functions returning boxed errors
+    fn parse(raw: &[u8]) -> Result<Vec<Entry>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(MTree::from_reader(raw).collect::<Result<_, _>>()?)
+    }
+    let error = parse(b"./a size=x\n").unwrap_err();
+    assert!(error.downcast_ref::<Error>().is_some());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_escaped_paths() {
+    let raw = br"./opt/Blackmagic\040Design/file\tname type=link link=a\040b uname=x\\y";
+    let entry = MTree::from_reader(&raw[..]).next().unwrap().unwrap();
+    assert_eq!(
+        entry.path(),
+        Path::new("./opt/Blackmagic Design/file\tname")
+    );
+    assert_eq!(entry.link(), Some(Path::new("a b")));
+    assert_eq!(entry.uname().unwrap(), &b"x\\y"[..]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_format_version() {
+    let mut mtree = MTree::from_reader(&b"#mtree v2.0\n./a type=file\n"[..]);
+    assert_eq!(mtree.format_version(), None);
+    mtree.next().unwrap().unwrap();
+    assert_eq!(mtree.format_version(), Some(FormatVersion::V2));
+
+    let mut mtree = MTree::from_reader(&b"\n#mtree v2.0\n./a type=file\n"[..]);
+    mtree.next().unwrap().unwrap();
+    assert_eq!(mtree.format_version(), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_strictness() {
+    let raw = b"\
+./a size=1 bogus=1
+/bogus
+./b size=2 mode=999
+./c size=3
+";
+    let mut mtree = MTree::from_reader(&raw[..]);
+    assert!(matches!(mtree.next(), Some(Err(Error::Parser(_)))));
+    assert!(mtree.next().is_none());
+
+    let options = MTreeOptions::new().strictness(Strictness::Lenient);
+    let mut mtree = MTree::with_options(&raw[..], options);
+    let sizes = mtree
+        .by_ref()
+        .map(|entry| entry.unwrap().size().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, vec![1, 2, 3]);
+    assert_eq!(mtree.errors().len(), 3);
+
+    let options = MTreeOptions::new().strictness(Strictness::SkipLines);
+    let mut mtree = MTree::with_options(&raw[..], options);
+    let sizes = mtree
+        .by_ref()
+        .map(|entry| entry.unwrap().size().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, vec![3]);
+    let lines = mtree.errors().iter().map(|e| e.line()).collect::<Vec<_>>();
+    assert_eq!(lines, vec![Some(1), Some(2), Some(3)]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_validate() {
+    let raw = b"/set type=file\n./a size=1 bogus=1 mode=999\n..\n./b\n/unset bogus\n";
+    let problems = validate(&raw[..]).unwrap();
+    let found = problems
+        .iter()
+        .map(|(line, e)| (*line, e.offset()))
+        .collect::<Vec<_>>();
+    assert_eq!(found, vec![(2, Some(15)), (2, Some(15)), (5, Some(50))]);
+    assert!(validate(&b"./a type=file\n"[..]).unwrap().is_empty());
+
+    struct Broken;
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+    assert!(validate(Broken).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_limits() {
+    let long = format!("./{} size=2", "b".repeat(100));
+    let raw = format!(
+        "./a size=1\n{}\n./c size=3 mode=644 uid=0 gid=0\ndir type=dir\n    \
+         subdirectory.txt size=5\n    e size=6\n..\n./d size=4",
+        long
+    );
+    let options = MTreeOptions::new()
+        .strictness(Strictness::Lenient)
+        .max_line_length(40)
+        .max_keywords(3)
+        .max_path_length(12);
+    let mut mtree = MTree::with_options(raw.as_bytes(), options.clone());
+    let paths = mtree
+        .by_ref()
+        .map(|entry| entry.unwrap().spec_path().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["./a", "./dir", "./dir/e", "./d"].map(PathBuf::from));
+    let errors = mtree
+        .errors()
+        .iter()
+        .map(|e| (e.line().unwrap(), e.offset().unwrap(), e.kind().clone()))
+        .collect::<Vec<_>>();
+    let exceeded = |limit, max| ParserErrorKind::LimitExceeded { limit, max };
+    assert_eq!(
+        errors,
+        [
+            (2, 11, exceeded(Limit::LineLength, 40)),
+            (3, 11 + long.len() as u64 + 1, exceeded(Limit::Keywords, 3)),
+            (
+                5,
+                raw.find("    sub").unwrap() as u64,
+                exceeded(Limit::PathLength, 12)
+            ),
+        ]
+    );
+
+    // the limit is inclusive, and doesn't count the newline
+    let raw = b"./a size=1\n./b size=22";
+    let options = MTreeOptions::new().max_line_length(10);
+    let mut mtree = MTree::with_options(&raw[..], options);
+    assert!(mtree.next().unwrap().is_ok());
+    match mtree.next() {
+        Some(Err(Error::Parser(e))) => assert_eq!(e.line(), Some(2)),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    assert!(mtree.next().is_none());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_validate_names() {
+    let raw = b"/set type=file uname=root gname=Staff\n./a uname=Bad\\040Name\n./b\n";
+    // names aren't checked by default
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries[0].uname().unwrap(), "Bad Name");
+    assert_eq!(entries[1].gname().unwrap().to_string(), "Staff");
+
+    let options = MTreeOptions::new().validate_names(true);
+    let error = match MTree::with_options(&raw[..], options).next() {
+        Some(Err(Error::Parser(error))) => error,
+        other => panic!("expected an error, got {:?}", other),
+    };
+    assert_eq!(
+        error.kind(),
+        &ParserErrorKind::InvalidName {
+            keyword: b"gname".to_vec(),
+            value: b"Staff".to_vec(),
+        }
+    );
+    assert_eq!(error.line(), Some(1));
+
+    let options = MTreeOptions::new()
+        .strictness(Strictness::Lenient)
+        .validate_names(true);
+    let mut mtree = MTree::with_options(&raw[..], options);
+    let entries = mtree.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries[0].uname().unwrap(), "root");
+    assert_eq!(entries[0].gname(), None);
+    assert_eq!(entries[1].uname().unwrap(), "root");
+    let lines = mtree.errors().iter().map(|e| e.line()).collect::<Vec<_>>();
+    assert_eq!(lines, [Some(1), Some(2)]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_time_overflow() {
+    let raw = b"./a time=18446744073709551615.0\n";
+    match MTree::from_reader(&raw[..]).next() {
+        Some(Err(Error::Parser(e))) => assert_eq!(
+            e.kind(),
+            &ParserErrorKind::InvalidTime {
+                value: b"18446744073709551615.0".to_vec()
+            }
+        ),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    let entry = LazyMTree::from_reader(&raw[..]).next().unwrap().unwrap();
+    assert!(entry.time().is_err());
+
+    let mut params = Params::default();
+    params.set(Keyword::Time(Duration::MAX));
+    assert_eq!(params.time, None);
+    assert_eq!(Params::builder().mtime(Duration::MAX).build().time, None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_mtime() {
+    let raw = b"./a time=1523250049.905171912\n./b\n";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let mtime = Duration::new(1523250049, 905171912);
+    assert_eq!(entries[0].mtime(), Some(mtime));
+    assert_eq!(entries[1].mtime(), None);
+    assert_eq!(
+        Params::builder().mtime(mtime).build().time,
+        entries[0].time()
+    );
+
+    #[cfg(feature = "chrono")]
+    assert_eq!(
+        entries[0].mtime_datetime().unwrap().to_string(),
+        "2018-04-09 05:00:49.905171912 UTC"
+    );
+    #[cfg(feature = "time")]
+    {
+        let time = entries[0].mtime_offset_datetime().unwrap();
+        assert_eq!(time.unix_timestamp(), 1523250049);
+        assert_eq!(time.nanosecond(), 905171912);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_explicit_keywords() {
+    let raw = b"\
+/set type=file uid=0 mode=644
+./a mode=600 size=1
+. type=dir
+    b uid=0 xattr.user.x=1
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        entries[0].explicit_keywords(),
+        KeywordSet::MODE | KeywordSet::SIZE
+    );
+    assert_eq!(entries[1].explicit_keywords(), KeywordSet::TYPE);
+    assert_eq!(
+        entries[2].explicit_keywords(),
+        KeywordSet::UID | KeywordSet::XATTR
+    );
+
+    // the same entry without defaults compares equal
+    let raw = b"./a type=file uid=0 mode=600 size=1\n";
+    let entry = MTree::from_reader(&raw[..]).next().unwrap().unwrap();
+    assert_eq!(entry, entries[0]);
+    assert_eq!(
+        entry.explicit_keywords(),
+        KeywordSet::TYPE | KeywordSet::UID | KeywordSet::MODE | KeywordSet::SIZE
+    );
+    let entry = Entry::new("./a").with_params(Params::builder().size(1).build());
+    assert_eq!(entry.explicit_keywords(), KeywordSet::SIZE);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_error_position() {
+    let raw = b"#mtree\n./a size=1\n./b size=x\n";
+    let mut mtree = MTree::from_reader(&raw[..]);
+    mtree.next().unwrap().unwrap();
+    match mtree.next() {
+        Some(Err(Error::Parser(e))) => {
+            assert_eq!(e.line(), Some(3));
+            assert_eq!(e.offset(), Some(18));
+            assert!(e.to_string().starts_with("line 3: "));
+        }
+        other => panic!("expected parser error, found {:?}", other),
+    }
+
+    let options = MTreeOptions::new().strictness(Strictness::Lenient);
+    let mut mtree = MTree::with_options(&raw[..], options);
+    assert_eq!(mtree.by_ref().count(), 2);
+    assert_eq!(mtree.errors()[0].line(), Some(3));
+}
+
+#[cfg(feature = "flate2")]
+#[test]
+fn test_from_gzip_reader() {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(b"#mtree\n/set type=file\n./a size=1\n./b size=2\n")
+        .unwrap();
+    let compressed = encoder.finish().unwrap();
+    let sizes = MTree::from_gzip_reader(&compressed[..])
+        .map(|entry| entry.unwrap().size().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, vec![1, 2]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_defaults_overridden() {
+    let raw = b"/set type=file uname=root link=default\n./a uname=bin\n./b link=other\n";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries[0].uname().unwrap(), "bin");
+    assert_eq!(entries[0].link(), Some(Path::new("default")));
+    assert_eq!(entries[1].uname().unwrap(), "root");
+    assert_eq!(entries[1].link(), Some(Path::new("other")));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_names_inline() {
+    let raw =
+        b"/set type=file uname=root gname=wheel\n./a\n./b uname=a_32_character_long_user_name_x\n";
+    for entry in MTree::from_reader(&raw[..]) {
+        let params = entry.unwrap().params;
+        assert!(!params.uname.unwrap().0.spilled());
+        assert!(!params.gname.unwrap().0.spilled());
+    }
+}
@@ -0,0 +1,300 @@
+//! Parsing specs straight from a memory map.
+use crate::lazy::LazyParser;
+use crate::{
+    Entry, Error, Event, FormatVersion, LazyEntry, MTree, MTreeOptions, Params, ParserError,
+};
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+/// An mtree parser for a spec mapped into memory.
+///
+/// Use [`MTree::from_mmap`] to construct an instance. Lines are parsed in just the same way as
+/// by [`MTree`], but straight from the map, so they are never copied into a buffer first. To
+/// also avoid copying paths and keywords out of the map, read the spec with
+/// [`MmapMTree::lazy_entries`] instead.
+///
+/// ```no_run
+/// use mtree::MTree;
+///
+/// // SAFETY: nothing changes the file while it is mapped
+/// let entries = unsafe { MTree::from_mmap("/var/db/mtree/BSD.root.dist") }.unwrap();
+/// for entry in entries {
+///     println!("{}", entry.unwrap());
+/// }
+/// ```
+pub struct MmapMTree {
+    /// The spec.
+    map: Mmap,
+    /// The offset in `map` of the next line.
+    pos: usize,
+    /// The directory relative entries are resolved against.
+    root: PathBuf,
+    /// Parses the lines, but never reads anything itself.
+    parser: MTree<io::Empty>,
+}
+
+impl MTree<io::Empty> {
+    /// Parse the spec at `path` by mapping it into memory, rather than reading it.
+    ///
+    /// For very large specs this saves the read calls and copying each line. The spec isn't
+    /// decompressed.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed (e.g. truncated) while the `MmapMTree` exists, since that is
+    /// undefined behavior, as described in [`memmap2::Mmap`].
+    pub unsafe fn from_mmap(path: impl AsRef<Path>) -> io::Result<MmapMTree> {
+        MTree::from_mmap_with_options(path, MTreeOptions::default())
+    }
+
+    /// Parse the spec at `path` by mapping it into memory, according to `options`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`MTree::from_mmap`].
+    pub unsafe fn from_mmap_with_options(
+        path: impl AsRef<Path>,
+        options: MTreeOptions,
+    ) -> io::Result<MmapMTree> {
+        let map = Mmap::map(&File::open(path)?)?;
+        Ok(MmapMTree {
+            map,
+            pos: 0,
+            root: env::current_dir().unwrap_or_default(),
+            parser: MTree::with_options(io::empty(), options),
+        })
+    }
+}
+
+impl MmapMTree {
+    /// Resolve entries on relative lines against `root`, rather than the current directory of
+    /// the process.
+    ///
+    /// See [`MTree::with_root`].
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> MmapMTree {
+        self.root = root.as_ref().to_owned();
+        self.parser = self.parser.with_root(&self.root);
+        self
+    }
+
+    /// Parse the spec from the start as [`LazyEntry`]s, which borrow their paths and keywords
+    /// from the map where they can.
+    ///
+    /// This doesn't copy anything for most entries, and only parses the keywords that are asked
+    /// for, as with [`LazyMTree`](crate::LazyMTree). It is independent of reading the spec with
+    /// [`MmapMTree::next_event`] or as an iterator.
+    ///
+    /// ```no_run
+    /// use mtree::MTree;
+    ///
+    /// // SAFETY: nothing changes the file while it is mapped
+    /// let spec = unsafe { MTree::from_mmap("/var/db/mtree/BSD.root.dist") }.unwrap();
+    /// for entry in spec.lazy_entries() {
+    ///     let entry = entry.unwrap();
+    ///     println!("{} {:?}", entry.path().display(), entry.size());
+    /// }
+    /// ```
+    pub fn lazy_entries(&self) -> MmapLazyEntries<'_> {
+        let mut parser = LazyParser::new(self.parser.state.options.clone());
+        parser.state.cwd = self.root.clone();
+        MmapLazyEntries {
+            map: &self.map,
+            pos: 0,
+            parser,
+        }
+    }
+
+    /// Read the next line of the spec as an [`Event`], or `None` at the end of the spec.
+    ///
+    /// See [`MTree::next_event`].
+    pub fn next_event(&mut self) -> Option<Result<Event, Error>> {
+        while !self.parser.state.finished {
+            let (line, len) = next_line(&self.map, &mut self.pos)?;
+            let max = self.parser.state.options.max_line_length;
+            let event = if max.is_some_and(|max| line.len() > max) {
+                self.parser.long_line(len as u64)
+            } else {
+                self.parser.parse_line(line)
+            };
+            if event.is_some() {
+                return event;
+            }
+        }
+        None
+    }
+
+    /// The problems that have been skipped over so far.
+    ///
+    /// See [`MTree::errors`].
+    pub fn errors(&self) -> &[ParserError] {
+        self.parser.errors()
+    }
+
+    /// The format version declared by the `#mtree` signature on the first line.
+    ///
+    /// See [`MTree::format_version`].
+    pub fn format_version(&self) -> Option<FormatVersion> {
+        self.parser.format_version()
+    }
+
+    /// The defaults set by the `/set` and `/unset` lines read so far.
+    ///
+    /// See [`MTree::current_defaults`].
+    pub fn current_defaults(&self) -> &Params {
+        self.parser.current_defaults()
+    }
+}
+
+/// The next line of `map` from `pos`, without its newline, and its length with the newline.
+fn next_line<'a>(map: &'a [u8], pos: &mut usize) -> Option<(&'a [u8], usize)> {
+    let rest = map.get(*pos..).filter(|rest| !rest.is_empty())?;
+    let (line, len) = match memchr::memchr(b'\n', rest) {
+        Some(end) => (&rest[..end], end + 1),
+        // the last line may not end in a newline
+        None => (rest, rest.len()),
+    };
+    *pos += len;
+    Some((line, len))
+}
+
+impl Iterator for MmapMTree {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Result<Entry, Error>> {
+        loop {
+            match self.next_event()? {
+                Ok(Event::Entry(entry)) => return Some(Ok(entry)),
+                Ok(_) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// The entries of a memory mapped spec as [`LazyEntry`]s borrowing from the map, created by
+/// [`MmapMTree::lazy_entries`].
+pub struct MmapLazyEntries<'a> {
+    /// The spec.
+    map: &'a [u8],
+    /// The offset in `map` of the next line.
+    pos: usize,
+    /// Parses the lines.
+    parser: LazyParser,
+}
+
+impl MmapLazyEntries<'_> {
+    /// The problems that have been skipped over so far.
+    ///
+    /// See [`MTree::errors`].
+    pub fn errors(&self) -> &[ParserError] {
+        &self.parser.state.errors
+    }
+}
+
+impl<'a> Iterator for MmapLazyEntries<'a> {
+    type Item = Result<LazyEntry<'a>, Error>;
+
+    fn next(&mut self) -> Option<Result<LazyEntry<'a>, Error>> {
+        while !self.parser.state.finished {
+            let (line, len) = next_line(self.map, &mut self.pos)?;
+            let max = self.parser.state.options.max_line_length;
+            let result = if max.is_some_and(|max| line.len() > max) {
+                Err(self.parser.state.long_line(len as u64).into())
+            } else {
+                self.parser.next_entry(line).map_err(Error::from)
+            };
+            match result {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => (),
+                Err(e) => {
+                    if let Some(e) = self.parser.state.line_error(e) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_from_mmap() {
+    use crate::Strictness;
+
+    let path = std::env::temp_dir().join(format!("mtree-mmap-{}", std::process::id()));
+    let spec = b"#mtree\n/set type=file\n./a size=1\n./b size=bogus\n\n./c size=3";
+    std::fs::write(&path, spec).unwrap();
+    let options = MTreeOptions::new().strictness(Strictness::Lenient);
+    let mut entries = unsafe { MTree::from_mmap_with_options(&path, options.clone()) }.unwrap();
+    let sizes = entries
+        .by_ref()
+        .map(|entry| entry.unwrap().size())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [Some(1), None, Some(3)]);
+    let mut expected = MTree::with_options(&spec[..], options);
+    expected.by_ref().for_each(drop);
+    assert_eq!(entries.errors(), expected.errors());
+    assert_eq!(entries.format_version(), Some(FormatVersion::Unversioned));
+
+    // strict parsing stops at the first error
+    let mut entries = unsafe { MTree::from_mmap(&path) }.unwrap();
+    assert!(entries.next().unwrap().is_ok());
+    assert!(entries.next().unwrap().is_err());
+    assert!(entries.next().is_none());
+
+    std::fs::write(&path, b"").unwrap();
+    assert!(unsafe { MTree::from_mmap(&path) }.unwrap().next().is_none());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_mmap_lazy_entries() {
+    use crate::{LazyMTree, Strictness};
+
+    let path = std::env::temp_dir().join(format!("mtree-mmap-lazy-{}", std::process::id()));
+    let spec = b"./a size=1  mode=644\n./b\\040c size=2\n/set uid=0\nd type=dir\n./e size=bogus\n";
+    std::fs::write(&path, spec).unwrap();
+    let mmap = unsafe { MTree::from_mmap(&path) }
+        .unwrap()
+        .with_root("/root");
+    let entries = mmap.lazy_entries().collect::<Result<Vec<_>, _>>().unwrap();
+    let expected = LazyMTree::from_reader(&spec[..])
+        .with_root("/root")
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries, expected);
+
+    // only what had to be put together is owned
+    let in_map = |bytes: &[u8]| mmap.map.as_ptr_range().contains(&bytes.as_ptr());
+    let keywords = |i: usize| entries[i].raw_keywords().next().unwrap();
+    assert!(in_map(keywords(0)));
+    assert_eq!(entries[0].size().unwrap(), Some(1));
+    assert!(!in_map(keywords(2)));
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = |i: usize| entries[i].path().as_os_str().as_bytes();
+        assert!(in_map(path(0)));
+        assert!(!in_map(path(1)));
+        assert!(!in_map(path(2)));
+    }
+    assert_eq!(entries[3].uid().unwrap(), Some(0));
+    assert!(entries[3].size().is_err());
+
+    // the parser keeps going after bad lines unless it is strict
+    std::fs::write(&path, b"./a\n/bogus\n./b\n").unwrap();
+    let mmap = unsafe { MTree::from_mmap(&path) }.unwrap();
+    let mut entries = mmap.lazy_entries();
+    assert!(entries.next().unwrap().is_ok());
+    assert!(entries.next().unwrap().is_err());
+    assert!(entries.next().is_none());
+    let options = MTreeOptions::new().strictness(Strictness::SkipLines);
+    let mmap = unsafe { MTree::from_mmap_with_options(&path, options) }.unwrap();
+    let mut entries = mmap.lazy_entries();
+    assert_eq!(entries.by_ref().count(), 2);
+    assert_eq!(entries.errors().len(), 1);
+    drop(entries);
+    std::fs::remove_file(&path).unwrap();
+}
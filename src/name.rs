@@ -0,0 +1,160 @@
+//! The `uname` and `gname` of an entry.
+use smallvec::SmallVec;
+use std::fmt;
+use std::ops::Deref;
+use std::str;
+
+/// Whether `name` is a valid user or group name: up to 32 chars, matching the regex
+/// `[a-z_][a-z0-9_-]*[$]?`.
+pub(crate) fn is_valid_name(name: &[u8]) -> bool {
+    let (first, rest) = match name.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+    let rest = rest.strip_suffix(b"$").unwrap_or(rest);
+    name.len() <= 32
+        && matches!(first, b'a'..=b'z' | b'_')
+        && rest
+            .iter()
+            .all(|ch| matches!(ch, b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-'))
+}
+
+macro_rules! name_type {
+    ($(#[$attr:meta])* $name:ident, $keyword:literal) => {
+        $(#[$attr])*
+        ///
+        /// Names are stored inline if they are up to 32 bytes long, so repeating a name on every
+        /// entry doesn't allocate. Any bytes are accepted, so that specs with unusual names can
+        /// still be read, but [`is_valid`](Self::is_valid) checks the usual rules.
+        #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+        pub struct $name(pub(crate) SmallVec<[u8; 32]>);
+
+        impl $name {
+            #[doc = concat!("A `", $keyword, "` from its bytes.")]
+            pub fn new(name: impl AsRef<[u8]>) -> $name {
+                $name(SmallVec::from_slice(name.as_ref()))
+            }
+
+            /// The name as bytes.
+            pub fn as_bytes(&self) -> &[u8] {
+                &self.0
+            }
+
+            /// The name as a string, or `None` if it isn't valid UTF-8.
+            pub fn as_str(&self) -> Option<&str> {
+                str::from_utf8(&self.0).ok()
+            }
+
+            /// Whether the name is up to 32 chars and matches the regex
+            /// `[a-z_][a-z0-9_-]*[$]?`.
+            pub fn is_valid(&self) -> bool {
+                is_valid_name(&self.0)
+            }
+        }
+
+        impl Deref for $name {
+            type Target = [u8];
+
+            fn deref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(name: &str) -> $name {
+                $name::new(name)
+            }
+        }
+
+        impl From<&[u8]> for $name {
+            fn from(name: &[u8]) -> $name {
+                $name::new(name)
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.as_bytes() == other.as_bytes()
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.as_bytes() == other.as_bytes()
+            }
+        }
+
+        impl PartialEq<[u8]> for $name {
+            fn eq(&self, other: &[u8]) -> bool {
+                self.as_bytes() == other
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let mut tuple = f.debug_tuple(stringify!($name));
+                match self.as_str() {
+                    Some(name) => tuple.field(&name),
+                    None => tuple.field(&self.as_bytes()),
+                };
+                tuple.finish()
+            }
+        }
+
+        /// Writes the name, with any invalid UTF-8 replaced by `U+FFFD`.
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&String::from_utf8_lossy(&self.0), f)
+            }
+        }
+    };
+}
+
+name_type!(
+    /// The name of the owner of a file, from `uname`.
+    Uname,
+    "uname"
+);
+
+name_type!(
+    /// The name of the group of a file, from `gname`.
+    Gname,
+    "gname"
+);
+
+#[test]
+fn test_names() {
+    for name in ["root", "_apt", "www-data", "nobody4", "machine$", "a"] {
+        assert!(Uname::from(name).is_valid(), "{}", name);
+    }
+    for name in [
+        "",
+        "Root",
+        "1user",
+        "-user",
+        "us er",
+        "user$$",
+        "$",
+        "caf\u{e9}",
+        "abcdefghijklmnopqrstuvwxyz0123456",
+    ] {
+        assert!(!Gname::from(name).is_valid(), "{}", name);
+    }
+
+    let name = Uname::new("root");
+    assert_eq!(name.as_str(), Some("root"));
+    assert_eq!(name, "root");
+    assert_eq!(name.to_string(), "root");
+    assert_eq!(format!("{:?}", name), r#"Uname("root")"#);
+
+    let name = Gname::new(b"caf\xe9");
+    assert_eq!(name.as_str(), None);
+    assert_eq!(name.as_bytes(), b"caf\xe9");
+    assert_eq!(name.to_string(), "caf\u{fffd}");
+}
@@ -1,9 +1,34 @@
-//! Stuff for parsing mtree files.
-use crate::{
-    util::{from_oct_ch, parse_time, Array48, Array64, FromDec, FromHex},
-    Device,
-};
-use std::{fmt, time::Duration};
+//! The line and keyword parser that [`MTree`](crate::MTree) is built on.
+//!
+//! This works on one line or word at a time and keeps no state between lines, so `/set`
+//! defaults and relative paths are left to the caller. Results borrow from the input where
+//! they can. It doesn't need the `std` feature.
+//!
+//! ```
+//! use mtree::parser::{Keyword, MTreeLine, Words};
+//!
+//! let line = br"./usr/bin/a\040b type=file size=10";
+//! match MTreeLine::from_bytes(line, None).unwrap() {
+//!     MTreeLine::Full(path, keywords) => {
+//!         assert_eq!(&*path, b"./usr/bin/a b");
+//!         assert_eq!(keywords[1], Keyword::Size(10));
+//!     }
+//!     other => panic!("unexpected line {:?}", other),
+//! }
+//!
+//! // or a word at a time
+//! let word = Words::new(line).nth(1).unwrap();
+//! let keyword = Keyword::from_bytes(word, None).unwrap();
+//! assert_eq!(keyword.to_string(), "type=file");
+//! ```
+// Some of this is only used by the parts of the crate that need std, or unix.
+#![cfg_attr(not(all(feature = "std", unix)), allow(dead_code))]
+use crate::util::{fits_system_time, from_oct_ch, parse_number, parse_time, FromDec, FromHex};
+pub use crate::util::{unvis, vis, Words};
+use alloc::borrow::{Cow, ToOwned};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{fmt, str::FromStr, time::Duration};
 
 /// An mtree file is a sequence of lines, each a semantic unit.
 #[derive(Debug)]
@@ -12,23 +37,45 @@ pub enum MTreeLine<'a> {
     Blank,
     /// Lines starting with a '#' are ignored.
     Comment(&'a [u8]),
-    /// Special commands (starting with '/') alter the behavior of later entries.
-    Special(SpecialKind, Vec<Keyword<'a>>),
+    /// `/set` sets defaults for the keywords of later entries.
+    Set(Vec<Keyword<'a>>),
+    /// `/unset` removes defaults for the named keywords of later entries.
+    Unset(KeywordSet),
     /// If the first word does not contain a '/', it is a file in the current
     /// directory.
-    Relative(&'a [u8], Vec<Keyword<'a>>),
+    ///
+    /// The path has had any escape sequences decoded.
+    Relative(Cow<'a, [u8]>, Vec<Keyword<'a>>),
     /// Change the current directory to the parent of the current directory.
     DotDot,
     /// If the first word does contain a '/', it is a file relative to the starting
     /// (not current) directory.
-    Full(&'a [u8], Vec<Keyword<'a>>),
+    ///
+    /// The path has had any escape sequences decoded.
+    Full(Cow<'a, [u8]>, Vec<Keyword<'a>>),
 }
 
 impl<'a> MTreeLine<'a> {
-    pub fn from_bytes(input: &'a [u8]) -> ParserResult<MTreeLine<'a>> {
-        let mut parts = input
-            .split(|ch| *ch == b' ')
-            .filter(|word| !word.is_empty());
+    /// Parse a line, failing if any keyword on it is invalid.
+    ///
+    /// Keywords we don't recognise are offered to `handler`, if there is one.
+    pub fn from_bytes(
+        input: &'a [u8],
+        handler: Option<&KeywordHandler>,
+    ) -> ParserResult<MTreeLine<'a>> {
+        MTreeLine::from_bytes_with(input, handler, Err)
+    }
+
+    /// Parse a line, passing any problem with an individual keyword to `on_error`.
+    ///
+    /// Keywords we don't recognise are offered to `handler`, if there is one. If `on_error`
+    /// returns an error then parsing stops, otherwise the keyword is skipped.
+    pub fn from_bytes_with(
+        input: &'a [u8],
+        handler: Option<&KeywordHandler>,
+        mut on_error: impl FnMut(ParserError) -> ParserResult<()>,
+    ) -> ParserResult<MTreeLine<'a>> {
+        let mut parts = Words::new(input);
         // Blank
         let first = match parts.next() {
             Some(f) => f,
@@ -42,33 +89,147 @@ impl<'a> MTreeLine<'a> {
         if first == b".." {
             return Ok(MTreeLine::DotDot);
         }
+        // Unset takes bare keyword names rather than `key=value` pairs
+        if first[0] == b'/' && SpecialKind::from_bytes(&first[1..])? == SpecialKind::Unset {
+            let mut keywords = KeywordSet::empty();
+            for part in parts {
+                match KeywordSet::from_bytes(part) {
+                    Ok(keyword) => keywords |= keyword,
+                    Err(e) => on_error(e)?,
+                }
+            }
+            return Ok(MTreeLine::Unset(keywords));
+        }
         // the rest need params
         let mut params = Vec::new();
         for part in parts {
-            let keyword = Keyword::from_bytes(part);
-            debug_assert!(
-                keyword.is_ok(),
-                "could not parse bytes: {}",
-                String::from_utf8_lossy(part)
-            );
-            if let Ok(keyword) = keyword {
-                params.push(keyword);
+            match Keyword::from_bytes(part, handler) {
+                Ok(keyword) => params.push(keyword),
+                Err(e) => on_error(e)?,
             }
         }
 
-        // Special
+        // Set
         if first[0] == b'/' {
-            let kind = SpecialKind::from_bytes(&first[1..])?;
-            Ok(MTreeLine::Special(kind, params))
+            Ok(MTreeLine::Set(params))
         // Full
         } else if first.contains(&b'/') {
-            Ok(MTreeLine::Full(first, params))
+            Ok(MTreeLine::Full(unvis(first), params))
         } else {
-            Ok(MTreeLine::Relative(first, params))
+            Ok(MTreeLine::Relative(unvis(first), params))
+        }
+    }
+}
+
+#[test]
+fn test_line_borrows() {
+    let input = br"sub link=../a uname=root";
+    match MTreeLine::from_bytes(input, None).unwrap() {
+        MTreeLine::Relative(Cow::Borrowed(path), keywords) => {
+            assert_eq!(path, b"sub");
+            assert!(matches!(
+                &keywords[0],
+                Keyword::Link(Cow::Borrowed(b"../a"))
+            ));
+            assert!(matches!(
+                &keywords[1],
+                Keyword::Uname(Cow::Borrowed(b"root"))
+            ));
+        }
+        other => panic!("unexpected line {:?}", other),
+    }
+    // only escaped values are copied
+    match MTreeLine::from_bytes(br"a\040b", None).unwrap() {
+        MTreeLine::Relative(Cow::Owned(path), _) => assert_eq!(path, b"a b"),
+        other => panic!("unexpected line {:?}", other),
+    }
+}
+
+#[test]
+fn test_line_keyword_errors() {
+    use alloc::vec;
+    let input = b"./a size=1 bogus=1 mode=999 uid=0";
+    assert!(MTreeLine::from_bytes(input, None).is_err());
+    let mut errors = Vec::new();
+    match MTreeLine::from_bytes_with(input, None, |e| {
+        errors.push(e);
+        Ok(())
+    }) {
+        Ok(MTreeLine::Full(_, keywords)) => {
+            assert_eq!(keywords, vec![Keyword::Size(1), Keyword::Uid(0)])
+        }
+        other => panic!("expected full entry, found {:?}", other),
+    }
+    assert_eq!(errors.len(), 2);
+}
+
+/// A function that gives a value to keywords the parser doesn't recognise.
+///
+/// It is passed the keyword and its value (if any), and returns `None` if it doesn't recognise
+/// the keyword either.
+pub type KeywordHandler = dyn Fn(&[u8], Option<&[u8]>) -> Option<ExtensionValue> + Send + Sync;
+
+/// The value of a non-standard keyword, as returned by a [`KeywordHandler`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExtensionValue {
+    /// The keyword is present, e.g. a keyword without a value.
+    Flag,
+    /// A numeric value.
+    Number(u64),
+    /// Any other value, as raw bytes.
+    Bytes(#[cfg_attr(feature = "serde", serde(with = "crate::serialize::byte_string"))] Vec<u8>),
+}
+
+/// The version of the mtree format, as given in the `#mtree` signature on the first line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FormatVersion {
+    /// A bare `#mtree` signature, without a version.
+    Unversioned,
+    /// `#mtree v1.0`
+    V1,
+    /// `#mtree v2.0`, as written by libarchive and NetBSD.
+    V2,
+}
+
+impl FormatVersion {
+    /// Get the version from a signature comment, or `None` if the comment is not a signature.
+    pub fn from_signature(comment: &[u8]) -> Option<FormatVersion> {
+        let mut words = comment
+            .split(|ch| ch.is_ascii_whitespace())
+            .filter(|word| !word.is_empty());
+        if words.next()? != b"#mtree" {
+            return None;
+        }
+        let version = match words.next() {
+            None => FormatVersion::Unversioned,
+            Some(b"v1.0") => FormatVersion::V1,
+            Some(b"v2.0") => FormatVersion::V2,
+            Some(_) => return None,
+        };
+        match words.next() {
+            None => Some(version),
+            Some(_) => None,
         }
     }
 }
 
+#[test]
+fn test_format_version_from_signature() {
+    for (input, res) in [
+        (&b"#mtree"[..], Some(FormatVersion::Unversioned)),
+        (b"#mtree v1.0", Some(FormatVersion::V1)),
+        (b"#mtree v2.0", Some(FormatVersion::V2)),
+        (b"  #mtree  v2.0\r", Some(FormatVersion::V2)),
+        (b"#mtree v3.0", None),
+        (b"#mtree v2.0 and more", None),
+        (b"# mtree v2.0", None),
+        (b"#mtreev2.0", None),
+    ] {
+        assert_eq!(FormatVersion::from_signature(input), res);
+    }
+}
+
 /// A command that alters the behavior of later commands.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum SpecialKind {
@@ -79,15 +240,15 @@ pub enum SpecialKind {
 }
 
 impl SpecialKind {
-    fn from_bytes(input: &[u8]) -> ParserResult<SpecialKind> {
+    /// Parse the name of a command, without the leading `/`, e.g. `set`.
+    pub fn from_bytes(input: &[u8]) -> ParserResult<SpecialKind> {
         Ok(match input {
             b"set" => SpecialKind::Set,
             b"unset" => SpecialKind::Unset,
             _ => {
-                return Err(format!(
-                    r#""{}" is not a special command"#,
-                    String::from_utf8_lossy(input)
-                )
+                return Err(ParserErrorKind::UnknownCommand {
+                    command: input.to_owned(),
+                }
                 .into());
             }
         })
@@ -103,23 +264,23 @@ pub enum Keyword<'a> {
     // guaranteed.
     Checksum(u64),
     /// `device` The device number for *block* or *char* file types.
-    DeviceRef(DeviceRef<'a>),
+    Device(Device),
     /// `contents` The full pathname of a file that holds the contents of this file.
-    Contents(&'a [u8]),
+    Contents(Cow<'a, [u8]>),
     /// `flags` The file flags as a symbolic name.
     ///
     /// I think this is bsd-specific.
-    Flags(&'a [u8]),
+    Flags(FileFlags),
     /// `gid` The file group as a numeric value.
-    Gid(u64),
+    Gid(u32),
     /// `gname` The file group as a symbolic name.
-    Gname(&'a [u8]),
+    Gname(Cow<'a, [u8]>),
     /// `ignore` Ignore any file hierarchy below this line.
     Ignore,
     /// `inode` The inode number.
     Inode(u64),
     /// `link` The target of the symbolic link when type=link.
-    Link(&'a [u8]),
+    Link(Cow<'a, [u8]>),
     /// `md5|md5digest` The MD5 message digest of the file.
     Md5(u128),
     /// `mode` The current file's permissions as a numeric (octal) or symbolic value.
@@ -135,7 +296,7 @@ pub enum Keyword<'a> {
     /// `resdevice` The "resident" device number of the file, e.g. the ID of the
     /// device that contains the file. Its format is the same as the one for
     /// `device`.
-    ResidentDeviceRef(DeviceRef<'a>),
+    ResidentDevice(Device),
     /// `rmd160|rmd160digest|ripemd160digest` The RIPEMD160 message digest of
     /// the file.
     Rmd160([u8; 20]),
@@ -144,137 +305,928 @@ pub enum Keyword<'a> {
     /// `sha256|sha256digest` The FIPS 180-2 ("SHA-256") message digest of the file.
     Sha256([u8; 32]),
     /// `sha384|sha384digest` The FIPS 180-2 ("SHA-384") message digest of the file.
-    Sha384(Array48<u8>),
+    Sha384([u8; 48]),
     /// `sha512|sha512digest` The FIPS 180-2 ("SHA-512") message digest of the file.
-    Sha512(Array64<u8>),
+    Sha512([u8; 64]),
     /// `size` The size, in bytes, of the file.
     Size(u64),
     /// `time` The last modification time of the file, as a duration since the unix epoch.
     // The last modification time of the file, in seconds and nanoseconds. The value should
-    // include a period character and exactly nine digits after the period.
+    // include a period character and exactly nine digits after the period, but we also accept
+    // integer seconds and other fraction lengths.
     Time(Duration),
     /// `type` The type of the file.
     Type(FileType),
     /// The file owner as a numeric value.
-    Uid(u64),
+    Uid(u32),
     /// The file owner as a symbolic name.
-    Uname(&'a [u8]),
+    Uname(Cow<'a, [u8]>),
+    /// An extended attribute, written as `xattr.<name>=<value>`.
+    ///
+    /// The value is kept as written (go-mtree base64-encodes it).
+    Xattr(&'a [u8], &'a [u8]),
+    /// A keyword we don't know about, given a value by a [`KeywordHandler`].
+    Extension(&'a [u8], ExtensionValue),
 }
 impl<'a> Keyword<'a> {
     /// Parse a keyword with optional value.
-    fn from_bytes(input: &'a [u8]) -> ParserResult<Keyword<'a>> {
-        fn next<'a>(field: &'static str, val: Option<&'a [u8]>) -> ParserResult<&'a [u8]> {
-            val.ok_or_else(|| format!(r#""{}" requires a parameter, none found"#, field).into())
+    ///
+    /// Keywords we don't recognise are passed to `handler`, and are an error if it declines them.
+    pub fn from_bytes(
+        input: &'a [u8],
+        handler: Option<&KeywordHandler>,
+    ) -> ParserResult<Keyword<'a>> {
+        fn digest<T: FromHex>(keyword: &[u8], value: &[u8]) -> ParserResult<T> {
+            T::from_hex(value).ok_or_else(|| {
+                ParserErrorKind::InvalidDigest {
+                    keyword: keyword.to_owned(),
+                    value: value.to_owned(),
+                }
+                .into()
+            })
         }
-        let mut iter = input.splitn(2, |ch| *ch == b'=');
-        let key = iter.next().unwrap(); // cannot fail
+        fn device(keyword: &[u8], value: &[u8]) -> ParserResult<Device> {
+            Device::from_bytes(value).ok_or_else(|| {
+                ParserErrorKind::InvalidDevice {
+                    keyword: keyword.to_owned(),
+                    value: value.to_owned(),
+                }
+                .into()
+            })
+        }
+        let (key, raw_value) = match memchr::memchr(b'=', input) {
+            Some(i) => (&input[..i], Some(&input[i + 1..])),
+            None => (input, None),
+        };
+        let value = || -> ParserResult<&'a [u8]> {
+            raw_value.ok_or_else(|| {
+                ParserErrorKind::MissingValue {
+                    keyword: key.to_owned(),
+                }
+                .into()
+            })
+        };
+        let number = || -> ParserResult<u64> {
+            let value = value()?;
+            u64::from_dec(value).ok_or_else(|| {
+                ParserErrorKind::InvalidNumber {
+                    keyword: key.to_owned(),
+                    value: value.to_owned(),
+                }
+                .into()
+            })
+        };
+        let id = || -> ParserResult<u32> {
+            let value = value()?;
+            u32::from_dec(value).ok_or_else(|| {
+                ParserErrorKind::InvalidNumber {
+                    keyword: key.to_owned(),
+                    value: value.to_owned(),
+                }
+                .into()
+            })
+        };
         Ok(match key {
-            b"cksum" => Keyword::Checksum(u64::from_dec(next("cksum", iter.next())?)?),
-            b"device" => Keyword::DeviceRef(DeviceRef::from_bytes(next("devices", iter.next())?)?),
-            b"contents" => Keyword::Contents(next("contents", iter.next())?),
-            b"flags" => Keyword::Flags(next("flags", iter.next())?),
-            b"gid" => Keyword::Gid(u64::from_dec(next("gid", iter.next())?)?),
-            b"gname" => Keyword::Gname(next("gname", iter.next())?),
+            b"cksum" => Keyword::Checksum(number()?),
+            b"device" => Keyword::Device(device(key, value()?)?),
+            b"contents" => Keyword::Contents(unvis(value()?)),
+            b"flags" => Keyword::Flags(FileFlags::from_bytes(value()?)?),
+            b"gid" => Keyword::Gid(id()?),
+            b"gname" => Keyword::Gname(unvis(value()?)),
             b"ignore" => Keyword::Ignore,
-            b"inode" => Keyword::Inode(u64::from_dec(next("inode", iter.next())?)?),
-            b"link" => Keyword::Link(next("link", iter.next())?),
-            b"md5" | b"md5digest" => {
-                Keyword::Md5(u128::from_hex(next("md5|md5digest", iter.next())?)?)
-            }
-            b"mode" => Keyword::Mode(FileMode::from_bytes(next("mode", iter.next())?)?),
-            b"nlink" => Keyword::NLink(u64::from_dec(next("nlink", iter.next())?)?),
+            b"inode" => Keyword::Inode(number()?),
+            b"link" => Keyword::Link(unvis(value()?)),
+            b"md5" | b"md5digest" => Keyword::Md5(digest(key, value()?)?),
+            b"mode" => Keyword::Mode(FileMode::from_bytes(value()?)?),
+            b"nlink" => Keyword::NLink(number()?),
             b"nochange" => Keyword::NoChange,
             b"optional" => Keyword::Optional,
-            b"resdevice" => {
-                Keyword::ResidentDeviceRef(DeviceRef::from_bytes(next("resdevice", iter.next())?)?)
+            b"resdevice" => Keyword::ResidentDevice(device(key, value()?)?),
+            b"rmd160" | b"rmd160digest" | b"ripemd160digest" => {
+                Keyword::Rmd160(digest(key, value()?)?)
+            }
+            b"sha1" | b"sha1digest" => Keyword::Sha1(digest(key, value()?)?),
+            b"sha256" | b"sha256digest" => Keyword::Sha256(digest(key, value()?)?),
+            b"sha384" | b"sha384digest" => Keyword::Sha384(digest(key, value()?)?),
+            b"sha512" | b"sha512digest" => Keyword::Sha512(digest(key, value()?)?),
+            b"size" => Keyword::Size(number()?),
+            b"time" => {
+                let value = value()?;
+                Keyword::Time(
+                    parse_time(value)
+                        .filter(|time| fits_system_time(*time))
+                        .ok_or_else(|| ParserErrorKind::InvalidTime {
+                            value: value.to_owned(),
+                        })?,
+                )
+            }
+            b"type" => Keyword::Type(FileType::from_bytes(value()?)?),
+            b"uid" => Keyword::Uid(id()?),
+            b"uname" => Keyword::Uname(unvis(value()?)),
+            other if other.starts_with(b"xattr.") && other.len() > 6 => {
+                Keyword::Xattr(&other[6..], value()?)
             }
-            b"rmd160" | b"rmd160digest" | b"ripemd160digest" => Keyword::Rmd160(
-                <[u8; 20]>::from_hex(next("rmd160|rmd160digest|ripemd160digest", iter.next())?)?,
+            other => match handler.and_then(|handler| handler(other, raw_value)) {
+                Some(extension) => Keyword::Extension(other, extension),
+                None => {
+                    return Err(ParserErrorKind::UnknownKeyword {
+                        keyword: other.to_owned(),
+                    }
+                    .into());
+                }
+            },
+        })
+    }
+}
+
+#[test]
+fn test_keyword_errors() {
+    for (input, kind) in [
+        (
+            &b"bogus=1"[..],
+            ParserErrorKind::UnknownKeyword {
+                keyword: b"bogus".to_vec(),
+            },
+        ),
+        (
+            b"size",
+            ParserErrorKind::MissingValue {
+                keyword: b"size".to_vec(),
+            },
+        ),
+        (
+            b"size=12a",
+            ParserErrorKind::InvalidNumber {
+                keyword: b"size".to_vec(),
+                value: b"12a".to_vec(),
+            },
+        ),
+        (
+            b"uid=4294967296",
+            ParserErrorKind::InvalidNumber {
+                keyword: b"uid".to_vec(),
+                value: b"4294967296".to_vec(),
+            },
+        ),
+        (
+            b"gid=-1",
+            ParserErrorKind::InvalidNumber {
+                keyword: b"gid".to_vec(),
+                value: b"-1".to_vec(),
+            },
+        ),
+        (
+            b"sha256digest=abc",
+            ParserErrorKind::InvalidDigest {
+                keyword: b"sha256digest".to_vec(),
+                value: b"abc".to_vec(),
+            },
+        ),
+        (
+            b"mode=999",
+            ParserErrorKind::InvalidMode {
+                value: b"999".to_vec(),
+            },
+        ),
+        (
+            b"time=x",
+            ParserErrorKind::InvalidTime {
+                value: b"x".to_vec(),
+            },
+        ),
+        (
+            b"type=thing",
+            ParserErrorKind::InvalidFileType {
+                value: b"thing".to_vec(),
+            },
+        ),
+        (
+            b"resdevice=bogus,1,2",
+            ParserErrorKind::InvalidDevice {
+                keyword: b"resdevice".to_vec(),
+                value: b"bogus,1,2".to_vec(),
+            },
+        ),
+    ] {
+        assert_eq!(Keyword::from_bytes(input, None).unwrap_err().kind(), &kind);
+    }
+    assert_eq!(
+        Keyword::from_bytes(b"uid=4294967295", None).unwrap(),
+        Keyword::Uid(u32::MAX)
+    );
+    assert_eq!(
+        Keyword::from_bytes(b"device=2049", None).unwrap(),
+        Keyword::Device(Device::from_dev_t(2049, Format::Native).unwrap())
+    );
+}
+
+impl Keyword<'_> {
+    /// The name of this keyword, as a set containing just that keyword.
+    pub fn kind(&self) -> KeywordSet {
+        match self {
+            Keyword::Checksum(_) => KeywordSet::CHECKSUM,
+            Keyword::Device(_) => KeywordSet::DEVICE,
+            Keyword::Contents(_) => KeywordSet::CONTENTS,
+            Keyword::Flags(_) => KeywordSet::FLAGS,
+            Keyword::Gid(_) => KeywordSet::GID,
+            Keyword::Gname(_) => KeywordSet::GNAME,
+            Keyword::Ignore => KeywordSet::IGNORE,
+            Keyword::Inode(_) => KeywordSet::INODE,
+            Keyword::Link(_) => KeywordSet::LINK,
+            Keyword::Md5(_) => KeywordSet::MD5,
+            Keyword::Mode(_) => KeywordSet::MODE,
+            Keyword::NLink(_) => KeywordSet::NLINK,
+            Keyword::NoChange => KeywordSet::NO_CHANGE,
+            Keyword::Optional => KeywordSet::OPTIONAL,
+            Keyword::ResidentDevice(_) => KeywordSet::RESIDENT_DEVICE,
+            Keyword::Rmd160(_) => KeywordSet::RMD160,
+            Keyword::Sha1(_) => KeywordSet::SHA1,
+            Keyword::Sha256(_) => KeywordSet::SHA256,
+            Keyword::Sha384(_) => KeywordSet::SHA384,
+            Keyword::Sha512(_) => KeywordSet::SHA512,
+            Keyword::Size(_) => KeywordSet::SIZE,
+            Keyword::Time(_) => KeywordSet::TIME,
+            Keyword::Type(_) => KeywordSet::TYPE,
+            Keyword::Uid(_) => KeywordSet::UID,
+            Keyword::Uname(_) => KeywordSet::UNAME,
+            Keyword::Xattr(_, _) => KeywordSet::XATTR,
+            Keyword::Extension(_, _) => KeywordSet::EXTENSIONS,
+        }
+    }
+}
+
+/// Writes the keyword as it appears in an mtree file, e.g. `size=1024`.
+impl fmt::Display for Keyword<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn hex(f: &mut fmt::Formatter, name: &str, digest: &[u8]) -> fmt::Result {
+            write!(f, "{}=", name)?;
+            for byte in digest {
+                write!(f, "{:02x}", byte)?;
+            }
+            Ok(())
+        }
+        fn bytes(f: &mut fmt::Formatter, name: &str, value: &[u8]) -> fmt::Result {
+            write!(f, "{}={}", name, String::from_utf8_lossy(&vis(value)))
+        }
+        match self {
+            Keyword::Checksum(v) => write!(f, "cksum={}", v),
+            Keyword::Device(v) => write!(f, "device={}", v),
+            Keyword::Contents(v) => bytes(f, "contents", v),
+            Keyword::Flags(v) => write!(f, "flags={}", v),
+            Keyword::Gid(v) => write!(f, "gid={}", v),
+            Keyword::Gname(v) => bytes(f, "gname", v),
+            Keyword::Ignore => f.write_str("ignore"),
+            Keyword::Inode(v) => write!(f, "inode={}", v),
+            Keyword::Link(v) => bytes(f, "link", v),
+            Keyword::Md5(v) => write!(f, "md5digest={:032x}", v),
+            Keyword::Mode(v) => write!(f, "mode={:o}", v),
+            Keyword::NLink(v) => write!(f, "nlink={}", v),
+            Keyword::NoChange => f.write_str("nochange"),
+            Keyword::Optional => f.write_str("optional"),
+            Keyword::ResidentDevice(v) => write!(f, "resdevice={}", v),
+            Keyword::Rmd160(v) => hex(f, "rmd160digest", v),
+            Keyword::Sha1(v) => hex(f, "sha1digest", v),
+            Keyword::Sha256(v) => hex(f, "sha256digest", v),
+            Keyword::Sha384(v) => hex(f, "sha384digest", v),
+            Keyword::Sha512(v) => hex(f, "sha512digest", v),
+            Keyword::Size(v) => write!(f, "size={}", v),
+            Keyword::Time(v) => write!(f, "time={}.{:09}", v.as_secs(), v.subsec_nanos()),
+            Keyword::Type(v) => write!(f, "type={}", v),
+            Keyword::Uid(v) => write!(f, "uid={}", v),
+            Keyword::Uname(v) => bytes(f, "uname", v),
+            Keyword::Xattr(name, value) => write!(
+                f,
+                "xattr.{}={}",
+                String::from_utf8_lossy(&vis(name)),
+                String::from_utf8_lossy(&vis(value))
             ),
-            b"sha1" | b"sha1digest" => {
-                Keyword::Sha1(<[u8; 20]>::from_hex(next("sha1|sha1digest", iter.next())?)?)
+            Keyword::Extension(name, value) => {
+                let name = String::from_utf8_lossy(name);
+                match value {
+                    ExtensionValue::Flag => write!(f, "{}", name),
+                    ExtensionValue::Number(v) => write!(f, "{}={}", name, v),
+                    ExtensionValue::Bytes(v) => bytes(f, &name, v),
+                }
             }
-            b"sha256" | b"sha256digest" => Keyword::Sha256(<[u8; 32]>::from_hex(next(
-                "sha256|sha256digest",
-                iter.next(),
-            )?)?),
-            b"sha384" | b"sha384digest" => Keyword::Sha384(<Array48<u8>>::from_hex(next(
-                "sha384|sha384digest",
-                iter.next(),
-            )?)?),
-            b"sha512" | b"sha512digest" => Keyword::Sha512(<Array64<u8>>::from_hex(next(
-                "sha512|sha512digest",
-                iter.next(),
-            )?)?),
-            b"size" => Keyword::Size(u64::from_dec(next("size", iter.next())?)?),
-            b"time" => Keyword::Time(parse_time(next("time", iter.next())?)?),
-            b"type" => Keyword::Type(FileType::from_bytes(next("type", iter.next())?)?),
-            b"uid" => Keyword::Uid(u64::from_dec(next("uid", iter.next())?)?),
-            b"uname" => Keyword::Uname(next("uname", iter.next())?),
+        }
+    }
+}
+
+#[test]
+fn test_keyword_display() {
+    use alloc::string::ToString;
+    for input in [
+        &b"cksum=1234"[..],
+        b"device=linux,8,1",
+        b"resdevice=bsdos,1,2,3",
+        br"contents=a\040b",
+        b"flags=nodump,uchg",
+        b"ignore",
+        b"md5digest=13c0a46c2fb9f18a1a237d4904b6916e",
+        b"mode=4755",
+        b"nochange",
+        b"sha1digest=0102030405060708090a0b0c0d0e0f1011121314",
+        b"time=1523250074.300237174",
+        b"type=dir",
+        br"uname=r\303\266t",
+        b"xattr.user.foo=YmFy",
+    ] {
+        let keyword = Keyword::from_bytes(input, None).unwrap();
+        assert_eq!(keyword.to_string().as_bytes(), input);
+    }
+}
+
+bitflags::bitflags! {
+    /// A set of keyword names, without values.
+    ///
+    /// This is what `/unset` takes as arguments.
+    pub struct KeywordSet: u32 {
+        /// `cksum`
+        const CHECKSUM = 1 << 0;
+        /// `device`
+        const DEVICE = 1 << 1;
+        /// `contents`
+        const CONTENTS = 1 << 2;
+        /// `flags`
+        const FLAGS = 1 << 3;
+        /// `gid`
+        const GID = 1 << 4;
+        /// `gname`
+        const GNAME = 1 << 5;
+        /// `ignore`
+        const IGNORE = 1 << 6;
+        /// `inode`
+        const INODE = 1 << 7;
+        /// `link`
+        const LINK = 1 << 8;
+        /// `md5|md5digest`
+        const MD5 = 1 << 9;
+        /// `mode`
+        const MODE = 1 << 10;
+        /// `nlink`
+        const NLINK = 1 << 11;
+        /// `nochange`
+        const NO_CHANGE = 1 << 12;
+        /// `optional`
+        const OPTIONAL = 1 << 13;
+        /// `resdevice`
+        const RESIDENT_DEVICE = 1 << 14;
+        /// `rmd160|rmd160digest|ripemd160digest`
+        const RMD160 = 1 << 15;
+        /// `sha1|sha1digest`
+        const SHA1 = 1 << 16;
+        /// `sha256|sha256digest`
+        const SHA256 = 1 << 17;
+        /// `sha384|sha384digest`
+        const SHA384 = 1 << 18;
+        /// `sha512|sha512digest`
+        const SHA512 = 1 << 19;
+        /// `size`
+        const SIZE = 1 << 20;
+        /// `time`
+        const TIME = 1 << 21;
+        /// `type`
+        const TYPE = 1 << 22;
+        /// `uid`
+        const UID = 1 << 23;
+        /// `uname`
+        const UNAME = 1 << 24;
+        /// All `xattr.*` keywords.
+        const XATTR = 1 << 25;
+        /// All keywords parsed by a [`KeywordHandler`]. These can only be unset by `all`.
+        const EXTENSIONS = 1 << 26;
+    }
+}
+
+impl KeywordSet {
+    /// The canonical name for each keyword, in the order they are displayed.
+    const NAMES: &'static [(KeywordSet, &'static str)] = &[
+        (KeywordSet::CHECKSUM, "cksum"),
+        (KeywordSet::DEVICE, "device"),
+        (KeywordSet::CONTENTS, "contents"),
+        (KeywordSet::FLAGS, "flags"),
+        (KeywordSet::GID, "gid"),
+        (KeywordSet::GNAME, "gname"),
+        (KeywordSet::IGNORE, "ignore"),
+        (KeywordSet::INODE, "inode"),
+        (KeywordSet::LINK, "link"),
+        (KeywordSet::MD5, "md5digest"),
+        (KeywordSet::MODE, "mode"),
+        (KeywordSet::NLINK, "nlink"),
+        (KeywordSet::NO_CHANGE, "nochange"),
+        (KeywordSet::OPTIONAL, "optional"),
+        (KeywordSet::RESIDENT_DEVICE, "resdevice"),
+        (KeywordSet::RMD160, "rmd160digest"),
+        (KeywordSet::SHA1, "sha1digest"),
+        (KeywordSet::SHA256, "sha256digest"),
+        (KeywordSet::SHA384, "sha384digest"),
+        (KeywordSet::SHA512, "sha512digest"),
+        (KeywordSet::SIZE, "size"),
+        (KeywordSet::TIME, "time"),
+        (KeywordSet::TYPE, "type"),
+        (KeywordSet::UID, "uid"),
+        (KeywordSet::UNAME, "uname"),
+        (KeywordSet::XATTR, "xattr"),
+    ];
+
+    /// Parse a single keyword name (or `all`).
+    pub fn from_bytes(input: &[u8]) -> ParserResult<KeywordSet> {
+        Ok(match input {
+            b"all" => KeywordSet::all(),
+            b"cksum" => KeywordSet::CHECKSUM,
+            b"device" => KeywordSet::DEVICE,
+            b"contents" => KeywordSet::CONTENTS,
+            b"flags" => KeywordSet::FLAGS,
+            b"gid" => KeywordSet::GID,
+            b"gname" => KeywordSet::GNAME,
+            b"ignore" => KeywordSet::IGNORE,
+            b"inode" => KeywordSet::INODE,
+            b"link" => KeywordSet::LINK,
+            b"md5" | b"md5digest" => KeywordSet::MD5,
+            b"mode" => KeywordSet::MODE,
+            b"nlink" => KeywordSet::NLINK,
+            b"nochange" => KeywordSet::NO_CHANGE,
+            b"optional" => KeywordSet::OPTIONAL,
+            b"resdevice" => KeywordSet::RESIDENT_DEVICE,
+            b"rmd160" | b"rmd160digest" | b"ripemd160digest" => KeywordSet::RMD160,
+            b"sha1" | b"sha1digest" => KeywordSet::SHA1,
+            b"sha256" | b"sha256digest" => KeywordSet::SHA256,
+            b"sha384" | b"sha384digest" => KeywordSet::SHA384,
+            b"sha512" | b"sha512digest" => KeywordSet::SHA512,
+            b"size" => KeywordSet::SIZE,
+            b"time" => KeywordSet::TIME,
+            b"type" => KeywordSet::TYPE,
+            b"uid" => KeywordSet::UID,
+            b"uname" => KeywordSet::UNAME,
+            b"xattr" => KeywordSet::XATTR,
             other => {
-                return Err(format!(
-                    r#""{}" is not a valid parameter key (in "{}")"#,
-                    String::from_utf8_lossy(other),
-                    String::from_utf8_lossy(input)
-                )
+                return Err(ParserErrorKind::UnknownKeyword {
+                    keyword: other.to_owned(),
+                }
                 .into());
             }
         })
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
-pub struct DeviceRef<'a> {
-    /// The device format
-    format: Format,
-    /// The device major identifier
-    major: &'a [u8],
-    /// The device minor identifier
-    minor: &'a [u8],
+/// Writes the keyword names separated by spaces, as `/unset` takes them.
+impl fmt::Display for KeywordSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_all() {
+            return f.write_str("all");
+        }
+        let mut names = KeywordSet::NAMES
+            .iter()
+            .filter(|(keyword, _)| self.contains(*keyword))
+            .map(|(_, name)| name);
+        if let Some(name) = names.next() {
+            f.write_str(name)?;
+        }
+        for name in names {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses keyword names separated by commas or spaces, as `mtree -k` takes them (e.g.
+/// `type,size,sha256digest`).
+impl FromStr for KeywordSet {
+    type Err = ParserError;
+
+    fn from_str(s: &str) -> Result<KeywordSet, ParserError> {
+        s.split(|ch: char| ch == ',' || ch.is_ascii_whitespace())
+            .filter(|name| !name.is_empty())
+            .try_fold(KeywordSet::empty(), |keywords, name| {
+                Ok(keywords | KeywordSet::from_bytes(name.as_bytes())?)
+            })
+    }
+}
+
+#[test]
+fn test_unset_from_bytes() {
+    use alloc::string::ToString;
+    match MTreeLine::from_bytes(b"/unset uid md5digest gname", None).unwrap() {
+        MTreeLine::Unset(keywords) => assert_eq!(
+            keywords,
+            KeywordSet::UID | KeywordSet::MD5 | KeywordSet::GNAME
+        ),
+        other => panic!("expected unset, found {:?}", other),
+    }
+    assert_eq!(
+        (KeywordSet::UID | KeywordSet::MD5 | KeywordSet::GNAME).to_string(),
+        "gname md5digest uid"
+    );
+    match MTreeLine::from_bytes(b"/unset all", None).unwrap() {
+        MTreeLine::Unset(keywords) => assert_eq!(keywords, KeywordSet::all()),
+        other => panic!("expected unset, found {:?}", other),
+    }
+    assert!(MTreeLine::from_bytes(b"/unset uid=0", None).is_err());
+    assert_eq!(
+        "type,size sha256digest".parse::<KeywordSet>().unwrap(),
+        KeywordSet::TYPE | KeywordSet::SIZE | KeywordSet::SHA256
+    );
+    assert!("type,bogus".parse::<KeywordSet>().is_err());
+}
+
+/// A unix device.
+///
+/// The numbers are format independent: `format` says how they are packed into a `dev_t`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Device {
+    /// The device format.
+    pub format: Format,
+    /// The device major identifier.
+    pub major: u32,
+    /// The device minor identifier.
+    pub minor: u32,
     /// The device subunit identifier, if applicable.
-    subunit: Option<&'a [u8]>,
-}
-
-impl<'a> DeviceRef<'a> {
-    /// Take ownership of the underlying data by copying
-    pub fn to_device(&self) -> Device {
-        Device {
-            format: self.format,
-            major: self.major.to_owned(),
-            minor: self.minor.to_owned(),
-            subunit: self.subunit.map(|val| val.to_owned()),
-        }
-    }
-
-    fn from_bytes(input: &'a [u8]) -> ParserResult<DeviceRef<'a>> {
-        let mut iter = input.splitn(4, |ch| *ch == b',');
-        let format = Format::from_bytes(iter.next().ok_or_else(|| {
-            format!(
-                r#"could not read format from device "{}""#,
-                String::from_utf8_lossy(input)
-            )
-        })?)?;
-        let major = iter.next().ok_or_else(|| {
-            format!(
-                r#"could not read major field from device "{}""#,
-                String::from_utf8_lossy(input)
-            )
-        })?;
-        let minor = iter.next().ok_or_else(|| {
-            format!(
-                r#"could not read minor field from device "{}""#,
-                String::from_utf8_lossy(input)
-            )
-        })?;
-        // optional, so no '?'
-        let subunit = iter.next();
-        Ok(DeviceRef {
+    pub subunit: Option<u32>,
+}
+
+impl Device {
+    /// Parse a device of the form `format,major,minor[,subunit]`, or a bare number.
+    ///
+    /// The numbers can be decimal, hex (with a `0x` prefix), or octal (with a leading `0`). A
+    /// bare number is a raw `dev_t`, which is split with [`Format::Native`].
+    pub fn from_bytes(input: &[u8]) -> Option<Device> {
+        if !input.contains(&b',') {
+            return Device::from_dev_t(u64::from(parse_number(input)?), Format::Native);
+        }
+        let mut iter = input.split(|ch| *ch == b',');
+        let format = Format::from_bytes(iter.next()?)?;
+        let major = parse_number(iter.next()?)?;
+        let minor = parse_number(iter.next()?)?;
+        // optional, so no '?' on the `next`
+        let subunit = match iter.next() {
+            Some(subunit) => Some(parse_number(subunit)?),
+            None => None,
+        };
+        if iter.next().is_some() {
+            return None;
+        }
+        Some(Device {
             format,
             major,
             minor,
             subunit,
         })
     }
+
+    /// Pack the device numbers into a `dev_t`, using the layout of `format`.
+    ///
+    /// Use [`Format::Native`] to get a value that can be compared with
+    /// `std::os::unix::fs::MetadataExt::rdev`. Returns `None` if the numbers don't fit in the
+    /// fields of the format, or if there is a subunit and the format doesn't have one.
+    pub fn to_dev_t(&self, format: Format) -> Option<u64> {
+        /// Pack a `major_bits` major number above a `minor_bits` minor number.
+        fn pack(major: u32, minor: u32, major_bits: u32, minor_bits: u32) -> Option<u64> {
+            let (major, minor) = (u64::from(major), u64::from(minor));
+            if major >> major_bits != 0 || minor >> minor_bits != 0 {
+                return None;
+            }
+            Some(major << minor_bits | minor)
+        }
+        let (major, minor) = (self.major, self.minor);
+        match (format, self.subunit) {
+            (Format::BsdOs, Some(subunit)) => {
+                if major >> 12 != 0 || minor >> 12 != 0 || subunit >> 8 != 0 {
+                    return None;
+                }
+                Some(u64::from(major) << 20 | u64::from(minor) << 8 | u64::from(subunit))
+            }
+            (_, Some(_)) => None,
+            (Format::Native, None) => native_dev_t(major, minor),
+            (Format::Bsd386, None)
+            | (Format::Bsd4, None)
+            | (Format::Isc, None)
+            | (Format::Linux, None)
+            | (Format::Sco, None)
+            | (Format::SunOs, None)
+            | (Format::Svr3, None)
+            | (Format::Ultrix, None) => pack(major, minor, 8, 8),
+            (Format::BsdOs, None) | (Format::Osf1, None) => pack(major, minor, 12, 20),
+            (Format::Solaris, None) | (Format::Svr4, None) => pack(major, minor, 14, 18),
+            (Format::Hpux, None) => pack(major, minor, 8, 24),
+            (Format::FreeBsd, None) => {
+                if major >> 8 != 0 || minor & !0xffff_00ff != 0 {
+                    return None;
+                }
+                Some(u64::from(major << 8 | minor))
+            }
+            (Format::NetBsd, None) => netbsd_dev_t(major, minor),
+        }
+    }
+
+    /// Split a `dev_t` into its numbers, using the layout of `format`. This is the reverse of
+    /// [`Device::to_dev_t`].
+    ///
+    /// Use [`Format::Native`] for a value from `std::os::unix::fs::MetadataExt::rdev`. Returns
+    /// `None` if `dev` has bits set outside the fields of the format. A `bsdos` value is split
+    /// into a major and minor number, without a subunit.
+    ///
+    /// ```
+    /// use mtree::{Device, Format};
+    ///
+    /// let device = Device::from_dev_t(0x0801, Format::Linux).unwrap();
+    /// assert_eq!((device.major, device.minor), (8, 1));
+    /// assert_eq!(device.to_dev_t(Format::Linux), Some(0x0801));
+    /// ```
+    pub fn from_dev_t(dev: u64, format: Format) -> Option<Device> {
+        /// Split a `major_bits` major number above a `minor_bits` minor number.
+        fn unpack(dev: u64, major_bits: u32, minor_bits: u32) -> Option<(u32, u32)> {
+            if dev >> (major_bits + minor_bits) != 0 {
+                return None;
+            }
+            Some((
+                (dev >> minor_bits) as u32,
+                (dev & ((1 << minor_bits) - 1)) as u32,
+            ))
+        }
+        let (major, minor) = match format {
+            Format::Native => native_major_minor(dev),
+            Format::Bsd386
+            | Format::Bsd4
+            | Format::Isc
+            | Format::Linux
+            | Format::Sco
+            | Format::SunOs
+            | Format::Svr3
+            | Format::Ultrix => unpack(dev, 8, 8)?,
+            Format::BsdOs | Format::Osf1 => unpack(dev, 12, 20)?,
+            Format::Solaris | Format::Svr4 => unpack(dev, 14, 18)?,
+            Format::Hpux => unpack(dev, 8, 24)?,
+            Format::FreeBsd => {
+                if dev >> 32 != 0 {
+                    return None;
+                }
+                ((dev >> 8 & 0xff) as u32, (dev & 0xffff_00ff) as u32)
+            }
+            Format::NetBsd => {
+                if dev >> 32 != 0 {
+                    return None;
+                }
+                (
+                    (dev >> 8 & 0xfff) as u32,
+                    (dev >> 12 & 0xfff00 | dev & 0xff) as u32,
+                )
+            }
+        };
+        Some(Device {
+            format,
+            major,
+            minor,
+            subunit: None,
+        })
+    }
+
+    /// Whether this is the same device as `other`, even if the formats are different.
+    pub(crate) fn same_numbers(&self, other: &Device) -> bool {
+        (self.major, self.minor, self.subunit) == (other.major, other.minor, other.subunit)
+    }
+}
+
+/// Writes the device as it appears in a spec, e.g. `linux,8,1`.
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{}", self.format, self.major, self.minor)?;
+        if let Some(subunit) = self.subunit {
+            write!(f, ",{}", subunit)?;
+        }
+        Ok(())
+    }
+}
+
+/// The NetBSD `dev_t` layout: 12 bits of major, and 20 bits of minor split around it.
+fn netbsd_dev_t(major: u32, minor: u32) -> Option<u64> {
+    if major >> 12 != 0 || minor >> 20 != 0 {
+        return None;
+    }
+    Some(u64::from(
+        major << 8 | (minor & 0xfff00) << 12 | minor & 0xff,
+    ))
+}
+
+/// The `dev_t` layout of the system we are running on.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn native_dev_t(major: u32, minor: u32) -> Option<u64> {
+    if major >> 8 != 0 || minor >> 24 != 0 {
+        return None;
+    }
+    Some(u64::from(major << 24 | minor))
+}
+
+/// The `dev_t` layout of the system we are running on.
+#[cfg(target_os = "freebsd")]
+fn native_dev_t(major: u32, minor: u32) -> Option<u64> {
+    let (major, minor) = (u64::from(major), u64::from(minor));
+    Some(
+        (major & 0xffff_ff00) << 32
+            | (major & 0xff) << 8
+            | (minor & 0xff00) << 24
+            | minor & 0xffff_00ff,
+    )
+}
+
+/// The `dev_t` layout of the system we are running on.
+#[cfg(target_os = "netbsd")]
+fn native_dev_t(major: u32, minor: u32) -> Option<u64> {
+    netbsd_dev_t(major, minor)
+}
+
+/// The `dev_t` layout of the system we are running on.
+#[cfg(target_os = "openbsd")]
+fn native_dev_t(major: u32, minor: u32) -> Option<u64> {
+    if major >> 8 != 0 || minor >> 24 != 0 {
+        return None;
+    }
+    Some(u64::from(
+        (major & 0xff) << 8 | minor & 0xff | (minor & 0xff_ff00) << 8,
+    ))
+}
+
+/// The `dev_t` layout of the system we are running on.
+///
+/// This is the glibc layout, which is also used by musl and bionic.
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn native_dev_t(major: u32, minor: u32) -> Option<u64> {
+    let (major, minor) = (u64::from(major), u64::from(minor));
+    Some(
+        (major & 0xffff_f000) << 32
+            | (major & 0xfff) << 8
+            | (minor & 0xffff_ff00) << 12
+            | minor & 0xff,
+    )
+}
+
+/// Split a `dev_t` of the system we are running on into its major and minor numbers.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn native_major_minor(dev: u64) -> (u32, u32) {
+    ((dev >> 24 & 0xff) as u32, (dev & 0xff_ffff) as u32)
+}
+
+/// Split a `dev_t` of the system we are running on into its major and minor numbers.
+#[cfg(target_os = "freebsd")]
+fn native_major_minor(dev: u64) -> (u32, u32) {
+    (
+        (dev >> 32 & 0xffff_ff00 | dev >> 8 & 0xff) as u32,
+        (dev >> 24 & 0xff00 | dev & 0xffff_00ff) as u32,
+    )
+}
+
+/// Split a `dev_t` of the system we are running on into its major and minor numbers.
+#[cfg(target_os = "netbsd")]
+fn native_major_minor(dev: u64) -> (u32, u32) {
+    (
+        (dev >> 8 & 0xfff) as u32,
+        (dev >> 12 & 0xfff00 | dev & 0xff) as u32,
+    )
+}
+
+/// Split a `dev_t` of the system we are running on into its major and minor numbers.
+#[cfg(target_os = "openbsd")]
+fn native_major_minor(dev: u64) -> (u32, u32) {
+    (
+        (dev >> 8 & 0xff) as u32,
+        (dev >> 8 & 0xff_ff00 | dev & 0xff) as u32,
+    )
+}
+
+/// Split a `dev_t` of the system we are running on into its major and minor numbers.
+///
+/// This is the glibc layout, which is also used by musl and bionic.
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn native_major_minor(dev: u64) -> (u32, u32) {
+    (
+        (dev >> 32 & 0xffff_f000 | dev >> 8 & 0xfff) as u32,
+        (dev >> 12 & 0xffff_ff00 | dev & 0xff) as u32,
+    )
+}
+
+#[test]
+fn test_device_from_bytes() {
+    use alloc::string::ToString;
+    assert_eq!(
+        Device::from_bytes(b"linux,8,1"),
+        Some(Device {
+            format: Format::Linux,
+            major: 8,
+            minor: 1,
+            subunit: None
+        })
+    );
+    assert_eq!(
+        Device::from_bytes(b"bsdos,0x10,010,3"),
+        Some(Device {
+            format: Format::BsdOs,
+            major: 16,
+            minor: 8,
+            subunit: Some(3)
+        })
+    );
+    for input in [
+        &b"linux,8"[..],
+        b"linux,8,x",
+        b"linux,8,1,2,3",
+        b"bogus,8,1",
+        b"08",
+    ] {
+        assert_eq!(Device::from_bytes(input), None);
+    }
+    for (input, display) in [
+        (&b"linux,8,1"[..], "linux,8,1"),
+        (b"bsdos,0x10,010,3", "bsdos,16,8,3"),
+    ] {
+        assert_eq!(Device::from_bytes(input).unwrap().to_string(), display);
+    }
+
+    // a bare number is a native `dev_t`
+    for (input, dev) in [(&b"2049"[..], 2049), (b"0x801", 0x801), (b"0", 0)] {
+        let device = Device::from_bytes(input).unwrap();
+        assert_eq!(Some(device), Device::from_dev_t(dev, Format::Native));
+        assert_eq!(device.to_dev_t(Format::Native), Some(dev));
+    }
+}
+
+#[test]
+fn test_device_to_dev_t() {
+    let device = |major, minor, subunit| Device {
+        format: Format::Native,
+        major,
+        minor,
+        subunit,
+    };
+    assert_eq!(device(8, 1, None).to_dev_t(Format::Linux), Some(0x0801));
+    assert_eq!(device(256, 1, None).to_dev_t(Format::Linux), None);
+    assert_eq!(device(8, 1, None).to_dev_t(Format::Hpux), Some(0x0800_0001));
+    assert_eq!(device(8, 1, None).to_dev_t(Format::Osf1), Some(0x0080_0001));
+    assert_eq!(device(8, 1, None).to_dev_t(Format::Svr4), Some(0x0020_0001));
+    assert_eq!(
+        device(8, 0x12345, None).to_dev_t(Format::NetBsd),
+        Some(0x1230_0845)
+    );
+    assert_eq!(
+        device(8, 0x10000, None).to_dev_t(Format::FreeBsd),
+        Some(0x0001_0800)
+    );
+    assert_eq!(device(8, 0x100, None).to_dev_t(Format::FreeBsd), None);
+    assert_eq!(
+        device(1, 2, Some(3)).to_dev_t(Format::BsdOs),
+        Some(0x0010_0203)
+    );
+    assert_eq!(device(1, 2, Some(3)).to_dev_t(Format::Linux), None);
+    #[cfg(target_os = "linux")]
+    assert_eq!(
+        device(259, 0x12345, None).to_dev_t(Format::Native),
+        Some(0x1231_0345)
+    );
+    #[cfg(target_os = "linux")]
+    assert_eq!(native_major_minor(0x1231_0345), (259, 0x12345));
+}
+
+#[test]
+fn test_device_from_dev_t() {
+    let device = |format, major, minor| Device {
+        format,
+        major,
+        minor,
+        subunit: None,
+    };
+    for device in [
+        device(Format::Linux, 8, 1),
+        device(Format::Hpux, 8, 1),
+        device(Format::Osf1, 8, 1),
+        device(Format::Svr4, 8, 1),
+        device(Format::NetBsd, 8, 0x12345),
+        device(Format::FreeBsd, 8, 0x10000),
+        device(Format::BsdOs, 0xfff, 0xfffff),
+        device(Format::Native, 259, 0x12345),
+    ] {
+        let dev = device.to_dev_t(device.format).unwrap();
+        assert_eq!(Device::from_dev_t(dev, device.format), Some(device));
+    }
+    assert_eq!(Device::from_dev_t(0x1_0000, Format::Linux), None);
+    assert_eq!(Device::from_dev_t(1 << 32, Format::Svr4), None);
+    assert_eq!(Device::from_dev_t(1 << 32, Format::FreeBsd), None);
+    assert_eq!(Device::from_dev_t(1 << 32, Format::NetBsd), None);
+    #[cfg(target_os = "linux")]
+    assert_eq!(
+        Device::from_dev_t(0x1231_0345, Format::Native),
+        Some(device(Format::Native, 259, 0x12345))
+    );
 }
 
 /// The available device formats.
@@ -299,8 +1251,9 @@ pub enum Format {
 }
 
 impl Format {
-    fn from_bytes(bytes: &[u8]) -> ParserResult<Format> {
-        Ok(match bytes {
+    /// Parse the name of a format, as used in a `device` value.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Format> {
+        Some(match bytes {
             b"native" => Format::Native,
             b"386bsd" => Format::Bsd386,
             b"4bsd" => Format::Bsd4,
@@ -317,20 +1270,43 @@ impl Format {
             b"svr3" => Format::Svr3,
             b"svr4" => Format::Svr4,
             b"ultrix" => Format::Ultrix,
-            ref other => {
-                return Err(format!(
-                    r#""{}" is not a valid format"#,
-                    String::from_utf8_lossy(other)
-                )
-                .into());
-            }
+            _ => return None,
         })
     }
+
+    /// The name of the format, as it appears in a `device` value, e.g. `linux`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Native => "native",
+            Format::Bsd386 => "386bsd",
+            Format::Bsd4 => "4bsd",
+            Format::BsdOs => "bsdos",
+            Format::FreeBsd => "freebsd",
+            Format::Hpux => "hpux",
+            Format::Isc => "isc",
+            Format::Linux => "linux",
+            Format::NetBsd => "netbsd",
+            Format::Osf1 => "osf1",
+            Format::Sco => "sco",
+            Format::Solaris => "solaris",
+            Format::SunOs => "sunos",
+            Format::Svr3 => "svr3",
+            Format::Svr4 => "svr4",
+            Format::Ultrix => "ultrix",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[test]
 fn test_format_from_butes() {
-    for (input, res) in vec![
+    use alloc::string::ToString;
+    for (input, res) in [
         (&b"native"[..], Format::Native),
         (&b"386bsd"[..], Format::Bsd386),
         (&b"4bsd"[..], Format::Bsd4),
@@ -348,7 +1324,9 @@ fn test_format_from_butes() {
         (&b"svr4"[..], Format::Svr4),
         (&b"ultrix"[..], Format::Ultrix),
     ] {
-        assert_eq!(Format::from_bytes(&input[..]), Ok(res));
+        assert_eq!(Format::from_bytes(input), Some(res));
+        assert_eq!(res.as_str().as_bytes(), input);
+        assert_eq!(res.to_string().as_bytes(), input);
     }
 }
 
@@ -375,7 +1353,8 @@ pub enum FileType {
 }
 
 impl FileType {
-    fn from_bytes(input: &[u8]) -> ParserResult<FileType> {
+    /// Parse a `type` value, e.g. `file`.
+    pub fn from_bytes(input: &[u8]) -> ParserResult<FileType> {
         Ok(match input {
             b"block" => FileType::BlockDevice,
             b"char" => FileType::CharacterDevice,
@@ -385,16 +1364,16 @@ impl FileType {
             b"link" => FileType::SymbolicLink,
             b"socket" => FileType::Socket,
             _ => {
-                return Err(format!(
-                    r#""{}" is not a valid file type"#,
-                    String::from_utf8_lossy(input)
-                )
+                return Err(ParserErrorKind::InvalidFileType {
+                    value: input.to_owned(),
+                }
                 .into());
             }
         })
     }
 
-    fn as_str(&self) -> &'static str {
+    /// The name of the type, as written in a spec.
+    pub fn as_str(&self) -> &'static str {
         match self {
             FileType::BlockDevice => "block",
             FileType::CharacterDevice => "char",
@@ -415,7 +1394,7 @@ impl fmt::Display for FileType {
 
 #[test]
 fn test_type_from_bytes() {
-    for (input, res) in vec![
+    for (input, res) in [
         (&b"block"[..], FileType::BlockDevice),
         (&b"char"[..], FileType::CharacterDevice),
         (&b"dir"[..], FileType::Directory),
@@ -424,7 +1403,7 @@ fn test_type_from_bytes() {
         (&b"link"[..], FileType::SymbolicLink),
         (&b"socket"[..], FileType::Socket),
     ] {
-        assert_eq!(FileType::from_bytes(&input[..]), Ok(res));
+        assert_eq!(FileType::from_bytes(input), Ok(res));
     }
     assert!(FileType::from_bytes(&b"other"[..]).is_err());
 }
@@ -462,89 +1441,693 @@ impl fmt::Display for Perms {
     }
 }
 
-/// The file/dir permissions for owner/group/everyone else.
+bitflags::bitflags! {
+    /// BSD file flags, as set by chflags(1).
+    ///
+    /// The values match FreeBSD's `st_flags`.
+    pub struct FileFlags: u32 {
+        /// `nodump` Do not dump the file.
+        const NODUMP = 0x0000_0001;
+        /// `uchg` The file may not be changed (user settable).
+        const UCHG = 0x0000_0002;
+        /// `uappnd` The file may only be appended to (user settable).
+        const UAPPND = 0x0000_0004;
+        /// `opaque` The directory is opaque when viewed through a union mount.
+        const OPAQUE = 0x0000_0008;
+        /// `uunlnk` The file may not be removed or renamed (user settable).
+        const UUNLNK = 0x0000_0010;
+        /// `system` The Windows system flag.
+        const SYSTEM = 0x0000_0080;
+        /// `sparse` The Windows sparse flag.
+        const SPARSE = 0x0000_0100;
+        /// `offline` The Windows offline flag.
+        const OFFLINE = 0x0000_0200;
+        /// `reparse` The Windows reparse point flag.
+        const REPARSE = 0x0000_0400;
+        /// `uarch` The file is archived (user settable).
+        const UARCH = 0x0000_0800;
+        /// `rdonly` The Windows readonly flag.
+        const RDONLY = 0x0000_1000;
+        /// `hidden` The file is hidden.
+        const HIDDEN = 0x0000_8000;
+        /// `arch` The file is archived (super-user settable).
+        const ARCH = 0x0001_0000;
+        /// `schg` The file may not be changed (super-user settable).
+        const SCHG = 0x0002_0000;
+        /// `sappnd` The file may only be appended to (super-user settable).
+        const SAPPND = 0x0004_0000;
+        /// `sunlnk` The file may not be removed or renamed (super-user settable).
+        const SUNLNK = 0x0010_0000;
+        /// `snapshot` The file is a snapshot.
+        const SNAPSHOT = 0x0020_0000;
+    }
+}
+
+impl FileFlags {
+    /// The canonical name for each flag, in the order they are displayed.
+    const NAMES: &'static [(FileFlags, &'static str)] = &[
+        (FileFlags::ARCH, "arch"),
+        (FileFlags::NODUMP, "nodump"),
+        (FileFlags::OPAQUE, "opaque"),
+        (FileFlags::SAPPND, "sappnd"),
+        (FileFlags::SCHG, "schg"),
+        (FileFlags::SNAPSHOT, "snapshot"),
+        (FileFlags::SUNLNK, "sunlnk"),
+        (FileFlags::UAPPND, "uappnd"),
+        (FileFlags::UARCH, "uarch"),
+        (FileFlags::UCHG, "uchg"),
+        (FileFlags::HIDDEN, "hidden"),
+        (FileFlags::OFFLINE, "offline"),
+        (FileFlags::RDONLY, "rdonly"),
+        (FileFlags::SPARSE, "sparse"),
+        (FileFlags::SYSTEM, "system"),
+        (FileFlags::REPARSE, "reparse"),
+        (FileFlags::UUNLNK, "uunlnk"),
+    ];
+
+    /// Parse a comma separated list of flag names, or `none`.
+    ///
+    /// The alternative spellings accepted by chflags(1) (e.g. `schange`) are also accepted.
+    pub fn from_bytes(input: &[u8]) -> ParserResult<FileFlags> {
+        let mut flags = FileFlags::empty();
+        for name in input.split(|ch| *ch == b',') {
+            flags |= match name {
+                b"none" => FileFlags::empty(),
+                b"arch" | b"archived" => FileFlags::ARCH,
+                b"nodump" => FileFlags::NODUMP,
+                b"opaque" => FileFlags::OPAQUE,
+                b"sappnd" | b"sappend" => FileFlags::SAPPND,
+                b"schg" | b"schange" | b"simmutable" => FileFlags::SCHG,
+                b"snapshot" => FileFlags::SNAPSHOT,
+                b"sunlnk" | b"sunlink" => FileFlags::SUNLNK,
+                b"uappnd" | b"uappend" => FileFlags::UAPPND,
+                b"uarch" | b"uarchive" => FileFlags::UARCH,
+                b"uchg" | b"uchange" | b"uimmutable" => FileFlags::UCHG,
+                b"hidden" | b"uhidden" => FileFlags::HIDDEN,
+                b"offline" | b"uoffline" => FileFlags::OFFLINE,
+                b"rdonly" | b"urdonly" | b"readonly" => FileFlags::RDONLY,
+                b"sparse" | b"usparse" => FileFlags::SPARSE,
+                b"system" | b"usystem" => FileFlags::SYSTEM,
+                b"reparse" | b"ureparse" => FileFlags::REPARSE,
+                b"uunlnk" | b"uunlink" => FileFlags::UUNLNK,
+                _ => {
+                    return Err(ParserErrorKind::InvalidFlags {
+                        value: input.to_owned(),
+                    }
+                    .into())
+                }
+            };
+        }
+        Ok(flags)
+    }
+}
+
+impl fmt::Display for FileFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+        let mut names = FileFlags::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name);
+        if let Some(name) = names.next() {
+            f.write_str(name)?;
+        }
+        for name in names {
+            write!(f, ",{}", name)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flags_from_bytes() {
+    use alloc::string::ToString;
+    for (input, res, display) in [
+        (&b"none"[..], FileFlags::empty(), "none"),
+        (b"uchg", FileFlags::UCHG, "uchg"),
+        (
+            b"schange,nodump,uappend",
+            FileFlags::SCHG | FileFlags::NODUMP | FileFlags::UAPPND,
+            "nodump,schg,uappnd",
+        ),
+    ] {
+        let flags = FileFlags::from_bytes(input).unwrap();
+        assert_eq!(flags, res);
+        assert_eq!(flags.to_string(), display);
+    }
+    assert!(FileFlags::from_bytes(b"uchg,bogus").is_err());
+    assert!(FileFlags::from_bytes(b"").is_err());
+}
+
+/// The file/dir permissions for owner/group/everyone else, along with the setuid, setgid and
+/// sticky bits.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct FileMode {
-    /// Executable files with this bit set will
-    /// run with effective uid set to the uid of the file owner.
-    pub setuid: bool,
-    /// Executable files with this bit set will
-    /// run with effective gid set to the gid of the file owner.
-    pub setgid: bool,
-    /// The permissions for the owner of the file.
-    pub owner: Perms,
-    /// The permissions for everyone who is not the owner, but in the group.
-    pub group: Perms,
-    /// The permissions for everyone who is not the owner and not in the group.
-    pub other: Perms,
+    /// The numeric mode, e.g. `0o4755`.
+    mode: u32,
 }
 
 impl FileMode {
-    fn from_bytes(input: &[u8]) -> ParserResult<FileMode> {
-        // file mode can either be symbolic, or octal. For now only support octal
+    const SETUID: u32 = 0o4000;
+    const SETGID: u32 = 0o2000;
+    const STICKY: u32 = 0o1000;
+
+    /// The numeric mode, e.g. `0o4755`, which can be passed to `chmod`.
+    ///
+    /// ```
+    /// use mtree::FileMode;
+    ///
+    /// let mode = FileMode::from(0o100644);
+    /// assert_eq!(mode.as_raw(), 0o644);
+    /// ```
+    pub fn as_raw(&self) -> u32 {
+        self.mode
+    }
+
+    /// The mode of a file, from its metadata.
+    ///
+    /// ```no_run
+    /// use mtree::FileMode;
+    ///
+    /// let metadata = std::fs::symlink_metadata("/etc/passwd")?;
+    /// assert_eq!(FileMode::from_metadata(&metadata).to_string(), "rw-r--r--");
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(all(feature = "std", unix))]
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> FileMode {
+        use std::os::unix::fs::MetadataExt;
+        FileMode::from(metadata.mode())
+    }
+
+    /// Parse a `mode` value, in octal or symbolic form.
+    pub fn from_bytes(input: &[u8]) -> ParserResult<FileMode> {
+        // file mode can either be symbolic, or octal.
         #[inline]
-        fn from_bytes_opt(mut input: &[u8]) -> Option<FileMode> {
-            let (setuid, setgid) = if input.len() == 4 {
-                let setid = input[0];
-                input = &input[1..];
-                (setid & 0b100 != 0, setid & 0b010 != 0)
-            } else {
-                (false, false)
+        fn from_octal(input: &[u8]) -> Option<FileMode> {
+            // leading zeros are allowed, as in the `%#o` form FreeBSD writes, e.g. `04755`
+            let digits = match input.iter().position(|ch| *ch != b'0') {
+                Some(start) => &input[start..],
+                None if input.is_empty() => return None,
+                None => &[],
             };
-            if input.len() != 3 {
+            if digits.len() > 4 {
                 return None;
             }
-            let owner = from_oct_ch(input[0])?;
-            let group = from_oct_ch(input[1])?;
-            let other = from_oct_ch(input[2])?;
-            Some(FileMode {
-                setuid,
-                setgid,
-                owner: Perms { bits: owner },
-                group: Perms { bits: group },
-                other: Perms { bits: other },
-            })
+            let mut mode = 0;
+            for ch in digits {
+                mode = mode * 8 + u32::from(from_oct_ch(*ch)?);
+            }
+            (mode <= 0o7777).then_some(FileMode { mode })
         }
-        from_bytes_opt(input).ok_or_else(|| {
-            format!(
-                r#"mode value must be 3 octal chars, found "{}""#,
-                String::from_utf8_lossy(input)
-            )
+        let mode = match input.first() {
+            Some(b'0'..=b'9') => from_octal(input),
+            _ => FileMode::from_symbolic(input),
+        };
+        mode.ok_or_else(|| {
+            ParserErrorKind::InvalidMode {
+                value: input.to_owned(),
+            }
             .into()
         })
     }
+
+    /// Parse a symbolic mode as understood by chmod(1), e.g. `u=rwx,go=rx`.
+    ///
+    /// Clauses are applied in order, starting from a mode of `000`. An empty "who" means
+    /// everyone (there is no umask to consult).
+    fn from_symbolic(input: &[u8]) -> Option<FileMode> {
+        let mut mode = 0;
+        for clause in input.split(|ch| *ch == b',') {
+            let mut chars = clause.iter().peekable();
+            // the bits each "who" is allowed to touch
+            let mut who = 0;
+            while let Some(ch) = chars.peek() {
+                who |= match ch {
+                    b'u' => 0o4700,
+                    b'g' => 0o2070,
+                    b'o' => 0o1007,
+                    b'a' => 0o7777,
+                    _ => break,
+                };
+                chars.next();
+            }
+            if who == 0 {
+                who = 0o7777;
+            }
+            // each clause needs at least one action
+            chars.peek()?;
+            while let Some(op) = chars.next() {
+                let mut perms = 0;
+                match chars.peek() {
+                    // copy the permissions of another "who"
+                    Some(b'u') | Some(b'g') | Some(b'o') => {
+                        let shift = match chars.next() {
+                            Some(b'u') => 6,
+                            Some(b'g') => 3,
+                            _ => 0,
+                        };
+                        perms = (mode >> shift & 0o7) * 0o111;
+                    }
+                    _ => {
+                        while let Some(ch) = chars.peek() {
+                            perms |= match ch {
+                                b'r' => 0o444,
+                                b'w' => 0o222,
+                                b'x' => 0o111,
+                                // execute only if someone already has execute
+                                b'X' if mode & 0o111 != 0 => 0o111,
+                                b'X' => 0,
+                                b's' => FileMode::SETUID | FileMode::SETGID,
+                                b't' => FileMode::STICKY,
+                                _ => break,
+                            };
+                            chars.next();
+                        }
+                    }
+                }
+                let perms = perms & who;
+                match op {
+                    b'+' => mode |= perms,
+                    b'-' => mode &= !perms,
+                    b'=' => mode = (mode & !who) | perms,
+                    _ => return None,
+                }
+            }
+        }
+        Some(FileMode { mode })
+    }
+
+    /// The permissions for the owner of the file.
+    pub fn owner(&self) -> Perms {
+        Perms::from_bits_truncate((self.mode >> 6) as u8)
+    }
+
+    /// The permissions for everyone who is not the owner, but in the group.
+    pub fn group(&self) -> Perms {
+        Perms::from_bits_truncate((self.mode >> 3) as u8)
+    }
+
+    /// The permissions for everyone who is not the owner and not in the group.
+    pub fn other(&self) -> Perms {
+        Perms::from_bits_truncate(self.mode as u8)
+    }
+
+    /// Executable files with this bit set will run with effective uid set to the uid of the
+    /// file owner.
+    pub fn setuid(&self) -> bool {
+        self.mode & FileMode::SETUID != 0
+    }
+
+    /// Executable files with this bit set will run with effective gid set to the gid of the
+    /// file owner.
+    pub fn setgid(&self) -> bool {
+        self.mode & FileMode::SETGID != 0
+    }
+
+    /// Directories with this bit set only allow the owner of a file to rename or delete it.
+    pub fn sticky(&self) -> bool {
+        self.mode & FileMode::STICKY != 0
+    }
+}
+
+/// A mode from its numeric value, e.g. from `Metadata::mode`. Bits other than the permission and
+/// special bits, such as the file type, are ignored.
+impl From<u32> for FileMode {
+    fn from(mode: u32) -> FileMode {
+        FileMode {
+            mode: mode & 0o7777,
+        }
+    }
+}
+
+impl From<FileMode> for u32 {
+    fn from(mode: FileMode) -> u32 {
+        mode.as_raw()
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+impl From<FileMode> for std::fs::Permissions {
+    fn from(mode: FileMode) -> std::fs::Permissions {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::Permissions::from_mode(mode.as_raw())
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+impl From<std::fs::Permissions> for FileMode {
+    fn from(permissions: std::fs::Permissions) -> FileMode {
+        use std::os::unix::fs::PermissionsExt;
+        FileMode::from(permissions.mode())
+    }
 }
 
 impl fmt::Display for FileMode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}{}{}", self.owner, self.group, self.other)
+        // special bits replace the execute bit, in upper case if execute isn't set (like `ls`)
+        let triples = [
+            (self.owner(), self.setuid(), 's'),
+            (self.group(), self.setgid(), 's'),
+            (self.other(), self.sticky(), 't'),
+        ];
+        for (perms, special, ch) in triples.iter() {
+            f.write_str(if perms.contains(Perms::READ) {
+                "r"
+            } else {
+                "-"
+            })?;
+            f.write_str(if perms.contains(Perms::WRITE) {
+                "w"
+            } else {
+                "-"
+            })?;
+            match (special, perms.contains(Perms::EXECUTE)) {
+                (true, true) => write!(f, "{}", ch)?,
+                (true, false) => write!(f, "{}", ch.to_ascii_uppercase())?,
+                (false, true) => f.write_str("x")?,
+                (false, false) => f.write_str("-")?,
+            }
+        }
+        Ok(())
     }
 }
 
 impl fmt::Octal for FileMode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:o}{:o}{:o}", self.owner, self.group, self.other)
+        write!(f, "{:03o}", self.mode)
+    }
+}
+
+#[test]
+fn test_mode_from_bytes() {
+    use alloc::{format, string::ToString};
+    for (input, display, octal) in [
+        (&b"644"[..], "rw-r--r--", "644"),
+        (&b"0755"[..], "rwxr-xr-x", "755"),
+        (&b"4755"[..], "rwsr-xr-x", "4755"),
+        (&b"2750"[..], "rwxr-s---", "2750"),
+        (&b"1777"[..], "rwxrwxrwt", "1777"),
+        (&b"1666"[..], "rw-rw-rwT", "1666"),
+        (&b"66"[..], "---rw-rw-", "066"),
+        (&b"0"[..], "---------", "000"),
+        (&b"04755"[..], "rwsr-xr-x", "4755"),
+        (&b"000644"[..], "rw-r--r--", "644"),
+        (&b"00"[..], "---------", "000"),
+    ] {
+        let mode = FileMode::from_bytes(input).unwrap();
+        assert_eq!(mode.to_string(), display);
+        assert_eq!(format!("{:o}", mode), octal);
+    }
+    let mode = FileMode::from_bytes(b"6751").unwrap();
+    assert!(mode.setuid() && mode.setgid() && !mode.sticky());
+    assert_eq!(mode.owner(), Perms::all());
+    assert_eq!(mode.group(), Perms::READ | Perms::EXECUTE);
+    assert_eq!(mode.other(), Perms::EXECUTE);
+    assert_eq!(u32::from(mode), 0o6751);
+    assert_eq!(
+        FileMode::from(0o40755),
+        FileMode::from_bytes(b"755").unwrap()
+    );
+    for input in [&b""[..], b"12345", b"012345", b"8", b"0789", b"rwx"] {
+        assert!(FileMode::from_bytes(input).is_err());
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+#[test]
+fn test_mode_permissions() {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = FileMode::from_bytes(b"4750").unwrap();
+    let permissions = Permissions::from(mode);
+    assert_eq!(permissions.mode(), 0o4750);
+    assert_eq!(FileMode::from(permissions), mode);
+
+    let path = std::env::temp_dir().join(format!("mtree-mode-{}", std::process::id()));
+    fs::write(&path, b"").unwrap();
+    fs::set_permissions(&path, FileMode::from_bytes(b"640").unwrap().into()).unwrap();
+    let metadata = fs::symlink_metadata(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(FileMode::from_metadata(&metadata).to_string(), "rw-r-----");
+}
+
+#[test]
+fn test_mode_from_symbolic() {
+    use alloc::format;
+    for (input, octal) in [
+        (&b"u=rwx,go=rx"[..], "755"),
+        (b"a=r,u+w", "644"),
+        (b"=rw,o-w", "664"),
+        (b"u=rwx,g=u,o=", "770"),
+        (b"u=rwxs,g=rx,o=rx", "4755"),
+        (b"a=rwx,+t", "1777"),
+        (b"u=rw,a+X", "600"),
+        (b"u=rwx,a+X", "711"),
+        (b"ug=rw-w", "440"),
+    ] {
+        let mode = FileMode::from_bytes(input).unwrap();
+        assert_eq!(format!("{:o}", mode), octal, "parsing {:?}", input);
+    }
+    for input in [&b"u"[..], b"u=rwx,", b"q=rwx", b"u=rwxz", b"u*rwx"] {
+        assert!(FileMode::from_bytes(input).is_err(), "parsing {:?}", input);
     }
 }
 
-pub(crate) type ParserResult<T> = Result<T, ParserError>;
+/// The result of parsing a line, keyword or value.
+pub type ParserResult<T> = Result<T, ParserError>;
 
 /// An error occurred during parsing a record.
 ///
-/// This currently just gives an error report at the moment.
+/// This says what went wrong (see [`ParserErrorKind`]), and where in the input the problem was
+/// (when known).
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub struct ParserError(pub String);
+pub struct ParserError {
+    /// What went wrong.
+    kind: ParserErrorKind,
+    /// The line the problem occurred on (starting at 1).
+    line: Option<usize>,
+    /// The byte offset of the start of that line.
+    offset: Option<u64>,
+}
+
+impl ParserError {
+    /// What went wrong.
+    pub fn kind(&self) -> &ParserErrorKind {
+        &self.kind
+    }
+
+    /// The line number (starting from 1) that the problem occurred on, if known.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The byte offset of the start of the line that the problem occurred on, if known.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// Record where in the input the error occurred.
+    pub(crate) fn at(mut self, line: usize, offset: u64) -> ParserError {
+        self.line = Some(line);
+        self.offset = Some(offset);
+        self
+    }
+}
 
-impl From<String> for ParserError {
-    fn from(s: String) -> ParserError {
-        ParserError(s)
+impl From<ParserErrorKind> for ParserError {
+    fn from(kind: ParserErrorKind) -> ParserError {
+        ParserError {
+            kind,
+            line: None,
+            offset: None,
+        }
     }
 }
 
 impl fmt::Display for ParserError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&self.0)
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        write!(f, "{}", self.kind)
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for ParserError {}
+
+/// The different problems that can occur while parsing.
+///
+/// Keywords and values are the raw bytes from the input.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[non_exhaustive]
+pub enum ParserErrorKind {
+    /// A line started with `/`, but wasn't a command we know about.
+    UnknownCommand {
+        /// The command, without the leading `/`.
+        command: Vec<u8>,
+    },
+    /// The keyword isn't one we know about.
+    UnknownKeyword {
+        /// The unrecognised keyword.
+        keyword: Vec<u8>,
+    },
+    /// The keyword needs a value, but none was given.
+    MissingValue {
+        /// The keyword missing a value.
+        keyword: Vec<u8>,
+    },
+    /// The value should have been a decimal number.
+    InvalidNumber {
+        /// The keyword the value was for.
+        keyword: Vec<u8>,
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// The value should have been a hex message digest of the right length.
+    InvalidDigest {
+        /// The keyword the value was for.
+        keyword: Vec<u8>,
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// The value should have been a `format,major,minor[,subunit]` device.
+    InvalidDevice {
+        /// The keyword the value was for.
+        keyword: Vec<u8>,
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// The `mode` value was neither octal nor a valid symbolic mode.
+    InvalidMode {
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// The `time` value was not of the form `<seconds>.<nanoseconds>`.
+    InvalidTime {
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// The `type` value was not a file type we know about.
+    InvalidFileType {
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// The `flags` value contained a flag we don't know about.
+    InvalidFlags {
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// A `uname` or `gname` wasn't a valid name, when names are being validated.
+    InvalidName {
+        /// The keyword the value was for.
+        keyword: Vec<u8>,
+        /// The offending value.
+        value: Vec<u8>,
+    },
+    /// An entry on a relative line (one without a `/`) has nowhere to go, because the
+    /// current directory couldn't be found and no root was given.
+    NoRoot {
+        /// The path of the entry, unescaped.
+        path: Vec<u8>,
+    },
+    /// There is more than one entry for the path, with different params.
+    DuplicatePath {
+        /// The path, unescaped.
+        path: Vec<u8>,
+    },
+    /// A line went over one of the limits set in the parser's options.
+    LimitExceeded {
+        /// Which limit it was.
+        limit: Limit,
+        /// The value of the limit.
+        max: usize,
+    },
+}
+
+impl fmt::Display for ParserErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParserErrorKind::*;
+        let lossy = String::from_utf8_lossy;
+        match self {
+            UnknownCommand { command } => {
+                write!(f, r#""/{}" is not a special command"#, lossy(command))
+            }
+            UnknownKeyword { keyword } => {
+                write!(f, r#""{}" is not a valid keyword"#, lossy(keyword))
+            }
+            MissingValue { keyword } => {
+                write!(f, r#""{}" requires a value, none found"#, lossy(keyword))
+            }
+            InvalidNumber { keyword, value } => write!(
+                f,
+                r#"could not parse "{}" as a number for "{}""#,
+                lossy(value),
+                lossy(keyword)
+            ),
+            InvalidDigest { keyword, value } => write!(
+                f,
+                r#""{}" is not a valid hex digest for "{}""#,
+                lossy(value),
+                lossy(keyword)
+            ),
+            InvalidDevice { keyword, value } => write!(
+                f,
+                r#""{}" is not a valid device for "{}""#,
+                lossy(value),
+                lossy(keyword)
+            ),
+            InvalidMode { value } => write!(
+                f,
+                r#"mode value must be octal, up to 7777, or a symbolic mode, found "{}""#,
+                lossy(value)
+            ),
+            InvalidTime { value } => {
+                write!(f, r#"couldn't parse time from "{}""#, lossy(value))
+            }
+            InvalidFileType { value } => {
+                write!(f, r#""{}" is not a valid file type"#, lossy(value))
+            }
+            InvalidFlags { value } => {
+                write!(f, r#""{}" is not a valid list of file flags"#, lossy(value))
+            }
+            InvalidName { keyword, value } => write!(
+                f,
+                r#""{}" is not a valid name for "{}""#,
+                lossy(value),
+                lossy(keyword)
+            ),
+            NoRoot { path } => write!(
+                f,
+                r#"relative entry "{}" needs a root directory, and the current directory is unknown"#,
+                lossy(path)
+            ),
+            DuplicatePath { path } => write!(
+                f,
+                r#""{}" has more than one entry, with different params"#,
+                lossy(path)
+            ),
+            LimitExceeded { limit, max } => match limit {
+                Limit::LineLength => write!(f, "line is longer than the limit of {} bytes", max),
+                Limit::Keywords => write!(f, "line has more than the limit of {} keywords", max),
+                Limit::PathLength => write!(f, "path is longer than the limit of {} bytes", max),
+            },
+        }
+    }
+}
+
+/// A limit on the size of the input, for [`ParserErrorKind::LimitExceeded`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[non_exhaustive]
+pub enum Limit {
+    /// The number of bytes in a line, not counting the newline.
+    LineLength,
+    /// The number of keywords on a line.
+    Keywords,
+    /// The number of bytes in the path of an entry, once it is unescaped and any relative path
+    /// is joined to its directory.
+    PathLength,
+}
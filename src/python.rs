@@ -0,0 +1,355 @@
+//! Python bindings, enabled by the `python` feature.
+//!
+//! This makes a Python extension module called `mtree`, with `MTree`, `Entry` and `Verifier`
+//! classes. It can be built with maturin, or by building the crate as a library Python can load,
+//! e.g. `cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib`,
+//! and renaming the result to `mtree.so`.
+//!
+//! ```python
+//! import mtree
+//!
+//! for entry in mtree.MTree.from_path("/var/lib/pkg/mtree"):
+//!     print(entry.path, entry.type, entry.size)
+//!
+//! for result in mtree.Verifier("/").verify(mtree.MTree.from_path("spec")):
+//!     if not result.is_ok:
+//!         print(result)
+//! ```
+use crate::{Entry, Error, KeywordSet, MTree, Verification, Verifier};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Parse errors become `ValueError`, and io errors the matching `OSError`.
+fn py_err(error: Error) -> PyErr {
+    match error {
+        Error::Io(e) => e.into(),
+        Error::Parser(e) => PyValueError::new_err(e.to_string()),
+    }
+}
+
+/// An mtree parser, iterating over the entries of a spec.
+#[pyclass(name = "MTree", module = "mtree", unsendable)]
+struct PyMTree {
+    inner: MTree<Box<dyn Read>>,
+}
+
+#[pymethods]
+impl PyMTree {
+    /// Parse the spec in `data`, which may be compressed.
+    #[new]
+    fn new(data: Vec<u8>) -> PyResult<PyMTree> {
+        let inner = MTree::from_compressed_reader(Cursor::new(data))?;
+        Ok(PyMTree { inner })
+    }
+
+    /// Parse the spec in the file at `path`, which may be compressed.
+    #[staticmethod]
+    fn from_path(path: PathBuf) -> PyResult<PyMTree> {
+        let inner = MTree::from_path(path)?;
+        Ok(PyMTree { inner })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<PyEntry>> {
+        match self.inner.next() {
+            Some(Ok(inner)) => Ok(Some(PyEntry { inner })),
+            Some(Err(e)) => Err(py_err(e)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// An entry in a spec. Keywords that aren't present are `None`.
+#[pyclass(name = "Entry", module = "mtree", frozen)]
+#[derive(Clone)]
+struct PyEntry {
+    inner: Entry,
+}
+
+#[pymethods]
+impl PyEntry {
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.inner.path().to_owned()
+    }
+
+    #[getter(r#type)]
+    fn file_type(&self) -> Option<String> {
+        self.inner.file_type().map(|t| t.to_string())
+    }
+
+    #[getter]
+    fn mode(&self) -> Option<u32> {
+        self.inner.mode().map(|mode| mode.as_raw())
+    }
+
+    #[getter]
+    fn uid(&self) -> Option<u32> {
+        self.inner.uid()
+    }
+
+    #[getter]
+    fn gid(&self) -> Option<u32> {
+        self.inner.gid()
+    }
+
+    #[getter]
+    fn uname(&self) -> Option<&[u8]> {
+        self.inner.uname().map(|name| name.as_bytes())
+    }
+
+    #[getter]
+    fn gname(&self) -> Option<&[u8]> {
+        self.inner.gname().map(|name| name.as_bytes())
+    }
+
+    #[getter]
+    fn size(&self) -> Option<u64> {
+        self.inner.size()
+    }
+
+    /// Seconds since the epoch.
+    #[getter]
+    fn time(&self) -> Option<f64> {
+        let time = self.inner.time()?;
+        Some(match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_secs_f64(),
+            Err(before) => -before.duration().as_secs_f64(),
+        })
+    }
+
+    #[getter]
+    fn link(&self) -> Option<PathBuf> {
+        self.inner.link().map(ToOwned::to_owned)
+    }
+
+    #[getter]
+    fn contents(&self) -> Option<PathBuf> {
+        self.inner.contents().map(ToOwned::to_owned)
+    }
+
+    #[getter]
+    fn nlink(&self) -> Option<u64> {
+        self.inner.nlink()
+    }
+
+    #[getter]
+    fn inode(&self) -> Option<u64> {
+        self.inner.inode()
+    }
+
+    #[getter]
+    fn checksum(&self) -> Option<u64> {
+        self.inner.checksum()
+    }
+
+    #[getter]
+    fn flags(&self) -> Option<String> {
+        self.inner.flags().map(|flags| flags.to_string())
+    }
+
+    #[getter]
+    fn md5<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        let md5 = self.inner.md5()?;
+        Some(PyBytes::new(py, &md5.to_be_bytes()))
+    }
+
+    #[getter]
+    fn sha1(&self) -> Option<&[u8]> {
+        self.inner.sha1().map(|digest| &digest[..])
+    }
+
+    #[getter]
+    fn sha256(&self) -> Option<&[u8]> {
+        self.inner.sha256().map(|digest| &digest[..])
+    }
+
+    #[getter]
+    fn sha384(&self) -> Option<&[u8]> {
+        self.inner.sha384().map(|digest| &digest[..])
+    }
+
+    #[getter]
+    fn sha512(&self) -> Option<&[u8]> {
+        self.inner.sha512().map(|digest| &digest[..])
+    }
+
+    #[getter]
+    fn rmd160(&self) -> Option<&[u8]> {
+        self.inner.rmd160().map(|digest| &digest[..])
+    }
+
+    #[getter]
+    fn ignore(&self) -> bool {
+        self.inner.ignore()
+    }
+
+    #[getter]
+    fn no_change(&self) -> bool {
+        self.inner.no_change()
+    }
+
+    #[getter]
+    fn optional(&self) -> bool {
+        self.inner.optional()
+    }
+
+    fn __str__(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<mtree.Entry {}>", self.inner)
+    }
+}
+
+/// Checks files on disk against the entries of a spec.
+#[pyclass(name = "Verifier", module = "mtree", frozen)]
+struct PyVerifier {
+    inner: Verifier,
+}
+
+#[pymethods]
+impl PyVerifier {
+    /// Check files under `root`. `keywords` limits the keywords checked, e.g. `"type,size"`,
+    /// and times within `time_tolerance` seconds are treated as equal.
+    #[new]
+    #[pyo3(signature = (root, keywords = None, time_tolerance = 0.0))]
+    fn new(root: PathBuf, keywords: Option<&str>, time_tolerance: f64) -> PyResult<PyVerifier> {
+        let mut inner = Verifier::new(root);
+        if let Some(keywords) = keywords {
+            let keywords = keywords
+                .parse::<KeywordSet>()
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+            inner = inner.keywords(keywords);
+        }
+        let tolerance = Duration::try_from_secs_f64(time_tolerance)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PyVerifier {
+            inner: inner.time_tolerance(tolerance),
+        })
+    }
+
+    /// Check each of `entries`, and then look for files that aren't in them.
+    fn verify(&self, entries: Vec<PyEntry>) -> PyResult<Vec<PyVerification>> {
+        self.inner
+            .verify(entries.into_iter().map(|entry| Ok(entry.inner)))
+            .map(|result| result.map(|inner| PyVerification { inner }).map_err(py_err))
+            .collect()
+    }
+}
+
+/// The result of checking a file.
+#[pyclass(name = "Verification", module = "mtree", frozen)]
+struct PyVerification {
+    inner: Verification,
+}
+
+#[pymethods]
+impl PyVerification {
+    /// One of `ok`, `missing`, `optional_missing`, `extra` or `mismatch`.
+    #[getter]
+    fn status(&self) -> &'static str {
+        match self.inner {
+            Verification::Ok(_) => "ok",
+            Verification::Missing(_) => "missing",
+            Verification::OptionalMissing(_) => "optional_missing",
+            Verification::Extra(_) => "extra",
+            Verification::Mismatch(..) => "mismatch",
+        }
+    }
+
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.inner.path().to_owned()
+    }
+
+    /// Each mismatch, e.g. `mode changed from 644 to 755`.
+    #[getter]
+    fn mismatches(&self) -> Vec<String> {
+        match &self.inner {
+            Verification::Mismatch(_, mismatches) => {
+                mismatches.iter().map(ToString::to_string).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    #[getter]
+    fn is_ok(&self) -> bool {
+        self.inner.is_ok()
+    }
+
+    fn __str__(&self) -> String {
+        self.inner.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<mtree.Verification {}>", self.inner)
+    }
+}
+
+/// The `mtree` Python module.
+#[pymodule]
+fn mtree(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMTree>()?;
+    module.add_class::<PyEntry>()?;
+    module.add_class::<PyVerifier>()?;
+    module.add_class::<PyVerification>()?;
+    Ok(())
+}
+
+#[test]
+fn test_python() {
+    use pyo3::types::PyDict;
+
+    let dir = std::env::temp_dir().join(format!("mtree-python-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a"), b"abc").unwrap();
+
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let module = PyModule::new(py, "mtree").unwrap();
+        mtree(&module).unwrap();
+        let locals = PyDict::new(py);
+        locals.set_item("mtree", module).unwrap();
+        locals.set_item("root", &dir).unwrap();
+        py.run(
+            pyo3::ffi::c_str!(
+                r#"
+entries = list(mtree.MTree(b"/set type=file uid=0\n./a size=3 mode=644\n./b size=1 optional\n"))
+assert len(entries) == 2
+a = entries[0]
+assert str(a.path) == "./a"
+assert a.type == "file" and a.size == 3 and a.mode == 0o644 and a.uid == 0
+assert a.gid is None and a.sha256 is None and not a.optional
+assert entries[1].optional
+
+try:
+    list(mtree.MTree(b"./a size=bogus\n"))
+except ValueError:
+    pass
+else:
+    raise AssertionError("no error")
+
+results = mtree.Verifier(root, keywords="type,size").verify(entries)
+assert [(r.status, r.is_ok) for r in results] == [("ok", True), ("optional_missing", True)]
+results = mtree.Verifier(root).verify(list(mtree.MTree(b"./a type=file size=4\n")))
+assert results[0].status == "mismatch"
+assert results[0].mismatches == ["size changed from 4 to 3"], results[0].mismatches
+"#
+            ),
+            None,
+            Some(&locals),
+        )
+        .unwrap();
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
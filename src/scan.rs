@@ -0,0 +1,1026 @@
+//! Generating an mtree spec from the filesystem.
+use crate::parser::{Device, FileMode, FileType, Format, KeywordSet};
+use crate::util::fnmatch;
+use crate::{Entry, Error, MTreeWriter, Params};
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::vec;
+
+/// A predicate deciding whether to skip a path, as given to [`Scanner::exclude_if`].
+type Filter = dyn Fn(&Path) -> bool + Send + Sync;
+
+/// Hooks for following a long scan or check, e.g. to drive a progress bar, as given to
+/// [`Scanner::progress`] and [`Verifier::progress`](crate::Verifier::progress).
+///
+/// Every method does nothing by default. With the `rayon` feature enabled, they may be called
+/// from several threads at once.
+///
+/// ```no_run
+/// use mtree::{Progress, Scanner};
+/// use std::path::Path;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct Counter {
+///     entries: AtomicU64,
+///     bytes: AtomicU64,
+/// }
+///
+/// impl Progress for Counter {
+///     fn entry(&self, path: &Path) {
+///         let entries = self.entries.fetch_add(1, Ordering::Relaxed) + 1;
+///         if entries % 1000 == 0 {
+///             eprintln!("{} entries, at {}", entries, path.display());
+///         }
+///     }
+///
+///     fn hashed(&self, bytes: u64) {
+///         self.bytes.fetch_add(bytes, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let counter = Arc::new(Counter::default());
+/// let entries = Scanner::new("/usr").progress(counter.clone()).scan().count();
+/// println!("{} entries, hashed {} bytes", entries, counter.bytes.load(Ordering::Relaxed));
+/// ```
+pub trait Progress: Send + Sync {
+    /// Called when starting on the entry for `path`, relative to the root (e.g. `./usr/bin`).
+    fn entry(&self, path: &Path) {
+        let _ = path;
+    }
+
+    /// Called as the contents of files are read to compute digests, with the number of bytes
+    /// read since the last call.
+    fn hashed(&self, bytes: u64) {
+        let _ = bytes;
+    }
+}
+
+impl<P> Progress for Arc<P>
+where
+    P: Progress + ?Sized,
+{
+    fn entry(&self, path: &Path) {
+        (**self).entry(path)
+    }
+
+    fn hashed(&self, bytes: u64) {
+        (**self).hashed(bytes)
+    }
+}
+
+/// Walks a directory tree and produces an entry for every file in it, like `mtree -c`.
+///
+/// Entries are produced depth first, with the entries in each directory sorted by name, so the
+/// output is the same every time for the same tree. Paths are relative to the root: the root
+/// itself is `.`, and everything else starts with `./`.
+///
+/// By default `type`, `uid`, `gid`, `mode`, `nlink`, `size`, `link` and `time` are recorded,
+/// like `mtree -c`. Use [`Scanner::keywords`] to choose a different set, including digests of
+/// regular files if the features for them are enabled.
+///
+/// Files can be skipped with [`Scanner::exclude`] or [`Scanner::exclude_if`]. Skipping a
+/// directory skips everything in it.
+///
+/// ```no_run
+/// use mtree::{MTreeWriter, Scanner};
+///
+/// let mut writer = MTreeWriter::new(std::io::stdout());
+/// Scanner::new("/usr/share/doc").write_to(&mut writer).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Scanner {
+    /// The directory to scan.
+    root: PathBuf,
+    /// The keywords to record.
+    keywords: KeywordSet,
+    /// Whether to record what symlinks point to, rather than the links themselves.
+    follow_symlinks: bool,
+    /// Whether to stay on the filesystem of the root.
+    one_filesystem: bool,
+    /// Glob patterns for files to skip.
+    excludes: Vec<Vec<u8>>,
+    /// Predicates for files to skip.
+    filters: Vec<Arc<Filter>>,
+    /// What to tell about the progress of the scan.
+    progress: Option<Arc<dyn Progress>>,
+}
+
+impl fmt::Debug for Scanner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scanner")
+            .field("root", &self.root)
+            .field("keywords", &self.keywords)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("one_filesystem", &self.one_filesystem)
+            .field(
+                "excludes",
+                &self
+                    .excludes
+                    .iter()
+                    .map(|pattern| String::from_utf8_lossy(pattern))
+                    .collect::<Vec<_>>(),
+            )
+            .field("filters", &self.filters.len())
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl Scanner {
+    /// Create a scanner for the tree at `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Scanner {
+        Scanner {
+            root: root.into(),
+            keywords: KeywordSet::TYPE
+                | KeywordSet::UID
+                | KeywordSet::GID
+                | KeywordSet::MODE
+                | KeywordSet::NLINK
+                | KeywordSet::SIZE
+                | KeywordSet::LINK
+                | KeywordSet::TIME,
+            follow_symlinks: false,
+            one_filesystem: false,
+            excludes: Vec::new(),
+            filters: Vec::new(),
+            progress: None,
+        }
+    }
+
+    /// Choose which keywords to record for each entry, like `mtree -k`.
+    ///
+    /// Keywords that can't be read from the filesystem (e.g. `ignore` or `optional`) are never
+    /// recorded. Digests are computed for regular files when the feature for them is enabled:
+    /// `md-5` for `md5digest`, `sha1` for `sha1digest`, `sha2` for `sha256digest`,
+    /// `sha384digest` and `sha512digest`, and `ripemd` for `rmd160digest`. The `ring` feature
+    /// computes the SHA digests with ring instead, and the `openssl` feature computes all but
+    /// `rmd160digest` with OpenSSL. Each file is only read once, however many digests are
+    /// chosen.
+    pub fn keywords(mut self, keywords: KeywordSet) -> Scanner {
+        self.keywords = keywords;
+        self
+    }
+
+    /// Choose whether to follow symlinks, like `mtree -L` (`true`) or `mtree -P` (`false`).
+    ///
+    /// When `false` (the default), a symlink is recorded as `type=link` along with its target.
+    /// When `true`, it is recorded with the metadata of the file it points to, and a link to a
+    /// directory is walked like any other directory. Links that don't point to anything are
+    /// still recorded as links, and a directory that contains itself is only walked once.
+    pub fn follow_symlinks(mut self, follow: bool) -> Scanner {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Choose whether to stay on the filesystem the root is on, like `mtree -x`.
+    ///
+    /// When `true`, directories on another filesystem (mount points) are recorded, but not
+    /// walked.
+    pub fn one_filesystem(mut self, one_filesystem: bool) -> Scanner {
+        self.one_filesystem = one_filesystem;
+        self
+    }
+
+    /// Skip files matching the glob `pattern`, like a line of the file given to `mtree -X`.
+    ///
+    /// A pattern containing a `/` is matched against the whole path of the entry (e.g.
+    /// `./var/cache/*`), and any other pattern against just its file name (e.g. `*.o`). `*`,
+    /// `?` and `[...]` work as in the shell, but never match a `/`, while `**` matches any
+    /// number of directories.
+    pub fn exclude(mut self, pattern: impl AsRef<[u8]>) -> Scanner {
+        self.excludes.push(pattern.as_ref().to_owned());
+        self
+    }
+
+    /// Skip files for which `predicate` returns `true`.
+    ///
+    /// The predicate is given the path of the entry, relative to the root (e.g. `./.git`).
+    pub fn exclude_if(
+        mut self,
+        predicate: impl Fn(&Path) -> bool + Send + Sync + 'static,
+    ) -> Scanner {
+        self.filters.push(Arc::new(predicate));
+        self
+    }
+
+    /// Tell `progress` about each entry as it is read, and each file as it is hashed.
+    pub fn progress(mut self, progress: impl Progress + 'static) -> Scanner {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Start walking the tree.
+    pub fn scan(self) -> Scan {
+        Scan {
+            scanner: self,
+            stack: Vec::new(),
+            ancestors: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Walk the tree, writing each entry to `writer` as it is found.
+    ///
+    /// This stops at the first error.
+    pub fn write_to<W: Write>(self, writer: &mut MTreeWriter<W>) -> Result<(), Error> {
+        for entry in self.scan() {
+            writer.write_entry(&entry?)?;
+        }
+        Ok(())
+    }
+
+    /// Read the entry for `path` (relative to the root), and if it's a directory that should be
+    /// walked, its listing.
+    ///
+    /// `ancestors` are the ids of the directories containing `path`, starting with the root.
+    fn read(&self, path: PathBuf, ancestors: &[DirId]) -> io::Result<(Entry, Option<Listing>)> {
+        let progress = self.progress.as_deref();
+        if let Some(progress) = progress {
+            progress.entry(&path);
+        }
+        let full_path = self.root.join(&path);
+        let (params, metadata) =
+            read_params(&full_path, self.keywords, self.follow_symlinks, progress)?;
+        let id = (metadata.dev(), metadata.ino());
+        let walk = metadata.is_dir()
+            && !ancestors.contains(&id)
+            && !(self.one_filesystem && ancestors.first().is_some_and(|root| root.0 != id.0));
+        let listing = if walk {
+            let mut children = fs::read_dir(&full_path)?
+                .map(|child| Ok(path.join(child?.file_name())))
+                .filter(|child| !matches!(child, Ok(child) if self.is_excluded(child)))
+                .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+            Some(Listing { id, children })
+        } else {
+            None
+        };
+        let entry = Entry {
+            path,
+            spec_path: None,
+            explicit: None,
+            params,
+        };
+        Ok((entry, listing))
+    }
+
+    /// Whether `path` should be skipped.
+    fn is_excluded(&self, path: &Path) -> bool {
+        let full_path = path.as_os_str().as_bytes();
+        let name = path.file_name().map_or(full_path, |name| name.as_bytes());
+        self.excludes.iter().any(|pattern| {
+            if pattern.contains(&b'/') {
+                fnmatch(pattern, full_path)
+            } else {
+                fnmatch(pattern, name)
+            }
+        }) || self.filters.iter().any(|filter| filter(path))
+    }
+}
+
+impl IntoIterator for Scanner {
+    type Item = Result<Entry, Error>;
+    type IntoIter = Scan;
+
+    fn into_iter(self) -> Scan {
+        self.scan()
+    }
+}
+
+/// An iterator over the entries of a directory tree, created by [`Scanner::scan`].
+///
+/// A file that can't be read produces an error, and the walk carries on with the next file.
+///
+/// With the `rayon` feature enabled, the files in each directory are read (and hashed) in
+/// parallel when the walk enters the directory. Entries are still produced in the same order.
+#[derive(Debug)]
+pub struct Scan {
+    /// What we are scanning.
+    scanner: Scanner,
+    /// The directories we are in.
+    stack: Vec<Dir>,
+    /// The ids of the directories we are in.
+    ancestors: Vec<DirId>,
+    /// Whether the root has been read.
+    started: bool,
+}
+
+impl Scan {
+    /// Read a child of the current directory.
+    #[cfg(not(feature = "rayon"))]
+    fn read(&self, path: Child) -> io::Result<(Entry, Option<Listing>)> {
+        self.scanner.read(path, &self.ancestors)
+    }
+
+    /// Read a child of the current directory.
+    #[cfg(feature = "rayon")]
+    fn read(&self, child: Child) -> io::Result<(Entry, Option<Listing>)> {
+        child
+    }
+
+    /// Start walking a directory.
+    fn enter(&mut self, listing: Listing) {
+        self.ancestors.push(listing.id);
+        #[cfg(feature = "rayon")]
+        let children = {
+            use rayon::iter::{IntoParallelIterator, ParallelIterator};
+            let (scanner, ancestors) = (&self.scanner, &self.ancestors);
+            listing
+                .children
+                .into_par_iter()
+                .map(|path| scanner.read(path, ancestors))
+                .collect::<Vec<_>>()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let children = listing.children;
+        self.stack.push(Dir {
+            children: children.into_iter(),
+        });
+    }
+}
+
+impl Iterator for Scan {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Result<Entry, Error>> {
+        let read = if !self.started {
+            self.started = true;
+            self.scanner.read(PathBuf::from("."), &[])
+        } else {
+            let child = loop {
+                match self.stack.last_mut()?.children.next() {
+                    Some(child) => break child,
+                    None => {
+                        self.stack.pop();
+                        self.ancestors.pop();
+                    }
+                }
+            };
+            self.read(child)
+        };
+        Some(match read {
+            Ok((entry, listing)) => {
+                if let Some(listing) = listing {
+                    self.enter(listing);
+                }
+                Ok(entry)
+            }
+            Err(e) => Err(e.into()),
+        })
+    }
+}
+
+/// The device and inode of a directory.
+type DirId = (u64, u64);
+
+/// A child of a directory being walked: its path, or with the `rayon` feature, the result of
+/// reading it.
+#[cfg(not(feature = "rayon"))]
+type Child = PathBuf;
+#[cfg(feature = "rayon")]
+type Child = io::Result<(Entry, Option<Listing>)>;
+
+/// A directory that has been listed, but not walked yet.
+#[derive(Debug)]
+struct Listing {
+    /// The device and inode of the directory.
+    id: DirId,
+    /// The paths of the children, in order.
+    children: Vec<PathBuf>,
+}
+
+/// A directory being walked.
+#[derive(Debug)]
+struct Dir {
+    /// The children that are still to be walked.
+    children: vec::IntoIter<Child>,
+}
+
+/// The keywords that can be read from the filesystem, with the features that are enabled.
+pub(crate) fn readable_keywords() -> KeywordSet {
+    let mut keywords = KeywordSet::TYPE
+        | KeywordSet::INODE
+        | KeywordSet::MODE
+        | KeywordSet::UID
+        | KeywordSet::GID
+        | KeywordSet::NLINK
+        | KeywordSet::SIZE
+        | KeywordSet::TIME
+        | KeywordSet::LINK
+        | KeywordSet::DEVICE;
+    if cfg!(any(feature = "md-5", feature = "openssl")) {
+        keywords |= KeywordSet::MD5;
+    }
+    if cfg!(feature = "ripemd") {
+        keywords |= KeywordSet::RMD160;
+    }
+    if cfg!(any(feature = "sha1", feature = "ring", feature = "openssl")) {
+        keywords |= KeywordSet::SHA1;
+    }
+    if cfg!(any(feature = "sha2", feature = "ring", feature = "openssl")) {
+        keywords |= KeywordSet::SHA256 | KeywordSet::SHA384 | KeywordSet::SHA512;
+    }
+    keywords
+}
+
+/// The keywords for the digests of a file's contents.
+#[cfg(feature = "digest")]
+pub(crate) fn digest_keywords() -> KeywordSet {
+    KeywordSet::MD5
+        | KeywordSet::RMD160
+        | KeywordSet::SHA1
+        | KeywordSet::SHA256
+        | KeywordSet::SHA384
+        | KeywordSet::SHA512
+}
+
+/// Read the params named in `keywords` for the file at `path`, along with its metadata.
+///
+/// If `follow_symlinks` is set and `path` is a symlink to something, that is read instead.
+/// `progress` is told about the bytes read to compute digests.
+#[cfg_attr(not(feature = "digest"), allow(unused_variables))]
+pub(crate) fn read_params(
+    path: &Path,
+    keywords: KeywordSet,
+    follow_symlinks: bool,
+    progress: Option<&dyn Progress>,
+) -> io::Result<(Params, Metadata)> {
+    let mut metadata = fs::symlink_metadata(path)?;
+    if follow_symlinks && metadata.file_type().is_symlink() {
+        if let Ok(target) = fs::metadata(path) {
+            metadata = target;
+        }
+    }
+    let mut params = Params::from_metadata(&metadata);
+    params.unset(!keywords);
+    if keywords.contains(KeywordSet::LINK) && metadata.file_type().is_symlink() {
+        params.link = Some(fs::read_link(path)?);
+    }
+    #[cfg(feature = "digest")]
+    if metadata.is_file() {
+        hash_file(path, keywords, &mut params, progress)?;
+    }
+    Ok((params, metadata))
+}
+
+impl Params {
+    /// The params that can be read from the metadata of a file: `type`, `inode`, `mode`, `uid`,
+    /// `gid`, `nlink`, `size` (for regular files), `time`, `resdevice`, and `device` (for block
+    /// and character devices).
+    ///
+    /// The link target and digests need more than the metadata, so they aren't set.
+    ///
+    /// ```no_run
+    /// use mtree::{FileType, Params};
+    ///
+    /// let params = Params::from_metadata(&std::fs::symlink_metadata("/etc").unwrap());
+    /// assert_eq!(params.file_type, Some(FileType::Directory));
+    /// ```
+    pub fn from_metadata(metadata: &Metadata) -> Params {
+        let file_type = metadata.file_type();
+        let file_type = if file_type.is_dir() {
+            FileType::Directory
+        } else if file_type.is_symlink() {
+            FileType::SymbolicLink
+        } else if file_type.is_block_device() {
+            FileType::BlockDevice
+        } else if file_type.is_char_device() {
+            FileType::CharacterDevice
+        } else if file_type.is_fifo() {
+            FileType::Fifo
+        } else if file_type.is_socket() {
+            FileType::Socket
+        } else {
+            FileType::File
+        };
+        // every native `dev_t` can be split
+        let device = |dev| Device::from_dev_t(dev, Format::Native);
+        Params {
+            file_type: Some(file_type),
+            inode: Some(metadata.ino()),
+            mode: Some(FileMode::from_metadata(metadata)),
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+            nlink: Some(metadata.nlink()),
+            size: if file_type == FileType::File {
+                Some(metadata.size())
+            } else {
+                None
+            },
+            time: metadata.modified().ok(),
+            device: match file_type {
+                FileType::BlockDevice | FileType::CharacterDevice => device(metadata.rdev()),
+                _ => None,
+            },
+            resident_device: device(metadata.dev()),
+            ..Params::default()
+        }
+    }
+}
+
+/// Compute the digests of the file at `path` that are named in `keywords`, telling `progress`
+/// how much has been read.
+#[cfg(feature = "digest")]
+pub(crate) fn hash_file(
+    path: &Path,
+    keywords: KeywordSet,
+    params: &mut Params,
+    progress: Option<&dyn Progress>,
+) -> io::Result<()> {
+    if keywords.intersects(digest_keywords()) {
+        let file = fs::File::open(path)?;
+        match progress {
+            Some(progress) => hash_reader(
+                ProgressReader {
+                    inner: file,
+                    progress,
+                },
+                keywords,
+                params,
+            )?,
+            None => hash_reader(file, keywords, params)?,
+        }
+    }
+    Ok(())
+}
+
+/// A reader that tells a [`Progress`] how many bytes have been read.
+#[cfg(feature = "digest")]
+struct ProgressReader<'a, R> {
+    /// Where the bytes come from.
+    inner: R,
+    /// Who to tell.
+    progress: &'a dyn Progress,
+}
+
+#[cfg(feature = "digest")]
+impl<R: io::Read> io::Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.hashed(read as u64);
+        Ok(read)
+    }
+}
+
+/// Compute the digests of the contents of `reader` that are named in `keywords`.
+#[cfg(feature = "digest")]
+pub(crate) fn hash_reader(
+    mut reader: impl io::Read,
+    keywords: KeywordSet,
+    params: &mut Params,
+) -> io::Result<()> {
+    let mut hashers = Vec::new();
+    for keyword in [
+        KeywordSet::MD5,
+        KeywordSet::RMD160,
+        KeywordSet::SHA1,
+        KeywordSet::SHA256,
+        KeywordSet::SHA384,
+        KeywordSet::SHA512,
+    ] {
+        if keywords.contains(keyword) {
+            if let Some(hasher) = hasher(keyword)? {
+                hashers.push((keyword, hasher));
+            }
+        }
+    }
+    if hashers.is_empty() {
+        return Ok(());
+    }
+
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for (_, hasher) in &mut hashers {
+            hasher.update(&buf[..len])?;
+        }
+    }
+    for (keyword, hasher) in hashers {
+        let digest = hasher.finish()?;
+        // each hasher produces the right length for its keyword
+        let set = set_digest(params, keyword, &digest);
+        debug_assert!(set);
+    }
+    Ok(())
+}
+
+/// Set the digest `keyword` of `params` to `digest`.
+///
+/// Returns `false`, leaving `params` alone, if `keyword` isn't a single digest or `digest` is
+/// the wrong length for it.
+#[cfg(feature = "digest")]
+pub(crate) fn set_digest(params: &mut Params, keyword: KeywordSet, digest: &[u8]) -> bool {
+    use std::convert::TryInto;
+
+    match keyword {
+        KeywordSet::MD5 => digest
+            .try_into()
+            .map(|digest| params.md5 = Some(u128::from_be_bytes(digest)))
+            .is_ok(),
+        KeywordSet::RMD160 => digest.try_into().map(|d| params.rmd160 = Some(d)).is_ok(),
+        KeywordSet::SHA1 => digest.try_into().map(|d| params.sha1 = Some(d)).is_ok(),
+        KeywordSet::SHA256 => digest.try_into().map(|d| params.sha256 = Some(d)).is_ok(),
+        KeywordSet::SHA384 => digest.try_into().map(|d| params.sha384 = Some(d)).is_ok(),
+        KeywordSet::SHA512 => digest.try_into().map(|d| params.sha512 = Some(d)).is_ok(),
+        _ => false,
+    }
+}
+
+/// A digest being computed, by whichever backend provides it.
+#[cfg(feature = "digest")]
+trait Hasher {
+    /// Hash `data`.
+    fn update(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// The digest of all the data.
+    fn finish(self: Box<Self>) -> io::Result<Vec<u8>>;
+}
+
+/// A hasher from one of the RustCrypto crates.
+#[cfg(any(
+    feature = "md-5",
+    feature = "ripemd",
+    feature = "sha1",
+    feature = "sha2"
+))]
+struct RustCrypto<D>(D);
+
+#[cfg(any(
+    feature = "md-5",
+    feature = "ripemd",
+    feature = "sha1",
+    feature = "sha2"
+))]
+impl<D: digest::DynDigest> Hasher for RustCrypto<D> {
+    fn update(&mut self, data: &[u8]) -> io::Result<()> {
+        self.0.update(data);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<Vec<u8>> {
+        Ok(digest::DynDigest::finalize(Box::new(self.0)).into_vec())
+    }
+}
+
+#[cfg(feature = "ring")]
+impl Hasher for ring::digest::Context {
+    fn update(&mut self, data: &[u8]) -> io::Result<()> {
+        ring::digest::Context::update(self, data);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<Vec<u8>> {
+        Ok(ring::digest::Context::finish(*self).as_ref().to_vec())
+    }
+}
+
+#[cfg(feature = "openssl")]
+impl Hasher for openssl::hash::Hasher {
+    fn update(&mut self, data: &[u8]) -> io::Result<()> {
+        openssl::hash::Hasher::update(self, data)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<Vec<u8>> {
+        Ok(openssl::hash::Hasher::finish(&mut self)?.to_vec())
+    }
+}
+
+/// A hasher for the digest `keyword`, if one of the enabled backends provides it.
+///
+/// OpenSSL is preferred, then ring, then the RustCrypto crates.
+#[cfg(feature = "digest")]
+#[allow(unreachable_code)]
+fn hasher(keyword: KeywordSet) -> io::Result<Option<Box<dyn Hasher>>> {
+    #[cfg(feature = "openssl")]
+    {
+        use openssl::hash::MessageDigest;
+        let digest = match keyword {
+            KeywordSet::MD5 => Some(MessageDigest::md5()),
+            KeywordSet::SHA1 => Some(MessageDigest::sha1()),
+            KeywordSet::SHA256 => Some(MessageDigest::sha256()),
+            KeywordSet::SHA384 => Some(MessageDigest::sha384()),
+            KeywordSet::SHA512 => Some(MessageDigest::sha512()),
+            _ => None,
+        };
+        if let Some(digest) = digest {
+            return Ok(Some(Box::new(openssl::hash::Hasher::new(digest)?)));
+        }
+    }
+    #[cfg(feature = "ring")]
+    {
+        use ring::digest;
+        let algorithm = match keyword {
+            KeywordSet::SHA1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+            KeywordSet::SHA256 => Some(&digest::SHA256),
+            KeywordSet::SHA384 => Some(&digest::SHA384),
+            KeywordSet::SHA512 => Some(&digest::SHA512),
+            _ => None,
+        };
+        if let Some(algorithm) = algorithm {
+            return Ok(Some(Box::new(digest::Context::new(algorithm))));
+        }
+    }
+    Ok(match keyword {
+        #[cfg(feature = "md-5")]
+        KeywordSet::MD5 => Some(Box::new(RustCrypto(md5::Md5::default()))),
+        #[cfg(feature = "ripemd")]
+        KeywordSet::RMD160 => Some(Box::new(RustCrypto(ripemd::Ripemd160::default()))),
+        #[cfg(feature = "sha1")]
+        KeywordSet::SHA1 => Some(Box::new(RustCrypto(sha1::Sha1::default()))),
+        #[cfg(feature = "sha2")]
+        KeywordSet::SHA256 => Some(Box::new(RustCrypto(sha2::Sha256::default()))),
+        #[cfg(feature = "sha2")]
+        KeywordSet::SHA384 => Some(Box::new(RustCrypto(sha2::Sha384::default()))),
+        #[cfg(feature = "sha2")]
+        KeywordSet::SHA512 => Some(Box::new(RustCrypto(sha2::Sha512::default()))),
+        _ => None,
+    })
+}
+
+/// An empty directory for the test `name` to work in.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mtree-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_params_from_metadata() {
+    let root = test_dir("metadata");
+    fs::write(root.join("file"), b"hello").unwrap();
+    let metadata = fs::metadata(root.join("file")).unwrap();
+    let params = Params::from_metadata(&metadata);
+    assert_eq!(params.file_type, Some(FileType::File));
+    assert_eq!(params.size, Some(5));
+    assert_eq!(params.inode, Some(metadata.ino()));
+    assert_eq!(params.nlink, Some(1));
+    assert_eq!(params.device, None);
+    assert_eq!(
+        params.resident_device.unwrap().to_dev_t(Format::Native),
+        Some(metadata.dev())
+    );
+
+    let params = Params::from_metadata(&fs::metadata(&root).unwrap());
+    assert_eq!(params.file_type, Some(FileType::Directory));
+    assert_eq!(params.size, None);
+
+    if let Ok(metadata) = fs::metadata("/dev/null") {
+        let params = Params::from_metadata(&metadata);
+        assert_eq!(params.file_type, Some(FileType::CharacterDevice));
+        assert_eq!(
+            params.device.unwrap().to_dev_t(Format::Native),
+            Some(metadata.rdev())
+        );
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_scan() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    let root = test_dir("scan");
+    fs::create_dir(root.join("b")).unwrap();
+    fs::write(root.join("b/file"), b"hello").unwrap();
+    fs::set_permissions(root.join("b/file"), fs::Permissions::from_mode(0o640)).unwrap();
+    fs::write(root.join("a"), b"").unwrap();
+    symlink("b/file", root.join("c")).unwrap();
+
+    let entries = Scanner::new(&root)
+        .scan()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let paths = entries.iter().map(Entry::path).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            Path::new("."),
+            Path::new("./a"),
+            Path::new("./b"),
+            Path::new("./b/file"),
+            Path::new("./c"),
+        ]
+    );
+    assert_eq!(entries[0].file_type(), Some(FileType::Directory));
+    assert_eq!(entries[0].size(), None);
+    assert_eq!(entries[3].size(), Some(5));
+    assert_eq!(entries[3].mode().unwrap().to_string(), "rw-r-----");
+    assert_eq!(entries[4].file_type(), Some(FileType::SymbolicLink));
+    assert_eq!(entries[4].link(), Some(Path::new("b/file")));
+
+    let mut writer = MTreeWriter::new(Vec::new());
+    Scanner::new(&root).write_to(&mut writer).unwrap();
+    let written = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(written.lines().count(), 5);
+    assert!(written.starts_with(". type=dir"));
+
+    let entries = Scanner::new(&root)
+        .keywords(KeywordSet::TYPE | KeywordSet::SIZE | KeywordSet::INODE)
+        .scan()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let keywords = |entry: &Entry| {
+        let params = entry.params();
+        params.keywords().map(|k| k.kind()).collect::<Vec<_>>()
+    };
+    assert_eq!(
+        keywords(&entries[3]),
+        vec![KeywordSet::TYPE, KeywordSet::INODE, KeywordSet::SIZE]
+    );
+    assert_eq!(
+        keywords(&entries[4]),
+        vec![KeywordSet::TYPE, KeywordSet::INODE]
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_scan_exclude() {
+    let root = test_dir("scan-exclude");
+    for dir in ["src", ".git", "target/debug"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for file in [
+        "src/main.rs",
+        "src/main.o",
+        ".git/HEAD",
+        "target/debug/main",
+    ] {
+        fs::write(root.join(file), b"").unwrap();
+    }
+
+    let paths = |scanner: Scanner| {
+        scanner
+            .scan()
+            .map(|entry| entry.unwrap().path().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let scanner = Scanner::new(&root)
+        .exclude("*.o")
+        .exclude("./target/*")
+        .exclude_if(|path| path.ends_with(".git"));
+    assert_eq!(
+        paths(scanner),
+        vec![
+            Path::new("."),
+            Path::new("./src"),
+            Path::new("./src/main.rs"),
+            Path::new("./target"),
+        ]
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_scan_follow_symlinks() {
+    use std::os::unix::fs::symlink;
+    let root = test_dir("scan-follow");
+    fs::create_dir(root.join("dir")).unwrap();
+    fs::write(root.join("dir/file"), b"hello").unwrap();
+    symlink("dir", root.join("link")).unwrap();
+    symlink("..", root.join("dir/parent")).unwrap();
+    symlink("missing", root.join("dangling")).unwrap();
+
+    let entries = Scanner::new(&root)
+        .follow_symlinks(true)
+        .scan()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let summary = entries
+        .iter()
+        .map(|entry| (entry.path().to_str().unwrap(), entry.file_type().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (".", FileType::Directory),
+            ("./dangling", FileType::SymbolicLink),
+            ("./dir", FileType::Directory),
+            ("./dir/file", FileType::File),
+            ("./dir/parent", FileType::Directory),
+            ("./link", FileType::Directory),
+            ("./link/file", FileType::File),
+            ("./link/parent", FileType::Directory),
+        ]
+    );
+    assert_eq!(entries[6].size(), Some(5));
+    assert_eq!(entries[1].link(), Some(Path::new("missing")));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+#[cfg(all(feature = "md-5", feature = "sha2"))]
+fn test_scan_digests() {
+    use crate::util::FromHex;
+    let root = test_dir("scan-digests");
+    fs::write(root.join("file"), b"hello\n").unwrap();
+
+    let entries = Scanner::new(&root)
+        .keywords(KeywordSet::TYPE | KeywordSet::MD5 | KeywordSet::SHA256)
+        .scan()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(entries[0].md5(), None);
+    assert_eq!(
+        entries[1].md5(),
+        u128::from_hex(b"b1946ac92492d2347c6235b4d2611184")
+    );
+    assert_eq!(
+        entries[1].sha256(),
+        <[u8; 32]>::from_hex(b"5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
+            .as_ref()
+    );
+    assert_eq!(entries[1].sha512(), None);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_progress() {
+    use crate::{MTree, Verifier};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        paths: Mutex<Vec<PathBuf>>,
+        bytes: Mutex<u64>,
+    }
+
+    impl Progress for Recorder {
+        fn entry(&self, path: &Path) {
+            self.paths.lock().unwrap().push(path.to_owned());
+        }
+
+        fn hashed(&self, bytes: u64) {
+            *self.bytes.lock().unwrap() += bytes;
+        }
+    }
+
+    let root = test_dir("progress");
+    fs::write(root.join("file"), b"hello\n").unwrap();
+    let recorder = Arc::new(Recorder::default());
+    let entries = Scanner::new(&root)
+        .keywords(KeywordSet::TYPE | KeywordSet::SIZE | readable_keywords() & KeywordSet::MD5)
+        .progress(recorder.clone())
+        .scan()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        *recorder.paths.lock().unwrap(),
+        [Path::new("."), Path::new("./file")]
+    );
+    let hashed = if cfg!(any(feature = "md-5", feature = "openssl")) {
+        6
+    } else {
+        0
+    };
+    assert_eq!(*recorder.bytes.lock().unwrap(), hashed);
+
+    let recorder = Arc::new(Recorder::default());
+    let spec = b"./file type=file md5digest=b1946ac92492d2347c6235b4d2611184\n";
+    Verifier::new(&root)
+        .progress(recorder.clone())
+        .verify(MTree::from_reader(&spec[..]))
+        .for_each(drop);
+    assert_eq!(*recorder.paths.lock().unwrap(), [Path::new("./file")]);
+    assert_eq!(*recorder.bytes.lock().unwrap(), hashed);
+    assert_eq!(entries.len(), 2);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn test_hash_reader() {
+    use crate::util::FromHex;
+
+    let mut params = Params::default();
+    hash_reader(&b"hello\n"[..], readable_keywords(), &mut params).unwrap();
+    if readable_keywords().contains(KeywordSet::MD5) {
+        assert_eq!(params.md5, Some(0xb1946ac92492d2347c6235b4d2611184));
+    }
+    if readable_keywords().contains(KeywordSet::SHA1) {
+        assert_eq!(
+            params.sha1,
+            FromHex::from_hex(b"f572d396fae9206628714fb2ce00f72e94f2258f")
+        );
+    }
+    if readable_keywords().contains(KeywordSet::SHA256) {
+        assert_eq!(
+            params.sha256,
+            FromHex::from_hex(b"5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
+        );
+    }
+}
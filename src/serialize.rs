@@ -0,0 +1,441 @@
+//! serde support, enabled by the `serde` feature.
+//!
+//! Digests are written as lowercase hex strings, and paths and names as strings when they are
+//! valid UTF-8 (and as bytes otherwise). File types, modes, flags and device formats are written
+//! the way they appear in an mtree file.
+use crate::parser::{FileFlags, FileMode, FileType, Format};
+use crate::util::{bytes_to_path, path_to_bytes, FromHex};
+#[cfg(unix)]
+use crate::{Finding, Verification, VerificationReport};
+use crate::{Gname, ParamMismatch, Uname};
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+#[cfg(unix)]
+use serde::ser::SerializeMap;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use smallvec::SmallVec;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A string, or some bytes if it isn't valid UTF-8.
+pub(crate) trait ByteString: Sized {
+    fn to_bytes(&self) -> Cow<'_, [u8]>;
+    fn from_byte_vec(bytes: Vec<u8>) -> Self;
+}
+
+impl ByteString for Vec<u8> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+
+    fn from_byte_vec(bytes: Vec<u8>) -> Self {
+        bytes
+    }
+}
+
+impl ByteString for Uname {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+
+    fn from_byte_vec(bytes: Vec<u8>) -> Self {
+        Uname(SmallVec::from_vec(bytes))
+    }
+}
+
+impl ByteString for Gname {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self)
+    }
+
+    fn from_byte_vec(bytes: Vec<u8>) -> Self {
+        Gname(SmallVec::from_vec(bytes))
+    }
+}
+
+impl ByteString for PathBuf {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        path_to_bytes(self)
+    }
+
+    fn from_byte_vec(bytes: Vec<u8>) -> Self {
+        bytes_to_path(&bytes).into_owned()
+    }
+}
+
+/// Serializes a byte string.
+struct BytesRef<'a>(Cow<'a, [u8]>);
+
+impl Serialize for BytesRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(&self.0) {
+            Ok(s) => serializer.serialize_str(s),
+            Err(_) => serializer.serialize_bytes(&self.0),
+        }
+    }
+}
+
+/// Deserializes a byte string from a string, bytes, or a sequence of bytes.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Bytes<T>(T);
+
+impl<'de, T: ByteString> Deserialize<'de> for Bytes<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+                Ok(v.as_bytes().to_owned())
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_owned())
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(bytes)
+            }
+        }
+
+        let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+        Ok(Bytes(T::from_byte_vec(bytes)))
+    }
+}
+
+/// `#[serde(with)]` for paths and names.
+pub(crate) mod byte_string {
+    use super::*;
+
+    pub fn serialize<T: ByteString, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        BytesRef(value.to_bytes()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: ByteString, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        Bytes::deserialize(deserializer).map(|b| b.0)
+    }
+}
+
+/// `#[serde(with)]` for optional paths and names.
+pub(crate) mod option_byte_string {
+    use super::*;
+
+    pub fn serialize<T: ByteString, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .as_ref()
+            .map(|v| BytesRef(v.to_bytes()))
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: ByteString, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        Option::<Bytes<T>>::deserialize(deserializer).map(|b| b.map(|b| b.0))
+    }
+}
+
+/// `#[serde(with)]` for maps keyed by byte strings, with byte string values (e.g. xattrs).
+pub(crate) mod byte_string_map {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        map: &BTreeMap<Vec<u8>, Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            map.iter()
+                .map(|(k, v)| (BytesRef(k.into()), BytesRef(v.into()))),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, D::Error> {
+        let map = BTreeMap::<Bytes<Vec<u8>>, Bytes<Vec<u8>>>::deserialize(deserializer)?;
+        Ok(map.into_iter().map(|(k, v)| (k.0, v.0)).collect())
+    }
+}
+
+/// `#[serde(with)]` for maps keyed by byte strings (e.g. extensions).
+pub(crate) mod byte_string_keys {
+    use super::*;
+
+    pub fn serialize<V: Serialize, S: Serializer>(
+        map: &BTreeMap<Vec<u8>, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(k, v)| (BytesRef(k.into()), v)))
+    }
+
+    pub fn deserialize<'de, V: Deserialize<'de>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Vec<u8>, V>, D::Error> {
+        let map = BTreeMap::<Bytes<Vec<u8>>, V>::deserialize(deserializer)?;
+        Ok(map.into_iter().map(|(k, v)| (k.0, v)).collect())
+    }
+}
+
+/// A digest that is written as hex.
+pub(crate) trait Digest: FromHex {
+    fn to_hex(&self) -> String;
+}
+
+impl Digest for u128 {
+    fn to_hex(&self) -> String {
+        format!("{:032x}", self)
+    }
+}
+
+impl<const N: usize> Digest for [u8; N] {
+    fn to_hex(&self) -> String {
+        self.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// `#[serde(with)]` for optional digests.
+pub(crate) mod option_hex {
+    use super::*;
+
+    pub fn serialize<T: Digest, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.as_ref().map(Digest::to_hex).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: Digest, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(hex) => T::from_hex(hex.as_bytes())
+                .map(Some)
+                .ok_or_else(|| de::Error::custom(format!(r#""{}" is not a valid digest"#, hex))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Implement `Serialize` and `Deserialize` using the mtree text form of a value.
+macro_rules! impl_serde_str {
+    ($type:ty, $expecting:expr, |$v:ident| $to_string:expr, |$s:ident| $from_bytes:expr) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let $v = self;
+                serializer.serialize_str(&$to_string)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let $s = String::deserialize(deserializer)?;
+                $from_bytes
+                    .ok_or_else(|| de::Error::invalid_value(de::Unexpected::Str(&$s), &$expecting))
+            }
+        }
+    };
+}
+
+impl_serde_str!(FileType, "a file type", |v| v.as_str(), |s| {
+    FileType::from_bytes(s.as_bytes()).ok()
+});
+impl_serde_str!(
+    FileMode,
+    "an octal or symbolic file mode",
+    |v| format!("{:o}", v),
+    |s| FileMode::from_bytes(s.as_bytes()).ok()
+);
+impl_serde_str!(
+    FileFlags,
+    "a comma separated list of file flags",
+    |v| v.to_string(),
+    |s| FileFlags::from_bytes(s.as_bytes()).ok()
+);
+impl_serde_str!(Format, "a device format", |v| v.as_str(), |s| {
+    Format::from_bytes(s.as_bytes())
+});
+
+/// Serializes a path as a byte string.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> BytesRef<'_> {
+    BytesRef(path_to_bytes(path))
+}
+
+impl Serialize for ParamMismatch {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (from, to) = self.values();
+        let mut s = serializer.serialize_struct("ParamMismatch", 3)?;
+        s.serialize_field("keyword", &self.name())?;
+        s.serialize_field("from", &from)?;
+        s.serialize_field("to", &to)?;
+        s.end()
+    }
+}
+
+#[cfg(unix)]
+impl Serialize for Verification {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let status = match self {
+            Verification::Ok(_) => "ok",
+            Verification::Missing(_) => "missing",
+            Verification::OptionalMissing(_) => "optional_missing",
+            Verification::Extra(_) => "extra",
+            Verification::Mismatch(..) => "mismatch",
+        };
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("path", &path_bytes(self.path()))?;
+        map.serialize_entry("status", status)?;
+        if let Verification::Mismatch(_, mismatches) = self {
+            map.serialize_entry("mismatches", mismatches)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(unix)]
+impl Serialize for Finding<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("path", &path_bytes(self.path()))?;
+        match self {
+            Finding::Missing(_) => map.serialize_entry("status", "missing")?,
+            Finding::Extra(_) => map.serialize_entry("status", "extra")?,
+            Finding::Mismatch(_, mismatch) => {
+                let (from, to) = mismatch.values();
+                map.serialize_entry("status", "mismatch")?;
+                map.serialize_entry("keyword", &mismatch.name())?;
+                map.serialize_entry("from", &from)?;
+                map.serialize_entry("to", &to)?;
+            }
+        }
+        map.end()
+    }
+}
+
+#[cfg(unix)]
+impl Serialize for VerificationReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        /// Serializes a list of paths.
+        struct Paths<'a>(&'a [PathBuf]);
+
+        impl Serialize for Paths<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().map(|path| path_bytes(path)))
+            }
+        }
+
+        /// Serializes the mismatches of each path.
+        struct Mismatches<'a>(&'a BTreeMap<PathBuf, Vec<ParamMismatch>>);
+
+        impl Serialize for Mismatches<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_map(self.0.iter().map(|(path, m)| (path_bytes(path), m)))
+            }
+        }
+
+        let mut s = serializer.serialize_struct("VerificationReport", 4)?;
+        s.serialize_field("ok", &self.ok)?;
+        s.serialize_field("mismatches", &Mismatches(&self.mismatches))?;
+        s.serialize_field("missing", &Paths(&self.missing))?;
+        s.serialize_field("extra", &Paths(&self.extra))?;
+        s.end()
+    }
+}
+
+#[test]
+fn test_serde_json() {
+    use crate::{Entry, MTree};
+    let raw = concat!(
+        "/set type=file uid=0 mode=644\n",
+        r"./usr/bin/a\040b size=10 flags=uchg link=\377 xattr.user.a=YQ== ",
+        "sha256digest=db1941d00645bfaab04dd3898ee8b8484874f4880bf03f717adf43a9f30d9b8c\n",
+        "./dev/sda type=block device=linux,8,0\n",
+    );
+    let entries = MTree::from_reader(raw.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let json = serde_json::to_value(&entries).unwrap();
+    let params = &json[0]["params"];
+    assert_eq!(json[0]["path"], "./usr/bin/a b");
+    assert_eq!(params["file_type"], "file");
+    assert_eq!(params["mode"], "644");
+    assert_eq!(params["flags"], "uchg");
+    assert_eq!(params["link"], serde_json::json!([255]));
+    assert_eq!(params["xattrs"]["user.a"], "YQ==");
+    assert_eq!(
+        params["sha256"],
+        "db1941d00645bfaab04dd3898ee8b8484874f4880bf03f717adf43a9f30d9b8c"
+    );
+    assert_eq!(json[1]["params"]["device"]["format"], "linux");
+    let back: Vec<Entry> = serde_json::from_value(json).unwrap();
+    assert_eq!(back, entries);
+    // missing params are unset
+    let entry: Entry = serde_json::from_str(r#"{"path": "./a", "params": {"size": 1}}"#).unwrap();
+    assert_eq!(entry.size(), Some(1));
+    assert!(serde_json::from_str::<Entry>(r#"{"path": "./a", "params": {"mode": "99"}}"#).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_serde_verification() {
+    let report = vec![
+        Verification::Ok("./a".into()),
+        Verification::Mismatch(
+            "./b".into(),
+            vec![ParamMismatch::Mode(
+                Some(FileMode::from(0o644)),
+                Some(FileMode::from(0o600)),
+            )],
+        ),
+        Verification::Missing("./c".into()),
+    ]
+    .into_iter()
+    .collect::<VerificationReport>();
+    assert_eq!(
+        serde_json::to_value(&report).unwrap(),
+        serde_json::json!({
+            "ok": 1,
+            "mismatches": {"./b": [{"keyword": "mode", "from": "644", "to": "600"}]},
+            "missing": ["./c"],
+            "extra": [],
+        })
+    );
+    let findings = report
+        .findings()
+        .map(|finding| serde_json::to_string(&finding).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        findings,
+        vec![
+            r#"{"path":"./b","status":"mismatch","keyword":"mode","from":"644","to":"600"}"#,
+            r#"{"path":"./c","status":"missing"}"#,
+        ]
+    );
+    assert_eq!(
+        serde_json::to_value(Verification::Extra("./d".into())).unwrap(),
+        serde_json::json!({"path": "./d", "status": "extra"})
+    );
+}
@@ -0,0 +1,512 @@
+//! Holding a whole spec in memory, indexed by path.
+#[cfg(feature = "sha2")]
+use crate::writer::full_path;
+use crate::{Entry, FileType, KeywordSet, ParserError, ParserErrorKind};
+use std::collections::hash_map::{self, HashMap};
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::path::{Component, Path, PathBuf};
+use std::slice;
+use std::time::SystemTime;
+use std::vec;
+
+/// The entries of a spec, in order, with an index to look them up by path.
+///
+/// Entries are looked up by [`Entry::spec_path`], ignoring a leading `/` or `./`, so
+/// `/usr/bin/gedit`, `./usr/bin/gedit` and `usr/bin/gedit` all find the entry for
+/// `./usr/bin/gedit`. If the spec has more than one entry for a path, the last one is found.
+///
+/// ```
+/// use mtree::{MTree, MTreeSpec};
+///
+/// let spec = "\
+/// ./usr/bin type=dir
+/// ./usr/bin/gedit type=file size=1234
+/// ";
+/// let spec = MTree::from_reader(spec.as_bytes())
+///     .collect::<Result<MTreeSpec, _>>()
+///     .unwrap();
+/// assert_eq!(spec.get("/usr/bin/gedit").unwrap().size(), Some(1234));
+/// assert!(!spec.contains("/usr/bin/vim"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MTreeSpec {
+    /// The entries, in order.
+    entries: Vec<Entry>,
+    /// The index of the last entry for each path, keyed by [`key`].
+    index: HashMap<PathBuf, usize>,
+}
+
+impl MTreeSpec {
+    /// An empty spec.
+    pub fn new() -> MTreeSpec {
+        MTreeSpec::default()
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add an entry to the end of the spec.
+    pub fn push(&mut self, entry: Entry) {
+        self.index
+            .insert(key(entry.spec_path()), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// Sort the entries into canonical order (see [`Entry::cmp_canonical`]), so that a spec
+    /// written from them is the same however they were found.
+    ///
+    /// Entries with the same path are kept in the order they were in.
+    pub fn sort(&mut self) {
+        self.entries.sort_by(Entry::cmp_canonical);
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| (key(entry.spec_path()), index))
+            .collect();
+    }
+
+    /// The paths that have more than one entry, in the order they first appear.
+    pub fn duplicates(&self) -> Vec<&Path> {
+        let mut counts = HashMap::new();
+        let mut duplicates = Vec::new();
+        for entry in &self.entries {
+            let count = counts.entry(key(entry.spec_path())).or_insert(0);
+            *count += 1;
+            if *count == 2 {
+                duplicates.push(entry.spec_path());
+            }
+        }
+        duplicates
+    }
+
+    /// Leave just one entry for each path, in the place of the first, combining the entries for
+    /// a path according to `policy`.
+    ///
+    /// With [`DuplicatePolicy::Error`], if there are entries for a path with different params,
+    /// this fails with [`ParserErrorKind::DuplicatePath`] and the spec is left as it was.
+    ///
+    /// ```
+    /// use mtree::{DuplicatePolicy, MTree, MTreeSpec};
+    ///
+    /// let spec = "\
+    /// ./a type=file size=1
+    /// ./a mode=644
+    /// ";
+    /// let mut spec = MTree::from_reader(spec.as_bytes())
+    ///     .collect::<Result<MTreeSpec, _>>()
+    ///     .unwrap();
+    /// assert!(spec.clone().dedup(DuplicatePolicy::Error).is_err());
+    /// spec.dedup(DuplicatePolicy::Merge).unwrap();
+    /// assert_eq!(spec.len(), 1);
+    /// assert_eq!(spec.get("a").unwrap().size(), Some(1));
+    /// ```
+    pub fn dedup(&mut self, policy: DuplicatePolicy) -> Result<(), ParserError> {
+        if policy == DuplicatePolicy::Error {
+            let mut first = HashMap::new();
+            for entry in &self.entries {
+                match first.entry(key(entry.spec_path())) {
+                    hash_map::Entry::Vacant(vacant) => {
+                        vacant.insert(entry);
+                    }
+                    hash_map::Entry::Occupied(first) if first.get().params() != entry.params() => {
+                        let path = entry.path_bytes().into_owned();
+                        return Err(ParserErrorKind::DuplicatePath { path }.into());
+                    }
+                    hash_map::Entry::Occupied(_) => (),
+                }
+            }
+        }
+        let mut index = HashMap::with_capacity(self.index.len());
+        let mut entries: Vec<Entry> = Vec::with_capacity(self.index.len());
+        for entry in self.entries.drain(..) {
+            match index.entry(key(entry.spec_path())) {
+                hash_map::Entry::Vacant(vacant) => {
+                    vacant.insert(entries.len());
+                    entries.push(entry);
+                }
+                hash_map::Entry::Occupied(first) => {
+                    let first = &mut entries[*first.get()];
+                    match policy {
+                        DuplicatePolicy::Error | DuplicatePolicy::KeepFirst => (),
+                        DuplicatePolicy::KeepLast => *first = entry,
+                        DuplicatePolicy::Merge => first.params_mut().merge(entry.params()),
+                    }
+                }
+            }
+        }
+        self.entries = entries;
+        self.index = index;
+        Ok(())
+    }
+
+    /// The entry for `path`.
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&Entry> {
+        let index = *self.index.get(&key(path.as_ref()))?;
+        Some(&self.entries[index])
+    }
+
+    /// The entry for `path`, to change its params.
+    pub fn get_mut(&mut self, path: impl AsRef<Path>) -> Option<&mut Entry> {
+        let index = *self.index.get(&key(path.as_ref()))?;
+        Some(&mut self.entries[index])
+    }
+
+    /// Whether there is an entry for `path`.
+    pub fn contains(&self, path: impl AsRef<Path>) -> bool {
+        self.index.contains_key(&key(path.as_ref()))
+    }
+
+    /// The entries, in order.
+    pub fn iter(&self) -> slice::Iter<'_, Entry> {
+        self.entries.iter()
+    }
+
+    /// The entries whose paths match the glob `pattern`, e.g. `usr/share/**/*.png`, in order.
+    ///
+    /// See [`Entry::matches`] for how paths are matched.
+    pub fn matching<'a>(
+        &'a self,
+        pattern: impl AsRef<[u8]> + 'a,
+    ) -> impl Iterator<Item = &'a Entry> + 'a {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.matches(&pattern))
+    }
+
+    /// Count the entries of each type, add up their sizes, and so on.
+    ///
+    /// ```
+    /// use mtree::{FileType, KeywordSet, MTree, MTreeSpec};
+    ///
+    /// let spec = "\
+    /// ./usr type=dir
+    /// ./usr/a type=file size=10 md5digest=b1946ac92492d2347c6235b4d2611184
+    /// ./usr/b type=file size=5
+    /// ";
+    /// let spec = MTree::from_reader(spec.as_bytes())
+    ///     .collect::<Result<MTreeSpec, _>>()
+    ///     .unwrap();
+    /// let stats = spec.stats();
+    /// assert_eq!(stats.types[&FileType::File], 2);
+    /// assert_eq!(stats.total_size, 15);
+    /// assert_eq!(stats.digests[&KeywordSet::MD5], 1);
+    /// ```
+    pub fn stats(&self) -> SpecStats {
+        let mut stats = SpecStats {
+            entries: self.entries.len(),
+            ..SpecStats::default()
+        };
+        for entry in &self.entries {
+            let params = entry.params();
+            if let Some(file_type) = params.file_type {
+                *stats.types.entry(file_type).or_insert(0) += 1;
+            }
+            if let Some(size) = params.size {
+                stats.total_size = stats.total_size.saturating_add(size);
+            }
+            for (keyword, present) in [
+                (KeywordSet::MD5, params.md5.is_some()),
+                (KeywordSet::RMD160, params.rmd160.is_some()),
+                (KeywordSet::SHA1, params.sha1.is_some()),
+                (KeywordSet::SHA256, params.sha256.is_some()),
+                (KeywordSet::SHA384, params.sha384.is_some()),
+                (KeywordSet::SHA512, params.sha512.is_some()),
+            ] {
+                if present {
+                    *stats.digests.entry(keyword).or_insert(0) += 1;
+                }
+            }
+            if let Some(time) = params.time {
+                stats.earliest_time = Some(stats.earliest_time.map_or(time, |t| t.min(time)));
+                stats.latest_time = Some(stats.latest_time.map_or(time, |t| t.max(time)));
+            }
+        }
+        stats
+    }
+
+    /// A SHA-256 hash of what the spec says, which is the same for specs that describe the
+    /// same files however they are written. This is enabled by the `sha2` feature.
+    ///
+    /// The entries are hashed in order of their paths, as lines with every parameter written
+    /// out, so neither the order of the lines nor the use of `/set` makes a difference. Only
+    /// the last entry for each path is included, as it's the one [`MTreeSpec::get`] finds.
+    ///
+    /// ```
+    /// use mtree::{MTree, MTreeSpec};
+    ///
+    /// let spec = |raw: &str| {
+    ///     MTree::from_reader(raw.as_bytes())
+    ///         .collect::<Result<MTreeSpec, _>>()
+    ///         .unwrap()
+    /// };
+    /// let a = spec("/set type=file mode=644\n./b size=2\n./a size=1\n");
+    /// let b = spec("./a type=file mode=0644 size=1\n./b type=file mode=644 size=2\n");
+    /// assert_eq!(a.fingerprint(), b.fingerprint());
+    /// ```
+    #[cfg(feature = "sha2")]
+    pub fn fingerprint(&self) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+
+        let mut paths = self.index.iter().collect::<Vec<_>>();
+        paths.sort();
+        let mut hasher = Sha256::new();
+        for (path, &index) in paths {
+            let path = Path::new(".").join(path).components().collect::<PathBuf>();
+            hasher.update(full_path(&path));
+            for keyword in self.entries[index].params().keywords() {
+                hasher.update(format!(" {}", keyword));
+            }
+            hasher.update(b"\n");
+        }
+        hasher.finalize().into()
+    }
+
+    /// The entries, in order.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Unwrap the entries, in order.
+    pub fn into_entries(self) -> Vec<Entry> {
+        self.entries
+    }
+}
+
+/// What [`MTreeSpec::dedup`] does with more than one entry for a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DuplicatePolicy {
+    /// Fail if the entries have different params, otherwise keep one of them.
+    #[default]
+    Error,
+    /// Keep the first entry.
+    KeepFirst,
+    /// Keep the last entry, which is the one [`MTreeSpec::get`] finds.
+    KeepLast,
+    /// Keep one entry, with the params of each later entry overlaid in turn (see
+    /// [`Params::merge`](crate::Params::merge)).
+    Merge,
+}
+
+/// A summary of the entries of a spec, made by [`MTreeSpec::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpecStats {
+    /// How many entries there are.
+    pub entries: usize,
+    /// How many entries there are of each type. Entries without a `type` aren't counted.
+    pub types: BTreeMap<FileType, usize>,
+    /// The total of the `size`s of the entries, in bytes.
+    pub total_size: u64,
+    /// How many entries have each digest, keyed by its keyword (e.g. [`KeywordSet::SHA256`]).
+    pub digests: BTreeMap<KeywordSet, usize>,
+    /// The earliest `time` of an entry.
+    pub earliest_time: Option<SystemTime>,
+    /// The latest `time` of an entry.
+    pub latest_time: Option<SystemTime>,
+}
+
+/// The path used to look up `path`, without any `/` or `.` components.
+fn key(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir | Component::RootDir))
+        .collect()
+}
+
+impl Extend<Entry> for MTreeSpec {
+    fn extend<T: IntoIterator<Item = Entry>>(&mut self, iter: T) {
+        for entry in iter {
+            self.push(entry);
+        }
+    }
+}
+
+impl FromIterator<Entry> for MTreeSpec {
+    fn from_iter<T: IntoIterator<Item = Entry>>(iter: T) -> MTreeSpec {
+        let mut spec = MTreeSpec::new();
+        spec.extend(iter);
+        spec
+    }
+}
+
+impl IntoIterator for MTreeSpec {
+    type Item = Entry;
+    type IntoIter = vec::IntoIter<Entry>;
+
+    fn into_iter(self) -> vec::IntoIter<Entry> {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MTreeSpec {
+    type Item = &'a Entry;
+    type IntoIter = slice::Iter<'a, Entry>;
+
+    fn into_iter(self) -> slice::Iter<'a, Entry> {
+        self.entries.iter()
+    }
+}
+
+#[test]
+fn test_mtree_spec() {
+    use crate::MTree;
+    let raw = b"\
+./usr type=dir
+./usr/bin/gedit type=file size=1
+./usr/bin/vim type=file size=2
+./usr/bin/gedit type=file size=3
+";
+    let mut spec = MTree::from_reader(&raw[..])
+        .collect::<Result<MTreeSpec, _>>()
+        .unwrap();
+    assert_eq!(spec.len(), 4);
+    assert_eq!(spec.get("/usr/bin/vim").unwrap().size(), Some(2));
+    assert_eq!(spec.get("usr/bin/gedit").unwrap().size(), Some(3));
+    assert_eq!(spec.get("./usr").unwrap().path(), Path::new("./usr"));
+    assert!(spec.contains("/usr"));
+    assert!(!spec.contains("/usr/bin"));
+    let paths = spec.iter().map(Entry::path).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            Path::new("./usr"),
+            Path::new("./usr/bin/gedit"),
+            Path::new("./usr/bin/vim"),
+            Path::new("./usr/bin/gedit")
+        ]
+    );
+
+    let matching = spec
+        .matching("usr/**/g*")
+        .map(Entry::path)
+        .collect::<Vec<_>>();
+    assert_eq!(matching, [Path::new("./usr/bin/gedit"); 2]);
+
+    let stats = spec.stats();
+    assert_eq!(stats.entries, 4);
+    assert_eq!(stats.types.get(&FileType::Directory), Some(&1));
+    assert_eq!(stats.types.get(&FileType::File), Some(&3));
+    assert_eq!(stats.total_size, 6);
+    assert!(stats.digests.is_empty());
+    assert_eq!(stats.earliest_time, None);
+
+    let mut sorted = spec.clone();
+    sorted.sort();
+    let paths = sorted.iter().map(Entry::path).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            Path::new("./usr"),
+            Path::new("./usr/bin/gedit"),
+            Path::new("./usr/bin/gedit"),
+            Path::new("./usr/bin/vim")
+        ]
+    );
+    assert_eq!(sorted.get("/usr/bin/gedit").unwrap().size(), Some(3));
+
+    spec.get_mut("/usr/bin/vim").unwrap().params_mut().size = Some(4);
+    assert_eq!(spec.entries()[2].size(), Some(4));
+}
+
+#[test]
+fn test_spec_stats_times() {
+    use crate::MTree;
+    use std::time::{Duration, UNIX_EPOCH};
+    let raw = b"\
+./a type=file time=20.0 sha256digest=5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03
+./b type=file time=10.5
+./c type=file time=30.0
+";
+    let stats = MTree::from_reader(&raw[..])
+        .collect::<Result<MTreeSpec, _>>()
+        .unwrap()
+        .stats();
+    assert_eq!(
+        stats.earliest_time,
+        Some(UNIX_EPOCH + Duration::from_millis(10_500))
+    );
+    assert_eq!(
+        stats.latest_time,
+        Some(UNIX_EPOCH + Duration::from_secs(30))
+    );
+    assert_eq!(stats.digests.get(&KeywordSet::SHA256), Some(&1));
+    assert_eq!(stats.total_size, 0);
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn test_fingerprint() {
+    use crate::MTree;
+    let spec = |raw: &[u8]| {
+        MTree::from_reader(raw)
+            .collect::<Result<MTreeSpec, _>>()
+            .unwrap()
+    };
+    let a = spec(b". type=dir\n/set type=file uid=0\n./b size=2\n./a size=1\n");
+    let b = spec(b"./a type=file uid=0 size=1\n./b size=2 uid=0 type=file\n. type=dir\n");
+    assert_eq!(a.fingerprint(), b.fingerprint());
+    // later entries for a path replace earlier ones
+    let c =
+        spec(b". type=dir\n./a size=5\n./a type=file uid=0 size=1\n./b type=file uid=0 size=2\n");
+    assert_eq!(a.fingerprint(), c.fingerprint());
+    let d = spec(b". type=dir\n./a type=file uid=0 size=1\n./b type=file uid=0 size=3\n");
+    assert_ne!(a.fingerprint(), d.fingerprint());
+    assert_ne!(MTreeSpec::new().fingerprint(), a.fingerprint());
+}
+
+#[test]
+fn test_dedup() {
+    use crate::MTree;
+    let raw = b"\
+./a type=file size=1
+./b type=dir
+./a size=2 mode=644
+./c type=file
+./b type=dir
+./a optional
+";
+    let spec = MTree::from_reader(&raw[..])
+        .collect::<Result<MTreeSpec, _>>()
+        .unwrap();
+    assert_eq!(spec.duplicates(), [Path::new("./a"), Path::new("./b")]);
+    let dedup = |policy| {
+        let mut spec = spec.clone();
+        spec.dedup(policy).map(|()| spec)
+    };
+
+    let error = dedup(DuplicatePolicy::Error).unwrap_err();
+    assert_eq!(
+        error.kind(),
+        &ParserErrorKind::DuplicatePath {
+            path: b"./a".to_vec()
+        }
+    );
+    let first = dedup(DuplicatePolicy::KeepFirst).unwrap();
+    let paths = first.iter().map(Entry::path).collect::<Vec<_>>();
+    assert_eq!(paths, ["./a", "./b", "./c"].map(Path::new));
+    assert_eq!(first.get("a").unwrap().size(), Some(1));
+    assert!(first.duplicates().is_empty());
+    let last = dedup(DuplicatePolicy::KeepLast).unwrap();
+    assert_eq!(last.get("a").unwrap().size(), None);
+    assert!(last.get("a").unwrap().optional());
+    let merged = dedup(DuplicatePolicy::Merge).unwrap();
+    let a = merged.get("a").unwrap();
+    assert_eq!(a.file_type(), Some(FileType::File));
+    assert_eq!(a.size(), Some(2));
+    assert!(a.mode().is_some() && a.optional());
+    assert_eq!(merged.entries()[0], *a);
+
+    // identical entries aren't a problem
+    let mut spec = MTree::from_reader(&b"./b type=dir\n./b type=dir\n"[..])
+        .collect::<Result<MTreeSpec, _>>()
+        .unwrap();
+    spec.dedup(DuplicatePolicy::Error).unwrap();
+    assert_eq!(spec.len(), 1);
+}
@@ -0,0 +1,183 @@
+//! The state kept between lines, shared by [`MTree`](crate::MTree) and
+//! [`LazyMTree`](crate::LazyMTree).
+use crate::util::{bytes_to_path, Words};
+use crate::{Error, Limit, MTreeOptions, ParserError, ParserErrorKind, Strictness};
+use std::env;
+use std::mem;
+use std::path::PathBuf;
+
+/// Where a parser is in a spec: the position of the next line, the directory that relative
+/// lines are in, and the problems skipped over so far.
+pub(crate) struct LineState {
+    /// The current working directory for dir calculations.
+    pub(crate) cwd: PathBuf,
+    /// The current directory relative to the root of the spec, e.g. `./usr`.
+    pub(crate) spec_cwd: PathBuf,
+    /// The directories we descended from, and their paths relative to the root of the spec,
+    /// restored in turn by `..`.
+    dir_stack: Vec<(PathBuf, PathBuf)>,
+    /// The number of lines read so far.
+    line: usize,
+    /// The byte offset of the start of the next line.
+    offset: u64,
+    /// The options the parser was constructed with.
+    pub(crate) options: MTreeOptions,
+    /// Problems that were skipped over, unless parsing is strict.
+    pub(crate) errors: Vec<ParserError>,
+    /// Set when an error has ended iteration.
+    pub(crate) finished: bool,
+}
+
+impl LineState {
+    /// The state at the start of a spec, with relative lines in the current directory.
+    pub(crate) fn new(options: MTreeOptions) -> LineState {
+        LineState {
+            cwd: env::current_dir().unwrap_or_default(),
+            spec_cwd: PathBuf::from("."),
+            dir_stack: Vec::new(),
+            line: 0,
+            offset: 0,
+            options,
+            errors: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Move on to a line `len` bytes long (including its newline), returning its line number
+    /// and offset.
+    pub(crate) fn start_line(&mut self, len: u64) -> (usize, u64) {
+        let (line_no, offset) = (self.line + 1, self.offset);
+        self.line = line_no;
+        self.offset += len;
+        (line_no, offset)
+    }
+
+    /// Move past a line that was skipped for being too long, `len` bytes including its
+    /// newline, returning its error.
+    pub(crate) fn long_line(&mut self, len: u64) -> ParserError {
+        let (line_no, offset) = self.start_line(len);
+        let max = self.options.max_line_length.unwrap_or_default();
+        ParserError::from(ParserErrorKind::LimitExceeded {
+            limit: Limit::LineLength,
+            max,
+        })
+        .at(line_no, offset)
+    }
+
+    /// Skip over the error in a line, unless parsing is strict, when it ends iteration.
+    pub(crate) fn line_error(&mut self, error: Error) -> Option<Error> {
+        match error {
+            Error::Parser(e) if self.options.strictness != Strictness::Strict => {
+                self.errors.push(e);
+                None
+            }
+            e => {
+                self.finished = true;
+                Some(e)
+            }
+        }
+    }
+
+    /// Deal with problems with single keywords, which are left out of the line.
+    ///
+    /// In lenient mode they are noted and the rest of the line is used, and otherwise the first
+    /// is an error for the whole line.
+    pub(crate) fn keyword_errors(
+        &mut self,
+        errors: impl IntoIterator<Item = ParserError>,
+    ) -> Result<(), ParserError> {
+        let mut errors = errors.into_iter();
+        match self.options.strictness {
+            Strictness::Strict | Strictness::SkipLines => errors.next().map_or(Ok(()), Err),
+            Strictness::Lenient => {
+                self.errors.extend(errors);
+                Ok(())
+            }
+        }
+    }
+
+    /// An error if `len` is over the limit set for `limit`, if any.
+    pub(crate) fn check_limit(
+        &self,
+        limit: Limit,
+        len: usize,
+        line_no: usize,
+        offset: u64,
+    ) -> Result<(), ParserError> {
+        self.options
+            .check_limit(limit, len)
+            .map_err(|e| e.at(line_no, offset))
+    }
+
+    /// An error if `line` has more keywords than allowed. Comments can have any number of words.
+    pub(crate) fn check_keywords(
+        &self,
+        line: &[u8],
+        line_no: usize,
+        offset: u64,
+    ) -> Result<(), ParserError> {
+        if self.options.max_keywords.is_none() {
+            return Ok(());
+        }
+        let mut words = Words::new(line);
+        match words.next() {
+            Some(first) if first[0] != b'#' => {
+                self.check_limit(Limit::Keywords, words.count(), line_no, offset)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// An error if the (unescaped) path of a full line is too long.
+    pub(crate) fn check_full_path(
+        &self,
+        path: &[u8],
+        line_no: usize,
+        offset: u64,
+    ) -> Result<(), ParserError> {
+        self.check_limit(Limit::PathLength, path.len(), line_no, offset)
+    }
+
+    /// The path of an entry on a relative line for the (unescaped) `path`, and its path
+    /// relative to the root of the spec.
+    pub(crate) fn relative_path(
+        &self,
+        path: &[u8],
+        line_no: usize,
+        offset: u64,
+    ) -> Result<(PathBuf, PathBuf), ParserError> {
+        if self.cwd.as_os_str().is_empty() {
+            let path = path.to_vec();
+            return Err(ParserError::from(ParserErrorKind::NoRoot { path }).at(line_no, offset));
+        }
+        let spec_cwd_len = self.spec_cwd.as_os_str().len();
+        let len = match path {
+            b"." => spec_cwd_len,
+            path => spec_cwd_len + 1 + path.len(),
+        };
+        self.check_limit(Limit::PathLength, len, line_no, offset)?;
+        // `.` names the current directory itself
+        Ok(if path == b"." {
+            (self.cwd.clone(), self.spec_cwd.clone())
+        } else {
+            let path = bytes_to_path(path);
+            (self.cwd.join(&path), self.spec_cwd.join(&path))
+        })
+    }
+
+    /// Make the directory of a relative line the current directory for following lines.
+    pub(crate) fn enter_dir(&mut self, path: PathBuf, spec_path: PathBuf) {
+        let parent = mem::replace(&mut self.cwd, path);
+        let spec_parent = mem::replace(&mut self.spec_cwd, spec_path);
+        self.dir_stack.push((parent, spec_parent));
+    }
+
+    /// Go back to the parent of the current directory, for a `..` line.
+    pub(crate) fn leave_dir(&mut self) {
+        // `..` at the top level has nowhere to go, so it is ignored.
+        if let Some((parent, spec_parent)) = self.dir_stack.pop() {
+            self.cwd = parent;
+            self.spec_cwd = spec_parent;
+        }
+    }
+}
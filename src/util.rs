@@ -1,45 +1,40 @@
 //! Utility misc stuff
-use crate::parser::{ParserError, ParserResult};
-use std::time::Duration;
+// Some of this is only used by the parts of the crate that need std, or unix.
+#![cfg_attr(not(all(feature = "std", unix)), allow(dead_code))]
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, Read};
+#[cfg(feature = "std")]
+use std::path::Path;
 
 /// Helper to parse a number from a slice of u8 in hexadecimal.
 pub trait FromHex: Sized {
     /// Parse a number from a slice of u8 in hexadecimal.
-    fn from_hex(input: &[u8]) -> ParserResult<Self>;
+    ///
+    /// Returns `None` if the input is not valid hex of the right length.
+    fn from_hex(input: &[u8]) -> Option<Self>;
 }
 
 /// Helper to parse a number from a slice of u8 in decimal.
 pub trait FromDec: Sized {
     /// Parse a number from a slice of u8 in decimal.
-    fn from_dec(input: &[u8]) -> ParserResult<Self>;
+    ///
+    /// Returns `None` if the input contains non-digits, or the number overflows.
+    fn from_dec(input: &[u8]) -> Option<Self>;
 }
 
 macro_rules! impl_FromDec_uint {
     ($from:ty) => {
         impl FromDec for $from {
-            fn from_dec(input: &[u8]) -> ParserResult<Self> {
+            fn from_dec(input: &[u8]) -> Option<Self> {
                 let mut acc: Self = 0;
-                for (idx, i) in input.iter().enumerate() {
-                    let val = from_dec_ch(*i).ok_or_else(|| {
-                        format!(
-                            r#"could not parse "{}" as a number, problem at char {}"#,
-                            String::from_utf8_lossy(input),
-                            idx
-                        )
-                    })?;
-                    acc = acc
-                        .checked_mul(10)
-                        .ok_or_else(|| {
-                            ParserError::from("could not parse integer - shift overflow".to_owned())
-                        })?
-                        .checked_add(val as $from)
-                        .ok_or_else(|| {
-                            ParserError::from(
-                                "could not parse integer - addition overflow".to_owned(),
-                            )
-                        })?;
+                for i in input {
+                    let val = from_dec_ch(*i)?;
+                    acc = acc.checked_mul(10)?.checked_add(val as $from)?;
                 }
-                Ok(acc)
+                Some(acc)
             }
         }
     };
@@ -50,129 +45,130 @@ impl_FromDec_uint!(u16);
 impl_FromDec_uint!(u32);
 impl_FromDec_uint!(u64);
 
-macro_rules! impl_FromHex_arr {
-    ($size:expr) => {
-        impl FromHex for [u8; $size] {
-            #[inline]
-            fn from_hex(input: &[u8]) -> ParserResult<Self> {
-                if input.len() != 2 * $size {
-                    return Err(format!(
-                        r#"input length ({}) must be twice the vec size ({}), but \
-                                                                        it is not (in "{}")"#,
-                        input.len(),
-                        $size,
-                        String::from_utf8_lossy(input)
-                    )
-                    .into());
-                }
-                let mut acc = [0; $size];
-                for (idx, chunk) in input.chunks(2).enumerate() {
-                    let high = from_hex_ch(chunk[0]).ok_or_else(|| {
-                        format!(
-                            r#"char at position {} in "{}" is not hex"#,
-                            2 * idx,
-                            String::from_utf8_lossy(input)
-                        )
-                    })?;
-                    let low = from_hex_ch(chunk[1]).ok_or_else(|| {
-                        format!(
-                            r#"char at position {} in "{}" is not hex"#,
-                            2 * idx + 1,
-                            String::from_utf8_lossy(input)
-                        )
-                    })?;
-                    acc[idx] = high * 16 + low;
-                }
-                Ok(acc)
-            }
-        }
-    };
-}
+/// The value of each hexadecimal digit, indexed by its character, or `0xff` for anything
+/// that isn't a hexadecimal digit.
+const HEX_VALUES: [u8; 256] = {
+    let mut table = [0xff; 256];
+    let mut i = 0;
+    while i < 10 {
+        table[b'0' as usize + i] = i as u8;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 6 {
+        table[b'a' as usize + i] = 10 + i as u8;
+        table[b'A' as usize + i] = 10 + i as u8;
+        i += 1;
+    }
+    table
+};
 
-impl_FromHex_arr!(16);
-impl_FromHex_arr!(20);
-impl_FromHex_arr!(32);
-
-macro_rules! impl_FromHex_newtype {
-    ($type:ty, $size:expr) => {
-        impl FromHex for $type {
-            #[inline]
-            fn from_hex(input: &[u8]) -> ParserResult<Self> {
-                if input.len() != 2 * $size {
-                    return Err(format!(
-                        r#"input length ({}) must be twice the vec size ({}), but \
-                                                                        it is not (in "{}")"#,
-                        input.len(),
-                        $size,
-                        String::from_utf8_lossy(input)
-                    )
-                    .into());
-                }
-                let mut acc = [0; $size];
-                for (idx, chunk) in input.chunks(2).enumerate() {
-                    let high = from_hex_ch(chunk[0]).ok_or_else(|| {
-                        format!(
-                            r#"char at position {} in "{}" is not hex"#,
-                            2 * idx,
-                            String::from_utf8_lossy(input)
-                        )
-                    })?;
-                    let low = from_hex_ch(chunk[1]).ok_or_else(|| {
-                        format!(
-                            r#"char at position {} in "{}" is not hex"#,
-                            2 * idx + 1,
-                            String::from_utf8_lossy(input)
-                        )
-                    })?;
-                    acc[idx] = high * 16 + low;
-                }
-                Ok(acc.into())
-            }
+impl<const N: usize> FromHex for [u8; N] {
+    #[inline]
+    fn from_hex(input: &[u8]) -> Option<Self> {
+        if input.len() != 2 * N {
+            return None;
         }
-    };
+        let mut acc = [0; N];
+        // check for bad digits once at the end, so the loop doesn't branch
+        let mut invalid = 0;
+        for (byte, pair) in acc.iter_mut().zip(input.chunks_exact(2)) {
+            let (high, low) = (
+                HEX_VALUES[usize::from(pair[0])],
+                HEX_VALUES[usize::from(pair[1])],
+            );
+            invalid |= high | low;
+            *byte = high << 4 | low;
+        }
+        if invalid & 0xf0 != 0 {
+            return None;
+        }
+        Some(acc)
+    }
 }
 
-impl_FromHex_newtype!(Array48<u8>, 48);
-impl_FromHex_newtype!(Array64<u8>, 64);
-
 impl FromHex for u128 {
     /// Convert hex to u128
     ///
-    /// # Panics
-    ///
     /// The input length must be exactly 32.
     #[inline]
-    fn from_hex(input: &[u8]) -> ParserResult<Self> {
-        if input.len() != 32 {
-            return Err(format!(
-                r#"could not parse "{}" as a number, must be 32 chars"#,
-                String::from_utf8_lossy(input)
-            )
-            .into());
-        }
-        let mut acc: Self = 0;
-        for (idx, i) in input.iter().enumerate() {
-            let val = from_hex_ch(*i).ok_or_else(|| {
-                format!(
-                    r#"could not parse "{}" as a number, problem at char {}"#,
-                    String::from_utf8_lossy(input),
-                    idx
-                )
-            })?;
-            acc = acc * 16 + val as u128;
-        }
-        Ok(acc)
-    }
-}
-
-/// If possible, quickly convert a character of a hexadecimal number into a u8.
-#[inline]
-fn from_hex_ch(i: u8) -> Option<u8> {
-    match i {
-        b'0'..=b'9' => Some(i - b'0'),
-        b'a'..=b'f' => Some(i - b'a' + 10),
-        b'A'..=b'F' => Some(i - b'A' + 10),
-        _ => None,
+    fn from_hex(input: &[u8]) -> Option<Self> {
+        <[u8; 16]>::from_hex(input).map(u128::from_be_bytes)
+    }
+}
+
+#[test]
+fn test_from_hex() {
+    assert_eq!(
+        u128::from_hex(b"13c0a46c2fb9f18a1a237d4904b6916e"),
+        Some(0x13c0a46c2fb9f18a1a237d4904b6916e)
+    );
+    assert_eq!(<[u8; 16]>::from_hex(b"000102030405060708090a0B0c0D0e0F"), {
+        let mut acc = [0; 16];
+        for (idx, val) in acc.iter_mut().enumerate() {
+            *val = idx as u8;
+        }
+        Some(acc)
+    });
+    assert_eq!(u128::from_hex(b"13c0a46c2fb9f18a1a237d4904b6916"), None);
+    assert_eq!(<[u8; 20]>::from_hex(b"zz"), None);
+    assert_eq!(<[u8; 64]>::from_hex(&[b'f'; 128]), Some([0xff; 64]));
+    assert_eq!(<[u8; 48]>::from_hex(&[b'f'; 128]), None);
+    for bad in [
+        &b"g0"[..],
+        b"0G",
+        b"0 ",
+        b"/0",
+        b":0",
+        b"@0",
+        b"`0",
+        b"\xff0",
+    ] {
+        assert_eq!(<[u8; 1]>::from_hex(bad), None);
+    }
+    for ch in 0..=255u8 {
+        let expected = char::from(ch).to_digit(16).map(|digit| digit as u8);
+        assert_eq!(<[u8; 1]>::from_hex(&[b'0', ch]).map(|[b]| b), expected);
+    }
+    assert_eq!(u8::from_dec(b"255"), Some(255));
+    assert_eq!(u8::from_dec(b"256"), None);
+    assert_eq!(u64::from_dec(b"12a"), None);
+}
+
+/// Parse an unsigned number like `strtoul(3)` with base 0 does: hexadecimal with a `0x`
+/// prefix, octal with a leading `0`, and decimal otherwise.
+pub fn parse_number(input: &[u8]) -> Option<u32> {
+    let (digits, radix) = match input {
+        [b'0', b'x', rest @ ..] | [b'0', b'X', rest @ ..] => (rest, 16),
+        [b'0', rest @ ..] if !rest.is_empty() => (rest, 8),
+        _ => (input, 10),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut acc: u32 = 0;
+    for ch in digits {
+        let val = char::from(*ch).to_digit(radix)?;
+        acc = acc.checked_mul(radix)?.checked_add(val)?;
+    }
+    Some(acc)
+}
+
+#[test]
+fn test_parse_number() {
+    for (input, res) in [
+        (&b"0"[..], Some(0)),
+        (b"123", Some(123)),
+        (b"0x1F", Some(31)),
+        (b"017", Some(15)),
+        (b"4294967295", Some(u32::MAX)),
+        (b"4294967296", None),
+        (b"0x", None),
+        (b"018", None),
+        (b"", None),
+        (b"-1", None),
+    ] {
+        assert_eq!(parse_number(input), res);
     }
 }
 
@@ -194,22 +190,575 @@ pub fn from_oct_ch(i: u8) -> Option<u8> {
     }
 }
 
-/// Convert a time of format `<seconds>.<nanos>` into a rust `Duration`.
-pub fn parse_time(input: &[u8]) -> ParserResult<Duration> {
-    let error = || -> ParserError {
-        format!(
-            r#"couldn't parse time from "{}""#,
-            String::from_utf8_lossy(input)
-        )
-        .into()
+/// Decode the escape sequences that vis(3) uses to encode whitespace and special characters.
+///
+/// Both octal escapes (`\040`) and C-style escapes (`\s`, `\t`, `\\`, ...) are supported.
+/// Anything else following a backslash is left untouched. The input is only copied if it
+/// contains a backslash.
+pub fn unvis(input: &[u8]) -> Cow<'_, [u8]> {
+    if memchr::memchr(b'\\', input).is_none() {
+        return Cow::Borrowed(input);
+    }
+    let mut output = Vec::with_capacity(input.len());
+    let mut idx = 0;
+    while idx < input.len() {
+        let ch = input[idx];
+        let rest = &input[idx + 1..];
+        if ch != b'\\' || rest.is_empty() {
+            output.push(ch);
+            idx += 1;
+            continue;
+        }
+        // octal escapes are always 3 digits, and must fit in a byte
+        if rest.len() >= 3 && rest[0] <= b'3' {
+            if let (Some(high), Some(mid), Some(low)) = (
+                from_oct_ch(rest[0]),
+                from_oct_ch(rest[1]),
+                from_oct_ch(rest[2]),
+            ) {
+                output.push(high << 6 | mid << 3 | low);
+                idx += 4;
+                continue;
+            }
+        }
+        let decoded = match rest[0] {
+            b'\\' => Some(b'\\'),
+            b'a' => Some(0x07),
+            b'b' => Some(0x08),
+            b'f' => Some(0x0c),
+            b'n' => Some(b'\n'),
+            b'r' => Some(b'\r'),
+            b's' => Some(b' '),
+            b't' => Some(b'\t'),
+            b'v' => Some(0x0b),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => {
+                output.push(decoded);
+                idx += 2;
+            }
+            None => {
+                output.push(ch);
+                idx += 1;
+            }
+        }
+    }
+    Cow::Owned(output)
+}
+
+#[test]
+fn test_unvis() {
+    for (input, res) in [
+        (&b"plain"[..], &b"plain"[..]),
+        (br"Blackmagic\040Design", b"Blackmagic Design"),
+        (br"a\sb\tc\nd", b"a b\tc\nd"),
+        (br"back\\slash", br"back\slash"),
+        (br"\043hash", b"#hash"),
+        (br"\777 not octal", br"\777 not octal"),
+        (br"unknown\q", br"unknown\q"),
+        (br"trailing\", br"trailing\"),
+    ] {
+        assert_eq!(&*unvis(input), res);
+    }
+    assert!(matches!(unvis(b"plain"), Cow::Borrowed(_)));
+}
+
+/// Escape a path or value so it is a single word in an mtree file, the inverse of `unvis`.
+///
+/// Whitespace, non-printable and non-ascii bytes, backslashes and glob characters are written
+/// as 3 digit octal escapes. The input is only copied if something needs escaping.
+pub fn vis(input: &[u8]) -> Cow<'_, [u8]> {
+    fn needs_escape(ch: u8) -> bool {
+        !ch.is_ascii_graphic() || matches!(ch, b'\\' | b'#' | b'*' | b'?' | b'[')
+    }
+    if !input.iter().any(|ch| needs_escape(*ch)) {
+        return Cow::Borrowed(input);
+    }
+    let mut output = Vec::with_capacity(input.len() + 8);
+    for ch in input {
+        if needs_escape(*ch) {
+            output.extend_from_slice(&[
+                b'\\',
+                b'0' + (ch >> 6),
+                b'0' + ((ch >> 3) & 0o7),
+                b'0' + (ch & 0o7),
+            ]);
+        } else {
+            output.push(*ch);
+        }
+    }
+    Cow::Owned(output)
+}
+
+#[test]
+fn test_vis() {
+    for (input, res) in [
+        (&b"plain"[..], &b"plain"[..]),
+        (b"Blackmagic Design", br"Blackmagic\040Design"),
+        (b"tab\tnl\n", br"tab\011nl\012"),
+        (br"back\slash", br"back\134slash"),
+        (b"#*?[", br"\043\052\077\133"),
+        ("caf\u{e9}".as_bytes(), br"caf\303\251"),
+    ] {
+        assert_eq!(&*vis(input), res);
+        assert_eq!(&*unvis(&vis(input)), input);
+    }
+    assert!(matches!(vis(b"plain"), Cow::Borrowed(_)));
+}
+
+/// The path with the (unescaped) bytes of a path from a spec.
+///
+/// On unix paths are bytes, so this is exact. Elsewhere paths are unicode, so any invalid UTF-8
+/// is replaced.
+#[cfg(all(feature = "std", unix))]
+pub fn bytes_to_path(bytes: &[u8]) -> Cow<'_, Path> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    Cow::Borrowed(Path::new(OsStr::from_bytes(bytes)))
+}
+
+/// The path with the (unescaped) bytes of a path from a spec.
+///
+/// On unix paths are bytes, so this is exact. Elsewhere paths are unicode, so any invalid UTF-8
+/// is replaced.
+#[cfg(all(feature = "std", not(unix)))]
+pub fn bytes_to_path(bytes: &[u8]) -> Cow<'_, Path> {
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(path) => Cow::Borrowed(Path::new(path)),
+        Cow::Owned(path) => Cow::Owned(path.into()),
+    }
+}
+
+/// The bytes of `path`, as they are written in a spec (before escaping).
+///
+/// On unix paths are bytes, so this is exact. Elsewhere paths are unicode, and are written as
+/// UTF-8, with anything that isn't valid unicode replaced.
+#[cfg(all(feature = "std", unix))]
+pub fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// The bytes of `path`, as they are written in a spec (before escaping).
+///
+/// On unix paths are bytes, so this is exact. Elsewhere paths are unicode, and are written as
+/// UTF-8, with anything that isn't valid unicode replaced. Specs always separate directories
+/// with `/`, so on Windows `\` is replaced too.
+#[cfg(all(feature = "std", not(unix)))]
+pub fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    let bytes = match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
     };
+    if cfg!(windows) && bytes.contains(&b'\\') {
+        let bytes = bytes.iter().map(|&ch| if ch == b'\\' { b'/' } else { ch });
+        Cow::Owned(bytes.collect())
+    } else {
+        bytes
+    }
+}
+
+/// Compare two paths from specs in canonical order: a byte-wise comparison of each directory
+/// name in turn, so that everything in a directory comes straight after it.
+///
+/// A leading `/` or `.` is ignored.
+#[cfg(feature = "std")]
+pub fn canonical_cmp(a: &Path, b: &Path) -> core::cmp::Ordering {
+    use std::path::Component;
+    fn names(path: &Path) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        path.components()
+            .filter(|c| !matches!(c, Component::CurDir | Component::RootDir))
+            .map(|c| path_to_bytes(Path::new(c.as_os_str())))
+    }
+    names(a).cmp(names(b))
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_canonical_cmp() {
+    let mut paths = ["./a/b", "./a.txt", ".", "./a", "/a/a", "./B", "./a/b/c"]
+        .map(Path::new)
+        .to_vec();
+    paths.sort_by(|a, b| canonical_cmp(a, b));
+    assert_eq!(
+        paths,
+        [".", "./B", "./a", "/a/a", "./a/b", "./a/b/c", "./a.txt"].map(Path::new)
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_path_bytes() {
+    for path in [&b"./usr/bin"[..], b"caf\xc3\xa9", b""] {
+        assert_eq!(&*path_to_bytes(&bytes_to_path(path)), path);
+    }
+    #[cfg(unix)]
+    assert_eq!(&*path_to_bytes(&bytes_to_path(b"\xff")), b"\xff");
+    #[cfg(windows)]
+    assert_eq!(&*path_to_bytes(Path::new(r"usr\bin")), b"usr/bin");
+}
+
+/// Whether `ch` separates words on a line: a space or a tab.
+pub fn is_space(ch: u8) -> bool {
+    ch == b' ' || ch == b'\t'
+}
+
+/// An iterator over the words of a line separated by runs of spaces and tabs, found with
+/// `memchr`.
+#[derive(Debug, Clone)]
+pub struct Words<'a> {
+    /// The rest of the line.
+    rest: &'a [u8],
+}
+
+impl<'a> Words<'a> {
+    /// The words of `line`.
+    pub fn new(line: &'a [u8]) -> Words<'a> {
+        Words { rest: line }
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let start = self.rest.iter().position(|ch| !is_space(*ch))?;
+        let rest = &self.rest[start..];
+        let end = memchr::memchr2(b' ', b'\t', rest).unwrap_or(rest.len());
+        self.rest = &rest[end..];
+        Some(&rest[..end])
+    }
+}
+
+/// What [`read_line`] found.
+#[cfg(feature = "std")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReadLine {
+    /// There are no more lines.
+    End,
+    /// A line was read.
+    Line,
+    /// The line was too long, so it was skipped. This holds the number of bytes in it,
+    /// including the newline.
+    TooLong(u64),
+}
+
+/// Read the next line of `reader` into `line`, without its newline.
+///
+/// A line longer than `max` bytes (not counting the newline) is skipped without being kept in
+/// memory, and `line` holds just its start.
+#[cfg(feature = "std")]
+pub fn read_line(
+    reader: &mut impl BufRead,
+    line: &mut Vec<u8>,
+    max: Option<usize>,
+) -> io::Result<ReadLine> {
+    line.clear();
+    let read = match max {
+        // read one more byte than allowed, so we can tell if the line is too long
+        Some(max) => reader.take(max as u64 + 1).read_until(b'\n', line)?,
+        None => reader.read_until(b'\n', line)?,
+    };
+    if read == 0 {
+        return Ok(ReadLine::End);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if max.is_some_and(|max| line.len() > max) {
+        return Ok(ReadLine::TooLong(read as u64 + skip_line(reader)?));
+    }
+    Ok(ReadLine::Line)
+}
+
+/// Skip the rest of the current line in `reader`, up to and including its newline, returning
+/// the number of bytes skipped.
+///
+/// Nothing is buffered beyond what `reader` holds already.
+#[cfg(feature = "std")]
+fn skip_line(reader: &mut impl BufRead) -> io::Result<u64> {
+    let mut skipped = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(skipped);
+        }
+        let (used, done) = match memchr::memchr(b'\n', buf) {
+            Some(end) => (end + 1, true),
+            None => (buf.len(), false),
+        };
+        reader.consume(used);
+        skipped += used as u64;
+        if done {
+            return Ok(skipped);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_read_line() {
+    let mut reader = &b"short\nmuch too long\nlast"[..];
+    let mut line = Vec::new();
+    let mut read = || {
+        let read = read_line(&mut reader, &mut line, Some(5)).unwrap();
+        (read, String::from_utf8(line.clone()).unwrap())
+    };
+    assert_eq!(read(), (ReadLine::Line, "short".to_owned()));
+    assert_eq!(read().0, ReadLine::TooLong(14));
+    assert_eq!(read(), (ReadLine::Line, "last".to_owned()));
+    assert_eq!(read().0, ReadLine::End);
+}
+
+#[test]
+fn test_words() {
+    let words = |line: &'static [u8]| Words::new(line).collect::<Vec<_>>();
+    assert_eq!(
+        words(b"./a  size=1 mode=644 "),
+        [&b"./a"[..], b"size=1", b"mode=644"]
+    );
+    assert_eq!(words(b"one"), [&b"one"[..]]);
+    assert_eq!(words(b"\t./a\t size=1\t"), [&b"./a"[..], b"size=1"]);
+    assert!(words(b"").is_empty());
+    assert!(words(b"   ").is_empty());
+}
+
+/// Whether `time` after the unix epoch can be held in a `SystemTime`, which has a smaller range
+/// than `Duration` on most platforms.
+#[cfg(feature = "std")]
+pub fn fits_system_time(time: Duration) -> bool {
+    std::time::UNIX_EPOCH.checked_add(time).is_some()
+}
+
+/// Without `std` there's no `SystemTime`, so any time fits.
+#[cfg(not(feature = "std"))]
+pub fn fits_system_time(_: Duration) -> bool {
+    true
+}
+
+/// Convert a time of format `<seconds>[.<fraction>]` into a rust `Duration`.
+///
+/// The fraction is normally exactly 9 digits (nanoseconds), but shorter fractions are scaled
+/// up, and any digits beyond nanosecond precision are ignored.
+pub fn parse_time(input: &[u8]) -> Option<Duration> {
     let mut time_iter = input.splitn(2, |ch| *ch == b'.');
-    let sec = time_iter.next().ok_or_else(error)?;
+    let sec = time_iter.next()?;
+    if sec.is_empty() {
+        return None;
+    }
     let sec = u64::from_dec(sec)?;
-    let nano = time_iter.next().ok_or_else(error)?;
-    let nano = u32::from_dec(nano)?;
-    Ok(Duration::new(sec, nano))
+    let nano = match time_iter.next() {
+        None => 0,
+        Some(fraction) => {
+            let (nano, rest) = fraction.split_at(fraction.len().min(9));
+            if !rest.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            (nano.len()..9).fold(u32::from_dec(nano)?, |nano, _| nano * 10)
+        }
+    };
+    Some(Duration::new(sec, nano))
 }
 
-newtype_array!(pub struct Array48(48));
-newtype_array!(pub struct Array64(64));
+#[test]
+fn test_parse_time() {
+    for (input, res) in [
+        (
+            &b"1523250074.300237174"[..],
+            Duration::new(1523250074, 300237174),
+        ),
+        (b"1523250074", Duration::new(1523250074, 0)),
+        (b"1523250074.", Duration::new(1523250074, 0)),
+        (b"1523250074.3", Duration::new(1523250074, 300000000)),
+        (b"1523250074.000001", Duration::new(1523250074, 1000)),
+        (
+            b"1523250074.3002371745678",
+            Duration::new(1523250074, 300237174),
+        ),
+    ] {
+        assert_eq!(parse_time(input), Some(res));
+    }
+    for input in [&b""[..], b".5", b"12a", b"12.3x", b"12.1234567890x"] {
+        assert_eq!(parse_time(input), None);
+    }
+}
+
+/// Match `name` against the shell glob `pattern`, like fnmatch(3) with `FNM_PATHNAME`.
+///
+/// `*` and `?` don't match `/`, `[...]` matches a set or range of bytes (negated with `!` or
+/// `^`), and a backslash matches the byte after it literally. As an extension, `**` matches
+/// anything including `/`, and `**/` also matches nothing, so `a/**/b` matches `a/b`.
+pub fn fnmatch(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', [b'*', rest @ ..])) => {
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                if fnmatch(after_slash, name) {
+                    return true;
+                }
+            }
+            (0..=name.len()).any(|start| fnmatch(rest, &name[start..]))
+        }
+        Some((b'*', rest)) => {
+            let mut name = name;
+            loop {
+                if fnmatch(rest, name) {
+                    return true;
+                }
+                match name.split_first() {
+                    Some((ch, tail)) if *ch != b'/' => name = tail,
+                    _ => return false,
+                }
+            }
+        }
+        Some((b'?', rest)) => match name.split_first() {
+            Some((ch, tail)) => *ch != b'/' && fnmatch(rest, tail),
+            None => false,
+        },
+        Some((b'[', rest)) => match (
+            name.split_first(),
+            match_bracket(rest, name.first().copied()),
+        ) {
+            (Some((_, tail)), Some((matched, rest))) => matched && fnmatch(rest, tail),
+            (None, Some(_)) => false,
+            // an unclosed `[` is just a `[`
+            (_, None) => name.first() == Some(&b'[') && fnmatch(rest, &name[1..]),
+        },
+        Some((b'\\', [ch, rest @ ..])) | Some((ch, rest)) => {
+            name.first() == Some(ch) && fnmatch(rest, &name[1..])
+        }
+    }
+}
+
+/// Match `ch` against the bracket expression at the start of `pattern` (just after the `[`).
+///
+/// Returns whether it matched and the rest of the pattern, or `None` if the `[` isn't closed.
+fn match_bracket(pattern: &[u8], ch: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negate, mut pattern) = match pattern.split_first() {
+        Some((b'!', rest)) | Some((b'^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    let ch = match ch {
+        Some(b'/') | None => return Some((false, skip_bracket(pattern)?)),
+        Some(ch) => ch,
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let (start, rest) = pattern.split_first()?;
+        if *start == b']' && !first {
+            return Some((matched != negate, rest));
+        }
+        first = false;
+        pattern = match rest {
+            [b'-', end, rest @ ..] if *end != b']' => {
+                matched |= (*start..=*end).contains(&ch);
+                rest
+            }
+            _ => {
+                matched |= *start == ch;
+                rest
+            }
+        };
+    }
+}
+
+/// Skip past the end of a bracket expression, returning `None` if it isn't closed.
+fn skip_bracket(pattern: &[u8]) -> Option<&[u8]> {
+    let end = pattern.iter().skip(1).position(|ch| *ch == b']')? + 1;
+    Some(&pattern[end + 1..])
+}
+
+#[test]
+fn test_fnmatch() {
+    for (pattern, name) in [
+        (&b"*.o"[..], &b"main.o"[..]),
+        (b"*", b""),
+        (b"a?c", b"abc"),
+        (b"[a-c]x", b"bx"),
+        (b"[!a-c]x", b"dx"),
+        (b"[]]", b"]"),
+        (br"\*", b"*"),
+        (b"[", b"["),
+        (b"./*/cache", b"./home/cache"),
+        (b"usr/**/*.png", b"usr/share/icons/a.png"),
+        (b"usr/**/*.png", b"usr/a.png"),
+        (b"usr/**", b"usr/share/icons"),
+    ] {
+        assert!(fnmatch(pattern, name), "{:?} {:?}", pattern, name);
+    }
+    for (pattern, name) in [
+        (&b"*.o"[..], &b"main.c"[..]),
+        (b"a?c", b"ac"),
+        (b"[a-c]x", b"dx"),
+        (b"[!a-c]x", b"ax"),
+        (br"\*", b"a"),
+        (b"./*/cache", b"./home/user/cache"),
+        (b"a?b", b"a/b"),
+        (b"usr/**/*.png", b"usr/a.png/b"),
+    ] {
+        assert!(!fnmatch(pattern, name), "{:?} {:?}", pattern, name);
+    }
+}
+
+/// The table for the CRC used by cksum(1), for each value of the top byte.
+#[cfg(feature = "std")]
+const CKSUM_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Compute the checksum of the data in `reader` the way cksum(1) does, as used by the `cksum`
+/// keyword.
+///
+/// This is the CRC defined by POSIX, which also covers the length of the data.
+///
+/// ```
+/// assert_eq!(mtree::cksum(&b"hello\n"[..]).unwrap(), 3015617425);
+/// ```
+#[cfg(feature = "std")]
+pub fn cksum(mut reader: impl Read) -> io::Result<u32> {
+    fn update(crc: u32, byte: u8) -> u32 {
+        crc << 8 ^ CKSUM_TABLE[usize::from((crc >> 24) as u8 ^ byte)]
+    }
+    let mut crc = 0;
+    let mut len: u64 = 0;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        crc = buf[..n].iter().fold(crc, |crc, byte| update(crc, *byte));
+        len += n as u64;
+    }
+    while len != 0 {
+        crc = update(crc, len as u8);
+        len >>= 8;
+    }
+    Ok(!crc)
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_cksum() {
+    assert_eq!(cksum(&b""[..]).unwrap(), 4294967295);
+    assert_eq!(cksum(&b"hello\n"[..]).unwrap(), 3015617425);
+    assert_eq!(cksum(io::repeat(0).take(100_000)).unwrap(), 1260869142);
+}
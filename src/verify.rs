@@ -0,0 +1,1031 @@
+//! Checking a directory tree against an mtree spec.
+use crate::parser::KeywordSet;
+use crate::scan::{read_params, readable_keywords, Scan};
+use crate::{Entry, Error, ParamMismatch, Params, Progress, Scanner};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Checks the files in a directory tree against the entries of a spec, like `mtree` without
+/// `-c`.
+///
+/// Files are found by each entry's [`spec_path`](Entry::spec_path), relative to the root, so
+/// it doesn't matter what directory the spec was read in or what root it was read with.
+///
+/// Only keywords that can be read from the filesystem are checked: `type`, `inode`, `mode`,
+/// `uid`, `gid`, `nlink`, `size`, `time`, `link` and `device`, along with any digests that the
+/// [`Scanner`] can compute with the features enabled. Devices match if their numbers are the
+/// same, whatever their format.
+///
+/// As in mtree(5), a missing file is not a failure if its entry has `optional`, only the
+/// existence of a file is checked if its entry has `nochange`, and nothing below a directory
+/// whose entry has `ignore` is checked or reported as extra.
+///
+/// ```no_run
+/// use mtree::{MTree, Verifier};
+/// use std::fs::File;
+///
+/// let spec = MTree::from_reader(File::open("/etc/mtree/BSD.root.dist").unwrap());
+/// for result in Verifier::new("/").verify(spec) {
+///     let result = result.unwrap();
+///     if !result.is_ok() {
+///         println!("{}", result);
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Verifier {
+    /// The directory the spec describes.
+    root: PathBuf,
+    /// The keywords to check.
+    keywords: KeywordSet,
+    /// How far apart times can be while still matching.
+    time_tolerance: Duration,
+    /// What to tell about the progress of the check.
+    progress: Option<Arc<dyn Progress>>,
+}
+
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Verifier")
+            .field("root", &self.root)
+            .field("keywords", &self.keywords)
+            .field("time_tolerance", &self.time_tolerance)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl Verifier {
+    /// Create a verifier for the tree at `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Verifier {
+        Verifier {
+            root: root.into(),
+            keywords: KeywordSet::all(),
+            time_tolerance: Duration::ZERO,
+            progress: None,
+        }
+    }
+
+    /// Only check the keywords in `keywords`, like `mtree -k` when checking.
+    ///
+    /// Files are still checked for existence, but other keywords in the spec are skipped. The
+    /// default is to check every keyword that can be read from the filesystem.
+    pub fn keywords(mut self, keywords: KeywordSet) -> Verifier {
+        self.keywords = keywords;
+        self
+    }
+
+    /// Treat times as matching if they are at most `tolerance` apart.
+    ///
+    /// Filesystems store times with different precision (e.g. FAT rounds to 2 seconds), so
+    /// comparing them exactly can report changes that didn't happen. The default is to compare
+    /// times exactly.
+    pub fn time_tolerance(mut self, tolerance: Duration) -> Verifier {
+        self.time_tolerance = tolerance;
+        self
+    }
+
+    /// Tell `progress` about each entry as it is checked, and each file as it is hashed.
+    ///
+    /// Looking for files that aren't in the spec isn't reported.
+    pub fn progress(mut self, progress: impl Progress + 'static) -> Verifier {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Check each of `entries`, and then look for files that aren't in `entries`.
+    ///
+    /// Errors reading `entries` are passed through, and the check carries on with the next
+    /// entry.
+    pub fn verify<I>(&self, entries: I) -> Verify<I::IntoIter>
+    where
+        I: IntoIterator<Item = Result<Entry, Error>>,
+    {
+        Verify {
+            verifier: self.clone(),
+            entries: entries.into_iter(),
+            listed: Listed::default(),
+            extras: None,
+        }
+    }
+
+    /// Check the file for a single entry.
+    ///
+    /// This honors `optional` and `nochange`, but `ignore` only affects [`Verifier::verify`].
+    pub fn verify_entry(&self, entry: &Entry) -> Result<Verification, Error> {
+        let path = spec_path(entry.spec_path());
+        let progress = self.progress.as_deref();
+        if let Some(progress) = progress {
+            progress.entry(&Path::new(".").join(&path));
+        }
+        let keywords = self.checked_keywords(entry, readable_keywords());
+        let actual = match read_params(&self.root.join(&path), keywords, false, progress) {
+            Ok((actual, _)) => Some(actual),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(self.compare(entry, path, keywords, actual.as_ref()))
+    }
+
+    /// Check the whole tree like [`Verifier::verify`], hashing files in parallel, and summarize
+    /// the results. This is enabled by the `rayon` feature.
+    ///
+    /// The files for the entries are looked at one after another, and then the files whose
+    /// digests need checking are read on rayon's thread pool. Reading the entries and looking
+    /// for extra files stops at the first error, like [`Verify::report`].
+    #[cfg(feature = "rayon")]
+    pub fn verify_parallel<I>(&self, entries: I) -> Result<VerificationReport, Error>
+    where
+        I: IntoIterator<Item = Result<Entry, Error>>,
+    {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        let mut listed = Listed::default();
+        let mut checks = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let path = spec_path(entry.spec_path());
+            if !listed.insert(path.clone(), &entry) {
+                continue;
+            }
+            let keywords = self.checked_keywords(&entry, readable_keywords());
+            #[cfg(feature = "digest")]
+            let stat_keywords = keywords - crate::scan::digest_keywords();
+            #[cfg(not(feature = "digest"))]
+            let stat_keywords = keywords;
+            let actual = match read_params(&self.root.join(&path), stat_keywords, false, None) {
+                Ok((actual, metadata)) => Some((actual, metadata.is_file())),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            checks.push((entry, path, keywords, actual));
+        }
+        let verifications = checks
+            .into_par_iter()
+            .map(|(entry, path, keywords, mut actual)| {
+                let progress = self.progress.as_deref();
+                if let Some(progress) = progress {
+                    progress.entry(&Path::new(".").join(&path));
+                }
+                #[cfg(feature = "digest")]
+                if let Some((params, true)) = &mut actual {
+                    crate::scan::hash_file(&self.root.join(&path), keywords, params, progress)?;
+                }
+                let actual = actual.as_mut().map(|(params, _)| &*params);
+                Ok(self.compare(&entry, path, keywords, actual))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut report = VerificationReport::new();
+        for verification in verifications {
+            report.push(verification);
+        }
+        for extra in listed.extras(&self.root) {
+            report.push(Verification::Extra(extra?));
+        }
+        Ok(report)
+    }
+
+    /// The keywords of `entry` to check, out of the `readable` ones.
+    pub(crate) fn checked_keywords(&self, entry: &Entry, readable: KeywordSet) -> KeywordSet {
+        if entry.params.no_change {
+            KeywordSet::empty()
+        } else {
+            entry
+                .params
+                .keywords()
+                .map(|k| k.kind())
+                .collect::<KeywordSet>()
+                & readable
+                & self.keywords
+        }
+    }
+
+    /// Compare the `keywords` of `entry`, at the spec path `path`, with the params of the file
+    /// that was found, if there was one.
+    pub(crate) fn compare(
+        &self,
+        entry: &Entry,
+        path: PathBuf,
+        keywords: KeywordSet,
+        actual: Option<&Params>,
+    ) -> Verification {
+        let path = Path::new(".").join(path);
+        let actual = match actual {
+            Some(actual) => actual,
+            None if entry.params.optional => return Verification::OptionalMissing(path),
+            None => return Verification::Missing(path),
+        };
+        let mut expected = entry.params.clone();
+        expected.unset(!keywords);
+        let mut mismatches = expected.diff(actual);
+        mismatches.retain(|mismatch| match mismatch {
+            ParamMismatch::Time(Some(expected), Some(actual)) => {
+                let difference = expected
+                    .duration_since(*actual)
+                    .or_else(|_| actual.duration_since(*expected))
+                    .unwrap_or(Duration::MAX);
+                difference > self.time_tolerance
+            }
+            // the numbers don't depend on the format, so e.g. `linux,1,3` matches `native,1,3`
+            ParamMismatch::Device(Some(expected), Some(actual))
+            | ParamMismatch::ResidentDevice(Some(expected), Some(actual)) => {
+                !expected.same_numbers(actual)
+            }
+            _ => true,
+        });
+        if mismatches.is_empty() {
+            Verification::Ok(path)
+        } else {
+            Verification::Mismatch(path, mismatches)
+        }
+    }
+}
+
+/// The path of the spec path `path` relative to the root of the spec, without a leading `./`
+/// (or `/`).
+pub(crate) fn spec_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir | Component::RootDir))
+        .collect()
+}
+
+/// The result of checking a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Verification {
+    /// The file matches its entry.
+    Ok(PathBuf),
+    /// The file for an entry doesn't exist.
+    Missing(PathBuf),
+    /// The file for an entry with `optional` doesn't exist, which is fine.
+    OptionalMissing(PathBuf),
+    /// The file isn't in the spec.
+    Extra(PathBuf),
+    /// The file doesn't match its entry. Each mismatch holds the value from the spec followed
+    /// by the value from the file, and digests that differ are included.
+    Mismatch(PathBuf, Vec<ParamMismatch>),
+}
+
+impl Verification {
+    /// The path of the file, relative to the root (e.g. `./usr/bin`).
+    pub fn path(&self) -> &Path {
+        match self {
+            Verification::Ok(path)
+            | Verification::Missing(path)
+            | Verification::OptionalMissing(path)
+            | Verification::Extra(path)
+            | Verification::Mismatch(path, _) => path,
+        }
+    }
+
+    /// The problems found by the check, one per mismatching keyword.
+    pub fn findings(&self) -> Vec<Finding<'_>> {
+        match self {
+            Verification::Ok(_) | Verification::OptionalMissing(_) => Vec::new(),
+            Verification::Missing(path) => vec![Finding::Missing(path)],
+            Verification::Extra(path) => vec![Finding::Extra(path)],
+            Verification::Mismatch(path, mismatches) => mismatches
+                .iter()
+                .map(|mismatch| Finding::Mismatch(path, mismatch))
+                .collect(),
+        }
+    }
+
+    /// Whether the check passed, i.e. the file matches its entry or is allowed to be missing.
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Verification::Ok(_) | Verification::OptionalMissing(_))
+    }
+}
+
+/// Writes e.g. `./etc/passwd: mode changed from 644 to 600`, with one line per mismatch.
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path().display();
+        match self {
+            Verification::Ok(_) => write!(f, "{}: ok", path),
+            Verification::Missing(_) => write!(f, "{}: missing", path),
+            Verification::OptionalMissing(_) => write!(f, "{}: missing (optional)", path),
+            Verification::Extra(_) => write!(f, "{}: extra", path),
+            Verification::Mismatch(..) => {
+                for (i, finding) in self.findings().iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", finding)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A single problem found by a check, e.g. one keyword that differs for a file.
+///
+/// With the `serde` feature enabled, this serializes as an object with the `path`, the
+/// `status` (`missing`, `extra` or `mismatch`), and for mismatches the `keyword` and the values
+/// it changed `from` (in the spec) and `to` (on disk), which is handy for writing one JSON
+/// object per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finding<'a> {
+    /// The file for an entry doesn't exist.
+    Missing(&'a Path),
+    /// The file isn't in the spec.
+    Extra(&'a Path),
+    /// A keyword of the file doesn't match its entry.
+    Mismatch(&'a Path, &'a ParamMismatch),
+}
+
+impl Finding<'_> {
+    /// The path of the file, relative to the root.
+    pub fn path(&self) -> &Path {
+        match self {
+            Finding::Missing(path) | Finding::Extra(path) | Finding::Mismatch(path, _) => path,
+        }
+    }
+}
+
+/// Writes e.g. `./etc/passwd: mode changed from 644 to 600`.
+impl fmt::Display for Finding<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::Missing(path) => write!(f, "{}: missing", path.display()),
+            Finding::Extra(path) => write!(f, "{}: extra", path.display()),
+            Finding::Mismatch(path, mismatch) => write!(f, "{}: {}", path.display(), mismatch),
+        }
+    }
+}
+
+/// A summary of checking a tree, made by [`Verify::report`] or by collecting [`Verification`]s.
+///
+/// With the `serde` feature enabled, this and [`Verification`] can be serialized, with paths
+/// as strings and mismatches as objects holding the `keyword` and the values it changed `from`
+/// and `to`, as they would appear in an mtree file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    /// How many files passed their checks.
+    pub ok: usize,
+    /// The files that don't match their entries, with the keywords that differ. Each mismatch
+    /// holds the value from the spec followed by the value from the file.
+    pub mismatches: BTreeMap<PathBuf, Vec<ParamMismatch>>,
+    /// The files for entries that don't exist.
+    pub missing: Vec<PathBuf>,
+    /// The files that aren't in the spec.
+    pub extra: Vec<PathBuf>,
+}
+
+impl VerificationReport {
+    /// An empty report.
+    pub fn new() -> VerificationReport {
+        VerificationReport::default()
+    }
+
+    /// Add the result of checking a file.
+    pub fn push(&mut self, verification: Verification) {
+        match verification {
+            Verification::Ok(_) | Verification::OptionalMissing(_) => self.ok += 1,
+            Verification::Missing(path) => self.missing.push(path),
+            Verification::Extra(path) => self.extra.push(path),
+            Verification::Mismatch(path, mismatches) => {
+                self.mismatches.insert(path, mismatches);
+            }
+        }
+    }
+
+    /// The problems found, with one per mismatching keyword, followed by the missing and extra
+    /// files.
+    pub fn findings(&self) -> impl Iterator<Item = Finding<'_>> {
+        let mismatches = self.mismatches.iter().flat_map(|(path, mismatches)| {
+            mismatches
+                .iter()
+                .map(move |mismatch| Finding::Mismatch(path, mismatch))
+        });
+        let missing = self.missing.iter().map(|path| Finding::Missing(path));
+        let extra = self.extra.iter().map(|path| Finding::Extra(path));
+        mismatches.chain(missing).chain(extra)
+    }
+
+    /// Whether every check passed.
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl Extend<Verification> for VerificationReport {
+    fn extend<T: IntoIterator<Item = Verification>>(&mut self, iter: T) {
+        for verification in iter {
+            self.push(verification);
+        }
+    }
+}
+
+impl FromIterator<Verification> for VerificationReport {
+    fn from_iter<T: IntoIterator<Item = Verification>>(iter: T) -> VerificationReport {
+        let mut report = VerificationReport::new();
+        report.extend(iter);
+        report
+    }
+}
+
+/// Writes each mismatching file followed by its mismatches, then the missing and extra files,
+/// e.g.
+///
+/// ```text
+/// ./etc/passwd:
+///     mode changed from 644 to 600
+///     size changed from 1024 to 1100
+/// missing: ./etc/group
+/// extra: ./etc/passwd-
+/// ```
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (path, mismatches) in &self.mismatches {
+            writeln!(f, "{}:", path.display())?;
+            for mismatch in mismatches {
+                writeln!(f, "    {}", mismatch)?;
+            }
+        }
+        for path in &self.missing {
+            writeln!(f, "missing: {}", path.display())?;
+        }
+        for path in &self.extra {
+            writeln!(f, "extra: {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the results of checking a tree, created by [`Verifier::verify`].
+///
+/// The entries are checked first, in order, followed by the files that aren't in the spec
+/// (but not the files inside a directory that isn't in the spec).
+#[derive(Debug)]
+pub struct Verify<I> {
+    /// How to check each entry.
+    verifier: Verifier,
+    /// The entries still to check.
+    entries: I,
+    /// The paths of the entries that have been checked.
+    listed: Listed,
+    /// The walk looking for extra files, once the entries have been checked.
+    extras: Option<Extras>,
+}
+
+impl<I> Verify<I>
+where
+    I: Iterator<Item = Result<Entry, Error>>,
+{
+    /// Check the whole tree, and summarize the results.
+    ///
+    /// This stops at the first error.
+    pub fn report(self) -> Result<VerificationReport, Error> {
+        let mut report = VerificationReport::new();
+        for verification in self {
+            report.push(verification?);
+        }
+        Ok(report)
+    }
+}
+
+impl<I> Iterator for Verify<I>
+where
+    I: Iterator<Item = Result<Entry, Error>>,
+{
+    type Item = Result<Verification, Error>;
+
+    fn next(&mut self) -> Option<Result<Verification, Error>> {
+        while self.extras.is_none() {
+            match self.entries.next() {
+                Some(Ok(entry)) => {
+                    let path = spec_path(entry.spec_path());
+                    if self.listed.insert(path, &entry) {
+                        return Some(self.verifier.verify_entry(&entry));
+                    }
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let listed = std::mem::take(&mut self.listed);
+                    self.extras = Some(listed.extras(&self.verifier.root));
+                }
+            }
+        }
+        Some(self.extras.as_mut()?.next()?.map(Verification::Extra))
+    }
+}
+
+/// The paths listed in a spec, used to find the files that aren't in it.
+#[derive(Debug, Default)]
+pub(crate) struct Listed {
+    /// The paths of the entries, relative to the root without a leading `./`.
+    seen: HashSet<PathBuf>,
+    /// The paths of the entries with `ignore`.
+    ignored: Vec<PathBuf>,
+}
+
+impl Listed {
+    /// Record that `entry` with the spec path `path` is listed, returning `false` if it's below
+    /// a directory with `ignore` and so shouldn't be checked.
+    pub(crate) fn insert(&mut self, path: PathBuf, entry: &Entry) -> bool {
+        let is_ignored = self
+            .ignored
+            .iter()
+            .any(|ignored| path != *ignored && path.starts_with(ignored));
+        if entry.params.ignore {
+            self.ignored.push(path.clone());
+        }
+        self.seen.insert(path);
+        !is_ignored
+    }
+
+    /// Whether `path` is listed, or is below a directory with `ignore`.
+    #[cfg(any(feature = "tar", feature = "zip"))]
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.seen.contains(path)
+            || self
+                .ignored
+                .iter()
+                .any(|ignored| path != ignored && path.starts_with(ignored))
+    }
+
+    /// Walk the tree at `root` looking for files that aren't listed.
+    pub(crate) fn extras(self, root: &Path) -> Extras {
+        let ignored = self.ignored;
+        let scanner = Scanner::new(root)
+            .keywords(KeywordSet::empty())
+            .exclude_if(move |path| {
+                let path = path.strip_prefix(".").unwrap_or(path);
+                path.parent()
+                    .is_some_and(|parent| ignored.iter().any(|i| parent == i))
+            });
+        Extras {
+            scan: scanner.scan(),
+            seen: self.seen,
+            last: None,
+        }
+    }
+}
+
+/// An iterator over the paths of files that aren't listed in a spec, created by
+/// [`Listed::extras`].
+///
+/// Nothing inside an extra directory is produced, like `mtree`.
+#[derive(Debug)]
+pub(crate) struct Extras {
+    /// The walk of the tree.
+    scan: Scan,
+    /// The paths that are listed.
+    seen: HashSet<PathBuf>,
+    /// The last extra path produced.
+    last: Option<PathBuf>,
+}
+
+impl Iterator for Extras {
+    type Item = Result<PathBuf, Error>;
+
+    fn next(&mut self) -> Option<Result<PathBuf, Error>> {
+        loop {
+            let path = match self.scan.next()? {
+                Ok(entry) => entry.path,
+                Err(e) => return Some(Err(e)),
+            };
+            if self
+                .last
+                .as_ref()
+                .is_some_and(|last| path.starts_with(last))
+            {
+                continue;
+            }
+            let spec_path = spec_path(&path);
+            if !spec_path.as_os_str().is_empty() && !self.seen.contains(&spec_path) {
+                self.last = Some(path.clone());
+                return Some(Ok(path));
+            }
+        }
+    }
+}
+
+/// The digests of an entry that were checked by [`verify_digests`].
+#[cfg(feature = "digest")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestMatches {
+    /// The digests that matched.
+    pub matched: KeywordSet,
+    /// The digests that didn't match, each holding the value from the entry followed by the
+    /// computed value.
+    pub mismatches: Vec<ParamMismatch>,
+}
+
+#[cfg(feature = "digest")]
+impl DigestMatches {
+    /// Whether every digest that was checked matched.
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Check every digest of `entry` against the contents of `reader`, reading it once.
+///
+/// Only the digests with an enabled feature are checked (see [`Scanner::keywords`]); the
+/// others are in neither [`DigestMatches::matched`] nor [`DigestMatches::mismatches`]. The
+/// reader isn't read at all if there are no digests to check.
+///
+/// ```
+/// use mtree::{verify_digests, Entry, KeywordSet, Params};
+///
+/// let entry = Entry::new("./hello").with_params(
+///     Params::builder()
+///         .md5(0xb1946ac92492d2347c6235b4d2611184)
+///         .build(),
+/// );
+/// let matches = verify_digests(&entry, &b"hello\n"[..]).unwrap();
+/// # #[cfg(feature = "md-5")]
+/// assert_eq!(matches.matched, KeywordSet::MD5);
+/// assert!(matches.is_ok());
+/// ```
+#[cfg(feature = "digest")]
+pub fn verify_digests(entry: &Entry, reader: impl io::Read) -> io::Result<DigestMatches> {
+    let keywords = entry
+        .params
+        .keywords()
+        .map(|k| k.kind())
+        .collect::<KeywordSet>()
+        & readable_keywords()
+        & (KeywordSet::MD5
+            | KeywordSet::RMD160
+            | KeywordSet::SHA1
+            | KeywordSet::SHA256
+            | KeywordSet::SHA384
+            | KeywordSet::SHA512);
+    let mut actual = Params::default();
+    crate::scan::hash_reader(reader, keywords, &mut actual)?;
+    let mut expected = entry.params.clone();
+    expected.unset(!keywords);
+    let mismatches = expected.diff(&actual);
+    let mismatched = mismatches
+        .iter()
+        .map(ParamMismatch::keyword)
+        .collect::<KeywordSet>();
+    Ok(DigestMatches {
+        matched: keywords - mismatched,
+        mismatches,
+    })
+}
+
+/// Check the `keyword` digest of `entry` (e.g. [`KeywordSet::SHA256`]) against the contents of
+/// `reader`, hashed with `D`.
+///
+/// Unlike [`verify_digests`], this works with any [`Digest`](digest::Digest) implementation,
+/// not just the ones built in, so it needs no backend feature. The reader isn't read at all if
+/// the entry has no such digest. It is an [`InvalidInput`](io::ErrorKind::InvalidInput) error
+/// if `keyword` isn't a single digest keyword, or `D` gives digests of the wrong length for it.
+///
+/// ```
+/// use mtree::{verify_digest, Entry, KeywordSet, Params};
+///
+/// # #[cfg(feature = "md-5")] {
+/// let entry = Entry::new("./hello").with_params(
+///     Params::builder()
+///         .md5(0xb1946ac92492d2347c6235b4d2611184)
+///         .build(),
+/// );
+/// let matches = verify_digest::<md5::Md5>(&entry, KeywordSet::MD5, &b"hello\n"[..]).unwrap();
+/// assert_eq!(matches.matched, KeywordSet::MD5);
+/// # }
+/// ```
+#[cfg(feature = "digest")]
+pub fn verify_digest<D: digest::Digest>(
+    entry: &Entry,
+    keyword: KeywordSet,
+    mut reader: impl io::Read,
+) -> io::Result<DigestMatches> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let mut expected = entry.params.clone();
+    expected.unset(!keyword);
+    let digests = KeywordSet::MD5
+        | KeywordSet::RMD160
+        | KeywordSet::SHA1
+        | KeywordSet::SHA256
+        | KeywordSet::SHA384
+        | KeywordSet::SHA512;
+    if keyword.bits().count_ones() != 1 || !digests.contains(keyword) {
+        return Err(invalid(format!("{} is not a digest keyword", keyword)));
+    }
+    if expected.keywords().next().is_none() {
+        return Ok(DigestMatches {
+            matched: KeywordSet::empty(),
+            mismatches: Vec::new(),
+        });
+    }
+
+    let mut hasher = D::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => hasher.update(&buf[..len]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let digest = hasher.finalize();
+    let mut actual = Params::default();
+    if !crate::scan::set_digest(&mut actual, keyword, &digest) {
+        let msg = format!(
+            "a {} byte digest is the wrong length for {}",
+            digest.len(),
+            keyword
+        );
+        return Err(invalid(msg));
+    }
+    let mismatches = expected.diff(&actual);
+    Ok(DigestMatches {
+        matched: if mismatches.is_empty() {
+            keyword
+        } else {
+            KeywordSet::empty()
+        },
+        mismatches,
+    })
+}
+
+#[test]
+fn test_verify() {
+    use crate::MTree;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    let root = crate::scan::test_dir("verify");
+    fs::create_dir_all(root.join("dir")).unwrap();
+    fs::write(root.join("dir/file"), b"hello").unwrap();
+    fs::set_permissions(root.join("dir/file"), fs::Permissions::from_mode(0o600)).unwrap();
+    fs::write(root.join("extra"), b"").unwrap();
+
+    let spec = b"\
+./dir type=dir
+./dir/file type=file mode=644 size=5
+./missing type=file
+";
+    let results = Verifier::new(&root)
+        .verify(MTree::from_reader(&spec[..]))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            Verification::Ok("./dir".into()),
+            Verification::Mismatch(
+                "./dir/file".into(),
+                vec![ParamMismatch::Mode(
+                    Some(crate::FileMode::from(0o644)),
+                    Some(crate::FileMode::from(0o600))
+                )]
+            ),
+            Verification::Missing("./missing".into()),
+            Verification::Extra("./extra".into()),
+        ]
+    );
+    assert_eq!(
+        results[1].to_string(),
+        "./dir/file: mode changed from 644 to 600"
+    );
+
+    let report = results.into_iter().collect::<VerificationReport>();
+    assert!(!report.is_ok());
+    assert_eq!(report.ok, 1);
+    assert_eq!(
+        report.to_string(),
+        "./dir/file:\n    mode changed from 644 to 600\nmissing: ./missing\nextra: ./extra\n"
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_verify_with_root() {
+    use crate::MTree;
+    use std::fs;
+    let root = crate::scan::test_dir("verify-root");
+    fs::create_dir_all(root.join("usr")).unwrap();
+    fs::write(root.join("usr/file"), b"hello").unwrap();
+
+    // the entries have absolute paths below `root`, but are found by their spec paths
+    let spec = b". type=dir\n    usr type=dir\n        file type=file size=5\n";
+    let results = Verifier::new(&root)
+        .verify(MTree::from_reader_with_root(&spec[..], &root))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            Verification::Ok(".".into()),
+            Verification::Ok("./usr".into()),
+            Verification::Ok("./usr/file".into()),
+        ]
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_verify_flags() {
+    use crate::MTree;
+    use std::fs;
+    let root = crate::scan::test_dir("verify-flags");
+    fs::create_dir_all(root.join("cache/sub")).unwrap();
+    fs::write(root.join("cache/sub/file"), b"").unwrap();
+    fs::write(root.join("log"), b"grown").unwrap();
+
+    let spec = b"\
+./cache type=dir ignore
+./cache/listed type=file
+./log type=file size=0 nochange
+./opt type=dir optional
+";
+    let results = Verifier::new(&root)
+        .verify(MTree::from_reader(&spec[..]))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            Verification::Ok("./cache".into()),
+            Verification::Ok("./log".into()),
+            Verification::OptionalMissing("./opt".into()),
+        ]
+    );
+    assert!(results.iter().all(Verification::is_ok));
+
+    let spec = b"./log type=dir size=0 mode=0\n";
+    let verifier = Verifier::new(&root).keywords(KeywordSet::TYPE | KeywordSet::MODE);
+    let mut results = verifier.verify(MTree::from_reader(&spec[..]));
+    assert!(!results.next().unwrap().unwrap().is_ok());
+    let verifier = Verifier::new(&root).keywords(KeywordSet::SIZE);
+    let mut results = verifier.verify(MTree::from_reader(&spec[..]));
+    assert_eq!(
+        results.next().unwrap().unwrap(),
+        Verification::Mismatch("./log".into(), vec![ParamMismatch::Size(Some(0), Some(5))])
+    );
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_verify_device() {
+    use crate::{Device, Format, MTree};
+    let spec = b"\
+./null type=char device=linux,1,3
+./zero type=char device=native,1,5
+./full type=char device=linux,1,3
+";
+    // everything else in `/dev` is extra
+    let results = Verifier::new("/dev")
+        .keywords(KeywordSet::TYPE | KeywordSet::DEVICE)
+        .verify(MTree::from_reader(&spec[..]))
+        .map(Result::unwrap)
+        .filter(|result| !matches!(result, Verification::Extra(..)))
+        .collect::<Vec<_>>();
+    let device = |format, minor| {
+        Some(Device {
+            format,
+            major: 1,
+            minor,
+            subunit: None,
+        })
+    };
+    assert_eq!(
+        results,
+        vec![
+            Verification::Ok("./null".into()),
+            Verification::Ok("./zero".into()),
+            Verification::Mismatch(
+                "./full".into(),
+                vec![ParamMismatch::Device(
+                    device(Format::Linux, 3),
+                    device(Format::Native, 7)
+                )]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_verify_time_tolerance() {
+    use crate::Params;
+    use std::fs;
+    let root = crate::scan::test_dir("verify-time");
+    fs::write(root.join("file"), b"").unwrap();
+    let modified = fs::metadata(root.join("file")).unwrap().modified().unwrap();
+
+    let entry = Entry::new("./file").with_params(
+        Params::builder()
+            .time(modified + Duration::from_millis(1500))
+            .build(),
+    );
+    assert!(!Verifier::new(&root).verify_entry(&entry).unwrap().is_ok());
+    let verifier = Verifier::new(&root).time_tolerance(Duration::from_secs(2));
+    assert!(verifier.verify_entry(&entry).unwrap().is_ok());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(all(feature = "md-5", feature = "sha2"))]
+#[test]
+fn test_verify_digests() {
+    let params = Params::builder()
+        .md5(0xb1946ac92492d2347c6235b4d2611184)
+        .sha256(
+            crate::util::FromHex::from_hex(
+                b"0000000000000000000000000000000000000000000000000000000000000000",
+            )
+            .unwrap(),
+        )
+        .size(6)
+        .build();
+    let entry = Entry::new("./hello").with_params(params);
+    let matches = verify_digests(&entry, &b"hello\n"[..]).unwrap();
+    assert_eq!(matches.matched, KeywordSet::MD5);
+    assert_eq!(matches.mismatches.len(), 1);
+    assert_eq!(matches.mismatches[0].keyword(), KeywordSet::SHA256);
+    assert!(!matches.is_ok());
+
+    let entry = Entry::new("./hello");
+    let matches = verify_digests(&entry, &b"hello\n"[..]).unwrap();
+    assert_eq!(matches.matched, KeywordSet::empty());
+    assert!(matches.is_ok());
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn test_verify_digest() {
+    use sha2::{Sha256, Sha512};
+
+    let sha256 = crate::util::FromHex::from_hex(
+        b"5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03",
+    )
+    .unwrap();
+    let entry = Entry::new("./hello").with_params(Params::builder().sha256(sha256).build());
+    let matches = verify_digest::<Sha256>(&entry, KeywordSet::SHA256, &b"hello\n"[..]).unwrap();
+    assert_eq!(matches.matched, KeywordSet::SHA256);
+    assert!(matches.is_ok());
+
+    let matches = verify_digest::<Sha256>(&entry, KeywordSet::SHA256, &b"hullo\n"[..]).unwrap();
+    assert_eq!(matches.matched, KeywordSet::empty());
+    assert_eq!(matches.mismatches[0].keyword(), KeywordSet::SHA256);
+
+    // no digest to check
+    let matches = verify_digest::<Sha256>(&entry, KeywordSet::SHA512, &b"hello\n"[..]).unwrap();
+    assert_eq!(matches.matched, KeywordSet::empty());
+    assert!(matches.is_ok());
+
+    let err = verify_digest::<Sha512>(&entry, KeywordSet::SHA256, &b"hello\n"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = verify_digest::<Sha256>(&entry, KeywordSet::SIZE, &b"hello\n"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let keywords = KeywordSet::SHA256 | KeywordSet::MD5;
+    let err = verify_digest::<Sha256>(&entry, keywords, &b"hello\n"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_verify_parallel() {
+    use crate::MTree;
+    use std::fs;
+    let root = crate::scan::test_dir("verify-parallel");
+    fs::create_dir_all(root.join("dir")).unwrap();
+    for i in 0..20 {
+        fs::write(root.join(format!("dir/{}", i)), format!("file {}", i)).unwrap();
+    }
+    fs::write(root.join("extra"), b"").unwrap();
+
+    // the md5 of `file 3` is wrong, and only checked with a digest feature
+    let mut spec = String::from("./dir type=dir\n./missing type=file\n");
+    for i in 0..20 {
+        spec.push_str(&format!(
+            "./dir/{} type=file size={}",
+            i,
+            format!("file {}", i).len()
+        ));
+        if i == 3 {
+            spec.push_str(" md5digest=00000000000000000000000000000000");
+        }
+        spec.push('\n');
+    }
+    let verifier = Verifier::new(&root);
+    let report = verifier
+        .verify_parallel(MTree::from_reader(spec.as_bytes()))
+        .unwrap();
+    let expected = verifier
+        .verify(MTree::from_reader(spec.as_bytes()))
+        .report()
+        .unwrap();
+    assert_eq!(report, expected);
+    assert_eq!(report.missing.len(), 1);
+    assert_eq!(report.extra.len(), 1);
+    assert_eq!(
+        report.mismatches.len(),
+        usize::from(readable_keywords().contains(KeywordSet::MD5))
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,395 @@
+//! Stuff for writing mtree files.
+use crate::parser::{Keyword, KeywordSet};
+use crate::util::{path_to_bytes, vis};
+use crate::{Entry, Params};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Writes entries out as an mtree spec.
+///
+/// Every entry is written as a full path (one containing a `/`), so the output doesn't depend
+/// on the current directory. The path written is [`Entry::spec_path`], and absolute paths are
+/// written relative to the root of the spec, e.g. `/usr/bin` becomes `./usr/bin`.
+///
+/// Entries written with [`write_entries`](MTreeWriter::write_entries) share `/set` defaults
+/// where they can (see [`WriterOptions`]), otherwise every parameter is written on the entry's
+/// own line.
+#[derive(Debug)]
+pub struct MTreeWriter<W>
+where
+    W: Write,
+{
+    /// Where the spec is written to.
+    inner: W,
+    /// The options we were constructed with.
+    options: WriterOptions,
+    /// The defaults from the `/set` lines we have written.
+    defaults: Params,
+}
+
+impl<W> MTreeWriter<W>
+where
+    W: Write,
+{
+    /// Create a writer that writes the spec to `inner`.
+    pub fn new(inner: W) -> MTreeWriter<W> {
+        MTreeWriter::with_options(inner, WriterOptions::default())
+    }
+
+    /// Create a writer that writes the spec to `inner` according to `options`.
+    pub fn with_options(inner: W, options: WriterOptions) -> MTreeWriter<W> {
+        MTreeWriter {
+            inner,
+            options,
+            defaults: Params::default(),
+        }
+    }
+
+    /// Write the `#mtree v2.0` signature.
+    ///
+    /// If used, this should be the first thing written.
+    pub fn write_signature(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"#mtree v2.0\n")
+    }
+
+    /// Write a comment line. Newlines in `comment` start a new comment line.
+    pub fn write_comment(&mut self, comment: &str) -> io::Result<()> {
+        for line in comment.lines() {
+            writeln!(self.inner, "# {}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Write `entries`, using `/set` and `/unset` lines for the values they share.
+    ///
+    /// For each keyword in [`WriterOptions::set_keywords`], the most common value becomes the
+    /// default if at least [`WriterOptions::set_threshold`] entries have it, and every entry has
+    /// some value for the keyword. Defaults carry over to later calls, and are kept while every
+    /// entry still has a value for them.
+    ///
+    /// The entries are written in the order given, or in canonical order if
+    /// [`WriterOptions::sorted`] is set.
+    pub fn write_entries(&mut self, entries: &[Entry]) -> io::Result<()> {
+        let mut counts: BTreeMap<Keyword<'_>, usize> = BTreeMap::new();
+        let mut everywhere = self.options.set_keywords;
+        for entry in entries {
+            let mut present = KeywordSet::empty();
+            for keyword in entry.params.keywords() {
+                let kind = keyword.kind();
+                present |= kind;
+                if everywhere.contains(kind) {
+                    *counts.entry(keyword).or_insert(0) += 1;
+                }
+            }
+            everywhere &= present;
+        }
+        let mut best: BTreeMap<KeywordSet, (&Keyword<'_>, usize)> = BTreeMap::new();
+        for (keyword, &count) in &counts {
+            let kind = keyword.kind();
+            if !everywhere.contains(kind) || count < self.options.set_threshold {
+                continue;
+            }
+            match best.get(&kind) {
+                Some((_, best_count)) if *best_count >= count => (),
+                _ => {
+                    best.insert(kind, (keyword, count));
+                }
+            }
+        }
+        let mut defaults = Params::default();
+        for (keyword, _) in best.values() {
+            defaults.set((*keyword).clone());
+        }
+        // keep existing defaults that are still usable, rather than unsetting them
+        for keyword in self.defaults.keywords() {
+            let kind = keyword.kind();
+            if everywhere.contains(kind) && !best.contains_key(&kind) {
+                defaults.set(keyword);
+            }
+        }
+        self.write_defaults(defaults)?;
+        let mut entries = entries.iter().collect::<Vec<_>>();
+        if self.options.sorted {
+            entries.sort_by(|a, b| a.cmp_canonical(b));
+        }
+        for entry in entries {
+            self.write_entry(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Write `/unset` and `/set` lines to change the current defaults to `defaults`.
+    fn write_defaults(&mut self, defaults: Params) -> io::Result<()> {
+        let current = self.defaults.keywords().collect::<Vec<_>>();
+        let unset = current
+            .iter()
+            .fold(KeywordSet::empty(), |acc, keyword| acc | keyword.kind())
+            - defaults
+                .keywords()
+                .fold(KeywordSet::empty(), |acc, keyword| acc | keyword.kind());
+        if !unset.is_empty() {
+            writeln!(self.inner, "/unset {}", unset)?;
+        }
+        let set = defaults
+            .keywords()
+            .filter(|keyword| !current.contains(keyword))
+            .collect::<Vec<_>>();
+        if !set.is_empty() {
+            self.inner.write_all(b"/set")?;
+            for keyword in &set {
+                write!(self.inner, " {}", keyword)?;
+            }
+            self.inner.write_all(b"\n")?;
+        }
+        self.defaults = defaults;
+        Ok(())
+    }
+
+    /// Write `entry` as a single line.
+    ///
+    /// Parameters that match the current `/set` defaults are left out. If the entry is missing
+    /// a parameter that has a default, that default is unset first.
+    pub fn write_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        let defaults = self.defaults.keywords().collect::<Vec<_>>();
+        let present = entry
+            .params
+            .keywords()
+            .fold(KeywordSet::empty(), |acc, keyword| acc | keyword.kind());
+        let missing = defaults
+            .iter()
+            .map(Keyword::kind)
+            .filter(|kind| !present.contains(*kind))
+            .fold(KeywordSet::empty(), |acc, kind| acc | kind);
+        if !missing.is_empty() {
+            writeln!(self.inner, "/unset {}", missing)?;
+            self.defaults.unset(missing);
+        }
+        self.inner.write_all(&full_path(entry.spec_path()))?;
+        let defaults = self.defaults.keywords().collect::<Vec<_>>();
+        for keyword in entry.params.keywords() {
+            if !defaults.contains(&keyword) {
+                write!(self.inner, " {}", keyword)?;
+            }
+        }
+        self.inner.write_all(b"\n")
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap this writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Escape `path` so it can be written as the full path of an entry.
+pub(crate) fn full_path(path: &Path) -> Vec<u8> {
+    let path = path_to_bytes(path);
+    let mut output = Vec::with_capacity(path.len() + 2);
+    // a path starting with `/` would be read as a special command, and one without a `/`
+    // would be read relative to the current directory. The root is written as a bare `.`, like
+    // BSD mtree, which names the current directory itself.
+    if &*path == b"." {
+        return path.into_owned();
+    } else if path.starts_with(b"/") {
+        output.push(b'.');
+    } else if !path.contains(&b'/') {
+        output.extend_from_slice(b"./");
+    }
+    output.extend_from_slice(&vis(&path));
+    output
+}
+
+/// Options controlling how an mtree is written.
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    /// The keywords that may be given defaults with `/set`.
+    set_keywords: KeywordSet,
+    /// How many entries must share a value before it becomes a default.
+    set_threshold: usize,
+    /// Whether to sort the entries given to `write_entries`.
+    sorted: bool,
+}
+
+impl Default for WriterOptions {
+    fn default() -> WriterOptions {
+        WriterOptions {
+            set_keywords: KeywordSet::TYPE
+                | KeywordSet::UID
+                | KeywordSet::GID
+                | KeywordSet::UNAME
+                | KeywordSet::GNAME
+                | KeywordSet::MODE
+                | KeywordSet::FLAGS,
+            set_threshold: 2,
+            sorted: false,
+        }
+    }
+}
+
+impl WriterOptions {
+    /// The default options.
+    ///
+    /// `type`, `uid`, `gid`, `uname`, `gname`, `mode` and `flags` are given defaults when at
+    /// least 2 entries share them, like `bsdtar --format=mtree`.
+    pub fn new() -> WriterOptions {
+        WriterOptions::default()
+    }
+
+    /// Choose which keywords may be given defaults with `/set`.
+    ///
+    /// `ignore`, `nochange`, `optional` and `xattr` can't be overridden by an entry once set,
+    /// so they are never given defaults. Pass an empty set to never write `/set` lines.
+    pub fn set_keywords(mut self, keywords: KeywordSet) -> WriterOptions {
+        self.set_keywords = keywords
+            - (KeywordSet::IGNORE
+                | KeywordSet::NO_CHANGE
+                | KeywordSet::OPTIONAL
+                | KeywordSet::XATTR
+                | KeywordSet::EXTENSIONS);
+        self
+    }
+
+    /// Choose how many entries must share a value before it becomes a default.
+    pub fn set_threshold(mut self, threshold: usize) -> WriterOptions {
+        self.set_threshold = threshold;
+        self
+    }
+
+    /// Choose whether [`write_entries`](MTreeWriter::write_entries) sorts the entries into
+    /// canonical order (see [`Entry::cmp_canonical`]) before writing them.
+    ///
+    /// Together with the choice of `/set` defaults, which doesn't depend on the order, this
+    /// makes the output the same for the same entries however they were found, so specs that
+    /// are regenerated diff cleanly. The default is to keep the order given.
+    pub fn sorted(mut self, sorted: bool) -> WriterOptions {
+        self.sorted = sorted;
+        self
+    }
+}
+
+#[test]
+fn test_write_entries() {
+    use crate::MTree;
+    let raw = br"#mtree v2.0
+/set type=file uid=0 gid=0 mode=644
+./usr type=dir time=1523250049.905171912
+./usr/bin/a\040b size=10 sha256digest=db1941d00645bfaab04dd3898ee8b8484874f4880bf03f717adf43a9f30d9b8c
+./usr/bin/c type=link link=a\040b optional nochange
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let mut writer = MTreeWriter::new(Vec::new());
+    writer.write_signature().unwrap();
+    for entry in &entries {
+        writer.write_entry(entry).unwrap();
+    }
+    let written = writer.into_inner();
+    assert_eq!(
+        String::from_utf8_lossy(&written),
+        "\
+#mtree v2.0
+./usr type=dir gid=0 mode=644 time=1523250049.905171912 uid=0
+./usr/bin/a\\040b type=file gid=0 mode=644 \
+sha256digest=db1941d00645bfaab04dd3898ee8b8484874f4880bf03f717adf43a9f30d9b8c size=10 uid=0
+./usr/bin/c type=link gid=0 link=a\\040b mode=644 nochange optional uid=0
+"
+    );
+    let reread = MTree::from_reader(&written[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(reread, entries);
+}
+
+#[test]
+fn test_write_absolute_path() {
+    let entry = Entry {
+        path: "/etc/passwd".into(),
+        spec_path: None,
+        explicit: None,
+        params: Default::default(),
+    };
+    let mut writer = MTreeWriter::new(Vec::new());
+    writer.write_entry(&entry).unwrap();
+    assert_eq!(writer.into_inner(), b"./etc/passwd\n");
+}
+
+#[test]
+fn test_write_entries_with_set() {
+    use crate::MTree;
+    let raw = b"\
+./a type=file uid=0 gid=0 mode=644 size=1
+./b type=file uid=0 gid=0 mode=644 size=2
+./c type=dir uid=0 gid=0 mode=755
+./d type=file uid=0 mode=644 size=3
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let mut writer = MTreeWriter::new(Vec::new());
+    writer.write_entries(&entries[..3]).unwrap();
+    writer.write_entries(&entries[3..]).unwrap();
+    let written = writer.into_inner();
+    assert_eq!(
+        String::from_utf8_lossy(&written),
+        "\
+/set type=file gid=0 mode=644 uid=0
+./a size=1
+./b size=2
+./c type=dir mode=755
+/unset gid
+./d size=3
+"
+    );
+    let reread = MTree::from_reader(&written[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(reread, entries);
+
+    // nothing is shared unless the threshold is met
+    let options = WriterOptions::new().set_threshold(4);
+    let mut writer = MTreeWriter::with_options(Vec::new(), options);
+    writer.write_entries(&entries[..3]).unwrap();
+    assert!(!writer.into_inner().starts_with(b"/set"));
+}
+
+#[test]
+fn test_write_sorted() {
+    use crate::MTree;
+    let raw = b"\
+./usr/lib type=dir
+./usr type=dir
+./usr.txt type=file
+./usr/bin type=dir
+";
+    let entries = MTree::from_reader(&raw[..])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let mut writer = MTreeWriter::with_options(Vec::new(), WriterOptions::new().sorted(true));
+    writer.write_entries(&entries).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&writer.into_inner()),
+        "\
+/set type=dir
+./usr
+./usr/bin
+./usr/lib
+./usr.txt type=file
+"
+    );
+}